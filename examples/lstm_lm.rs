@@ -8,8 +8,6 @@ type Tensor<'graph> = ag::Tensor<'graph, f32>;
 
 struct LSTM<'g> {
     vector_dim: usize,
-    hs: Vec<Tensor<'g>>,
-    cells: Vec<Tensor<'g>>,
     wx: Tensor<'g>,
     wh: Tensor<'g>,
     b: Tensor<'g>,
@@ -20,8 +18,6 @@ impl<'g> LSTM<'g> {
         let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
         LSTM {
             vector_dim,
-            hs: vec![],
-            cells: vec![],
             wx: s.variable(rng.random_normal(&[vector_dim, 4 * vector_dim], 0., 0.01)),
             wh: s.variable(rng.random_normal(&[vector_dim, 4 * vector_dim], 0., 0.01)),
             b: s.variable(ag::ndarray_ext::zeros(&[1, 4 * vector_dim])),
@@ -32,74 +28,81 @@ impl<'g> LSTM<'g> {
     /// `x` must be a tensor with shape `(batch_size, embedding_dim)`
     ///
     /// # Returns
-    /// Output tensor of this unit with shape `(batch_size, state_size)`.
-    fn step(&mut self, x: Tensor<'g>, s: &'g Graph<f32>) -> &Tensor<'g> {
-        let (cell, h) = {
-            let ref last_output = self.hs.pop().unwrap_or_else(|| s.zeros(&s.shape(x)));
-            let last_cell = self.cells.pop().unwrap_or_else(|| s.zeros(&s.shape(x)));
+    /// Output and cell state of this unit with shape `(batch_size, state_size)`.
+    fn step(
+        &self,
+        x: Tensor<'g>,
+        last_output: Tensor<'g>,
+        last_cell: Tensor<'g>,
+        s: &'g Graph<f32>,
+    ) -> (Tensor<'g>, Tensor<'g>) {
+        let xh = s.matmul(x, self.wx) + s.matmul(last_output, self.wh) + self.b;
 
-            let xh = s.matmul(x, self.wx) + s.matmul(last_output, self.wh) + self.b;
+        let size = self.vector_dim as isize;
+        let i = s.slice(xh, &[0, 0 * size], &[-1, 1 * size]);
+        let f = s.slice(xh, &[0, 1 * size], &[-1, 2 * size]);
+        let c = s.slice(xh, &[0, 2 * size], &[-1, 3 * size]);
+        let o = s.slice(xh, &[0, 3 * size], &[-1, 4 * size]);
 
-            let size = self.vector_dim as isize;
-            let i = s.slice(xh, &[0, 0 * size], &[-1, 1 * size]);
-            let f = s.slice(xh, &[0, 1 * size], &[-1, 2 * size]);
-            let c = s.slice(xh, &[0, 2 * size], &[-1, 3 * size]);
-            let o = s.slice(xh, &[0, 3 * size], &[-1, 4 * size]);
-
-            let cell = s.sigmoid(f) * last_cell + s.sigmoid(i) * s.tanh(c);
-            let h = s.sigmoid(o) * s.tanh(&cell);
-            (cell, h)
-        };
-        self.cells.push(cell);
-        self.hs.push(h);
-        self.hs.last().unwrap()
+        let cell = s.sigmoid(f) * last_cell + s.sigmoid(i) * s.tanh(c);
+        let h = s.sigmoid(o) * s.tanh(&cell);
+        (h, cell)
     }
 }
 
 // TODO: Use real-world data
-// TODO: Write in define-by-run style
 pub fn main() {
     let vec_dim = 4;
-    let max_sent = 3;
     let vocab_size = 5;
 
     ag::with(|s| {
-        let sentences = s.placeholder(&[-1, max_sent]);
-        let ref mut rnn = LSTM::new(vec_dim, s);
+        // Sentences of any length, and the words following each word
+        let sentences = s.placeholder(&[-1, -1]);
+        let next_words = s.placeholder(&[-1, -1]);
+        let ref rnn = LSTM::new(vec_dim, s);
 
         let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
         let lookup_table = s.variable(rng.random_normal(&[vocab_size, vec_dim], 0., 0.01));
         let w_pred = s.variable(rng.random_uniform(&[vec_dim, vocab_size], 0., 0.01));
 
-        // Compute cross entropy losses for each LSTM step
-        let losses: Vec<ag::Tensor<_>> = (0..max_sent)
-            .map(|i| {
-                let cur_id = s.slice(sentences, &[0, i], &[-1, i + 1]);
-                let next_id = s.slice(sentences, &[0, i + 1], &[-1, i + 2]);
-                let x = s.gather(lookup_table, &cur_id, 0);
-                let h = rnn.step(x, s);
-                let prediction = s.matmul(h, w_pred);
-                s.sparse_softmax_cross_entropy(prediction, next_id)
-            })
-            .collect();
-
-        // Aggregate losses of generated words
-        let loss = s.add_n(&losses);
+        // Runs the LSTM over the time steps without unrolling it.
+        let first_word = s.gather(lookup_table, s.gather(sentences, s.scalar(0.), 1), 0);
+        let initial_state = s.zeros(&s.shape(first_word));
+        let states = s.scan(
+            |states, word_ids| {
+                let x = s.gather(lookup_table, word_ids, 0);
+                let (h, cell) = rnn.step(x, states[0], states[1], s);
+                vec![h, cell]
+            },
+            &[initial_state, initial_state],
+            sentences,
+            1,
+        );
+        // Cross entropy losses of predicting the next words from the outputs of all the steps
+        let outputs = s.reshape(states[0], &[-1, vec_dim as isize]);
+        let prediction = s.matmul(outputs, w_pred);
+        let labels = s.reshape(next_words, &[-1, 1]);
+        let loss = s.reduce_mean(
+            s.sparse_softmax_cross_entropy(prediction, labels),
+            &[0, 1],
+            false,
+        );
 
         // Compute gradients
         let vars = &[rnn.wh, rnn.wx, rnn.b, lookup_table, w_pred];
         let grads = s.grad(&[loss], vars);
 
         // test with toy data
+        let sentences_val = ndarray::arr2(&[[2., 3., 1.], [3., 0., 1.]]).into_dyn();
+        let next_words_val = ndarray::arr2(&[[3., 1., 4.], [0., 1., 2.]]).into_dyn();
         ag::test_helper::check_theoretical_grads(
             loss,
             grads.as_slice(),
             vars,
-            &[sentences.given(
-                ndarray::arr2(&[[2., 3., 1.], [3., 0., 1.]])
-                    .into_dyn()
-                    .view(),
-            )],
+            &[
+                sentences.given(sentences_val.view()),
+                next_words.given(next_words_val.view()),
+            ],
             1e-3,
             1e-3,
         );
@@ -0,0 +1,140 @@
+//! Defining the reports of the anomaly detection mode.
+//!
+//! Enable the mode with [Eval::detect_anomaly](../struct.Eval.html#method.detect_anomaly)
+//! or [Graph::detect_anomaly](../struct.Graph.html#method.detect_anomaly). Then the evaluation
+//! fails with [EvalError::NonFinite](../enum.EvalError.html#variant.NonFinite) at the first op
+//! that outputs NaN or infinity.
+use crate::ndarray_ext::NdArrayView;
+use crate::Float;
+use std::fmt;
+use std::panic::Location;
+
+/// Summary of an array.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArrayStats {
+    /// Shape of the array.
+    pub shape: Vec<usize>,
+    /// Minimum of the finite elements (`NaN` if none).
+    pub min: f64,
+    /// Maximum of the finite elements (`NaN` if none).
+    pub max: f64,
+    /// Mean of the finite elements (`NaN` if none).
+    pub mean: f64,
+    /// Number of the `NaN` elements.
+    pub num_nan: usize,
+    /// Number of the infinite elements.
+    pub num_inf: usize,
+}
+
+impl ArrayStats {
+    pub(crate) fn new<F: Float>(x: &NdArrayView<F>) -> Self {
+        let (mut min, mut max, mut sum) = (f64::INFINITY, f64::NEG_INFINITY, 0.);
+        let (mut num_nan, mut num_inf) = (0, 0);
+        for &a in x.iter() {
+            let a = a.to_f64().unwrap();
+            if a.is_nan() {
+                num_nan += 1;
+            } else if a.is_infinite() {
+                num_inf += 1;
+            } else {
+                min = min.min(a);
+                max = max.max(a);
+                sum += a;
+            }
+        }
+        let num_finite = x.len() - num_nan - num_inf;
+        if num_finite == 0 {
+            min = f64::NAN;
+            max = f64::NAN;
+        }
+        ArrayStats {
+            shape: x.shape().to_vec(),
+            min,
+            max,
+            mean: sum / num_finite as f64,
+            num_nan,
+            num_inf,
+        }
+    }
+
+    /// Returns true if all the elements are finite.
+    pub fn is_finite(&self) -> bool {
+        self.num_nan == 0 && self.num_inf == 0
+    }
+}
+
+impl fmt::Display for ArrayStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "shape={:?} min={} max={} mean={} nan={} inf={}",
+            self.shape, self.min, self.max, self.mean, self.num_nan, self.num_inf
+        )
+    }
+}
+
+/// Report of the first op that output non-finite values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anomaly {
+    /// Id of the tensor.
+    pub id: usize,
+    /// `Op::name` of the tensor.
+    pub op_name: String,
+    /// Name given with `Tensor::with_name`.
+    pub name: Option<String>,
+    /// True if the tensor was created by differentiation (e.g. `Graph::grad`).
+    pub is_gradient: bool,
+    /// Where in the source the tensor was created, if known.
+    ///
+    /// For gradient tensors, this is usually in `Op::grad` of the op being differentiated.
+    pub location: Option<&'static Location<'static>>,
+    /// Index of the output having non-finite values.
+    pub output_index: usize,
+    /// Summary of the output.
+    pub output: ArrayStats,
+    /// Summaries of the inputs (`None` for empty, integer and boolean inputs).
+    pub inputs: Vec<Option<ArrayStats>>,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Non-finite values in the output {} of ",
+            self.output_index
+        )?;
+        if self.is_gradient {
+            write!(f, "gradient ")?;
+        }
+        write!(f, "{} (id {}", self.op_name, self.id)?;
+        if let Some(ref name) = self.name {
+            write!(f, ", name {}", name)?;
+        }
+        write!(f, ")")?;
+        if let Some(location) = self.location {
+            write!(f, " created at {}", location)?;
+        }
+        write!(f, "\n  output: {}", self.output)?;
+        for (i, x) in self.inputs.iter().enumerate() {
+            match x {
+                Some(x) => write!(f, "\n  input {}: {}", i, x)?,
+                None => write!(f, "\n  input {}: empty", i)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_array_stats() {
+    let x = ndarray::arr1(&[1., f32::NAN, -3., f32::INFINITY, 5.]).into_dyn();
+    let stats = ArrayStats::new(&x.view());
+    assert_eq!(stats.shape, vec![5]);
+    assert_eq!((stats.min, stats.max, stats.mean), (-3., 5., 1.));
+    assert_eq!((stats.num_nan, stats.num_inf), (1, 1));
+    assert!(!stats.is_finite());
+
+    let x = ndarray::arr1(&[f64::NAN]).into_dyn();
+    let stats = ArrayStats::new(&x.view());
+    assert!(stats.min.is_nan() && stats.max.is_nan() && stats.mean.is_nan());
+}
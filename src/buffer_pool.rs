@@ -0,0 +1,133 @@
+//! Defining a pool of array buffers recycled across evaluations.
+use crate::Float;
+use crate::FxHashMap;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Mutex;
+
+/// Default capacity of a pool in bytes.
+const DEFAULT_CAPACITY: usize = 256 << 20;
+
+/// Buffers of freed intermediate arrays, grouped by their length.
+///
+/// Each `Graph` owns one of these. The runtime puts the buffers of intermediate arrays here
+/// once they are no longer needed, and ops take them back through
+/// `ComputeContext::alloc_output`, so that consecutive evaluations of the same graph
+/// allocate little memory.
+///
+/// Only the lengths that ops have asked for in the current or the previous evaluation are
+/// kept, no more buffers of a length than the number of those requests, and no more bytes in
+/// total than the capacity.
+pub(crate) struct BufferPool<F: Float> {
+    inner: Mutex<PoolInner<F>>,
+}
+
+struct PoolInner<F> {
+    // len => (buffer, evaluation count when the buffer was put)
+    buffers: FxHashMap<usize, VecDeque<(Vec<F>, usize)>>,
+    // len => number of the requests in the current and the previous evaluations
+    demand: FxHashMap<usize, [usize; 2]>,
+    // Total bytes of `buffers`
+    num_bytes: usize,
+    capacity: usize,
+    // Number of the finished evaluations
+    epoch: usize,
+}
+
+impl<F: Float> BufferPool<F> {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        BufferPool {
+            inner: Mutex::new(PoolInner {
+                buffers: FxHashMap::default(),
+                demand: FxHashMap::default(),
+                num_bytes: 0,
+                capacity: DEFAULT_CAPACITY,
+                epoch: 0,
+            }),
+        }
+    }
+
+    /// Takes a buffer of length `len` out of the pool if available.
+    ///
+    /// The contents of the buffer are the values written by its previous user.
+    #[inline]
+    pub(crate) fn take(&self, len: usize) -> Option<Vec<F>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.demand.entry(len).or_default()[0] += 1;
+        let (buf, _) = inner.buffers.get_mut(&len)?.pop_front()?;
+        inner.num_bytes -= len * mem::size_of::<F>();
+        Some(buf)
+    }
+
+    /// Puts `buf` into the pool, or drops it if no op will take it or the pool is full.
+    #[inline]
+    pub(crate) fn put(&self, buf: Vec<F>) {
+        let len = buf.len();
+        let bytes = len * mem::size_of::<F>();
+        let mut inner = self.inner.lock().unwrap();
+        let wanted = match inner.demand.get(&len) {
+            Some(&[current, previous]) => current.max(previous),
+            None => 0,
+        };
+        if wanted == 0 || inner.num_bytes + bytes > inner.capacity {
+            return;
+        }
+        let epoch = inner.epoch;
+        let bufs = inner.buffers.entry(len).or_default();
+        if bufs.len() < wanted {
+            bufs.push_back((buf, epoch));
+            inner.num_bytes += bytes;
+        }
+    }
+
+    /// Marks the end of an evaluation.
+    ///
+    /// Buffers that were put before this evaluation and weren't taken during it are dropped,
+    /// so that buffers of ops not using the pool don't pile up.
+    pub(crate) fn finish_eval(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        let epoch = inner.epoch;
+        let mut dropped = 0;
+        inner.buffers.retain(|&len, bufs| {
+            let before = bufs.len();
+            bufs.retain(|&(_, put_at)| put_at == epoch);
+            dropped += (before - bufs.len()) * len * mem::size_of::<F>();
+            !bufs.is_empty()
+        });
+        inner.num_bytes -= dropped;
+        inner.demand.retain(|_, counts| {
+            *counts = [0, counts[0]];
+            counts[1] > 0
+        });
+        inner.epoch += 1;
+    }
+
+    /// Returns the total bytes of the buffers in the pool.
+    #[inline]
+    pub(crate) fn num_bytes(&self) -> usize {
+        self.inner.lock().unwrap().num_bytes
+    }
+
+    /// Sets the maximum total bytes of the buffers, dropping buffers to fit in it.
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.capacity = capacity;
+        let mut num_bytes = inner.num_bytes;
+        inner.buffers.retain(|&len, bufs| {
+            while num_bytes > capacity && !bufs.is_empty() {
+                bufs.pop_back();
+                num_bytes -= len * mem::size_of::<F>();
+            }
+            !bufs.is_empty()
+        });
+        inner.num_bytes = num_bytes;
+    }
+
+    /// Drops all the buffers in the pool.
+    pub(crate) fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.buffers.clear();
+        inner.num_bytes = 0;
+    }
+}
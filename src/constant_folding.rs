@@ -0,0 +1,185 @@
+//! Defining the constant folding pass.
+use crate::ndarray_ext::{NdArray, NdArrayView};
+use crate::op::{ComputeContext, Determinism, InputArray, OpInput, OpOutput};
+use crate::tensor::{DType, TensorInternal};
+use crate::{ArrRepr, Float, Graph};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+// Returns the statically known shape of `node`'s output.
+fn static_shape<F: Float>(node: &TensorInternal<F>) -> Option<Vec<usize>> {
+    if let Some(ref arr) = node.constant_array {
+        Some(arr.shape().to_vec())
+    } else if let Some(ref arr) = node.variable_array {
+        Some(arr.read().unwrap().shape().to_vec())
+    } else {
+        let known = node.known_shape.as_ref()?;
+        if known.is_fully_defined() {
+            Some(known.get().iter().map(|&a| a as usize).collect())
+        } else {
+            None
+        }
+    }
+}
+
+// Evaluates `node` if its outputs are determined by constants.
+//
+// Returns `None` if `node` can't be folded, or its op failed or returned multiple arrays.
+// Constants are float arrays, so integer and boolean nodes are not folded.
+pub(crate) fn try_fold<F: Float>(node: &TensorInternal<F>, g: &Graph<F>) -> Option<NdArray<F>> {
+    if node.is_placeholder
+        || node.has_persistent_array
+        || node.dtype != DType::Float
+        || node.build_error.is_some()
+    {
+        return None;
+    }
+    let determinism = node.op.determinism();
+    if determinism == Determinism::Unknown || node.in_edges.iter().any(|x| x.mut_usage) {
+        return None;
+    }
+
+    // Arrays standing in for the inputs whose values are irrelevant.
+    let mut dummies = InputArray::new();
+    for (x, &i) in node.in_edges.iter().zip(&node.input_indices) {
+        let x = x.get_inner(g);
+        if x.constant_array.is_some() && i == 0 {
+            continue;
+        }
+        if determinism == Determinism::InputShapes && i == 0 {
+            if let Some(shape) = static_shape(x) {
+                dummies.push((x.id(), shape));
+                continue;
+            }
+        }
+        return None;
+    }
+    let zero = ndarray::arr0(F::zero()).into_dyn();
+
+    let mut xs = InputArray::new();
+    for x in &node.in_edges {
+        let x = x.get_inner(g);
+        let view: NdArrayView<F> = match x.constant_array {
+            Some(ref arr) => arr.view(),
+            None => {
+                let (_, shape) = dummies.iter().find(|(id, _)| *id == x.id()).unwrap();
+                zero.broadcast(shape.as_slice()).unwrap()
+            }
+        };
+        xs.push(OpInput::new(view));
+    }
+    let mut ctx = ComputeContext::new(node, g, xs);
+    // A failing op is left for the evaluation, which reports the error.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| node.op.compute(&mut ctx)));
+    if result.is_err() {
+        return None;
+    }
+    let mut ys = ctx.extract_outputs();
+    if ys.len() != 1 {
+        return None;
+    }
+    match ys.pop() {
+        Some(Some(Ok(OpOutput::Float(ArrRepr::Owned(arr))))) => Some(arr),
+        Some(Some(Ok(OpOutput::Float(ArrRepr::View(view))))) => Some(view.to_owned()),
+        _ => None,
+    }
+}
+
+impl<F: Float> Graph<F> {
+    /// Evaluates the constant subgraphs of this graph once, and turns their nodes into constants.
+    ///
+    /// A node is folded if its op's outputs are determined by constants
+    /// (see [Op::determinism](op/trait.Op.html#method.determinism)): e.g. `zeros`, `ones`,
+    /// `scalar` and `convert_to_tensor` with constant inputs, or `shape`, `rank` and `size` of
+    /// constants, variables and placeholders with fully defined shapes.
+    /// Following evaluations skip the folded nodes, which is especially effective for the graphs
+    /// built by [grad](#method.grad).
+    ///
+    /// Returns the number of the folded nodes.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor::Variable;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.variable(ag::ndarray_ext::ones(&[2, 3]));
+    ///     let y = g.reduce_sum(x * g.ones(&[2, 3]), &[0, 1], false);
+    ///     let gx = g.grad(&[y], &[x])[0];
+    ///
+    ///     assert!(g.fold_constants() > 0);
+    ///     assert_eq!(gx.eval(&[]), Ok(ag::ndarray_ext::ones(&[2, 3])));
+    /// });
+    /// ```
+    pub fn fold_constants(&self) -> usize {
+        let mut num_folded = 0;
+        // Inputs of a node always have smaller ids than the node.
+        for id in 0..self.num_nodes() {
+            let node = self.access_node(id);
+            if let Some(arr) = try_fold(node, self) {
+                unsafe {
+                    let node = self.access_node_mut(id);
+                    node.constant_array = Some(Arc::new(arr));
+                    node.has_persistent_array = true;
+                }
+                num_folded += 1;
+            }
+        }
+        num_folded
+    }
+}
+
+#[test]
+fn test_fold_constants() {
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let a = g.ones(&[2, 3]);
+        let b = g.shape(a);
+        let c = g.zeros(&b);
+        let p = g.placeholder(&[-1, 3]);
+        let d = g.shape(p);
+        let e = g.size(g.placeholder(&[4, 3]));
+        let f = a + c;
+        assert!(g.fold_constants() > 0);
+
+        assert!(a.clone_persistent_array().is_some());
+        assert!(c.clone_persistent_array().is_some());
+        assert_eq!(
+            e.clone_persistent_array(),
+            Some(ndarray::arr0(12.).into_dyn())
+        );
+        // Not determined by constants
+        assert!(d.clone_persistent_array().is_none());
+        assert!(f.clone_persistent_array().is_none());
+        assert_eq!(f.eval(&[]), Ok(crate::ndarray_ext::ones(&[2, 3])));
+        // Nothing is left to fold.
+        assert_eq!(g.fold_constants(), 0);
+    });
+}
+
+#[test]
+fn test_fold_failing_op() {
+    struct Panicking;
+    impl crate::op::Op<f64> for Panicking {
+        fn compute(&self, _: &mut ComputeContext<f64>) {
+            panic!("always fails");
+        }
+        fn grad(&self, _: &mut crate::op::GradientContext<f64>) {}
+        fn determinism(&self) -> Determinism {
+            Determinism::InputValues
+        }
+    }
+
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let a = g.ones(&[2, 3]);
+        let b = crate::Tensor::builder()
+            .set_ro_inputs(&[&a])
+            .build(g, Panicking);
+        g.fold_constants();
+        assert!(a.clone_persistent_array().is_some());
+        // Left as is, and fails in the evaluation.
+        assert!(b.clone_persistent_array().is_none());
+        assert!(matches!(
+            b.eval(&[]),
+            Err(crate::EvalError::OpPanicked { .. })
+        ));
+    });
+}
@@ -0,0 +1,109 @@
+//! Defining the common subexpression elimination pass.
+use crate::{Float, FxHashMap, FxHashSet, Graph};
+
+// Identity of a node's computation: op name, op parameters, and inputs.
+type NodeKey = (String, String, Vec<(usize, usize)>, Vec<usize>, bool);
+
+impl<F: Float> Graph<F> {
+    /// Merges the nodes which compute the same thing.
+    ///
+    /// Two nodes are merged if they have the same op type, the same op parameters
+    /// (see [Op::cse_key](op/trait.Op.html#method.cse_key)) and the same inputs.
+    /// The consumers of the merged node are rewired to the node created first,
+    /// so evaluations compute them only once. This is especially effective for the graphs built by
+    /// [grad](#method.grad) and [jacobians](#method.jacobians), which create many copies of
+    /// e.g. `shape(x)`.
+    ///
+    /// Returns the number of the merged nodes whose consumers were rewired.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.placeholder(&[-1]);
+    ///     let a = g.exp(x) + g.shape(x);
+    ///     let b = g.exp(x) + g.shape(x);
+    ///     let c = a * b;
+    ///
+    ///     assert!(g.eliminate_common_subexpressions() > 0);
+    ///     let x_arr = ag::ndarray_ext::zeros(&[3]);
+    ///     assert_eq!(c.eval(&[x.given(x_arr.view())]), Ok(ndarray::arr1(&[16.; 3]).into_dyn()));
+    /// });
+    /// ```
+    pub fn eliminate_common_subexpressions(&self) -> usize {
+        // node id => id of the node which replaces it
+        let mut replaced: FxHashMap<usize, usize> = FxHashMap::default();
+        let mut first_nodes: FxHashMap<NodeKey, usize> = FxHashMap::default();
+        let mut rewired: FxHashSet<usize> = FxHashSet::default();
+        let mut redirect = |id: &mut usize, replaced: &FxHashMap<usize, usize>| {
+            if let Some(&first) = replaced.get(id) {
+                rewired.insert(*id);
+                *id = first;
+            }
+        };
+
+        // Inputs of a node always have smaller ids than the node.
+        for id in 0..self.num_nodes() {
+            let node = unsafe { self.access_node_mut(id) };
+            for input in node.in_edges.iter_mut() {
+                redirect(&mut input.id, &replaced);
+            }
+            if let Some(ref mut inputs) = node.backprop_inputs {
+                for input in inputs.iter_mut() {
+                    redirect(&mut input.id, &replaced);
+                }
+            }
+            if let Some(ref mut shape) = node.shape {
+                redirect(shape, &replaced);
+            }
+
+            if node.has_persistent_array
+                || node.is_placeholder
+                || node.in_edges.iter().any(|x| x.mut_usage)
+            {
+                continue;
+            }
+            let params = match node.op.cse_key() {
+                Some(params) => params,
+                None => continue,
+            };
+            let key = (
+                node.op.name().to_string(),
+                params,
+                node.in_edges
+                    .iter()
+                    .zip(&node.input_indices)
+                    .map(|(x, &i)| (x.id, i))
+                    .collect(),
+                node.get_backprop_inputs().iter().map(|x| x.id).collect(),
+                node.is_differentiable,
+            );
+            match first_nodes.get(&key) {
+                Some(&first) => {
+                    replaced.insert(id, first);
+                }
+                None => {
+                    first_nodes.insert(key, id);
+                }
+            }
+        }
+        rewired.len()
+    }
+}
+
+#[test]
+fn test_cse_on_gradients() {
+    use crate::tensor::Variable;
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let rng = crate::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.variable(rng.standard_normal(&[3, 4]));
+        let y = g.sigmoid(x) * g.tanh(x) + x;
+        let z = g.reduce_sum(y * y, &[0, 1], false);
+        let gx = g.grad(&[z], &[x])[0];
+        let expected = gx.eval(&[]).unwrap();
+
+        assert!(g.eliminate_common_subexpressions() > 0);
+        assert_eq!(gx.eval(&[]).unwrap(), expected);
+        assert_eq!(g.eliminate_common_subexpressions(), 0);
+    });
+}
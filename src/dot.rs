@@ -0,0 +1,170 @@
+//! Defining the Graphviz DOT export of graphs.
+use crate::op::short_op_name;
+use crate::tensor::{Tensor, TensorInternal};
+use crate::{Float, FxHashSet, Graph};
+use std::fmt::Write;
+
+// Escapes `s` for a double-quoted DOT string.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Returns DOT attributes of `node` except its label.
+fn node_style<F: Float>(g: &Graph<F>, node: &TensorInternal<F>) -> &'static str {
+    if node.is_placeholder {
+        "shape=box, style=filled, fillcolor=lightblue"
+    } else if node.variable_array.is_some() {
+        "shape=box, style=filled, fillcolor=gold"
+    } else if node.constant_array.is_some() {
+        "shape=box, style=filled, fillcolor=lightgray"
+    } else if g.is_gradient_node(node.id) {
+        "color=red, fontcolor=red"
+    } else {
+        "shape=ellipse"
+    }
+}
+
+impl<F: Float> Graph<F> {
+    /// Renders the subgraph that `targets` depend on in the Graphviz DOT language.
+    ///
+    /// Each node is labeled with its op name, id, name and known shape (if any).
+    /// Placeholders, variables and constants are drawn as filled boxes
+    /// (blue, yellow and gray respectively), nodes created by [grad](#method.grad) in red,
+    /// and `targets` with double borders.
+    /// Edges from the non-first outputs of multi-output ops are labeled with the output index.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.placeholder(&[-1, 3]);
+    ///     let y = g.sigmoid(x) * 2.;
+    ///     let dot = g.to_dot(&[y]);
+    ///     assert!(dot.starts_with("digraph {"));
+    ///     assert!(dot.contains("Sigmoid"));
+    ///     // e.g. `dot -Tsvg graph.dot -o graph.svg` to render it
+    ///     // std::fs::write("graph.dot", dot).unwrap();
+    /// });
+    /// ```
+    pub fn to_dot<'g, A>(&'g self, targets: &[A]) -> String
+    where
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        self.render_dot(targets, false)
+    }
+
+    /// Same as [to_dot](#method.to_dot) but also renders the backward graph, i.e. the
+    /// gradient nodes computed from the subgraph that `targets` depend on.
+    pub fn to_dot_with_backward<'g, A>(&'g self, targets: &[A]) -> String
+    where
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        self.render_dot(targets, true)
+    }
+
+    fn render_dot<'g, A>(&'g self, targets: &[A], with_backward: bool) -> String
+    where
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        let target_ids: FxHashSet<usize> = targets.iter().map(|t| t.as_ref().id()).collect();
+        let mut visited = FxHashSet::default();
+        self.visit_inputs(target_ids.iter().cloned().collect(), &mut visited);
+        if with_backward {
+            // Nodes are created after their inputs, so one pass in the order of ids finds
+            // all the gradient nodes depending on the visited nodes.
+            let mut added = Vec::new();
+            for id in 0..self.num_nodes() {
+                if !visited.contains(&id)
+                    && self.is_gradient_node(id)
+                    && self
+                        .access_node(id)
+                        .in_edges
+                        .iter()
+                        .any(|x| visited.contains(&x.id))
+                {
+                    visited.insert(id);
+                    added.push(id);
+                }
+            }
+            self.visit_inputs(added, &mut visited);
+        }
+
+        let mut ids: Vec<usize> = visited.into_iter().collect();
+        ids.sort();
+        let mut buf = String::from("digraph {\n");
+        for &id in &ids {
+            let node = self.access_node(id);
+            let mut label = format!("{}\\nid: {}", escape(short_op_name(node.op.name())), id);
+            if let Some(ref name) = node.name {
+                write!(label, "\\nname: {}", escape(name)).unwrap();
+            }
+            if let Some(ref shape) = node.known_shape {
+                write!(label, "\\nshape: {:?}", shape.get()).unwrap();
+            }
+            write!(
+                buf,
+                "    {} [label=\"{}\", {}",
+                id,
+                label,
+                node_style(self, node)
+            )
+            .unwrap();
+            if target_ids.contains(&id) {
+                buf += ", peripheries=2";
+            }
+            buf += "];\n";
+        }
+        for &id in &ids {
+            let node = self.access_node(id);
+            for (x, &i) in node.in_edges.iter().zip(&node.input_indices) {
+                write!(buf, "    {} -> {}", x.id, id).unwrap();
+                match (i, x.mut_usage) {
+                    (0, false) => {}
+                    (0, true) => buf += " [style=bold]",
+                    (i, false) => write!(buf, " [label=\"{}\"]", i).unwrap(),
+                    (i, true) => write!(buf, " [label=\"{}\", style=bold]", i).unwrap(),
+                }
+                buf += ";\n";
+            }
+        }
+        buf += "}\n";
+        buf
+    }
+
+    // Adds the nodes reachable from `stack` through the input edges to `visited`.
+    fn visit_inputs(&self, mut stack: Vec<usize>, visited: &mut FxHashSet<usize>) {
+        for &id in &stack {
+            visited.insert(id);
+        }
+        while let Some(id) = stack.pop() {
+            for x in &self.access_node(id).in_edges {
+                if visited.insert(x.id) {
+                    stack.push(x.id);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_to_dot() {
+    use crate::tensor::Variable;
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let x = g.placeholder(&[-1, 3]);
+        let w = g.variable(crate::ndarray_ext::ones(&[3, 2]));
+        let y = g.matmul(x, w);
+        let gw = g.grad(&[y], &[w])[0];
+
+        let dot = g.to_dot(&[y]);
+        assert_eq!(dot.matches(" -> ").count(), 2);
+        assert!(dot.contains(&format!("{} [label=\"MatMul\\nid: {}", y.id(), y.id())));
+        assert!(dot.contains("shape: [-1, 3]"));
+        assert!(dot.contains("fillcolor=gold"));
+        assert!(!dot.contains("color=red"));
+
+        let backward = g.to_dot_with_backward(&[y]);
+        assert!(backward.contains(&format!("    {} [", gw.id())));
+        assert!(backward.contains("color=red"));
+        assert_eq!(backward, g.to_dot_with_backward(&[y]));
+    });
+}
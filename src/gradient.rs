@@ -1,5 +1,5 @@
 //! Defining things related to gradient computation.
-use crate::op::{GradientContext, InputArray};
+use crate::op::{GradientContext, InputArray, JvpContext, VmapContext};
 use crate::tensor::{Tensor, TensorInternal};
 use crate::Float;
 use crate::FxHashMap;
@@ -11,7 +11,8 @@ use std::collections::binary_heap::BinaryHeap;
 struct GradInfo<'a, 'b, T: Float + 'a> {
     has_gradient: bool,
     grad_called: bool,
-    computed_grads: UnsafeCell<InputArray<Tensor<'b, T>>>,
+    // Gradients from the consumers, with the indices of the outputs they consume.
+    computed_grads: UnsafeCell<InputArray<(usize, Tensor<'b, T>)>>,
     accumulated_grad: UnsafeCell<Option<Tensor<'b, T>>>,
     default_grad: Option<&'a TensorInternal<T>>,
 }
@@ -29,12 +30,17 @@ impl<'t, 'g, T: Float> GradInfo<'t, 'g, T> {
     }
 
     #[inline]
-    fn push_grad(&self, g: Tensor<'g, T>) {
+    fn push_grad(&self, output_index: usize, g: Tensor<'g, T>) {
         unsafe {
-            (&mut *self.computed_grads.get()).push(g);
+            (&mut *self.computed_grads.get()).push((output_index, g));
         }
     }
 
+    #[inline]
+    fn has_computed_grads(&self) -> bool {
+        unsafe { !(&*self.computed_grads.get()).is_empty() }
+    }
+
     fn accumulate_then_get(&self, s: &'g Graph<T>) -> Tensor<'g, T> {
         unsafe {
             if let Some(ret) = *self.accumulated_grad.get() {
@@ -44,12 +50,13 @@ impl<'t, 'g, T: Float> GradInfo<'t, 'g, T> {
                 let before_acc = &*self.computed_grads.get();
                 if before_acc.len() > 1 {
                     // accumulate
-                    let accumulated = s.add_n(before_acc.as_slice());
+                    let grads: Vec<_> = before_acc.iter().map(|&(_, g)| g).collect();
+                    let accumulated = s.add_n(grads.as_slice());
                     *self.accumulated_grad.get() = Some(accumulated);
                     accumulated
                 } else {
                     // accumulation is not required
-                    before_acc[0]
+                    before_acc[0].1
                 }
             }
         }
@@ -142,6 +149,25 @@ pub(crate) fn symbolic_gradients<'t, 'g, T: Float>(
     gys: &[&'t TensorInternal<T>],
     g: &'g Graph<T>,
 ) -> Vec<Tensor<'g, T>> {
+    for x in wrt {
+        assert!(!ys.contains(x), "Can't differentiate with objective itself");
+    }
+    symbolic_gradients_opt(ys, wrt, gys, g)
+        .into_iter()
+        .map(|gx| gx.expect("Not differentiable with given tensor(s)."))
+        .collect()
+}
+
+/// Same as `symbolic_gradients` except that the gradients of `xs` not depending on `ys`
+/// are `None`.
+///
+/// `xs` may include some of `ys`, whose gradients include the given ones.
+pub(crate) fn symbolic_gradients_opt<'t, 'g, T: Float>(
+    ys: &[&'t TensorInternal<T>],
+    wrt: &[&'t TensorInternal<T>],
+    gys: &[&'t TensorInternal<T>],
+    g: &'g Graph<T>,
+) -> Vec<Option<Tensor<'g, T>>> {
     assert_eq!(ys.len(), gys.len(), "`ys.len()` must match `gys.len()`");
 
     // Setup gradient path.
@@ -156,6 +182,7 @@ pub(crate) fn symbolic_gradients<'t, 'g, T: Float>(
     // Prepare a heap with given ys.
     let mut heap = ys
         .iter()
+        .filter(|y| !y.in_edges.is_empty())
         .map(|y| y.wrapped())
         .collect::<BinaryHeap<TensorWrapper<T>>>();
 
@@ -164,26 +191,47 @@ pub(crate) fn symbolic_gradients<'t, 'g, T: Float>(
     while let Some(y) = heap.pop() {
         let gxs = {
             let info: &GradInfo<_> = between_nodes.get(&y.tsr.id()).unwrap();
-            let gy = if let Some(def) = info.default_grad {
-                g.scoped(def)
+            let (gy, output_grads) = if let Some(def) = info.default_grad {
+                let gy = g.scoped(def);
+                (gy, smallvec::smallvec![(0, gy)])
             } else {
-                info.accumulate_then_get(g)
+                let gy = info.accumulate_then_get(g);
+                (gy, unsafe { (&*info.computed_grads.get()).clone() })
             };
             // Call Op::grad
             let mut ctx = GradientContext::new(gy, g.scoped(y.tsr), g);
+            ctx.set_output_grads(output_grads);
+            ctx.set_requires_grads(
+                y.tsr
+                    .get_backprop_inputs()
+                    .iter()
+                    .map(|x| between_nodes[&x.id].has_gradient)
+                    .collect(),
+            );
             y.tsr.op.grad(&mut ctx);
             let gxs = ctx.extract_input_grads();
-            debug_assert_eq!(y.tsr.in_edges.len(), gxs.len());
+            // One for each input, or for each of the backprop inputs if any
+            debug_assert!(
+                gxs.len() == y.tsr.in_edges.len() || gxs.len() == y.tsr.get_backprop_inputs().len(),
+                "{}",
+                y.tsr.op.name()
+            );
             gxs
         };
         // Register computed gradients
         let xs = y.tsr.get_backprop_inputs();
-        for (gx, x) in gxs.into_iter().zip(xs) {
+        // Output indices of `xs` that `y` consumes.
+        let output_indices = if y.tsr.backprop_inputs.is_some() {
+            smallvec::smallvec![0; xs.len()]
+        } else {
+            y.tsr.input_indices.clone()
+        };
+        for ((gx, x), output_index) in gxs.into_iter().zip(xs).zip(output_indices) {
             let x = x.get(g);
             let mut x_info = between_nodes.get_mut(&x.id()).unwrap();
             if x_info.has_gradient {
                 if let Some(gx) = gx {
-                    x_info.push_grad(gx);
+                    x_info.push_grad(output_index, gx);
                     // update heap
                     if !x.is_source() && !x_info.grad_called {
                         x_info.grad_called = true;
@@ -197,20 +245,278 @@ pub(crate) fn symbolic_gradients<'t, 'g, T: Float>(
     // Aggregate and return xs's gradients
     let mut ret = Vec::with_capacity(wrt.len());
     for x in wrt {
-        let msg1: &str = "Not differentiable with given tensor(s).";
-        let info = between_nodes.get(&x.id()).expect(msg1);
-        if !info.has_gradient {
-            panic!(msg1);
+        let info = match between_nodes.get(&x.id()) {
+            Some(info) if info.has_gradient => info,
+            _ => {
+                ret.push(None);
+                continue;
+            }
+        };
+        let gx = match info.default_grad {
+            Some(def) if info.has_computed_grads() => {
+                Some(g.scoped(def) + info.accumulate_then_get(g))
+            }
+            Some(def) => Some(g.scoped(def)),
+            None if info.has_computed_grads() => Some(info.accumulate_then_get(g)),
+            None => None,
+        };
+        ret.push(gx);
+    }
+    ret
+}
+
+// Returns the nodes which `ys` depend on in a topological order.
+fn sorted_ancestors<'g, T: Float>(
+    ys: &[Tensor<'g, T>],
+    g: &'g Graph<T>,
+) -> Vec<&'g TensorInternal<T>> {
+    let mut ret = Vec::new();
+    let mut visited = crate::FxHashSet::default();
+    // dfs_stack: (node, should_visit)
+    let mut dfs_stack: Vec<_> = ys.iter().map(|y| (y.scoped_inner(), false)).collect();
+    while let Some((node, should_visit)) = dfs_stack.pop() {
+        if should_visit {
+            ret.push(node);
+        } else if visited.insert(node.id()) {
+            dfs_stack.push((node, true));
+            for x in &node.in_edges {
+                if !visited.contains(&x.id) {
+                    dfs_stack.push((g.access_node(x.id), false));
+                }
+            }
         }
-        assert!(
-            info.default_grad.is_none(),
-            "Can't differentiate with objective itself"
-        );
-        ret.push(info.accumulate_then_get(g));
     }
     ret
 }
 
+/// Returns symbolic tangents of `ys` from the tangents of `xs`.
+///
+/// This is the forward-mode counterpart of `symbolic_gradients`: the tangents are propagated
+/// from `xs` to `ys` in the graph's order with `Op::jvp` of the nodes between them.
+/// The tangents of `ys` not depending on `xs` are zeros.
+pub(crate) fn symbolic_jvp<'g, T: Float>(
+    ys: &[Tensor<'g, T>],
+    xs: &[Tensor<'g, T>],
+    tangents: &[Tensor<'g, T>],
+    g: &'g Graph<T>,
+) -> Vec<Tensor<'g, T>> {
+    assert_eq!(
+        xs.len(),
+        tangents.len(),
+        "`xs.len()` must match `tangents.len()`"
+    );
+
+    // Tangents of the outputs of the nodes, `None` for zeros.
+    let mut node_tangents = FxHashMap::<usize, InputArray<Option<Tensor<'g, T>>>>::default();
+    for node in sorted_ancestors(ys, g) {
+        if let Some(i) = xs.iter().position(|x| x.id() == node.id()) {
+            node_tangents.insert(node.id(), smallvec::smallvec![Some(tangents[i])]);
+            continue;
+        }
+        if !node.is_differentiable {
+            continue;
+        }
+        let input_tangents: InputArray<_> = node
+            .in_edges
+            .iter()
+            .zip(&node.input_indices)
+            .map(|(x, &k)| {
+                let tys = node_tangents.get(&x.id)?;
+                *tys.get(k).unwrap_or_else(|| {
+                    panic!(
+                        "{}::jvp didn't propagate the tangent of the output {}",
+                        g.access_node(x.id).op.name(),
+                        k
+                    )
+                })
+            })
+            .collect();
+        if input_tangents.iter().all(|t| t.is_none()) {
+            continue;
+        }
+        let mut ctx = JvpContext::new(g.scoped(node), input_tangents, g);
+        node.op.jvp(&mut ctx);
+        node_tangents.insert(node.id(), ctx.extract_output_tangents());
+    }
+
+    ys.iter()
+        .map(|y| {
+            node_tangents
+                .get(&y.id())
+                .and_then(|tys| tys[0])
+                .unwrap_or_else(|| g.zeros(&g.shape(y)))
+        })
+        .collect()
+}
+
+/// How Jacobians are computed by `Graph::jacobians_with_mode`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JacobianMode {
+    /// Reverse-mode: the rows are computed by backpropagating the one-hot cotangents of `y`.
+    Reverse,
+    /// Forward-mode: the columns are computed by propagating the one-hot tangents of `xs`.
+    ///
+    /// The shapes of `xs` must be known statically.
+    Forward,
+    /// Forward-mode if `xs` have fewer elements in total than `y`, otherwise reverse-mode.
+    Auto,
+}
+
+// Returns the flattened size of `x` if its shape is known statically.
+pub(crate) fn static_size<T: Float>(x: &Tensor<T>) -> Option<usize> {
+    x.static_shape()
+        .filter(|s| s.iter().all(|&d| d >= 0))
+        .map(|s| s.iter().product::<isize>() as usize)
+}
+
+/// Returns `ys` computed from `batched_xs` in place of `xs`, with whether each of them is batched.
+///
+/// `batched_xs[i]` stacks `batch_size` values of `xs[i]` along a new leading axis. The batched
+/// nodes between them are built with `Op::vmap` in the graph's order, so the whole batch goes
+/// through a single graph. `ys` not depending on `xs` are returned as they are.
+pub(crate) fn symbolic_vmap<'g, T: Float>(
+    ys: &[Tensor<'g, T>],
+    xs: &[Tensor<'g, T>],
+    batched_xs: &[Tensor<'g, T>],
+    batch_size: usize,
+    g: &'g Graph<T>,
+) -> Vec<(Tensor<'g, T>, bool)> {
+    assert_eq!(
+        xs.len(),
+        batched_xs.len(),
+        "`xs.len()` must match `batched_xs.len()`"
+    );
+
+    // Batched nodes replacing the nodes depending on `xs`
+    let mut batched = FxHashMap::<usize, Tensor<'g, T>>::default();
+    for node in sorted_ancestors(ys, g) {
+        if let Some(i) = xs.iter().position(|x| x.id() == node.id()) {
+            batched.insert(node.id(), batched_xs[i]);
+            continue;
+        }
+        if !node.in_edges.iter().any(|x| batched.contains_key(&x.id)) {
+            continue;
+        }
+        let inputs = node
+            .in_edges
+            .iter()
+            .map(|x| match batched.get(&x.id) {
+                Some(&bx) => (bx, true),
+                None => (x.get(g), false),
+            })
+            .collect();
+        let mut ctx = VmapContext::new(g.scoped(node), inputs, batch_size, g);
+        // `Op::vmap` builds ops on the first outputs of the inputs.
+        if node.input_indices.iter().all(|&k| k == 0) {
+            node.op.vmap(&mut ctx);
+        } else {
+            crate::op::vmap_by_compute(&mut ctx);
+        }
+        if let Some(y) = ctx.extract_output() {
+            batched.insert(node.id(), y);
+        }
+    }
+
+    ys.iter()
+        .map(|y| match batched.get(&y.id()) {
+            Some(&by) => (by, true),
+            None => (*y, false),
+        })
+        .collect()
+}
+
+// Returns the shape of `x` with the leading batch axis of `batch_size`, which is a constant
+// unless the static shape of `x` has multiple unknown dims.
+fn batched_shape<'g, T: Float>(
+    x: &Tensor<'g, T>,
+    batch_size: usize,
+    g: &'g Graph<T>,
+) -> Tensor<'g, T> {
+    let batch = T::from(batch_size).unwrap();
+    match x.static_shape() {
+        Some(shape) if shape.iter().filter(|&&d| d < 0).count() <= 1 => {
+            let dims = shape.iter().map(|&d| T::from(d.max(-1)).unwrap());
+            let shape: Vec<_> = std::iter::once(batch).chain(dims).collect();
+            g.convert_to_tensor(ndarray::Array1::from_vec(shape))
+        }
+        _ => g.concat(
+            &[g.convert_to_tensor(ndarray::arr1(&[batch])), g.shape(x)],
+            0,
+        ),
+    }
+}
+
+/// Returns the Jacobians of `y` of size `y_size` with respect to `xs`, each of which is
+/// a matrix of shape `(y_size, size of x)`.
+///
+/// The backward (or forward) graph is built once for a (co)tangent, which is then replaced with
+/// the batch of all the one-hot (co)tangents by `symbolic_vmap`, so the graph doesn't grow with
+/// the sizes and the batch is computed at once.
+/// Jacobians of `xs` that `y` doesn't depend on are zeros.
+pub(crate) fn batched_jacobians<'g, T: Float>(
+    y: &Tensor<'g, T>,
+    xs: &[Tensor<'g, T>],
+    y_size: usize,
+    mode: JacobianMode,
+    g: &'g Graph<T>,
+) -> Vec<Tensor<'g, T>> {
+    let x_sizes: Option<Vec<usize>> = xs.iter().map(static_size).collect();
+    let forward = match mode {
+        JacobianMode::Reverse => false,
+        JacobianMode::Forward => true,
+        JacobianMode::Auto => x_sizes
+            .as_ref()
+            .map_or(false, |sizes| sizes.iter().sum::<usize>() < y_size),
+    };
+    // One-hot (co)tangents of `x` stacked along the leading axis.
+    let one_hots = |x: &Tensor<'g, T>, n: usize| {
+        let eye = g.convert_to_tensor(ndarray::Array2::<T>::eye(n));
+        g.reshape(eye, &batched_shape(x, n, g))
+    };
+    // Flattens the batch of `n` into the rows of a matrix.
+    let rows = |(t, batched): (Tensor<'g, T>, bool), n: usize| {
+        if batched {
+            g.reshape(t, &[n as isize, -1])
+        } else {
+            // Same for all the (co)tangents, which happens only if it's zeros.
+            g.tile(g.expand_dims(g.flatten(t), &[0]), 0, n)
+        }
+    };
+
+    if forward {
+        let x_sizes = x_sizes.expect("Forward-mode Jacobians need the static shapes of `xs`");
+        // The columns of the Jacobian of each x are stacked as the rows.
+        xs.iter()
+            .zip(x_sizes)
+            .map(|(x, x_size)| {
+                // Any tensor of the shape of `x` works as the tangent to be replaced.
+                let tx = g.zeros(&g.shape(x));
+                let ty = symbolic_jvp(&[*y], &[*x], &[tx], g)[0];
+                let ty = symbolic_vmap(&[ty], &[tx], &[one_hots(x, x_size)], x_size, g)[0];
+                g.transpose(rows(ty, x_size), &[1, 0])
+            })
+            .collect()
+    } else {
+        // Any tensor of the shape of `y` works as the cotangent to be replaced.
+        let gy = g.zeros(&g.shape(y));
+        let wrt: Vec<_> = xs.iter().map(|x| x.scoped_inner()).collect();
+        let gxs = symbolic_gradients_opt(&[y.scoped_inner()], &wrt, &[gy.scoped_inner()], g);
+        let targets: Vec<_> = gxs.iter().flatten().copied().collect();
+        let mut batched_gxs =
+            symbolic_vmap(&targets, &[gy], &[one_hots(y, y_size)], y_size, g).into_iter();
+        gxs.iter()
+            .zip(xs)
+            .map(|(gx, x)| match gx {
+                Some(_) => rows(batched_gxs.next().unwrap(), y_size),
+                None => g.reshape(
+                    g.zeros(&batched_shape(x, y_size, g)),
+                    &[y_size as isize, -1],
+                ),
+            })
+            .collect()
+    }
+}
+
 struct TensorWrapper<'t, T: Float + 't> {
     tsr: &'t TensorInternal<T>,
 }
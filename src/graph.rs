@@ -1,8 +1,11 @@
 //! Defining things related to `ag::Graph`.
 
-use crate::{tensor::TensorInternal, Float};
-use std::cell::UnsafeCell;
+use crate::buffer_pool::BufferPool;
+use crate::tensor::{Tensor, TensorInternal};
+use crate::{Float, FxHashMap};
+use std::cell::{Cell, RefCell, UnsafeCell};
 use std::fmt;
+use std::ops::Range;
 
 /// Generator of `Tensor` objects.
 ///
@@ -37,7 +40,19 @@ use std::fmt;
 /// // tensors in graph1 destructed here.
 /// ```
 pub struct Graph<F: Float> {
-    node_set: UnsafeCell<Vec<TensorInternal<F>>>,
+    // Nodes are boxed so that their addresses held by `Tensor`s stay valid while the set grows.
+    #[allow(clippy::vec_box)]
+    node_set: UnsafeCell<Vec<Box<TensorInternal<F>>>>,
+    // Recycled buffers for the arrays computed in this graph.
+    pub(crate) pool: BufferPool<F>,
+    // Id ranges of the nodes created by `grad`.
+    gradient_nodes: RefCell<Vec<Range<usize>>>,
+    // Ids of the named nodes.
+    names: RefCell<FxHashMap<String, usize>>,
+    // Stack of the current name scopes.
+    name_scopes: RefCell<Vec<String>>,
+    // Default of the anomaly detection mode in evaluations.
+    pub(crate) detect_anomaly: Cell<bool>,
 }
 
 impl<'a, 'b, F: Float> Graph<F> {
@@ -46,7 +61,7 @@ impl<'a, 'b, F: Float> Graph<F> {
             let inner = &mut *self.node_set.get();
             let id = inner.len();
             node.id = id;
-            inner.push(node);
+            inner.push(Box::new(node));
             inner.get_unchecked(id)
         }
     }
@@ -60,6 +75,187 @@ impl<'a, 'b, F: Float> Graph<F> {
         }
     }
 
+    // `i` must be an id generated internally, and the returned node must not be accessed
+    // by others while it's borrowed.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn access_node_mut(&self, i: usize) -> &'a mut TensorInternal<F> {
+        let inner = &mut *self.node_set.get();
+        inner.get_unchecked_mut(i)
+    }
+
+    // Number of the nodes in this graph.
+    #[inline]
+    pub(crate) fn num_nodes(&self) -> usize {
+        unsafe { (&*self.node_set.get()).len() }
+    }
+
+    // Marks the nodes created since `start` as gradient nodes.
+    pub(crate) fn mark_gradient_nodes(&self, start: usize) {
+        let end = self.num_nodes();
+        if start < end {
+            self.gradient_nodes.borrow_mut().push(start..end);
+        }
+    }
+
+    // True if the node `i` was created by `grad`.
+    pub(crate) fn is_gradient_node(&self, i: usize) -> bool {
+        self.gradient_nodes
+            .borrow()
+            .iter()
+            .any(|range| range.contains(&i))
+    }
+
+    // Prefixes `name` with the current name scopes.
+    pub(crate) fn scoped_name(&self, name: &str) -> String {
+        let mut ret = String::new();
+        for scope in self.name_scopes.borrow().iter() {
+            ret += scope;
+            ret.push('/');
+        }
+        ret + name
+    }
+
+    // Names the node `id`, replacing its old name.
+    //
+    // Returns false if another node has the name.
+    pub(crate) fn register_name(&self, id: usize, name: String) -> bool {
+        let mut names = self.names.borrow_mut();
+        if let Some(&other) = names.get(&name) {
+            return other == id;
+        }
+        let node = unsafe { self.access_node_mut(id) };
+        if let Some(old) = node.name.take() {
+            names.remove(&old);
+        }
+        names.insert(name.clone(), id);
+        node.name = Some(name);
+        true
+    }
+
+    // Id of the node named `name`.
+    pub(crate) fn id_by_name(&self, name: &str) -> Option<usize> {
+        self.names.borrow().get(name).cloned()
+    }
+
+    /// Returns the tensor named `name` with [Tensor::with_name](tensor/struct.Tensor.html#method.with_name).
+    ///
+    /// `name` is the full name including the name scopes.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.placeholder_named("x", &[-1, 2]);
+    ///     assert_eq!(g.tensor_by_name("x").map(|t| t.id()), Some(x.id()));
+    ///     assert!(g.tensor_by_name("y").is_none());
+    /// });
+    /// ```
+    pub fn tensor_by_name(&'a self, name: &str) -> Option<Tensor<'a, F>> {
+        let id = self.id_by_name(name)?;
+        Some(self.scoped(self.access_node(id)))
+    }
+
+    /// Calls `f` in the name scope `scope`.
+    ///
+    /// Names given to tensors in `f` are prefixed with `scope` and a slash.
+    /// Scopes can be nested.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor::Variable;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let w = g.name_scope("encoder", || {
+    ///         g.name_scope("layer1", || g.variable(ag::ndarray_ext::ones(&[2, 2])).with_name("w"))
+    ///     });
+    ///     assert_eq!(w.name(), Some("encoder/layer1/w"));
+    ///     assert!(g.tensor_by_name("encoder/layer1/w").is_some());
+    /// });
+    /// ```
+    pub fn name_scope<R, FN: FnOnce() -> R>(&self, scope: &str, f: FN) -> R {
+        self.name_scopes.borrow_mut().push(scope.to_string());
+        let _guard = NameScopeGuard(&self.name_scopes);
+        f()
+    }
+
+    /// Releases the array buffers that this graph keeps for reuse.
+    ///
+    /// Buffers of intermediate arrays are recycled across evaluations in the same graph
+    /// to reduce allocations. Call this to give the memory back, e.g. after evaluating
+    /// a large batch that won't be repeated.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let a = g.exp(g.ones(&[1000]));
+    ///     // `a` is freed into the pool after `softmax` takes its output buffer from there.
+    ///     let b = g.softmax(a, 0);
+    ///     b.eval(&[]);
+    ///     assert!(g.buffer_pool_bytes() > 0);
+    ///     g.clear_buffer_pool();
+    ///     assert_eq!(g.buffer_pool_bytes(), 0);
+    /// });
+    /// ```
+    pub fn clear_buffer_pool(&self) {
+        self.pool.clear();
+    }
+
+    /// Sets the maximum total bytes of the array buffers that this graph keeps for reuse
+    /// (256 MiB by default).
+    ///
+    /// Freed buffers that don't fit in the capacity are dropped, and `0` disables the reuse.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let a = g.exp(g.ones(&[1000]));
+    ///     let b = g.sigmoid(g.tanh(a));
+    ///     g.set_buffer_pool_capacity(1000 * 4);
+    ///     b.eval(&[]);
+    ///     assert!(g.buffer_pool_bytes() <= 1000 * 4);
+    /// });
+    /// ```
+    pub fn set_buffer_pool_capacity(&self, bytes: usize) {
+        self.pool.set_capacity(bytes);
+    }
+
+    /// Enables or disables the anomaly detection mode in the evaluations of this graph
+    /// (disabled by default).
+    ///
+    /// This applies to `Graph::eval`, `Tensor::eval`, and the `Eval`s and `ExecutionPlan`s created
+    /// after this call. See [Eval::detect_anomaly](struct.Eval.html#method.detect_anomaly)
+    /// for the details.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f64>| {
+    ///     g.detect_anomaly(true);
+    ///     let x = g.placeholder(&[]);
+    ///     let y = g.sqrt(x);
+    ///     let gx = g.grad(&[y], &[x])[0];
+    ///
+    ///     // The gradient of sqrt is infinite at 0.
+    ///     let x_val = ndarray::arr0(0.);
+    ///     let ret = g.eval(&[y, gx], &[x.given(x_val.view())]);
+    ///     assert!(ret[0].is_ok());
+    ///     match &ret[1] {
+    ///         Err(ag::EvalError::NonFinite(anomaly)) => assert!(anomaly.is_gradient),
+    ///         _ => unreachable!(),
+    ///     }
+    /// });
+    /// ```
+    pub fn detect_anomaly(&self, detect_anomaly: bool) {
+        self.detect_anomaly.set(detect_anomaly);
+    }
+
+    /// Returns the total bytes of the array buffers that this graph keeps for reuse.
+    pub fn buffer_pool_bytes(&self) -> usize {
+        self.pool.num_bytes()
+    }
+
     // Removes all tensors (nodes) in this graph.
     //
     // Be careful not to remove tensors that will be needed later.
@@ -68,6 +264,17 @@ impl<'a, 'b, F: Float> Graph<F> {
         unsafe {
             (&mut *self.node_set.get()).clear();
         }
+        self.gradient_nodes.borrow_mut().clear();
+        self.names.borrow_mut().clear();
+    }
+}
+
+// Pops the innermost name scope when dropped, even if the closure of the scope panics.
+struct NameScopeGuard<'a>(&'a RefCell<Vec<String>>);
+
+impl Drop for NameScopeGuard<'_> {
+    fn drop(&mut self) {
+        self.0.borrow_mut().pop();
     }
 }
 
@@ -94,6 +301,11 @@ where
 {
     let mut g = Graph {
         node_set: UnsafeCell::new(Vec::with_capacity(128)),
+        pool: BufferPool::new(),
+        gradient_nodes: RefCell::new(Vec::new()),
+        names: RefCell::new(FxHashMap::default()),
+        name_scopes: RefCell::new(Vec::new()),
+        detect_anomaly: Cell::new(false),
     };
     f(&mut g);
 }
@@ -3,7 +3,7 @@ use super::*;
 use crate::ndarray_ext::NdArrayView;
 use std::marker::PhantomData;
 
-pub(crate) trait Hook<T: Float> {
+pub(crate) trait Hook<T: Float>: Send + Sync {
     /// Calls this hook with the value of the tensor where this hook is set.
     fn call(&self, arr: &crate::ndarray::ArrayViewD<T>) -> ();
 }
@@ -12,11 +12,11 @@ pub(crate) struct Print(pub &'static str);
 
 pub(crate) struct Show;
 
-pub(crate) struct ShowWith(pub &'static str);
+pub(crate) struct ShowWith(pub String);
 
 pub(crate) struct ShowShape;
 
-pub(crate) struct ShowShapeWith(pub &'static str);
+pub(crate) struct ShowShapeWith(pub String);
 
 // Calls the given function.
 pub(crate) struct Raw<T: Float, FUN: Fn(&NdArrayView<T>) -> () + Send + Sync> {
@@ -123,6 +123,9 @@
 //! ```
 //!
 
+// `map_or` stands for `Option::is_some_and` and `is_none_or`, which need a newer Rust.
+#![allow(clippy::unnecessary_map_or)]
+
 #[allow(unused_imports)]
 // Expose to prevent version conflict
 #[macro_use(s)]
@@ -143,14 +146,23 @@ extern crate rayon;
 extern crate rustc_hash;
 pub(crate) extern crate smallvec;
 
+pub mod anomaly;
+mod buffer_pool;
+mod constant_folding;
+mod cse;
+mod dot;
+mod fusion;
 mod gradient;
 pub(crate) mod graph;
 mod hook;
 pub mod ndarray_ext;
+pub mod onnx;
 pub mod op;
 mod ops;
 pub mod optimizers;
+pub mod profiler;
 mod runtime;
+pub mod serialization;
 pub mod tensor;
 pub mod test_helper;
 
@@ -226,14 +238,18 @@ pub use crate::ndarray_ext::array_gen;
 
 pub use crate::ndarray_ext::{NdArray, NdArrayView, NdArrayViewMut};
 
-pub use crate::runtime::{Eval, Feed};
+pub use crate::ndarray_ext::{BoolArray, IntArray};
+
+pub use crate::runtime::{Eval, ExecutionPlan, Feed, FeedValue};
 
-pub use crate::tensor::Tensor;
+pub use crate::tensor::{DType, Tensor};
 
 pub(crate) use crate::ndarray_ext::ArrRepr;
 
 pub use crate::graph::{with, Graph};
 
+pub use crate::gradient::JacobianMode;
+
 #[inline]
 pub(crate) unsafe fn uninitialized_vec<T>(size: usize) -> Vec<T> {
     let mut buf = Vec::with_capacity(size);
@@ -250,7 +266,10 @@ pub(crate) fn cast_as<A: 'static + Copy, B: 'static + Copy>(a: &A) -> B {
 }
 
 /// Error during tensor's evaluation.
-#[derive(Debug, PartialEq)]
+///
+/// Errors in the feeds fail all the tensors evaluated together, and errors in an op fail
+/// the tensors depending on it.
+#[derive(Clone, Debug, PartialEq)]
 pub enum EvalError {
     /// Error during `Op`'s computation.
     OpError(op::OpError),
@@ -259,15 +278,116 @@ pub enum EvalError {
     /// For example, compute results of inplace ops (e.g. optimizers) are not available
     /// and are represented as `Empty`.
     Empty,
+    /// A placeholder needed for the evaluation is not fed.
+    MissingFeed { id: usize, name: Option<String> },
+    /// The shape of a fed value doesn't match the known shape of the placeholder.
+    FeedShapeMismatch {
+        id: usize,
+        name: Option<String>,
+        expected: Vec<isize>,
+        actual: Vec<usize>,
+    },
+    /// The dtype of a fed value doesn't match that of the placeholder.
+    FeedTypeMismatch {
+        id: usize,
+        name: Option<String>,
+        expected: tensor::DType,
+        actual: tensor::DType,
+    },
+    /// A feed made by `Feed::named` names no placeholder.
+    UnknownFeed(String),
+    /// The number of the feeds given to `ExecutionPlan::run` doesn't match the number of its
+    /// placeholders.
+    FeedCountMismatch { expected: usize, actual: usize },
+    /// `Op::compute` panicked.
+    OpPanicked {
+        op_name: String,
+        id: usize,
+        message: String,
+    },
+    /// An op output NaN or infinity in the anomaly detection mode.
+    ///
+    /// See [anomaly](anomaly/index.html).
+    NonFinite(Box<anomaly::Anomaly>),
+    /// A tensor created in the closure of a control flow op (e.g. `Graph::cond`) is evaluated
+    /// outside of it.
+    ///
+    /// `id` is the placeholder replacing an outer tensor in the subgraph, on which the evaluated
+    /// tensor depends.
+    SubgraphTensor { id: usize },
+    /// The evaluation of the subgraph of a control flow op failed.
+    Subgraph {
+        op_name: String,
+        id: usize,
+        error: Box<EvalError>,
+    },
 }
 
 impl std::error::Error for EvalError {}
 
+// Placeholder in error messages
+fn placeholder_label(id: usize, name: &Option<String>) -> String {
+    match name {
+        Some(name) => name.clone(),
+        None => format!("#{}", id),
+    }
+}
+
 impl fmt::Display for EvalError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             EvalError::OpError(e) => e.fmt(f),
             EvalError::Empty => write!(f, "Empty return value from a stateful op"),
+            EvalError::MissingFeed { id, name } => {
+                write!(f, "Placeholder {} is not fed", placeholder_label(*id, name))
+            }
+            EvalError::FeedShapeMismatch {
+                id,
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Shape error: placeholder {} required {:?}, but got {:?}",
+                placeholder_label(*id, name),
+                expected,
+                actual
+            ),
+            EvalError::FeedTypeMismatch {
+                id,
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Type error: placeholder {} required {} values, but got {}",
+                placeholder_label(*id, name),
+                expected,
+                actual
+            ),
+            EvalError::UnknownFeed(name) => write!(f, "No placeholder is named {}", name),
+            EvalError::FeedCountMismatch { expected, actual } => {
+                write!(f, "{} feeds are required, but got {}", expected, actual)
+            }
+            EvalError::OpPanicked {
+                op_name,
+                id,
+                message,
+            } => write!(f, "{} (id {}) panicked: {}", op_name, id, message),
+            EvalError::NonFinite(anomaly) => anomaly.fmt(f),
+            EvalError::SubgraphTensor { id } => write!(
+                f,
+                "A tensor created in a control flow closure is evaluated outside of it \
+                 (it depends on the subgraph parameter #{})",
+                id
+            ),
+            EvalError::Subgraph { op_name, id, error } => {
+                write!(
+                    f,
+                    "{} (id {}) failed in its subgraph: {}",
+                    op_name, id, error
+                )
+            }
         }
     }
 }
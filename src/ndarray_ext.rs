@@ -14,6 +14,18 @@ pub type NdArrayView<'a, T> = ndarray::ArrayView<'a, T, ndarray::IxDyn>;
 /// alias for `ndarray::ArrayViewMut<T, IxDyn>`
 pub type NdArrayViewMut<'a, T> = ndarray::ArrayViewMut<'a, T, ndarray::IxDyn>;
 
+/// alias for `ndarray::Array<i64, IxDyn>`, the array of an integer tensor
+pub type IntArray = ndarray::Array<i64, ndarray::IxDyn>;
+
+/// alias for `ndarray::ArrayView<i64, IxDyn>`
+pub type IntArrayView<'a> = ndarray::ArrayView<'a, i64, ndarray::IxDyn>;
+
+/// alias for `ndarray::Array<bool, IxDyn>`, the array of a boolean tensor
+pub type BoolArray = ndarray::Array<bool, ndarray::IxDyn>;
+
+/// alias for `ndarray::ArrayView<bool, IxDyn>`
+pub type BoolArrayView<'a> = ndarray::ArrayView<'a, bool, ndarray::IxDyn>;
+
 // expose array_gen
 pub use crate::array_gen::*;
 
@@ -64,13 +76,6 @@ pub(crate) fn as_shape<T: Float>(x: &NdArrayView<T>) -> Vec<usize> {
     target
 }
 
-#[inline]
-pub(crate) fn expand_dims_view<T: Float>(x: NdArrayView<T>, axis: usize) -> NdArrayView<T> {
-    let mut shape = x.shape().to_vec();
-    shape.insert(axis, 1);
-    x.into_shape(shape).unwrap()
-}
-
 #[inline]
 pub(crate) fn expand_dims<T: Float>(x: NdArray<T>, axis: usize) -> NdArray<T> {
     let mut shape = x.shape().to_vec();
@@ -230,15 +235,17 @@ pub(crate) fn get_batch_ptrs<A: Float, B>(
 pub mod array_gen {
     use super::*;
     use rand::distributions::Distribution;
-    use rand::rngs::ThreadRng;
-    use rand::{self, Rng};
+    use rand::rngs::StdRng;
+    use rand::{self, Rng, SeedableRng};
     use rand_distr;
     use std::marker::PhantomData;
     use std::sync::Mutex;
 
     /// Helper structure to create ndarrays whose elements are pseudorandom numbers.
     ///
-    /// This is actually a wrapper of an arbitrary `rand::Rng`, the default is `rand::rngs::ThreadRng`.
+    /// This is actually a wrapper of an arbitrary `rand::Rng`, the default is `rand::rngs::StdRng`.
+    /// The random ops of a graph need a `Send` one, since parallel evaluations run them on
+    /// worker threads.
     ///
     /// ```
     /// use autograd as ag;
@@ -249,21 +256,21 @@ pub mod array_gen {
     /// let my_rng = ag::ndarray_ext::ArrayRng::new(rand::thread_rng());
     /// let random: NdArray = my_rng.standard_normal(&[2, 3]);
     ///
-    /// // The default is `ThreadRng` (seed number is not fixed).
+    /// // The default is `StdRng` seeded by `thread_rng` (seed number is not fixed).
     /// let default = ag::ndarray_ext::ArrayRng::default();
     /// let random: NdArray = default.standard_normal(&[2, 3]);
     /// ```
-    pub struct ArrayRng<T: Float, R: Rng = ThreadRng> {
+    pub struct ArrayRng<T: Float, R: Rng = StdRng> {
         phantom: PhantomData<T>,
         rng: Mutex<R>,
     }
 
     impl<T: Float> Default for ArrayRng<T> {
-        /// Initialize with `rand::rngs::ThreadRng`.
+        /// Initialize with `rand::rngs::StdRng` seeded by `rand::thread_rng`.
         fn default() -> Self {
             ArrayRng {
                 phantom: PhantomData,
-                rng: Mutex::new(rand::thread_rng()),
+                rng: Mutex::new(StdRng::from_rng(rand::thread_rng()).unwrap()),
             }
         }
     }
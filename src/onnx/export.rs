@@ -0,0 +1,625 @@
+//! Defining the ONNX export of graphs.
+use super::proto::Writer;
+use super::{data_type, tensor_data_type, tensor_name, FLOAT, INT64, IR_VERSION, OPSET_VERSION};
+use crate::serialization::{op_key, Attribute, SerializationError};
+use crate::tensor::{DType, Tensor, TensorInternal};
+use crate::{Float, FxHashSet, Graph, NdArray};
+use std::path::Path;
+
+// Attribute of an ONNX node
+enum NodeAttribute {
+    Int(i64),
+    Float(f32),
+    Ints(Vec<i64>),
+    Tensor(Vec<u8>),
+}
+
+// Builder of the ONNX graph of the nodes in a `Graph`.
+struct Exporter<'a, F: Float> {
+    graph: &'a Graph<F>,
+    // Encoded `NodeProto`s
+    nodes: Vec<Vec<u8>>,
+    // Encoded `TensorProto`s
+    initializers: Vec<Vec<u8>>,
+    num_temporaries: usize,
+}
+
+// ONNX op types of the unary ops which map one-to-one.
+fn unary_op_type(name: &str) -> Option<&'static str> {
+    let ret = match name {
+        "Identity" | "StopGradient" => "Identity",
+        "ReLU" => "Relu",
+        "Sigmoid" => "Sigmoid",
+        "Softplus" => "Softplus",
+        "Tanh" => "Tanh",
+        "Exp" => "Exp",
+        "Ln" => "Log",
+        "Sqrt" => "Sqrt",
+        "NegOp" => "Neg",
+        "Abs" => "Abs",
+        "Floor" => "Floor",
+        "Ceil" => "Ceil",
+        "Sign" => "Sign",
+        "Inv" => "Reciprocal",
+        "Sin" => "Sin",
+        "Cos" => "Cos",
+        "Tan" => "Tan",
+        "Asin" => "Asin",
+        "Acos" => "Acos",
+        "Atan" => "Atan",
+        "Sinh" => "Sinh",
+        "Cosh" => "Cosh",
+        "Asinh" => "Asinh",
+        "Acosh" => "Acosh",
+        "Atanh" => "Atanh",
+        _ => return None,
+    };
+    Some(ret)
+}
+
+// ONNX op types of the binary ops which map one-to-one.
+fn binary_op_type(name: &str) -> Option<&'static str> {
+    let ret = match name {
+        "AddOp" => "Add",
+        "SubOp" => "Sub",
+        "MulOp" => "Mul",
+        "DivOp" => "Div",
+        "Maximum" => "Max",
+        "Minimum" => "Min",
+        _ => return None,
+    };
+    Some(ret)
+}
+
+// ONNX op types of the comparison ops, whose boolean outputs are cast to the output dtypes.
+fn comparison_op_type(name: &str) -> Option<&'static str> {
+    let ret = match name {
+        "Equal" => "Equal",
+        "Greater" => "Greater",
+        "Lesser" => "Less",
+        "GreaterEqual" => "GreaterOrEqual",
+        "LesserEqual" => "LessOrEqual",
+        _ => return None,
+    };
+    Some(ret)
+}
+
+fn reduction_op_type(name: &str) -> Option<&'static str> {
+    let ret = match name {
+        "ReduceSum" => "ReduceSum",
+        "ReduceMean" => "ReduceMean",
+        "ReduceProd" => "ReduceProd",
+        "ReduceMin" => "ReduceMin",
+        "ReduceMax" => "ReduceMax",
+        _ => return None,
+    };
+    Some(ret)
+}
+
+// Encodes `arr` as the raw data of a `TensorProto`.
+fn raw_data<F: Float>(arr: &NdArray<F>) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(arr.len() * std::mem::size_of::<F>());
+    for &a in arr.iter() {
+        if data_type::<F>() == FLOAT {
+            ret.extend_from_slice(&a.to_f32().unwrap().to_le_bytes());
+        } else {
+            ret.extend_from_slice(&a.to_f64().unwrap().to_le_bytes());
+        }
+    }
+    ret
+}
+
+// Encodes a `TensorProto`.
+fn tensor_proto(name: &str, dims: &[usize], data_type: i64, raw_data: &[u8]) -> Vec<u8> {
+    let mut w = Writer::new();
+    for &d in dims {
+        w.int(1, d as i64);
+    }
+    w.int(2, data_type);
+    if !name.is_empty() {
+        w.string(8, name);
+    }
+    w.bytes(9, raw_data);
+    w.into_bytes()
+}
+
+// Encodes a `ValueInfoProto` of a tensor with elements of `data_type`.
+fn value_info(name: &str, data_type: i64, shape: Option<&[isize]>) -> Vec<u8> {
+    let mut w = Writer::new();
+    w.string(1, name);
+    w.message(2, |w| {
+        w.message(1, |w| {
+            w.int(1, data_type);
+            if let Some(shape) = shape {
+                w.message(2, |w| {
+                    for &d in shape {
+                        // Unknown dims are left empty.
+                        w.message(1, |w| {
+                            if d >= 0 {
+                                w.int(1, d as i64);
+                            }
+                        });
+                    }
+                });
+            }
+        });
+    });
+    w.into_bytes()
+}
+
+impl<'a, F: Float> Exporter<'a, F> {
+    fn temporary(&mut self) -> String {
+        self.num_temporaries += 1;
+        format!("tmp{}", self.num_temporaries)
+    }
+
+    fn node(
+        &mut self,
+        op_type: &str,
+        inputs: &[&str],
+        output: &str,
+        attributes: Vec<(&str, NodeAttribute)>,
+    ) {
+        let mut w = Writer::new();
+        for x in inputs {
+            w.string(1, x);
+        }
+        w.string(2, output);
+        w.string(3, output);
+        w.string(4, op_type);
+        for (name, a) in attributes {
+            w.message(5, |w| {
+                w.string(1, name);
+                match a {
+                    NodeAttribute::Float(a) => {
+                        w.float(2, a);
+                        w.int(20, 1);
+                    }
+                    NodeAttribute::Int(a) => {
+                        w.int(3, a);
+                        w.int(20, 2);
+                    }
+                    NodeAttribute::Tensor(a) => {
+                        w.bytes(5, &a);
+                        w.int(20, 4);
+                    }
+                    NodeAttribute::Ints(a) => {
+                        for a in a {
+                            w.int(8, a);
+                        }
+                        w.int(20, 7);
+                    }
+                }
+            });
+        }
+        self.nodes.push(w.into_bytes());
+    }
+
+    // Emits `op_type` whose output is a new temporary, and returns the name of it.
+    fn temporary_node(
+        &mut self,
+        op_type: &str,
+        inputs: &[&str],
+        attributes: Vec<(&str, NodeAttribute)>,
+    ) -> String {
+        let ret = self.temporary();
+        self.node(op_type, inputs, &ret, attributes);
+        ret
+    }
+
+    fn cast(&mut self, input: &str, to: i64) -> String {
+        self.temporary_node("Cast", &[input], vec![("to", NodeAttribute::Int(to))])
+    }
+
+    fn array_initializer(&mut self, name: &str, arr: &NdArray<F>) {
+        let proto = tensor_proto(name, arr.shape(), data_type::<F>(), &raw_data(arr));
+        self.initializers.push(proto);
+    }
+
+    fn scalar(&mut self, a: F) -> String {
+        let ret = self.temporary();
+        self.array_initializer(&ret, &ndarray::arr0(a).into_dyn());
+        ret
+    }
+
+    fn ints(&mut self, a: &[i64]) -> String {
+        let ret = self.temporary();
+        let data: Vec<u8> = a.iter().flat_map(|a| a.to_le_bytes().to_vec()).collect();
+        let proto = tensor_proto(&ret, &[a.len()], INT64, &data);
+        self.initializers.push(proto);
+        ret
+    }
+
+    // Returns the value of `node` if it's a constant.
+    fn constant_value(&self, node: &TensorInternal<F>) -> Option<NdArray<F>> {
+        if let Some(ref arr) = node.constant_array {
+            return Some((**arr).clone());
+        }
+        match (op_key(node.op.name()), node.op.attributes()?.first()) {
+            ("ConvertToTensor", Some(Attribute::Array(arr))) => Some(arr.clone()),
+            ("Scalar", Some(&Attribute::Float(a))) => Some(ndarray::arr0(a).into_dyn()),
+            _ => None,
+        }
+    }
+
+    fn export_node(&mut self, node: &TensorInternal<F>) -> Result<(), SerializationError> {
+        let unsupported = || SerializationError::UnsupportedOp(node.op.name().to_string());
+        if node.in_edges.iter().any(|x| x.mut_usage) {
+            return Err(unsupported());
+        }
+        let name = op_key(node.op.name());
+        let attrs = node.op.attributes().unwrap_or_default();
+        let int = |i: usize| attrs[i].as_int().unwrap();
+        let float = |i: usize| attrs[i].as_float().unwrap();
+        let boolean = |i: usize| attrs[i].as_bool().unwrap();
+        let out = tensor_name(node.id);
+        let xs: Vec<String> = node
+            .in_edges
+            .iter()
+            .zip(&node.input_indices)
+            .map(|(x, &i)| {
+                if i == 0 {
+                    tensor_name(x.id)
+                } else {
+                    format!("{}:{}", tensor_name(x.id), i)
+                }
+            })
+            .collect();
+        let x: Vec<&str> = xs.iter().map(|a| a.as_str()).collect();
+        let dtype = data_type::<F>();
+        // ONNX data type of the output
+        let to = tensor_data_type::<F>(node.dtype);
+
+        if let Some(op_type) = unary_op_type(name) {
+            self.node(op_type, &x[..1], &out, vec![]);
+        } else if let Some(op_type) = binary_op_type(name) {
+            self.node(op_type, &x[..2], &out, vec![]);
+        } else if let Some(op_type) = comparison_op_type(name) {
+            let y = self.temporary_node(op_type, &x[..2], vec![]);
+            self.node("Cast", &[&y], &out, vec![("to", NodeAttribute::Int(to))]);
+        } else if let Some(op_type) = reduction_op_type(name) {
+            if boolean(1) {
+                // sparse_axes
+                return Err(unsupported());
+            }
+            let axes = self.cast(x[1], INT64);
+            let keep_dims = NodeAttribute::Int(boolean(0) as i64);
+            self.node(op_type, &[x[0], &axes], &out, vec![("keepdims", keep_dims)]);
+        } else {
+            match name {
+                "Variable" => {
+                    let arr = node.lock_variable_array().unwrap();
+                    self.array_initializer(&out, &arr);
+                }
+                "Const" | "ConvertToTensor" | "Scalar" => {
+                    let arr = self.constant_value(node).ok_or_else(unsupported)?;
+                    self.array_initializer(&out, &arr);
+                }
+                "NotEqual" => {
+                    let y = self.temporary_node("Equal", &x[..2], vec![]);
+                    let y = self.temporary_node("Not", &[&y], vec![]);
+                    self.node("Cast", &[&y], &out, vec![("to", NodeAttribute::Int(to))]);
+                }
+                "Square" => self.node("Mul", &[x[0], x[0]], &out, vec![]),
+                "InvSqrt" => {
+                    let y = self.temporary_node("Sqrt", &x[..1], vec![]);
+                    self.node("Reciprocal", &[&y], &out, vec![]);
+                }
+                "Exp2" | "Exp10" => {
+                    let base = self.scalar(F::from(if name == "Exp2" { 2. } else { 10. }).unwrap());
+                    self.node("Pow", &[&base, x[0]], &out, vec![]);
+                }
+                "Log2" | "Log10" => {
+                    let base: F = F::from(if name == "Log2" { 2. } else { 10. }).unwrap();
+                    let y = self.temporary_node("Log", &x[..1], vec![]);
+                    let ln_base = self.scalar(base.ln());
+                    self.node("Div", &[&y, &ln_base], &out, vec![]);
+                }
+                "Pow" => {
+                    let a = self.scalar(float(0));
+                    self.node("Pow", &[x[0], &a], &out, vec![]);
+                }
+                "ELU" => {
+                    let alpha = NodeAttribute::Float(float(0).to_f32().unwrap());
+                    self.node("Elu", &x[..1], &out, vec![("alpha", alpha)]);
+                }
+                "Softmax" | "LogSoftmax" => {
+                    let axis = vec![("axis", NodeAttribute::Int(int(0)))];
+                    self.node(name, &x[..1], &out, axis);
+                }
+                "LogSumExp" => {
+                    let axes = self.ints(&[int(0)]);
+                    let keep_dims = NodeAttribute::Int(boolean(1) as i64);
+                    let attributes = vec![("keepdims", keep_dims)];
+                    self.node("ReduceLogSumExp", &[x[0], &axes], &out, attributes);
+                }
+                "ReduceSumToScalar" => {
+                    let keep_dims = vec![("keepdims", NodeAttribute::Int(0))];
+                    self.node("ReduceSum", &x[..1], &out, keep_dims);
+                }
+                "ArgMax" => {
+                    let attributes = vec![
+                        ("axis", NodeAttribute::Int(int(0))),
+                        ("keepdims", NodeAttribute::Int(boolean(1) as i64)),
+                    ];
+                    let y = self.temporary_node("ArgMax", &x[..1], attributes);
+                    self.node("Cast", &[&y], &out, vec![("to", NodeAttribute::Int(to))]);
+                }
+                "Clip" => {
+                    let min = self.scalar(float(0));
+                    let max = self.scalar(float(1));
+                    self.node("Clip", &[x[0], &min, &max], &out, vec![]);
+                }
+                "MatMul" if boolean(0) || boolean(1) => {
+                    let attributes = vec![
+                        ("transA", NodeAttribute::Int(boolean(0) as i64)),
+                        ("transB", NodeAttribute::Int(boolean(1) as i64)),
+                    ];
+                    self.node("Gemm", &x[..2], &out, attributes);
+                }
+                "MatMul" => self.node("MatMul", &x[..2], &out, vec![]),
+                "BatchMatMul" if !boolean(0) && !boolean(1) => {
+                    self.node("MatMul", &x[..2], &out, vec![])
+                }
+                "Conv2D" | "Conv2DTranspose" => {
+                    let (pad, stride, dilation) = (int(0), int(1), int(2));
+                    let attributes = vec![
+                        ("pads", NodeAttribute::Ints(vec![pad; 4])),
+                        ("strides", NodeAttribute::Ints(vec![stride; 2])),
+                        ("dilations", NodeAttribute::Ints(vec![dilation; 2])),
+                    ];
+                    let op_type = if name == "Conv2D" {
+                        "Conv"
+                    } else {
+                        "ConvTranspose"
+                    };
+                    self.node(op_type, &x[..2], &out, attributes);
+                }
+                "MaxPool2D" => {
+                    let (pad, stride, size) = (int(0), int(1), int(2));
+                    let attributes = vec![
+                        ("kernel_shape", NodeAttribute::Ints(vec![size; 2])),
+                        ("pads", NodeAttribute::Ints(vec![pad; 4])),
+                        ("strides", NodeAttribute::Ints(vec![stride; 2])),
+                    ];
+                    self.node("MaxPool", &x[..1], &out, attributes);
+                }
+                "Concat" => {
+                    let axis = vec![("axis", NodeAttribute::Int(int(0)))];
+                    self.node("Concat", &x, &out, axis);
+                }
+                "AddN" => self.node("Sum", &x, &out, vec![]),
+                "Cast" => {
+                    let dtype = DType::from_index(int(0)).unwrap();
+                    let to = vec![("to", NodeAttribute::Int(tensor_data_type::<F>(dtype)))];
+                    self.node("Cast", &x[..1], &out, to);
+                }
+                "Gather" => {
+                    // The inputs are (indices, param).
+                    let indices = self.cast(x[0], INT64);
+                    let axis = vec![("axis", NodeAttribute::Int(int(0)))];
+                    self.node("Gather", &[x[1], &indices], &out, axis);
+                }
+                "Reshape" | "ExpandDims" | "Squeeze" => {
+                    let shape_or_axes = self.cast(x[1], INT64);
+                    let op_type = match name {
+                        "Reshape" => "Reshape",
+                        "ExpandDims" => "Unsqueeze",
+                        _ => "Squeeze",
+                    };
+                    self.node(op_type, &[x[0], &shape_or_axes], &out, vec![]);
+                }
+                "Transpose" => {
+                    let perm = self
+                        .constant_value(self.graph.access_node(node.in_edges[1].id))
+                        .ok_or_else(unsupported)?;
+                    let perm: Vec<i64> = perm.iter().map(|a| a.to_i64().unwrap()).collect();
+                    let perm = if boolean(0) {
+                        let mut inverse = vec![0; perm.len()];
+                        for (i, &p) in perm.iter().enumerate() {
+                            inverse[p as usize] = i as i64;
+                        }
+                        inverse
+                    } else {
+                        perm
+                    };
+                    let attributes = vec![("perm", NodeAttribute::Ints(perm))];
+                    self.node("Transpose", &x[..1], &out, attributes);
+                }
+                "Slice" => {
+                    let indices = crate::ops::slice_indices_from_ints(attrs[0].as_ints().unwrap())
+                        .ok_or_else(unsupported)?;
+                    let (mut starts, mut ends, mut axes, mut steps, mut squeezed) =
+                        (vec![], vec![], vec![], vec![], vec![]);
+                    for (axis, &index) in indices.iter().enumerate() {
+                        let (start, end, step) = match index {
+                            ndarray::SliceOrIndex::Slice { step, .. } if step < 0 => {
+                                // ndarray and ONNX differ in negative steps.
+                                return Err(unsupported());
+                            }
+                            ndarray::SliceOrIndex::Slice { start, end, step } => (
+                                start as i64,
+                                end.map_or(i64::MAX, |e| e as i64),
+                                step as i64,
+                            ),
+                            ndarray::SliceOrIndex::Index(i) => {
+                                squeezed.push(axis as i64);
+                                let end = if i == -1 { i64::MAX } else { i as i64 + 1 };
+                                (i as i64, end, 1)
+                            }
+                        };
+                        starts.push(start);
+                        ends.push(end);
+                        axes.push(axis as i64);
+                        steps.push(step);
+                    }
+                    let inputs = [
+                        self.ints(&starts),
+                        self.ints(&ends),
+                        self.ints(&axes),
+                        self.ints(&steps),
+                    ];
+                    let inputs = [x[0], &inputs[0], &inputs[1], &inputs[2], &inputs[3]];
+                    if squeezed.is_empty() {
+                        self.node("Slice", &inputs, &out, vec![]);
+                    } else {
+                        let y = self.temporary_node("Slice", &inputs, vec![]);
+                        let squeezed = self.ints(&squeezed);
+                        self.node("Squeeze", &[&y, &squeezed], &out, vec![]);
+                    }
+                }
+                "Split" => {
+                    let inputs = [
+                        self.ints(&[int(1)]),
+                        self.ints(&[int(2)]),
+                        self.ints(&[int(0)]),
+                    ];
+                    let inputs = [x[0], &inputs[0], &inputs[1], &inputs[2]];
+                    self.node("Slice", &inputs, &out, vec![]);
+                }
+                "Shape" | "Size" | "Rank" => {
+                    let y = match name {
+                        "Shape" => self.temporary_node("Shape", &x[..1], vec![]),
+                        "Size" => self.temporary_node("Size", &x[..1], vec![]),
+                        _ => {
+                            let shape = self.temporary_node("Shape", &x[..1], vec![]);
+                            self.temporary_node("Size", &[&shape], vec![])
+                        }
+                    };
+                    self.node("Cast", &[&y], &out, vec![("to", NodeAttribute::Int(to))]);
+                }
+                "Zeros" | "Ones" => {
+                    let shape = self.cast(x[0], INT64);
+                    let value =
+                        ndarray::arr1(&[if name == "Zeros" { F::zero() } else { F::one() }])
+                            .into_dyn();
+                    let value = tensor_proto("", &[1], dtype, &raw_data(&value));
+                    let attributes = vec![("value", NodeAttribute::Tensor(value))];
+                    self.node("ConstantOfShape", &[&shape], &out, attributes);
+                }
+                _ => return Err(unsupported()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<F: Float> Graph<F> {
+    /// Exports the subgraph that `targets` depend on as an ONNX model.
+    ///
+    /// Placeholders become the inputs of the model, variables and constants become
+    /// initializers, and `targets` become the outputs. Values are named `t<id>` after the
+    /// ids of the tensors. The model uses the default opset of version 18.
+    ///
+    /// Supported are the ops with ONNX counterparts, e.g. elementwise math, matmul, conv2d,
+    /// conv2d_transpose, max_pool2d, activations, softmax, reductions, reshape, concat,
+    /// gather, slice and transpose with a constant permutation.
+    /// Others (e.g. losses and gradient ops) fail with `SerializationError::UnsupportedOp`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor::Variable;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.placeholder(&[-1, 4]);
+    ///     let w = g.variable(ag::ndarray_ext::ones(&[4, 3]));
+    ///     let y = g.softmax(g.relu(g.matmul(x, w)), 1);
+    ///     let model: Vec<u8> = g.to_onnx(&[y]).unwrap();
+    ///     assert!(!model.is_empty());
+    ///     // g.save_onnx(&[y], "model.onnx").unwrap();
+    /// });
+    /// ```
+    pub fn to_onnx<'g, A>(&'g self, targets: &[A]) -> Result<Vec<u8>, SerializationError>
+    where
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        let mut visited = FxHashSet::default();
+        let mut stack: Vec<usize> = targets.iter().map(|t| t.as_ref().id()).collect();
+        while let Some(id) = stack.pop() {
+            if visited.insert(id) {
+                stack.extend(self.access_node(id).in_edges.iter().map(|x| x.id));
+            }
+        }
+        let mut ids: Vec<usize> = visited.into_iter().collect();
+        ids.sort();
+
+        let mut exporter = Exporter {
+            graph: self,
+            nodes: Vec::new(),
+            initializers: Vec::new(),
+            num_temporaries: 0,
+        };
+        let mut inputs = Vec::new();
+        for &id in &ids {
+            let node = self.access_node(id);
+            if node.is_placeholder {
+                let shape = node.known_shape.as_ref().map(|s| s.get());
+                let data_type = tensor_data_type::<F>(node.dtype);
+                inputs.push(value_info(&tensor_name(id), data_type, shape));
+            } else {
+                exporter.export_node(node)?;
+            }
+        }
+
+        let mut model = Writer::new();
+        model.int(1, IR_VERSION);
+        model.string(2, "autograd");
+        model.string(3, env!("CARGO_PKG_VERSION"));
+        model.message(7, |w| {
+            for node in &exporter.nodes {
+                w.bytes(1, node);
+            }
+            w.string(2, "autograd");
+            for initializer in &exporter.initializers {
+                w.bytes(5, initializer);
+            }
+            for input in &inputs {
+                w.bytes(11, input);
+            }
+            for t in targets {
+                let t = t.as_ref();
+                let data_type = tensor_data_type::<F>(t.dtype());
+                w.bytes(12, &value_info(&tensor_name(t.id()), data_type, None));
+            }
+        });
+        model.message(8, |w| w.int(2, OPSET_VERSION));
+        Ok(model.into_bytes())
+    }
+
+    /// Exports the subgraph that `targets` depend on to an ONNX file at `path`.
+    ///
+    /// See [to_onnx](#method.to_onnx).
+    pub fn save_onnx<'g, A, P>(&'g self, targets: &[A], path: P) -> Result<(), SerializationError>
+    where
+        A: AsRef<Tensor<'g, F>> + Copy,
+        P: AsRef<Path>,
+    {
+        std::fs::write(path, self.to_onnx(targets)?)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_to_onnx() {
+    use crate::tensor::Variable;
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let x = g.placeholder(&[-1, 1, 4, 4]);
+        let w = g.variable(crate::ndarray_ext::ones(&[2, 1, 3, 3]));
+        let h = g.max_pool2d(g.relu(g.conv2d(x, w, 1, 1)), 2, 0, 2);
+        let y = g.softmax(g.reshape(h, &[-1, 8]), 1);
+        let model = g.to_onnx(&[y]).unwrap();
+        let contains = |s: &str| model.windows(s.len()).any(|a| a == s.as_bytes());
+        for op_type in &["Conv", "Relu", "MaxPool", "Reshape", "Softmax", "Cast"] {
+            assert!(contains(op_type), "{} is missing", op_type);
+        }
+        assert!(contains(&tensor_name(x.id())));
+        assert!(contains(&tensor_name(w.id())));
+        assert!(contains(&tensor_name(y.id())));
+
+        let loss = g.sparse_softmax_cross_entropy(y, g.placeholder(&[-1]));
+        match g.to_onnx(&[loss]) {
+            Err(SerializationError::UnsupportedOp(_)) => {}
+            _ => panic!("expected UnsupportedOp"),
+        }
+    });
+}
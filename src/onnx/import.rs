@@ -0,0 +1,1110 @@
+//! Defining the ONNX import of models.
+use super::proto::Reader;
+use super::{
+    BOOL, DOUBLE, FLOAT, INT16, INT32, INT64, INT8, OPSET_VERSION, UINT16, UINT32, UINT64, UINT8,
+};
+use crate::ops::basic_source_ops::Placeholder;
+use crate::serialization::SerializationError;
+use crate::tensor::{DType, Tensor, Variable};
+use crate::{Float, FxHashMap, Graph, NdArray};
+use std::convert::TryInto;
+use std::path::Path;
+
+/// Tensors of a model imported by [Graph::import_onnx](../struct.Graph.html#method.import_onnx).
+pub struct OnnxModel<'g, F: Float> {
+    /// Placeholders for the inputs of the model, with their names.
+    pub inputs: Vec<(String, Tensor<'g, F>)>,
+    /// Outputs of the model, with their names.
+    pub outputs: Vec<(String, Tensor<'g, F>)>,
+    /// Variables made from the floating-point initializers, with their names.
+    pub variables: Vec<(String, Tensor<'g, F>)>,
+}
+
+// Attribute of a `NodeProto`
+enum NodeAttribute<F: Float> {
+    Int(i64),
+    Float(f32),
+    Ints(Vec<i64>),
+    String(String),
+    Tensor(NdArray<F>),
+    Other,
+}
+
+// Parsed `NodeProto`
+struct Node<F: Float> {
+    op_type: String,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    attributes: FxHashMap<String, NodeAttribute<F>>,
+}
+
+impl<F: Float> Node<F> {
+    fn int(&self, name: &str, default: i64) -> i64 {
+        match self.attributes.get(name) {
+            Some(&NodeAttribute::Int(a)) => a,
+            _ => default,
+        }
+    }
+
+    fn float(&self, name: &str, default: f32) -> F {
+        match self.attributes.get(name) {
+            Some(&NodeAttribute::Float(a)) => F::from(a).unwrap(),
+            _ => F::from(default).unwrap(),
+        }
+    }
+
+    fn ints(&self, name: &str) -> Option<&[i64]> {
+        match self.attributes.get(name) {
+            Some(NodeAttribute::Ints(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        match self.attributes.get(name) {
+            Some(NodeAttribute::String(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    fn tensor(&self, name: &str) -> Option<&NdArray<F>> {
+        match self.attributes.get(name) {
+            Some(NodeAttribute::Tensor(a)) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+// Parsed `TensorProto`
+struct Initializer<F: Float> {
+    name: String,
+    data_type: i64,
+    array: NdArray<F>,
+}
+
+// Parsed `ValueInfoProto`; `shape` is `None` if the rank is unknown.
+struct ValueInfo {
+    name: String,
+    shape: Option<Vec<isize>>,
+}
+
+// Parsed `GraphProto`
+struct GraphProto<F: Float> {
+    nodes: Vec<Node<F>>,
+    initializers: Vec<Initializer<F>>,
+    inputs: Vec<ValueInfo>,
+    outputs: Vec<String>,
+}
+
+// Decodes little-endian elements of `data_type`.
+fn decode_raw_data<F: Float>(raw: &[u8], data_type: i64) -> Result<Vec<F>, String> {
+    let size = match data_type {
+        UINT8 | INT8 | BOOL => 1,
+        UINT16 | INT16 => 2,
+        FLOAT | INT32 | UINT32 => 4,
+        DOUBLE | INT64 | UINT64 => 8,
+        t => return Err(format!("unsupported data type {}", t)),
+    };
+    let chunks = raw.chunks_exact(size);
+    if !chunks.remainder().is_empty() {
+        return Err("truncated raw_data".to_string());
+    }
+    let ret = chunks
+        .map(|c| {
+            let a = match data_type {
+                UINT8 | BOOL => f64::from(c[0]),
+                INT8 => f64::from(c[0] as i8),
+                UINT16 => f64::from(u16::from_le_bytes(c.try_into().unwrap())),
+                INT16 => f64::from(i16::from_le_bytes(c.try_into().unwrap())),
+                FLOAT => f64::from(f32::from_le_bytes(c.try_into().unwrap())),
+                INT32 => f64::from(i32::from_le_bytes(c.try_into().unwrap())),
+                UINT32 => f64::from(u32::from_le_bytes(c.try_into().unwrap())),
+                DOUBLE => f64::from_le_bytes(c.try_into().unwrap()),
+                INT64 => i64::from_le_bytes(c.try_into().unwrap()) as f64,
+                _ => u64::from_le_bytes(c.try_into().unwrap()) as f64,
+            };
+            F::from(a).unwrap()
+        })
+        .collect();
+    Ok(ret)
+}
+
+fn parse_tensor<F: Float>(buf: &[u8]) -> Result<Initializer<F>, String> {
+    let (mut name, mut data_type, mut raw_data) = (String::new(), FLOAT, None);
+    let (mut dims, mut ints, mut floats, mut doubles) = (vec![], vec![], vec![], vec![]);
+    for field in Reader::new(buf) {
+        let (number, value) = field?;
+        match number {
+            1 => value.ints(&mut dims)?,
+            2 => data_type = value.int()?,
+            4 => value.floats(&mut floats)?,
+            // int32_data and int64_data
+            5 | 7 => value.ints(&mut ints)?,
+            8 => name = value.string()?,
+            9 => raw_data = Some(value.bytes()?),
+            10 => value.doubles(&mut doubles)?,
+            13 | 14 => return Err(format!("external data of {} is not supported", name)),
+            _ => {}
+        }
+    }
+    let data: Vec<F> = match (raw_data, data_type) {
+        (Some(raw), _) => decode_raw_data(raw, data_type)?,
+        (None, FLOAT) => floats.into_iter().map(|a| F::from(a).unwrap()).collect(),
+        (None, DOUBLE) => doubles.into_iter().map(|a| F::from(a).unwrap()).collect(),
+        (None, UINT8)
+        | (None, INT8)
+        | (None, UINT16)
+        | (None, INT16)
+        | (None, INT32)
+        | (None, INT64)
+        | (None, BOOL)
+        | (None, UINT32)
+        | (None, UINT64) => ints.into_iter().map(|a| F::from(a).unwrap()).collect(),
+        (None, t) => return Err(format!("unsupported data type {}", t)),
+    };
+    let dims: Vec<usize> = dims.into_iter().map(|d| d as usize).collect();
+    let array = NdArray::from_shape_vec(dims, data).map_err(|e| format!("{}: {}", name, e))?;
+    Ok(Initializer {
+        name,
+        data_type,
+        array,
+    })
+}
+
+fn parse_attribute<F: Float>(buf: &[u8]) -> Result<(String, NodeAttribute<F>), String> {
+    let (mut name, mut ty) = (String::new(), 0);
+    let (mut f, mut i, mut s, mut t, mut ints) = (0., 0, String::new(), None, vec![]);
+    for field in Reader::new(buf) {
+        let (number, value) = field?;
+        match number {
+            1 => name = value.string()?,
+            2 => f = value.float()?,
+            3 => i = value.int()?,
+            4 => s = value.string()?,
+            5 => t = Some(parse_tensor(value.bytes()?)?.array),
+            8 => value.ints(&mut ints)?,
+            20 => ty = value.int()?,
+            _ => {}
+        }
+    }
+    // `AttributeProto.AttributeType`
+    let attribute = match (ty, t) {
+        (1, _) => NodeAttribute::Float(f),
+        (2, _) => NodeAttribute::Int(i),
+        (3, _) => NodeAttribute::String(s),
+        (4, Some(t)) => NodeAttribute::Tensor(t),
+        (7, _) => NodeAttribute::Ints(ints),
+        _ => NodeAttribute::Other,
+    };
+    Ok((name, attribute))
+}
+
+fn parse_node<F: Float>(buf: &[u8]) -> Result<Node<F>, String> {
+    let mut node = Node {
+        op_type: String::new(),
+        inputs: vec![],
+        outputs: vec![],
+        attributes: FxHashMap::default(),
+    };
+    for field in Reader::new(buf) {
+        let (number, value) = field?;
+        match number {
+            1 => node.inputs.push(value.string()?),
+            2 => node.outputs.push(value.string()?),
+            4 => node.op_type = value.string()?,
+            5 => {
+                let (name, attribute) = parse_attribute(value.bytes()?)?;
+                node.attributes.insert(name, attribute);
+            }
+            7 => {
+                let domain = value.string()?;
+                if !domain.is_empty() && domain != "ai.onnx" {
+                    return Err(format!("unsupported domain {}", domain));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(node)
+}
+
+fn parse_value_info(buf: &[u8]) -> Result<ValueInfo, String> {
+    let mut ret = ValueInfo {
+        name: String::new(),
+        shape: None,
+    };
+    for field in Reader::new(buf) {
+        match field? {
+            (1, value) => ret.name = value.string()?,
+            (2, type_proto) => {
+                for field in Reader::new(type_proto.bytes()?) {
+                    if let (1, tensor_type) = field? {
+                        for field in Reader::new(tensor_type.bytes()?) {
+                            if let (2, shape) = field? {
+                                ret.shape = Some(parse_shape(shape.bytes()?)?);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(ret)
+}
+
+// Parses a `TensorShapeProto`, where symbolic or zero dims become -1.
+fn parse_shape(buf: &[u8]) -> Result<Vec<isize>, String> {
+    let mut ret = vec![];
+    for field in Reader::new(buf) {
+        if let (1, dim) = field? {
+            let mut d = -1;
+            for field in Reader::new(dim.bytes()?) {
+                if let (1, value) = field? {
+                    d = value.int()? as isize;
+                }
+            }
+            ret.push(if d > 0 { d } else { -1 });
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_graph<F: Float>(buf: &[u8]) -> Result<GraphProto<F>, String> {
+    let mut ret = GraphProto {
+        nodes: vec![],
+        initializers: vec![],
+        inputs: vec![],
+        outputs: vec![],
+    };
+    for field in Reader::new(buf) {
+        let (number, value) = field?;
+        match number {
+            1 => ret.nodes.push(parse_node(value.bytes()?)?),
+            5 => ret.initializers.push(parse_tensor(value.bytes()?)?),
+            11 => ret.inputs.push(parse_value_info(value.bytes()?)?),
+            12 => ret.outputs.push(parse_value_info(value.bytes()?)?.name),
+            _ => {}
+        }
+    }
+    Ok(ret)
+}
+
+// Parses a `ModelProto` into the graph and the version of the default opset.
+fn parse_model<F: Float>(buf: &[u8]) -> Result<(GraphProto<F>, i64), String> {
+    let (mut graph, mut opset) = (None, OPSET_VERSION);
+    for field in Reader::new(buf) {
+        let (number, value) = field?;
+        match number {
+            7 => graph = Some(parse_graph(value.bytes()?)?),
+            8 => {
+                let (mut domain, mut version) = (String::new(), 0);
+                for field in Reader::new(value.bytes()?) {
+                    match field? {
+                        (1, value) => domain = value.string()?,
+                        (2, value) => version = value.int()?,
+                        _ => {}
+                    }
+                }
+                if domain.is_empty() || domain == "ai.onnx" {
+                    opset = version;
+                }
+            }
+            _ => {}
+        }
+    }
+    let graph = graph.ok_or_else(|| "no graph".to_string())?;
+    Ok((graph, opset))
+}
+
+// Builder of the tensors of the nodes in an ONNX graph.
+struct Importer<'g, F: Float> {
+    graph: &'g Graph<F>,
+    opset: i64,
+    // Tensors of the values created so far
+    tensors: FxHashMap<String, Tensor<'g, F>>,
+    // Values known at import time, which become tensors on first use
+    constants: FxHashMap<String, NdArray<F>>,
+    // Names of the constants which become variables
+    trainable: Vec<String>,
+}
+
+// DType of the values of ONNX `data_type`
+fn dtype_of(data_type: i64) -> Option<DType> {
+    match data_type {
+        FLOAT | DOUBLE => Some(DType::Float),
+        UINT8 | INT8 | UINT16 | INT16 | INT32 | INT64 | UINT32 | UINT64 => Some(DType::Int),
+        BOOL => Some(DType::Bool),
+        _ => None,
+    }
+}
+
+fn invalid(message: String) -> SerializationError {
+    SerializationError::InvalidModel(message)
+}
+
+impl<'g, F: Float> Importer<'g, F> {
+    fn tensor(&mut self, name: &str) -> Result<Tensor<'g, F>, SerializationError> {
+        if let Some(&t) = self.tensors.get(name) {
+            return Ok(t);
+        }
+        let arr = self
+            .constants
+            .get(name)
+            .ok_or_else(|| invalid(format!("{} is undefined", name)))?
+            .clone();
+        let t = if self.trainable.iter().any(|a| a == name) {
+            self.graph.variable(arr)
+        } else {
+            self.graph.convert_to_tensor(arr)
+        };
+        self.tensors.insert(name.to_string(), t);
+        Ok(t)
+    }
+
+    // Returns the `i` th input of `node`, or `None` if the optional input is missing.
+    fn optional_input(
+        &mut self,
+        node: &Node<F>,
+        i: usize,
+    ) -> Result<Option<Tensor<'g, F>>, SerializationError> {
+        match node.inputs.get(i) {
+            Some(name) if !name.is_empty() => self.tensor(name).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    // Returns the `i` th input of `node` as is, which can be an integer or boolean tensor.
+    fn input_of_any_dtype(
+        &mut self,
+        node: &Node<F>,
+        i: usize,
+    ) -> Result<Tensor<'g, F>, SerializationError> {
+        self.optional_input(node, i)?
+            .ok_or_else(|| invalid(format!("{} lacks input {}", node.op_type, i)))
+    }
+
+    // Returns the `i` th input of `node` as a float tensor, which most ops take.
+    fn input(&mut self, node: &Node<F>, i: usize) -> Result<Tensor<'g, F>, SerializationError> {
+        let x = self.input_of_any_dtype(node, i)?;
+        if x.dtype() == DType::Float {
+            Ok(x)
+        } else {
+            Ok(self.graph.cast(x, DType::Float))
+        }
+    }
+
+    // Returns the value of the `i` th input of `node` if it's known at import time.
+    fn constant(&self, node: &Node<F>, i: usize) -> Option<&NdArray<F>> {
+        self.constants.get(node.inputs.get(i)?)
+    }
+
+    // Returns the value of the `i` th input of `node` as ints, which must be known if present.
+    fn constant_ints(
+        &self,
+        node: &Node<F>,
+        i: usize,
+    ) -> Result<Option<Vec<i64>>, SerializationError> {
+        match node.inputs.get(i) {
+            Some(name) if !name.is_empty() => {
+                let arr = self
+                    .constant(node, i)
+                    .ok_or_else(|| SerializationError::UnsupportedOp(node.op_type.clone()))?;
+                // Saturates e.g. `i64::MAX` that ends slices, which isn't exact in `F`.
+                Ok(Some(
+                    arr.iter().map(|a| a.to_f64().unwrap() as i64).collect(),
+                ))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Returns the rank of the `i` th input of `node` if it's known.
+    fn rank(&self, node: &Node<F>, i: usize) -> Option<usize> {
+        if let Some(arr) = self.constant(node, i) {
+            return Some(arr.ndim());
+        }
+        let t = self.tensors.get(node.inputs.get(i)?)?;
+        let known_shape = self.graph.access_node(t.id()).known_shape.as_ref()?;
+        Some(known_shape.get().len())
+    }
+
+    // Returns the axes in the attribute (older opsets) or in the `i` th input.
+    fn axes(
+        &mut self,
+        node: &Node<F>,
+        i: usize,
+    ) -> Result<Option<Tensor<'g, F>>, SerializationError> {
+        if let Some(axes) = node.ints("axes") {
+            Ok(Some(self.ints(axes)))
+        } else {
+            self.optional_input(node, i)
+        }
+    }
+
+    fn ints(&self, a: &[i64]) -> Tensor<'g, F> {
+        let a: Vec<F> = a.iter().map(|&a| F::from(a).unwrap()).collect();
+        self.graph.convert_to_tensor(ndarray::arr1(&a))
+    }
+
+    fn import_node(&mut self, node: &Node<F>) -> Result<(), SerializationError> {
+        let unsupported = || SerializationError::UnsupportedOp(node.op_type.clone());
+        let output = node
+            .outputs
+            .first()
+            .ok_or_else(|| invalid(format!("{} has no outputs", node.op_type)))?;
+
+        // Values known at import time
+        match node.op_type.as_str() {
+            "Constant" => {
+                let value = if let Some(t) = node.tensor("value") {
+                    t.clone()
+                } else if let Some(a) = node.attributes.get("value_float") {
+                    match a {
+                        &NodeAttribute::Float(a) => ndarray::arr0(F::from(a).unwrap()).into_dyn(),
+                        _ => return Err(unsupported()),
+                    }
+                } else if let Some(&NodeAttribute::Int(a)) = node.attributes.get("value_int") {
+                    ndarray::arr0(F::from(a).unwrap()).into_dyn()
+                } else if let Some(a) = node.ints("value_ints") {
+                    let a: Vec<F> = a.iter().map(|&a| F::from(a).unwrap()).collect();
+                    ndarray::arr1(&a).into_dyn()
+                } else {
+                    return Err(unsupported());
+                };
+                self.constants.insert(output.clone(), value);
+                return Ok(());
+            }
+            "Cast" | "Identity" => {
+                // Casts of variables are shapes, indices etc. rather than weights.
+                let trainable = node
+                    .inputs
+                    .first()
+                    .map_or(false, |a| self.trainable.contains(a));
+                if let Some(arr) = self.constant(node, 0) {
+                    if node.op_type == "Cast" {
+                        // Constants stay in `F` (e.g. for the shapes), with the values cast.
+                        let dtype = dtype_of(node.int("to", FLOAT)).ok_or_else(unsupported)?;
+                        let arr = match dtype {
+                            DType::Float => arr.clone(),
+                            DType::Int => arr.mapv(F::trunc),
+                            DType::Bool => {
+                                arr.mapv(|a| if a != F::zero() { F::one() } else { F::zero() })
+                            }
+                        };
+                        self.constants.insert(output.clone(), arr);
+                        return Ok(());
+                    } else if !trainable {
+                        let arr = arr.clone();
+                        self.constants.insert(output.clone(), arr);
+                        return Ok(());
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let g = self.graph;
+        let start = g.num_nodes();
+        let x = match node.op_type.as_str() {
+            // These keep the dtypes of the inputs, while the others take float inputs.
+            "Cast" | "Identity" | "Dropout" => self.input_of_any_dtype(node, 0)?,
+            _ => self.input(node, 0)?,
+        };
+        let y = match node.op_type.as_str() {
+            "Cast" => match dtype_of(node.int("to", FLOAT)).ok_or_else(unsupported)? {
+                dtype if dtype == x.dtype() => x,
+                dtype => g.cast(x, dtype),
+            },
+            "Identity" | "Dropout" => x,
+            "Relu" => g.relu(x),
+            "Sigmoid" => g.sigmoid(x),
+            "Tanh" => g.tanh(x),
+            "Softplus" => g.softplus(x),
+            "Exp" => g.exp(x),
+            "Log" => g.ln(x),
+            "Sqrt" => g.sqrt(x),
+            "Neg" => g.neg(x),
+            "Abs" => g.abs(x),
+            "Floor" => g.floor(x),
+            "Ceil" => g.ceil(x),
+            "Sign" => g.sign(x),
+            "Reciprocal" => g.inv(x),
+            "Sin" => g.sin(x),
+            "Cos" => g.cos(x),
+            "Tan" => g.tan(x),
+            "Asin" => g.asin(x),
+            "Acos" => g.acos(x),
+            "Atan" => g.atan(x),
+            "Sinh" => g.sinh(x),
+            "Cosh" => g.cosh(x),
+            "Asinh" => g.asinh(x),
+            "Acosh" => g.acosh(x),
+            "Atanh" => g.atanh(x),
+            "Not" => g.sub(g.scalar(F::one()), x),
+            "Elu" => g.elu(x, node.float("alpha", 1.)),
+            "LeakyRelu" => g.leaky_relu(x, node.float("alpha", 0.01)),
+            "Softmax" | "LogSoftmax" => {
+                let default_axis = if self.opset >= 13 { -1 } else { 1 };
+                let axis = node.int("axis", default_axis) as isize;
+                if node.op_type == "Softmax" {
+                    g.softmax(x, axis)
+                } else {
+                    g.log_softmax(x, axis)
+                }
+            }
+            "Add" | "Sub" | "Mul" | "Div" | "Equal" | "Greater" | "GreaterOrEqual" | "Less"
+            | "LessOrEqual" => {
+                let y = self.input(node, 1)?;
+                match node.op_type.as_str() {
+                    "Add" => g.add(x, y),
+                    "Sub" => g.sub(x, y),
+                    "Mul" => g.mul(x, y),
+                    "Div" => g.div(x, y),
+                    "Equal" => g.equal(x, y),
+                    "Greater" => g.greater(x, y),
+                    "GreaterOrEqual" => g.greater_equal(x, y),
+                    "Less" => g.lesser(x, y),
+                    _ => g.lesser_equal(x, y),
+                }
+            }
+            "Max" | "Min" | "Sum" => {
+                let mut xs = vec![x];
+                for i in 1..node.inputs.len() {
+                    xs.push(self.input(node, i)?);
+                }
+                match node.op_type.as_str() {
+                    _ if xs.len() == 1 => x,
+                    "Max" => xs[1..].iter().fold(x, |acc, &a| g.maximum(acc, a)),
+                    "Min" => xs[1..].iter().fold(x, |acc, &a| g.minimum(acc, a)),
+                    _ => g.add_n(&xs),
+                }
+            }
+            "Pow" => match (self.constant(node, 0), self.constant(node, 1)) {
+                (_, Some(a)) if a.len() == 1 => g.pow(x, *a.iter().next().unwrap()),
+                // `exp(y * ln(b))` is exact for a positive base, as exported for `exp2` and `exp10`.
+                (Some(b), _) if b.len() == 1 && *b.iter().next().unwrap() > F::zero() => {
+                    let ln_base = b.iter().next().unwrap().ln();
+                    let y = self.input(node, 1)?;
+                    g.exp(g.mul(y, g.scalar(ln_base)))
+                }
+                // There is no elementwise pow, and `exp(y * ln(x))` is NaN for negative `x`.
+                _ => return Err(unsupported()),
+            },
+            "Clip" => {
+                let bound = |i: usize, name: &str, default: F| match self.constant(node, i) {
+                    Some(a) if a.len() == 1 => Ok(*a.iter().next().unwrap()),
+                    Some(_) => Err(unsupported()),
+                    None if node.inputs.get(i).map_or(false, |a| !a.is_empty()) => {
+                        Err(unsupported())
+                    }
+                    None => Ok(node.float(name, default.to_f32().unwrap())),
+                };
+                let min = bound(1, "min", F::neg_infinity())?;
+                let max = bound(2, "max", F::infinity())?;
+                g.clip(x, min, max)
+            }
+            "MatMul" => {
+                let y = self.input(node, 1)?;
+                match (self.rank(node, 0), self.rank(node, 1)) {
+                    (Some(a), Some(b)) if a > 2 || b > 2 => g.batch_matmul(x, y),
+                    _ => g.matmul(x, y),
+                }
+            }
+            "Gemm" => {
+                let b = self.input(node, 1)?;
+                let a = if node.int("transA", 0) != 0 {
+                    g.transpose(x, &[1, 0])
+                } else {
+                    x
+                };
+                let b = if node.int("transB", 0) != 0 {
+                    g.transpose(b, &[1, 0])
+                } else {
+                    b
+                };
+                let mut y = g.matmul(a, b);
+                let alpha = node.float("alpha", 1.);
+                if alpha != F::one() {
+                    y = g.mul(y, g.scalar(alpha));
+                }
+                if let Some(mut c) = self.optional_input(node, 2)? {
+                    if self.rank(node, 2).map_or(false, |r| r < 2) {
+                        // Binary ops broadcast arrays of the same rank.
+                        c = g.reshape(c, &[1, -1]);
+                    }
+                    let beta = node.float("beta", 1.);
+                    if beta != F::one() {
+                        c = g.mul(c, g.scalar(beta));
+                    }
+                    y = g.add(y, c);
+                }
+                y
+            }
+            "Conv" | "ConvTranspose" => {
+                let w = self.input(node, 1)?;
+                if node.int("group", 1) != 1
+                    || node.string("auto_pad").map_or(false, |a| a != "NOTSET")
+                    || node
+                        .ints("output_padding")
+                        .map_or(false, |a| a.iter().any(|&a| a != 0))
+                    || node.ints("output_shape").is_some()
+                    || self.rank(node, 1).map_or(false, |r| r != 4)
+                {
+                    return Err(unsupported());
+                }
+                let pad = uniform_ints(node, "pads", 4, 0).ok_or_else(unsupported)?;
+                let stride = uniform_ints(node, "strides", 2, 1).ok_or_else(unsupported)?;
+                let dilation = uniform_ints(node, "dilations", 2, 1).ok_or_else(unsupported)?;
+                let mut y = match (node.op_type.as_str(), dilation) {
+                    ("Conv", 1) => g.conv2d(x, w, pad, stride),
+                    ("Conv", _) => g.dilated_conv2d(x, w, pad, stride, dilation),
+                    (_, 1) => g.conv2d_transpose(x, w, pad, stride),
+                    _ => g.dilated_conv2d_transpose(x, w, pad, stride, dilation),
+                };
+                if let Some(b) = self.optional_input(node, 2)? {
+                    y = g.add(y, g.reshape(b, &[1, -1, 1, 1]));
+                }
+                y
+            }
+            "MaxPool" => {
+                if node.int("ceil_mode", 0) != 0
+                    || node.string("auto_pad").map_or(false, |a| a != "NOTSET")
+                    || uniform_ints(node, "dilations", 2, 1) != Some(1)
+                    || node.ints("kernel_shape").map_or(true, |a| a.len() != 2)
+                {
+                    return Err(unsupported());
+                }
+                let size = uniform_ints(node, "kernel_shape", 2, 0).ok_or_else(unsupported)?;
+                let pad = uniform_ints(node, "pads", 4, 0).ok_or_else(unsupported)?;
+                let stride = uniform_ints(node, "strides", 2, 1).ok_or_else(unsupported)?;
+                g.max_pool2d(x, size, pad, stride)
+            }
+            "GlobalAveragePool" => g.reduce_mean(x, &[2, 3], true),
+            "ReduceSum" | "ReduceMean" | "ReduceMax" | "ReduceMin" | "ReduceProd" => {
+                let keep_dims = node.int("keepdims", 1) != 0;
+                match (self.axes(node, 1)?, node.op_type.as_str()) {
+                    (Some(axes), "ReduceSum") => g.reduce_sum(x, &axes, keep_dims),
+                    (Some(axes), "ReduceMean") => g.reduce_mean(x, &axes, keep_dims),
+                    (Some(axes), "ReduceMax") => g.reduce_max(x, &axes, keep_dims),
+                    (Some(axes), "ReduceMin") => g.reduce_min(x, &axes, keep_dims),
+                    (Some(axes), _) => g.reduce_prod(x, &axes, keep_dims),
+                    (None, "ReduceSum") if !keep_dims => g.reduce_sum_to_scalar(x),
+                    _ => return Err(unsupported()),
+                }
+            }
+            "ReduceLogSumExp" => {
+                let axes = match node.ints("axes") {
+                    Some(axes) => axes.to_vec(),
+                    None => self.constant_ints(node, 1)?.ok_or_else(unsupported)?,
+                };
+                if axes.len() != 1 {
+                    return Err(unsupported());
+                }
+                g.reduce_logsumexp(x, axes[0] as isize, node.int("keepdims", 1) != 0)
+            }
+            "ArgMax" => {
+                if node.int("select_last_index", 0) != 0 {
+                    return Err(unsupported());
+                }
+                let axis = node.int("axis", 0) as isize;
+                g.argmax(x, axis, node.int("keepdims", 1) != 0)
+            }
+            "Concat" => {
+                let mut xs = vec![x];
+                for i in 1..node.inputs.len() {
+                    xs.push(self.input(node, i)?);
+                }
+                g.concat(&xs, node.int("axis", 0) as isize)
+            }
+            "Gather" => {
+                // The indices can be integers.
+                let indices = self.input_of_any_dtype(node, 1)?;
+                g.gather(x, indices, node.int("axis", 0) as isize)
+            }
+            "Reshape" => {
+                let shape = self.input(node, 1)?;
+                let copies_dims = self
+                    .constant(node, 1)
+                    .map_or(false, |a| a.iter().any(|&a| a == F::zero()));
+                if node.int("allowzero", 0) != 0 || copies_dims {
+                    return Err(unsupported());
+                }
+                g.reshape(x, &shape)
+            }
+            "Flatten" => match node.int("axis", 1) {
+                0 => g.reshape(x, &[1, -1]),
+                axis if axis > 0 => {
+                    let head = g.slice(g.shape(x), &[0], &[axis as isize]);
+                    let head = g.reduce_prod(head, &[0], true);
+                    let shape = g.concat(&[head, self.ints(&[-1])], 0);
+                    g.reshape(x, &shape)
+                }
+                _ => return Err(unsupported()),
+            },
+            "Transpose" => {
+                let perm = node.ints("perm").ok_or_else(unsupported)?;
+                g.transpose(x, &self.ints(perm))
+            }
+            "Squeeze" | "Unsqueeze" => {
+                let axes = self.axes(node, 1)?.ok_or_else(unsupported)?;
+                if node.op_type == "Squeeze" {
+                    g.squeeze(x, &axes)
+                } else {
+                    g.expand_dims(x, &axes)
+                }
+            }
+            "Slice" => {
+                let attribute = |name: &str| node.ints(name).map(|a| a.to_vec());
+                let (starts, ends, axes, steps) = if self.opset < 10 {
+                    (
+                        attribute("starts"),
+                        attribute("ends"),
+                        attribute("axes"),
+                        None,
+                    )
+                } else {
+                    (
+                        self.constant_ints(node, 1)?,
+                        self.constant_ints(node, 2)?,
+                        self.constant_ints(node, 3)?,
+                        self.constant_ints(node, 4)?,
+                    )
+                };
+                let indices = slice_indices(
+                    &starts.ok_or_else(unsupported)?,
+                    &ends.ok_or_else(unsupported)?,
+                    axes,
+                    steps,
+                )
+                .ok_or_else(unsupported)?;
+                Tensor::builder()
+                    .append_input(&x)
+                    .try_build(g, crate::ops::Slice { indices })
+                    .map_err(|e| invalid(format!("{}: {}", node.op_type, e)))?
+            }
+            "Shape" => {
+                if node.attributes.contains_key("start") || node.attributes.contains_key("end") {
+                    return Err(unsupported());
+                }
+                g.shape(x)
+            }
+            "Size" => g.size(x),
+            "ConstantOfShape" => match node.tensor("value").map(|a| a.iter().next()) {
+                Some(Some(&a)) if a != F::zero() => g.mul(g.ones(&x), g.scalar(a)),
+                _ => g.zeros(&x),
+            },
+            _ => return Err(unsupported()),
+        };
+        // The tensors built by the graph's ops keep their shape and dtype errors.
+        let error = (start..g.num_nodes()).find_map(|i| g.access_node(i).build_error.clone());
+        if let Some(e) = error {
+            return Err(invalid(format!("{}: {}", node.op_type, e)));
+        }
+        self.tensors.insert(output.clone(), y);
+        Ok(())
+    }
+}
+
+// Returns the value of the ints attribute `name` of length `len` if all the elements are
+// the same, as autograd's ops take one value for all the spatial axes.
+fn uniform_ints<F: Float>(node: &Node<F>, name: &str, len: usize, default: i64) -> Option<usize> {
+    match node.ints(name) {
+        None => Some(default as usize),
+        Some(a) if a.len() == len && a.iter().all(|&b| b == a[0]) && a[0] >= 0 => {
+            Some(a[0] as usize)
+        }
+        _ => None,
+    }
+}
+
+// Converts the parameters of ONNX's `Slice` into ndarray's.
+fn slice_indices(
+    starts: &[i64],
+    ends: &[i64],
+    axes: Option<Vec<i64>>,
+    steps: Option<Vec<i64>>,
+) -> Option<Vec<ndarray::SliceOrIndex>> {
+    let axes = axes.unwrap_or_else(|| (0..starts.len() as i64).collect());
+    let steps = steps.unwrap_or_else(|| vec![1; starts.len()]);
+    if ends.len() != starts.len() || axes.len() != starts.len() || steps.len() != starts.len() {
+        return None;
+    }
+    let rank = axes.iter().max().map_or(0, |&a| a as usize + 1);
+    let mut ret = vec![ndarray::SliceOrIndex::from(..); rank];
+    for i in 0..starts.len() {
+        // Negative axes need the rank, and ndarray and ONNX differ in negative steps.
+        if axes[i] < 0 || steps[i] <= 0 {
+            return None;
+        }
+        // Ends beyond the dims mean "to the end".
+        let end = if ends[i] >= i64::from(i32::MAX) {
+            None
+        } else {
+            Some(ends[i] as isize)
+        };
+        let slice = ndarray::Slice::new(starts[i] as isize, end, steps[i] as isize);
+        ret[axes[i] as usize] = ndarray::SliceOrIndex::from(slice);
+    }
+    Some(ret)
+}
+
+impl<F: Float> Graph<F> {
+    /// Imports an ONNX model into this graph.
+    ///
+    /// The inputs of the model become placeholders, the floating-point initializers become
+    /// variables, so the model can be fine-tuned with `grad` and the optimizers, and the other
+    /// initializers become constants. All values are of `F`.
+    ///
+    /// Supported are Gemm, MatMul, Conv, ConvTranspose, MaxPool, GlobalAveragePool,
+    /// activations (e.g. Relu, Sigmoid, Tanh, Softmax), elementwise math, reductions,
+    /// Reshape, Flatten, Concat, Gather, Transpose, Slice, Squeeze and Unsqueeze of the default
+    /// ONNX domain, which covers the models exported by [to_onnx](#method.to_onnx).
+    /// Convolutions and pooling need the same padding, stride and dilation in all the spatial
+    /// axes. Others fail with `SerializationError::UnsupportedOp`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor::Variable;
+    ///
+    /// let mut model = Vec::new();
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.placeholder(&[-1, 4]);
+    ///     let w = g.variable(ag::ndarray_ext::ones(&[4, 3]));
+    ///     let y = g.softmax(g.relu(g.matmul(x, w)), 1);
+    ///     model = g.to_onnx(&[y]).unwrap();
+    ///     // Or g.load_onnx("model.onnx")
+    /// });
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let model = g.import_onnx(&model).unwrap();
+    ///     let (x, y) = (model.inputs[0].1, model.outputs[0].1);
+    ///     let ret = y.eval(&[x.given(ag::ndarray_ext::zeros(&[2, 4]).view())]);
+    ///     assert_eq!(ret.unwrap().shape(), &[2, 3]);
+    ///
+    ///     // Fine-tunes the weights.
+    ///     let vars: Vec<_> = model.variables.iter().map(|v| v.1).collect();
+    ///     let grads = g.grad(&[g.reduce_sum_to_scalar(y)], &vars);
+    ///     assert_eq!(grads.len(), 1);
+    /// });
+    /// ```
+    pub fn import_onnx<'g>(&'g self, model: &[u8]) -> Result<OnnxModel<'g, F>, SerializationError> {
+        let (graph, opset) = parse_model::<F>(model).map_err(invalid)?;
+        let mut importer = Importer {
+            graph: self,
+            opset,
+            tensors: FxHashMap::default(),
+            constants: FxHashMap::default(),
+            trainable: Vec::new(),
+        };
+        for init in graph.initializers {
+            if init.data_type == FLOAT || init.data_type == DOUBLE {
+                importer.trainable.push(init.name.clone());
+            }
+            importer.constants.insert(init.name, init.array);
+        }
+
+        let mut inputs = Vec::new();
+        for input in graph.inputs {
+            // Older models list the initializers in the inputs too.
+            if importer.constants.contains_key(&input.name) {
+                continue;
+            }
+            let x = match input.shape {
+                Some(ref shape) => self.placeholder(shape),
+                None => Tensor::builder()
+                    .set_is_placeholder(true)
+                    .build(self, Placeholder),
+            };
+            importer.tensors.insert(input.name.clone(), x);
+            inputs.push((input.name, x));
+        }
+
+        for node in &graph.nodes {
+            let start = self.num_nodes();
+            importer.import_node(node)?;
+            // The tensors built by the graph's ops keep their shape errors.
+            let error =
+                (start..self.num_nodes()).find_map(|i| self.access_node(i).build_error.clone());
+            if let Some(e) = error {
+                return Err(invalid(format!("{}: {}", node.op_type, e)));
+            }
+        }
+
+        let mut outputs = Vec::new();
+        for name in graph.outputs {
+            let y = importer.tensor(&name)?;
+            outputs.push((name, y));
+        }
+        let variables = importer
+            .trainable
+            .iter()
+            .filter_map(|name| Some((name.clone(), *importer.tensors.get(name)?)))
+            .collect();
+        Ok(OnnxModel {
+            inputs,
+            outputs,
+            variables,
+        })
+    }
+
+    /// Imports the ONNX model at `path` into this graph.
+    ///
+    /// See [import_onnx](#method.import_onnx).
+    pub fn load_onnx<'g, P: AsRef<Path>>(
+        &'g self,
+        path: P,
+    ) -> Result<OnnxModel<'g, F>, SerializationError> {
+        self.import_onnx(&std::fs::read(path)?)
+    }
+}
+
+#[test]
+fn test_import_onnx() {
+    use crate::ndarray_ext::ArrayRng;
+    let rng = ArrayRng::<f32>::default();
+    let x_value = rng.standard_normal(&[2, 1, 4, 4]);
+    let w_value = rng.standard_normal(&[2, 1, 3, 3]);
+    let v_value = rng.standard_normal(&[6, 3]);
+    let (mut model, mut expected) = (Vec::new(), None);
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let x = g.placeholder(&[-1, 1, 4, 4]);
+        let w = g.variable(w_value);
+        let h = g.max_pool2d(g.relu(g.conv2d(x, w, 1, 1)), 2, 0, 2);
+        let h = g.slice(g.reshape(h, &[-1, 8]), &[0, 1], &[-1, 7]);
+        let v = g.variable(v_value);
+        let y = g.softmax(g.sigmoid(g.matmul(h, v)), 1);
+        expected = Some(y.eval(&[x.given(x_value.view())]).unwrap());
+        model = g.to_onnx(&[y]).unwrap();
+    });
+
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let model = g.import_onnx(&model).unwrap();
+        assert_eq!(model.inputs.len(), 1);
+        assert_eq!(model.outputs.len(), 1);
+        assert_eq!(model.variables.len(), 2);
+        let (x, y) = (model.inputs[0].1, model.outputs[0].1);
+        let ret = y.eval(&[x.given(x_value.view())]).unwrap();
+        assert!(ret.all_close(expected.as_ref().unwrap(), 1e-5));
+
+        let vars: Vec<_> = model.variables.iter().map(|v| v.1).collect();
+        let grads = g.grad(&[g.reduce_sum_to_scalar(y)], &vars);
+        let rets = g.eval(&grads, &[x.given(x_value.view())]);
+        assert!(rets.iter().all(|a| a.is_ok()));
+
+        match g.import_onnx(&[0x0a]) {
+            Err(SerializationError::InvalidModel(_)) => {}
+            _ => panic!("expected InvalidModel"),
+        }
+    });
+}
+
+#[test]
+fn test_import_pow() {
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let mut importer = Importer {
+            graph: g,
+            opset: 13,
+            tensors: FxHashMap::default(),
+            constants: FxHashMap::default(),
+            trainable: Vec::new(),
+        };
+        importer.tensors.insert(
+            "x".to_string(),
+            g.convert_to_tensor(ndarray::arr1(&[-2., 3.]).into_dyn()),
+        );
+        importer
+            .constants
+            .insert("two".to_string(), ndarray::arr0(2.).into_dyn());
+        let pow = |a: &str, b: &str, y: &str| Node {
+            op_type: "Pow".to_string(),
+            inputs: vec![a.to_string(), b.to_string()],
+            outputs: vec![y.to_string()],
+            attributes: FxHashMap::default(),
+        };
+
+        importer.import_node(&pow("x", "two", "square")).unwrap();
+        importer.import_node(&pow("two", "x", "exp2")).unwrap();
+        let square = importer.tensor("square").unwrap().eval(&[]).unwrap();
+        assert!(square.all_close(&ndarray::arr1(&[4., 9.]).into_dyn(), 1e-5));
+        let exp2 = importer.tensor("exp2").unwrap().eval(&[]).unwrap();
+        assert!(exp2.all_close(&ndarray::arr1(&[0.25, 8.]).into_dyn(), 1e-5));
+
+        match importer.import_node(&pow("x", "x", "y")) {
+            Err(SerializationError::UnsupportedOp(op)) => assert_eq!(op, "Pow"),
+            _ => panic!("expected UnsupportedOp"),
+        }
+    });
+}
+
+#[test]
+fn test_import_cast() {
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let mut importer = Importer {
+            graph: g,
+            opset: 13,
+            tensors: FxHashMap::default(),
+            constants: FxHashMap::default(),
+            trainable: Vec::new(),
+        };
+        let x = g.convert_to_tensor(ndarray::arr1(&[-1.5, 0., 2.7]).into_dyn());
+        importer.tensors.insert("x".to_string(), x);
+        let node = |op_type: &str, x: &str, to: i64, y: &str| {
+            let mut attributes = FxHashMap::default();
+            attributes.insert("to".to_string(), NodeAttribute::Int(to));
+            Node {
+                op_type: op_type.to_string(),
+                inputs: vec![x.to_string()],
+                outputs: vec![y.to_string()],
+                attributes,
+            }
+        };
+
+        importer
+            .import_node(&node("Cast", "x", INT64, "i"))
+            .unwrap();
+        importer.import_node(&node("Cast", "x", BOOL, "m")).unwrap();
+        importer.import_node(&node("Relu", "m", 0, "r")).unwrap();
+        let i = importer.tensor("i").unwrap();
+        let m = importer.tensor("m").unwrap();
+        assert_eq!(i.eval_int(&[]), Ok(ndarray::arr1(&[-1, 0, 2]).into_dyn()));
+        assert_eq!(
+            m.eval_bool(&[]),
+            Ok(ndarray::arr1(&[true, false, true]).into_dyn())
+        );
+        // The other ops take the values as floats.
+        let r = importer.tensor("r").unwrap();
+        assert_eq!(r.eval(&[]), Ok(ndarray::arr1(&[1., 0., 1.]).into_dyn()));
+    });
+}
+
+#[test]
+fn test_import_incompatible_shapes() {
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let mut importer = Importer {
+            graph: g,
+            opset: 13,
+            tensors: FxHashMap::default(),
+            constants: FxHashMap::default(),
+            trainable: Vec::new(),
+        };
+        importer
+            .constants
+            .insert("a".to_string(), crate::ndarray_ext::ones(&[4, 2]));
+        importer
+            .constants
+            .insert("b".to_string(), crate::ndarray_ext::ones(&[3, 2]));
+        let node = Node {
+            op_type: "MatMul".to_string(),
+            inputs: vec!["a".to_string(), "b".to_string()],
+            outputs: vec!["y".to_string()],
+            attributes: FxHashMap::default(),
+        };
+        match importer.import_node(&node) {
+            Err(SerializationError::InvalidModel(message)) => {
+                assert!(message.starts_with("MatMul"))
+            }
+            _ => panic!("expected InvalidModel"),
+        }
+    });
+}
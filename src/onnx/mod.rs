@@ -0,0 +1,51 @@
+//! Defining the interchange of graphs in the [ONNX](https://onnx.ai) format.
+//!
+//! See [Graph::to_onnx](../struct.Graph.html#method.to_onnx) and
+//! [Graph::import_onnx](../struct.Graph.html#method.import_onnx).
+use crate::tensor::DType;
+use crate::Float;
+
+mod export;
+mod import;
+mod proto;
+
+pub use self::import::OnnxModel;
+
+const IR_VERSION: i64 = 8;
+const OPSET_VERSION: i64 = 18;
+
+// `TensorProto.DataType`
+const FLOAT: i64 = 1;
+const UINT8: i64 = 2;
+const INT8: i64 = 3;
+const UINT16: i64 = 4;
+const INT16: i64 = 5;
+const INT32: i64 = 6;
+const INT64: i64 = 7;
+const BOOL: i64 = 9;
+const DOUBLE: i64 = 11;
+const UINT32: i64 = 12;
+const UINT64: i64 = 13;
+
+// ONNX data type of `F`
+fn data_type<F: Float>() -> i64 {
+    if crate::same_type::<F, f32>() {
+        FLOAT
+    } else {
+        DOUBLE
+    }
+}
+
+// ONNX data type of the tensors of `dtype`
+fn tensor_data_type<F: Float>(dtype: DType) -> i64 {
+    match dtype {
+        DType::Float => data_type::<F>(),
+        DType::Int => INT64,
+        DType::Bool => BOOL,
+    }
+}
+
+// Name of the value of a tensor in ONNX graphs
+fn tensor_name(id: usize) -> String {
+    format!("t{}", id)
+}
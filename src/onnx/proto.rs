@@ -0,0 +1,272 @@
+//! Minimal encoder and decoder of the protobuf wire format.
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const FIXED32: u64 = 5;
+const LENGTH_DELIMITED: u64 = 2;
+
+/// Encoder of a protobuf message.
+#[derive(Default)]
+pub(crate) struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.varint(field << 3 | wire_type);
+    }
+
+    /// Writes an `int32`, `int64` or enum field.
+    pub(crate) fn int(&mut self, field: u64, v: i64) {
+        self.key(field, VARINT);
+        self.varint(v as u64);
+    }
+
+    /// Writes a `float` field.
+    pub(crate) fn float(&mut self, field: u64, v: f32) {
+        self.key(field, FIXED32);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    /// Writes a `bytes` field.
+    pub(crate) fn bytes(&mut self, field: u64, v: &[u8]) {
+        self.key(field, LENGTH_DELIMITED);
+        self.varint(v.len() as u64);
+        self.buf.extend_from_slice(v);
+    }
+
+    /// Writes a `string` field.
+    pub(crate) fn string(&mut self, field: u64, v: &str) {
+        self.bytes(field, v.as_bytes());
+    }
+
+    /// Writes an embedded message field built by `f`.
+    pub(crate) fn message<C: FnOnce(&mut Writer)>(&mut self, field: u64, f: C) {
+        let mut w = Writer::new();
+        f(&mut w);
+        self.bytes(field, &w.buf);
+    }
+}
+
+/// Value of a field read by `Reader`.
+#[derive(Clone, Copy)]
+pub(crate) enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Value<'a> {
+    /// Reads an `int32`, `int64` or enum field.
+    pub(crate) fn int(self) -> Result<i64, String> {
+        match self {
+            Value::Varint(v) => Ok(v as i64),
+            _ => Err("expected a varint".to_string()),
+        }
+    }
+
+    /// Reads a `float` field.
+    pub(crate) fn float(self) -> Result<f32, String> {
+        match self {
+            Value::Fixed32(v) => Ok(f32::from_bits(v)),
+            _ => Err("expected a float".to_string()),
+        }
+    }
+
+    /// Reads a `bytes` field or an embedded message.
+    pub(crate) fn bytes(self) -> Result<&'a [u8], String> {
+        match self {
+            Value::Bytes(v) => Ok(v),
+            _ => Err("expected a length-delimited field".to_string()),
+        }
+    }
+
+    /// Reads a `string` field.
+    pub(crate) fn string(self) -> Result<String, String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|e| e.to_string())
+    }
+
+    /// Appends the elements of a repeated `int32` or `int64` field, packed or not.
+    pub(crate) fn ints(self, out: &mut Vec<i64>) -> Result<(), String> {
+        if let Value::Bytes(v) = self {
+            let mut r = Reader::new(v);
+            while !r.is_empty() {
+                out.push(r.varint()? as i64);
+            }
+        } else {
+            out.push(self.int()?);
+        }
+        Ok(())
+    }
+
+    /// Appends the elements of a repeated `float` field, packed or not.
+    pub(crate) fn floats(self, out: &mut Vec<f32>) -> Result<(), String> {
+        if let Value::Bytes(v) = self {
+            let chunks = fixed_size_chunks(v, 4)?;
+            out.extend(chunks.map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])));
+        } else {
+            out.push(self.float()?);
+        }
+        Ok(())
+    }
+
+    /// Appends the elements of a repeated `double` field, packed or not.
+    pub(crate) fn doubles(self, out: &mut Vec<f64>) -> Result<(), String> {
+        match self {
+            Value::Bytes(v) => {
+                let chunks = fixed_size_chunks(v, 8)?;
+                out.extend(
+                    chunks.map(|c| {
+                        f64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]])
+                    }),
+                );
+            }
+            Value::Fixed64(v) => out.push(f64::from_bits(v)),
+            _ => return Err("expected a double".to_string()),
+        }
+        Ok(())
+    }
+}
+
+// Splits packed fixed-size values.
+fn fixed_size_chunks(v: &[u8], size: usize) -> Result<std::slice::ChunksExact<'_, u8>, String> {
+    let chunks = v.chunks_exact(size);
+    if chunks.remainder().is_empty() {
+        Ok(chunks)
+    } else {
+        Err("truncated packed field".to_string())
+    }
+}
+
+/// Decoder of a protobuf message, which iterates over `(field number, value)`.
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Reader { buf }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if n > self.buf.len() {
+            return Err("unexpected end of message".to_string());
+        }
+        let (ret, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(ret)
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut ret = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.take(1)?[0];
+            ret |= u64::from(b & 0x7f) << shift;
+            if b < 0x80 {
+                return Ok(ret);
+            }
+        }
+        Err("malformed varint".to_string())
+    }
+
+    fn field(&mut self) -> Result<(u64, Value<'a>), String> {
+        let key = self.varint()?;
+        let value = match key & 7 {
+            VARINT => Value::Varint(self.varint()?),
+            FIXED64 => {
+                let b = self.take(8)?;
+                Value::Fixed64(u64::from_le_bytes([
+                    b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+                ]))
+            }
+            FIXED32 => {
+                let b = self.take(4)?;
+                Value::Fixed32(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            }
+            LENGTH_DELIMITED => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            t => return Err(format!("unsupported wire type {}", t)),
+        };
+        Ok((key >> 3, value))
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<(u64, Value<'a>), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_empty() {
+            return None;
+        }
+        let ret = self.field();
+        if ret.is_err() {
+            // Stops at the first error.
+            self.buf = &[];
+        }
+        Some(ret)
+    }
+}
+
+#[test]
+fn test_writer() {
+    let mut w = Writer::new();
+    w.int(1, 150);
+    w.string(2, "testing");
+    w.int(3, -1);
+    assert_eq!(
+        w.into_bytes(),
+        [
+            &[0x08, 0x96, 0x01][..],
+            &[0x12, 0x07, b't', b'e', b's', b't', b'i', b'n', b'g'],
+            &[0x18, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        ]
+        .concat()
+    );
+}
+
+#[test]
+fn test_reader() {
+    let mut w = Writer::new();
+    w.int(1, -1);
+    w.message(2, |w| {
+        w.string(1, "testing");
+        w.float(2, 0.5);
+    });
+    w.bytes(3, &[0x03, 0x8e, 0x02]);
+    let buf = w.into_bytes();
+    let fields = Reader::new(&buf).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(fields.len(), 3);
+    assert_eq!((fields[0].0, fields[0].1.int()), (1, Ok(-1)));
+    let inner = Reader::new(fields[1].1.bytes().unwrap())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(inner[0].1.string(), Ok("testing".to_string()));
+    assert_eq!(inner[1].1.float(), Ok(0.5));
+    let mut ints = Vec::new();
+    fields[2].1.ints(&mut ints).unwrap();
+    assert_eq!(ints, [3, 270]);
+    assert!(Reader::new(&buf[..buf.len() - 1]).any(|f| f.is_err()));
+}
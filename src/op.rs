@@ -1,12 +1,16 @@
 //! Defining things related to `ag::op::Op`.
 //!
+use crate::ndarray_ext::{BoolArray, BoolArrayView, IntArray, IntArrayView};
 use crate::ndarray_ext::{NdArrayView, NdArrayViewMut};
+pub use crate::ops::fused_ops::Elementwise;
+pub use crate::serialization::Attribute;
 use crate::smallvec::SmallVec;
-use crate::tensor::{Tensor, TensorInternal};
-use crate::{Float, NdArray};
+use crate::tensor::{DType, Input, Tensor, TensorInternal};
+use crate::{Float, Graph, NdArray};
 use std::any::type_name;
 use std::fmt;
 use std::marker::PhantomData;
+use std::mem;
 
 // Properties for op's `compute` method.
 // Actual number of inout/output nodes are around 1~2 in most cases.
@@ -16,7 +20,14 @@ pub(crate) const NUM_MAX_INPUT: usize = 4;
 pub(crate) type InputArray<T> = SmallVec<[T; NUM_MAX_INPUT]>;
 pub(crate) type OutputArray<T> = SmallVec<[T; NUM_MAX_OUTPUT]>;
 
-pub(crate) type ComputeResult<'v, T> = Result<crate::ArrRepr<'v, T>, OpError>;
+pub(crate) type ComputeResult<'v, T> = Result<OpOutput<'v, T>, OpError>;
+
+// An output array of `Op::compute`.
+pub(crate) enum OpOutput<'v, T: Float> {
+    Float(crate::ArrRepr<'v, T>),
+    Int(IntArray),
+    Bool(BoolArray),
+}
 
 /// Error in `Op`'s computation.
 #[derive(Clone, Debug, PartialEq)]
@@ -26,6 +37,7 @@ pub enum OpError {
     TypeUnsupported(String),
     InvalidDims(String),
     OutOfBounds(String),
+    Unsupported(String),
 }
 
 impl std::error::Error for OpError {}
@@ -34,14 +46,24 @@ impl fmt::Display for OpError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             OpError::NdArrayError(pref, e) => write!(f, "{}: ", pref).and_then(|()| e.fmt(f)),
-            OpError::IncompatibleShape(s) => write!(f, "{}: ", s),
-            OpError::TypeUnsupported(s) => write!(f, "{}: ", s),
-            OpError::InvalidDims(s) => write!(f, "{}: ", s),
-            OpError::OutOfBounds(s) => write!(f, "{}: ", s),
+            OpError::IncompatibleShape(s) => write!(f, "{}", s),
+            OpError::TypeUnsupported(s) => write!(f, "{}", s),
+            OpError::InvalidDims(s) => write!(f, "{}", s),
+            OpError::OutOfBounds(s) => write!(f, "{}", s),
+            OpError::Unsupported(s) => write!(f, "{}", s),
         }
     }
 }
 
+// Op name without the module path, e.g. `MulOp` for `autograd::ops::binary_ops::MulOp`.
+pub(crate) fn short_op_name(name: &str) -> &str {
+    let path_end = name.find('<').unwrap_or(name.len());
+    match name[..path_end].rfind("::") {
+        Some(i) => &name[i + 2..],
+        None => name,
+    }
+}
+
 /// Sequence of an op's compute result.
 ///
 /// Op can have multiple output NdArrays.
@@ -49,6 +71,9 @@ pub(crate) type Results<'v, T> = OutputArray<Option<ComputeResult<'v, T>>>;
 
 /// Operation trait. `Tensor` wraps trait-object of this.
 ///
+/// Ops are `Send + Sync` since parallel evaluations (see `Eval::parallel`) compute the nodes
+/// on worker threads.
+///
 /// # Implementing differentiable operations
 ///
 /// Many of well-known ops are pre-defined in `Graph`, but you can also
@@ -95,7 +120,7 @@ pub(crate) type Results<'v, T> = OutputArray<Option<ComputeResult<'v, T>>>;
 ///            .build(g, Sigmoid)
 /// }
 /// ```
-pub trait Op<F: Float> {
+pub trait Op<F: Float>: Send + Sync {
     /// Name of this op
     fn name(&self) -> &str {
         type_name::<Self>()
@@ -106,6 +131,107 @@ pub trait Op<F: Float> {
 
     /// Returns symbolic gradients for input nodes by use of output's gradients etc.
     fn grad(&self, ctx: &mut GradientContext<F>);
+
+    /// Returns the symbolic tangents of the outputs from those of the inputs, for the
+    /// forward-mode differentiation (see `Graph::jvp`).
+    ///
+    /// The default derives the tangent of the first output from `Op::grad`, by differentiating
+    /// the input gradients, which are linear in the output gradient, with respect to
+    /// the output gradient. Implementing this directly is much cheaper.
+    fn jvp(&self, ctx: &mut JvpContext<F>) {
+        jvp_by_grad(self, ctx)
+    }
+
+    /// Returns the output for inputs having an extra leading batch axis, which is how
+    /// `Graph::jacobians` pushes a batch of one-hot (co)tangents through a single graph.
+    ///
+    /// The default keeps the ops whose outputs are determined by the input shapes
+    /// (see `Op::determinism`) as they are, rebuilds elementwise ops (see `Op::elementwise`) on
+    /// the batched inputs, and otherwise computes this op for each slice of the batch in a
+    /// single node. Implementing this with batched ops is much faster for the latter.
+    fn vmap(&self, ctx: &mut VmapContext<F>) {
+        vmap_by_default(self, ctx)
+    }
+
+    /// Returns true if this op can write its output into the buffer of the `i` th input.
+    ///
+    /// When this returns true and nobody else reads the input array any more, the runtime
+    /// allows `Op::compute` to take it over with `ComputeContext::input_owned`.
+    fn supports_in_place(&self, _i: usize) -> bool {
+        false
+    }
+
+    /// Returns what determines the outputs of this op.
+    ///
+    /// `Graph::fold_constants` evaluates ahead of time the nodes whose outputs are determined
+    /// by constants. The default is `Determinism::Unknown`, which is always safe.
+    fn determinism(&self) -> Determinism {
+        Determinism::Unknown
+    }
+
+    /// Returns a string identifying the parameters of this op, if any two nodes of this op
+    /// with the same parameters and inputs always produce the same outputs.
+    ///
+    /// `Graph::eliminate_common_subexpressions` merges such nodes. The default is `None`, which
+    /// means the op is never merged (e.g. random ops).
+    fn cse_key(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the elementwise function that this op computes, if any.
+    ///
+    /// `Graph::fuse_elementwise_ops` fuses chains of such ops into single-pass kernels.
+    fn elementwise(&self) -> Option<Elementwise<F>> {
+        None
+    }
+
+    /// Returns the parameters of this op, from which the constructor registered in
+    /// `serialization::OpRegistry` rebuilds it.
+    ///
+    /// `Graph::save` requires this for all the ops to save. The default is `None`, which
+    /// means the op can't be serialized.
+    fn attributes(&self) -> Option<Vec<Attribute<F>>> {
+        None
+    }
+
+    /// Infers the shape of the output from the static shapes of the inputs, using `-1` for
+    /// unknown dims.
+    ///
+    /// This is called when the tensor is built, and the result is available as
+    /// `Tensor::static_shape`. `Ok(None)` means that even the rank is unknown, and an error
+    /// means that the input shapes are incompatible, which is returned by
+    /// `TensorBuilder::try_build` and by the evaluations of the tensor.
+    /// The default infers the shapes of elementwise ops (see `Op::elementwise`), and nothing
+    /// for the others.
+    fn infer_shape(&self, ctx: &ShapeContext<F>) -> Result<Option<Vec<isize>>, OpError> {
+        match self.elementwise() {
+            Some(ref f) if f.arity() == 1 => Ok(ctx.input_shape(0).map(|s| s.to_vec())),
+            Some(_) => ctx.broadcast_input_shapes(),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the dtype of the outputs from those of the inputs (see `DType`).
+    ///
+    /// This is called when the tensor is built, and an error means that the op doesn't
+    /// accept the input dtypes, which is reported like that of `infer_shape`. The default
+    /// accepts only float inputs and outputs float arrays.
+    fn output_dtype(&self, ctx: &ShapeContext<F>) -> Result<DType, OpError> {
+        ctx.check_input_dtypes(&[]).map(|()| DType::Float)
+    }
+}
+
+/// What determines the outputs of an op.
+///
+/// See `Op::determinism`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Determinism {
+    /// Nothing is guaranteed, e.g. random ops or ops with side effects.
+    Unknown,
+    /// The outputs are determined by the input arrays.
+    InputValues,
+    /// The outputs are determined by the shapes of the input arrays.
+    InputShapes,
 }
 
 pub(crate) struct DummyOp<F: Float> {
@@ -134,6 +260,13 @@ pub(crate) enum OpInput<'v, T: Float> {
     RO(Option<NdArrayView<'v, T>>),
     /// Read-write view
     RW(Option<NdArrayViewMut<'v, T>>),
+    /// Read-only view of an array which is not used by others.
+    /// The op can take over the array itself through the pointer.
+    Reusable(Option<NdArrayView<'v, T>>, *mut NdArray<T>),
+    /// Read-only view of an integer array
+    Int(Option<IntArrayView<'v>>),
+    /// Read-only view of a boolean array
+    Bool(Option<BoolArrayView<'v>>),
 }
 
 impl<'v, T: Float> OpInput<'v, T> {
@@ -148,6 +281,33 @@ impl<'v, T: Float> OpInput<'v, T> {
     pub fn new_mut(x: NdArrayViewMut<'v, T>) -> Self {
         OpInput::RW(Some(x))
     }
+
+    #[inline]
+    /// Make a read-only input array which can be taken over by the op
+    ///
+    /// `arr` must be valid and not be accessed by others while the op is computed.
+    pub unsafe fn new_reusable(arr: *mut NdArray<T>) -> Self {
+        OpInput::Reusable(Some((*arr).view()), arr)
+    }
+
+    #[inline]
+    /// Returns a read-only view of the float array unless the op has taken it
+    pub(crate) fn view(&self) -> Option<NdArrayView<'_, T>> {
+        match self {
+            OpInput::RO(a) | OpInput::Reusable(a, _) => a.as_ref().map(|a| a.view()),
+            OpInput::RW(a) => a.as_ref().map(|a| a.view()),
+            OpInput::Int(_) | OpInput::Bool(_) => None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn dtype(&self) -> DType {
+        match self {
+            OpInput::Int(_) => DType::Int,
+            OpInput::Bool(_) => DType::Bool,
+            _ => DType::Float,
+        }
+    }
 }
 
 /// Context of an `Op`'s computation phase.
@@ -178,10 +338,14 @@ impl<'v, T: Float> OpInput<'v, T> {
 /// ```
 pub struct ComputeContext<'k, 'v, T: Float> {
     node: &'k TensorInternal<T>,
+    // Graph of the node, which owns the buffer pool for the outputs
+    graph: &'k Graph<T>,
     // Input arrays
     xs: InputArray<OpInput<'v, T>>,
     // Output arrays
     ys: Results<'v, T>,
+    // Error of a subgraph evaluated by a control flow op, which is not an `OpError`
+    subgraph_error: Option<crate::EvalError>,
 }
 
 impl<'g, 't, 'v, T: Float> ComputeContext<'t, 'v, T> {
@@ -189,12 +353,41 @@ impl<'g, 't, 'v, T: Float> ComputeContext<'t, 'v, T> {
         self.ys
     }
 
+    // Takes all the input arrays, e.g. to compute another op with them.
+    #[inline]
+    pub(crate) fn take_inputs(&mut self) -> InputArray<OpInput<'v, T>> {
+        mem::take(&mut self.xs)
+    }
+
+    // Fails the op with an error of its subgraph.
     #[inline]
-    pub(crate) fn new(node: &'t TensorInternal<T>, xs: InputArray<OpInput<'v, T>>) -> Self {
+    pub(crate) fn set_subgraph_error(&mut self, e: crate::EvalError) {
+        self.subgraph_error = Some(e);
+    }
+
+    #[inline]
+    pub(crate) fn take_subgraph_error(&mut self) -> Option<crate::EvalError> {
+        self.subgraph_error.take()
+    }
+
+    // Graph of the node, e.g. for evaluating the subgraphs of control flow ops.
+    #[inline]
+    pub(crate) fn graph(&self) -> &'t Graph<T> {
+        self.graph
+    }
+
+    #[inline]
+    pub(crate) fn new(
+        node: &'t TensorInternal<T>,
+        graph: &'t Graph<T>,
+        xs: InputArray<OpInput<'v, T>>,
+    ) -> Self {
         ComputeContext {
             node,
+            graph,
             xs,
             ys: OutputArray::new(),
+            subgraph_error: None,
         }
     }
 
@@ -208,7 +401,7 @@ impl<'g, 't, 'v, T: Float> ComputeContext<'t, 'v, T> {
             None => panic!("Bad op impl: input index out of range."),
         };
         match x {
-            OpInput::RO(ref mut a) => match a.take() {
+            OpInput::RO(ref mut a) | OpInput::Reusable(ref mut a, _) => match a.take() {
                 Some(ret) => ret,
                 None => panic!(
                     "Bad op impl of {}: input({})/input_mut({}) cannot be called twice",
@@ -217,6 +410,11 @@ impl<'g, 't, 'v, T: Float> ComputeContext<'t, 'v, T> {
                     i
                 ),
             },
+            OpInput::Int(_) | OpInput::Bool(_) => panic!(
+                "Bad op impl of {}: input({}) is not a float array",
+                self.node.op.name(),
+                i
+            ),
             _ => {
                 panic!(
                     "Bad op impl of {}: cannot perform immutable borrowing for input({})",
@@ -256,12 +454,118 @@ impl<'g, 't, 'v, T: Float> ComputeContext<'t, 'v, T> {
         }
     }
 
+    /// Returns the dtype of the `i` th input array.
+    #[inline]
+    pub fn input_dtype(&self, i: usize) -> DType {
+        match self.xs.get(i) {
+            Some(x) => x.dtype(),
+            None => panic!("Bad op impl: input index out of range."),
+        }
+    }
+
+    /// Grabs the `i` th input array of `DType::Int`.
+    ///
+    /// Calling `input_int(i)` more than once causes panic.
+    #[inline]
+    pub fn input_int(&mut self, i: usize) -> IntArrayView<'v> {
+        match self.xs.get_mut(i) {
+            Some(OpInput::Int(ref mut a)) => a.take().unwrap_or_else(|| {
+                panic!(
+                    "Bad op impl of {}: input_int({}) cannot be called twice",
+                    self.node.op.name(),
+                    i
+                )
+            }),
+            _ => panic!(
+                "Bad op impl of {}: input({}) is not an integer array",
+                self.node.op.name(),
+                i
+            ),
+        }
+    }
+
+    /// Grabs the `i` th input array of `DType::Bool`.
+    ///
+    /// Calling `input_bool(i)` more than once causes panic.
+    #[inline]
+    pub fn input_bool(&mut self, i: usize) -> BoolArrayView<'v> {
+        match self.xs.get_mut(i) {
+            Some(OpInput::Bool(ref mut a)) => a.take().unwrap_or_else(|| {
+                panic!(
+                    "Bad op impl of {}: input_bool({}) cannot be called twice",
+                    self.node.op.name(),
+                    i
+                )
+            }),
+            _ => panic!(
+                "Bad op impl of {}: input({}) is not a boolean array",
+                self.node.op.name(),
+                i
+            ),
+        }
+    }
+
+    /// Grabs the `i` th input array of indices, which is either an integer or a float array.
+    ///
+    /// Float elements are truncated to integers. This is for the ops accepting
+    /// both kinds of indices (see `ShapeContext::check_input_dtypes`).
+    pub fn input_as_ints(&mut self, i: usize) -> IntArray {
+        match self.input_dtype(i) {
+            DType::Int => self.input_int(i).to_owned(),
+            _ => self.input(i).mapv(|a| a.to_i64().unwrap()),
+        }
+    }
+
+    /// Takes over the `i` th input array if the runtime allows it.
+    ///
+    /// Returns `None` unless `Op::supports_in_place(i)` is true and the input array is not
+    /// used by any other op, in which case `input(i)` should be used instead.
+    /// Calling `input(i)` after this returned an array causes panic.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// struct Double;
+    ///
+    /// impl<T: ag::Float> ag::op::Op<T> for Double {
+    ///     fn compute(&self, ctx: &mut ag::op::ComputeContext<T>) {
+    ///         let y = match ctx.input_owned(0) {
+    ///             Some(mut x) => {
+    ///                 x.mapv_inplace(|a| a + a);
+    ///                 x
+    ///             }
+    ///             None => ctx.input(0).mapv(|a| a + a),
+    ///         };
+    ///         ctx.append_output(y);
+    ///     }
+    ///
+    ///     fn grad(&self, ctx: &mut ag::op::GradientContext<T>) { /* ... */ }
+    ///
+    ///     fn supports_in_place(&self, _i: usize) -> bool {
+    ///         true
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn input_owned(&mut self, i: usize) -> Option<NdArray<T>> {
+        match self.xs.get_mut(i) {
+            Some(OpInput::Reusable(ref mut a, arr)) if a.is_some() => {
+                // Drop the view before moving the array out.
+                *a = None;
+                let empty = NdArray::zeros(ndarray::IxDyn(&[0]));
+                Some(unsafe { mem::replace(&mut **arr, empty) })
+            }
+            _ => None,
+        }
+    }
+
     /// Appends an `ndarray::ArrayView` to the back of the output list of the current op.
     ///
     /// NOTE: Implementor of `Op::compute` must not forget to call `append_*` as many as the number of its output in `Op::compute`, otherwise panic occurs.
     #[inline]
     pub fn append_output_view(&mut self, y: NdArrayView<'v, T>) {
-        self.ys.push(Some(Ok(crate::ArrRepr::View(y))));
+        self.ys
+            .push(Some(Ok(OpOutput::Float(crate::ArrRepr::View(y)))));
     }
 
     /// Appends an ndarray to the back of the output list of the current op.
@@ -269,7 +573,24 @@ impl<'g, 't, 'v, T: Float> ComputeContext<'t, 'v, T> {
     /// NOTE: Implementor of `Op::compute` must not forget to call `append_*` as many as the number of its output in `Op::compute`, otherwise panic occurs.
     #[inline]
     pub fn append_output(&mut self, y: NdArray<T>) {
-        self.ys.push(Some(Ok(crate::ArrRepr::Owned(y))));
+        self.ys
+            .push(Some(Ok(OpOutput::Float(crate::ArrRepr::Owned(y)))));
+    }
+
+    /// Appends an integer array to the back of the output list of the current op.
+    ///
+    /// The op's `Op::output_dtype` must be `DType::Int`.
+    #[inline]
+    pub fn append_output_int(&mut self, y: IntArray) {
+        self.ys.push(Some(Ok(OpOutput::Int(y))));
+    }
+
+    /// Appends a boolean array to the back of the output list of the current op.
+    ///
+    /// The op's `Op::output_dtype` must be `DType::Bool`.
+    #[inline]
+    pub fn append_output_bool(&mut self, y: BoolArray) {
+        self.ys.push(Some(Ok(OpOutput::Bool(y))));
     }
 
     /// Appends an empty result to the back of the output list of the current op.
@@ -295,6 +616,207 @@ impl<'g, 't, 'v, T: Float> ComputeContext<'t, 'v, T> {
     pub fn num_inputs(&self) -> usize {
         self.xs.len()
     }
+
+    /// Allocates a c-order array of the given shape for an output of the current op.
+    ///
+    /// The buffer is recycled from the arrays freed in the previous (or current) evaluations
+    /// of the graph if possible, so the contents of the returned array are unspecified.
+    /// Implementor of `Op::compute` must overwrite all of its elements.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ndarray::Zip;
+    ///
+    /// struct Double;
+    ///
+    /// impl<T: ag::Float> ag::op::Op<T> for Double {
+    ///     fn compute(&self, ctx: &mut ag::op::ComputeContext<T>) {
+    ///         let x = ctx.input(0);
+    ///         let mut y = ctx.alloc_output(x.shape());
+    ///         Zip::from(&mut y).and(&x).apply(|y, &x| *y = x + x);
+    ///         ctx.append_output(y);
+    ///     }
+    ///
+    ///     fn grad(&self, ctx: &mut ag::op::GradientContext<T>) { /* ... */ }
+    /// }
+    /// ```
+    #[inline]
+    pub fn alloc_output(&self, shape: &[usize]) -> NdArray<T> {
+        let len = shape.iter().product();
+        let buf = self
+            .graph
+            .pool
+            .take(len)
+            .unwrap_or_else(|| vec![T::zero(); len]);
+        NdArray::from_shape_vec(shape, buf).unwrap()
+    }
+
+    /// Allocates a buffer of length `len` for an output of the current op.
+    ///
+    /// Same as `alloc_output` except that newly allocated buffers are left uninitialized.
+    #[inline]
+    pub(crate) unsafe fn alloc_vec(&self, len: usize) -> Vec<T> {
+        match self.graph.pool.take(len) {
+            Some(buf) => buf,
+            None => crate::uninitialized_vec(len),
+        }
+    }
+}
+
+/// Static information about the inputs of an op, which is passed to `Op::infer_shape`.
+///
+/// ```
+/// use autograd as ag;
+///
+/// struct Double;
+///
+/// impl<T: ag::Float> ag::op::Op<T> for Double {
+///     fn compute(&self, ctx: &mut ag::op::ComputeContext<T>) { /* ... */ }
+///
+///     fn grad(&self, ctx: &mut ag::op::GradientContext<T>) { /* ... */ }
+///
+///     fn infer_shape(
+///         &self,
+///         ctx: &ag::op::ShapeContext<T>,
+///     ) -> Result<Option<Vec<isize>>, ag::op::OpError> {
+///         Ok(ctx.input_shape(0).map(|s| s.to_vec()))
+///     }
+/// }
+/// ```
+pub struct ShapeContext<'g, T: Float> {
+    graph: &'g Graph<T>,
+    // Input nodes, or `None` for the inputs that are not the first outputs of their ops.
+    xs: InputArray<Option<&'g TensorInternal<T>>>,
+    dtypes: InputArray<DType>,
+}
+
+impl<'g, T: Float> ShapeContext<'g, T> {
+    pub(crate) fn new(graph: &'g Graph<T>, inputs: &[Input], input_indices: &[usize]) -> Self {
+        let xs = inputs
+            .iter()
+            .zip(input_indices)
+            .map(|(x, &i)| {
+                if i == 0 {
+                    Some(graph.access_node(x.id))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let dtypes = inputs
+            .iter()
+            .map(|x| graph.access_node(x.id).dtype)
+            .collect();
+        ShapeContext { graph, xs, dtypes }
+    }
+
+    /// Returns the number of inputs.
+    #[inline]
+    pub fn num_inputs(&self) -> usize {
+        self.xs.len()
+    }
+
+    /// Returns the static shape of the `i` th input, or `None` if even its rank is unknown.
+    #[inline]
+    pub fn input_shape(&self, i: usize) -> Option<&'g [isize]> {
+        let x = self.xs.get(i).copied().flatten()?;
+        x.known_shape.as_ref().map(|s| s.get())
+    }
+
+    /// Returns the dtype of the `i` th input.
+    #[inline]
+    pub fn input_dtype(&self, i: usize) -> DType {
+        self.dtypes[i]
+    }
+
+    /// Fails unless all the inputs are float tensors, except that the inputs at `indices`
+    /// may also be integer tensors, e.g. the indices of `Graph::gather`.
+    pub fn check_input_dtypes(&self, indices: &[usize]) -> Result<(), OpError> {
+        for (i, &dtype) in self.dtypes.iter().enumerate() {
+            let ok = match dtype {
+                DType::Float => true,
+                DType::Int => indices.contains(&i),
+                DType::Bool => false,
+            };
+            if !ok {
+                return Err(OpError::TypeUnsupported(format!(
+                    "input {} of dtype {} is not supported",
+                    i, dtype
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value of the `i` th input if it's determined by constants and static shapes.
+    pub fn input_value(&self, i: usize) -> Option<NdArray<T>> {
+        let x = self.xs.get(i).copied().flatten()?;
+        match x.constant_array {
+            Some(ref arr) => Some((**arr).clone()),
+            None => crate::constant_folding::try_fold(x, self.graph),
+        }
+    }
+
+    /// Same as `input_value` but returns integers, e.g. of a shape or axes.
+    pub fn input_ints(&self, i: usize) -> Option<Vec<isize>> {
+        let arr = self.input_value(i)?;
+        arr.iter().map(|a| a.to_isize()).collect()
+    }
+
+    /// Returns the static shape given by the value of the `i` th input, e.g. the `shape`
+    /// argument of `Graph::zeros`.
+    ///
+    /// If the value is unknown but its length is, the rank is known.
+    pub fn input_as_shape(&self, i: usize) -> Option<Vec<isize>> {
+        match self.input_ints(i) {
+            Some(shape) => Some(shape),
+            None => match self.input_shape(i)? {
+                &[n] if n >= 0 => Some(vec![-1; n as usize]),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns the shape into which all the inputs are broadcast.
+    pub fn broadcast_input_shapes(&self) -> Result<Option<Vec<isize>>, OpError> {
+        let mut ret: Vec<isize> = Vec::new();
+        for i in 0..self.num_inputs() {
+            match self.input_shape(i) {
+                Some(shape) => ret = broadcast_shapes(&ret, shape)?,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(ret))
+    }
+}
+
+// Broadcasts static shapes in the numpy way. Scalars are broadcast to any shapes.
+pub(crate) fn broadcast_shapes(a: &[isize], b: &[isize]) -> Result<Vec<isize>, OpError> {
+    if a.is_empty() || a == [0] {
+        return Ok(b.to_vec());
+    }
+    if b.is_empty() || b == [0] {
+        return Ok(a.to_vec());
+    }
+    let rank = a.len().max(b.len());
+    let dim = |s: &[isize], i: usize| if i < s.len() { s[s.len() - 1 - i] } else { 1 };
+    let mut ret = vec![0; rank];
+    for i in 0..rank {
+        ret[rank - 1 - i] = match (dim(a, i), dim(b, i)) {
+            (x, y) if x == y => x,
+            (1, y) => y,
+            (x, 1) => x,
+            (-1, y) => y,
+            (x, -1) => x,
+            _ => {
+                return Err(OpError::IncompatibleShape(format!(
+                    "Can't broadcast {:?} and {:?}",
+                    a, b
+                )))
+            }
+        };
+    }
+    Ok(ret)
 }
 
 /// Context of an `Op`'s gradient propagation phase.
@@ -326,6 +848,10 @@ impl<'g, 't, 'v, T: Float> ComputeContext<'t, 'v, T> {
 /// ```
 pub struct GradientContext<'g, T: Float> {
     gy: Tensor<'g, T>,
+    // Gradients of the outputs with the output indices, of which `gy` is the sum.
+    output_grads: InputArray<(usize, Tensor<'g, T>)>,
+    // `requires_grads[i]` is false if the `i` th input is not on the gradient path.
+    requires_grads: Option<InputArray<bool>>,
     y: Tensor<'g, T>,
     graph: &'g crate::graph::Graph<T>,
     gxs: Option<InputArray<Option<Tensor<'g, T>>>>,
@@ -339,12 +865,38 @@ impl<'g, T: Float> GradientContext<'g, T> {
     ) -> Self {
         GradientContext {
             gy,
+            output_grads: smallvec::smallvec![(0, gy)],
+            requires_grads: None,
             y,
             graph,
             gxs: None,
         }
     }
 
+    pub(crate) fn set_output_grads(&mut self, output_grads: InputArray<(usize, Tensor<'g, T>)>) {
+        self.output_grads = output_grads;
+    }
+
+    pub(crate) fn set_requires_grads(&mut self, requires_grads: InputArray<bool>) {
+        self.requires_grads = Some(requires_grads);
+    }
+
+    // True if the gradient of the `i` th input is used, which is worth knowing for the ops
+    // computing the gradients of all the inputs together.
+    pub(crate) fn requires_grad(&self, i: usize) -> bool {
+        self.requires_grads.as_ref().map_or(true, |r| r[i])
+    }
+
+    // True if no outputs but the first have gradients.
+    pub(crate) fn only_first_output_grad(&self) -> bool {
+        self.output_grads.iter().all(|&(i, _)| i == 0)
+    }
+
+    // Number of the input gradients appended so far.
+    pub(crate) fn num_input_grads(&self) -> usize {
+        self.gxs.as_ref().map_or(0, |gxs| gxs.len())
+    }
+
     pub(crate) fn extract_input_grads(self) -> InputArray<Option<Tensor<'g, T>>> {
         self.gxs
             .expect("Bad Op impl: GradientContext::set_input_grads was not called")
@@ -356,6 +908,25 @@ impl<'g, T: Float> GradientContext<'g, T> {
         self.gy
     }
 
+    /// Returns the symbolic gradient of the `i` th output of the op, or `None` if the output has
+    /// no consumers on the gradient path.
+    ///
+    /// This is for ops with multiple outputs, for which `output_grad` is the sum of the
+    /// gradients of all the outputs.
+    pub fn nth_output_grad(&self, i: usize) -> Option<Tensor<'g, T>> {
+        let grads: Vec<_> = self
+            .output_grads
+            .iter()
+            .filter(|&&(j, _)| j == i)
+            .map(|&(_, gy)| gy)
+            .collect();
+        if grads.is_empty() {
+            None
+        } else {
+            Some(self.graph.add_n(grads.as_slice()))
+        }
+    }
+
     /// Grabs the symbolic output of the op.
     #[inline]
     pub fn output(&self) -> Tensor<'g, T> {
@@ -398,3 +969,301 @@ impl<'g, T: Float> GradientContext<'g, T> {
         self.gxs.as_mut().unwrap().push(gx);
     }
 }
+
+/// Context of an `Op`'s tangent propagation phase.
+///
+/// This is passed to an `Op` through `Op::jvp`, which should provide the tangents of
+/// its outputs by calling `JvpContext::append_output_tangent`.
+///
+/// ```
+/// use autograd as ag;
+///
+/// struct Sigmoid;
+///
+/// impl<T: ag::Float> ag::op::Op<T> for Sigmoid {
+///     fn compute(&self, ctx: &mut ag::op::ComputeContext<T>) { /* ... */ }
+///
+///     fn grad(&self, ctx: &mut ag::op::GradientContext<T>) { /* ... */ }
+///
+///     fn jvp(&self, ctx: &mut ag::op::JvpContext<T>) {
+///         let y = ctx.output();
+///         // `None` means that the tangent of the input is zeros.
+///         let ty = ctx
+///             .input_tangent(0)
+///             .map(|tx| tx * (y - ctx.graph().square(y)));
+///         ctx.append_output_tangent(ty);
+///     }
+/// }
+/// ```
+pub struct JvpContext<'g, T: Float> {
+    y: Tensor<'g, T>,
+    graph: &'g crate::graph::Graph<T>,
+    input_tangents: InputArray<Option<Tensor<'g, T>>>,
+    tys: Option<InputArray<Option<Tensor<'g, T>>>>,
+}
+
+impl<'g, T: Float> JvpContext<'g, T> {
+    pub(crate) fn new(
+        y: Tensor<'g, T>,
+        input_tangents: InputArray<Option<Tensor<'g, T>>>,
+        graph: &'g crate::graph::Graph<T>,
+    ) -> Self {
+        JvpContext {
+            y,
+            graph,
+            input_tangents,
+            tys: None,
+        }
+    }
+
+    pub(crate) fn extract_output_tangents(self) -> InputArray<Option<Tensor<'g, T>>> {
+        self.tys
+            .expect("Bad Op impl: JvpContext::append_output_tangent was not called")
+    }
+
+    /// Returns the symbolic tangent of the `i` th input, or `None` if it is zeros.
+    #[inline]
+    pub fn input_tangent(&self, i: usize) -> Option<Tensor<'g, T>> {
+        self.input_tangents[i]
+    }
+
+    /// Grabs the symbolic output of the op.
+    #[inline]
+    pub fn output(&self) -> Tensor<'g, T> {
+        self.y
+    }
+
+    /// Grabs the `i` th symbolic input.
+    #[inline]
+    pub fn input(&self, i: usize) -> Tensor<'g, T> {
+        self.y
+            .inner()
+            .in_edges
+            .get(i)
+            .expect("bad Op::jvp impl")
+            .get(self.graph)
+    }
+
+    /// Returns the number of inputs.
+    #[inline]
+    pub fn num_inputs(&self) -> usize {
+        self.y.inner().in_edges.len()
+    }
+
+    /// Returns a graph object that is usable for tensor computations in the context.
+    #[inline]
+    pub fn graph(&self) -> &'g crate::graph::Graph<T> {
+        self.graph
+    }
+
+    /// Propagates the tangent of the next output.
+    ///
+    /// `None` indicates that the tangent is zeros.
+    #[inline]
+    pub fn append_output_tangent(&mut self, ty: Option<Tensor<'g, T>>) {
+        self.tys.get_or_insert_with(InputArray::new).push(ty);
+    }
+}
+
+/// Context of an `Op`'s batching phase.
+///
+/// This is passed to an `Op` through `Op::vmap`, which should provide the output for the
+/// batched inputs by calling `VmapContext::set_output`.
+///
+/// ```
+/// use autograd as ag;
+///
+/// struct Sigmoid;
+///
+/// impl<T: ag::Float> ag::op::Op<T> for Sigmoid {
+///     fn compute(&self, ctx: &mut ag::op::ComputeContext<T>) { /* ... */ }
+///
+///     fn grad(&self, ctx: &mut ag::op::GradientContext<T>) { /* ... */ }
+///
+///     fn vmap(&self, ctx: &mut ag::op::VmapContext<T>) {
+///         // Elementwise functions don't care about the batch axis.
+///         let y = ctx.graph().sigmoid(ctx.input(0));
+///         ctx.set_output(Some(y));
+///     }
+/// }
+/// ```
+pub struct VmapContext<'g, T: Float> {
+    y: Tensor<'g, T>,
+    graph: &'g crate::graph::Graph<T>,
+    // Inputs replacing those of `y`, and whether they are batched
+    xs: InputArray<(Tensor<'g, T>, bool)>,
+    batch_size: usize,
+    output: Option<Option<Tensor<'g, T>>>,
+}
+
+impl<'g, T: Float> VmapContext<'g, T> {
+    pub(crate) fn new(
+        y: Tensor<'g, T>,
+        xs: InputArray<(Tensor<'g, T>, bool)>,
+        batch_size: usize,
+        graph: &'g crate::graph::Graph<T>,
+    ) -> Self {
+        VmapContext {
+            y,
+            graph,
+            xs,
+            batch_size,
+            output: None,
+        }
+    }
+
+    pub(crate) fn extract_output(self) -> Option<Tensor<'g, T>> {
+        self.output
+            .expect("Bad Op impl: VmapContext::set_output was not called")
+    }
+
+    /// Grabs the `i` th input, which has the batch axis if `is_batched(i)`.
+    #[inline]
+    pub fn input(&self, i: usize) -> Tensor<'g, T> {
+        self.xs.get(i).expect("bad Op::vmap impl").0
+    }
+
+    /// Returns true if the `i` th input has the batch axis.
+    #[inline]
+    pub fn is_batched(&self, i: usize) -> bool {
+        self.xs.get(i).expect("bad Op::vmap impl").1
+    }
+
+    /// Returns the number of inputs.
+    #[inline]
+    pub fn num_inputs(&self) -> usize {
+        self.xs.len()
+    }
+
+    /// Returns the size of the batch axis.
+    #[inline]
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Grabs the symbolic output of the op for the unbatched inputs.
+    #[inline]
+    pub fn output(&self) -> Tensor<'g, T> {
+        self.y
+    }
+
+    /// Returns a graph object that is usable for tensor computations in the context.
+    #[inline]
+    pub fn graph(&self) -> &'g crate::graph::Graph<T> {
+        self.graph
+    }
+
+    /// Sets the output having the batch axis.
+    ///
+    /// `None` indicates that the output doesn't depend on the batched inputs (e.g. their
+    /// shapes), so `output()` is used as it is.
+    #[inline]
+    pub fn set_output(&mut self, y: Option<Tensor<'g, T>>) {
+        self.output = Some(y);
+    }
+}
+
+// Default of `Op::jvp`.
+//
+// The input gradients `gx_i` are linear in the output gradient `u`, so differentiating
+// `sum_i <gx_i, t_i>` with respect to `u` gives the tangent `sum_i J_i t_i`.
+fn jvp_by_grad<F: Float, O: Op<F> + ?Sized>(op: &O, ctx: &mut JvpContext<F>) {
+    let g = ctx.graph;
+    let u = g.zeros(&g.shape(ctx.y));
+    let mut grad_ctx = GradientContext::new(u, ctx.y, g);
+    op.grad(&mut grad_ctx);
+    let mut gxs: Vec<Tensor<F>> = Vec::new();
+    let mut tangents: Vec<Vec<Tensor<F>>> = Vec::new();
+    for (gx, t) in grad_ctx
+        .extract_input_grads()
+        .iter()
+        .zip(&ctx.input_tangents)
+    {
+        if let (Some(gx), Some(t)) = (gx, t) {
+            match gxs.iter().position(|x| x.id() == gx.id()) {
+                Some(i) => tangents[i].push(*t),
+                None => {
+                    gxs.push(*gx);
+                    tangents.push(vec![*t]);
+                }
+            }
+        }
+    }
+    let tangents: Vec<_> = tangents.iter().map(|t| g.add_n(t)).collect();
+    let ys: Vec<_> = gxs.iter().map(|gx| gx.scoped_inner()).collect();
+    let gys: Vec<_> = tangents.iter().map(|t| t.scoped_inner()).collect();
+    let ty = crate::gradient::symbolic_gradients_opt(&ys, &[u.scoped_inner()], &gys, g)
+        .pop()
+        .unwrap();
+    ctx.append_output_tangent(ty);
+}
+
+// Default of `Op::vmap`.
+fn vmap_by_default<F: Float, O: Op<F> + ?Sized>(op: &O, ctx: &mut VmapContext<F>) {
+    if op.determinism() == Determinism::InputShapes {
+        ctx.set_output(None);
+    } else if let Some(y) = op.elementwise().and_then(|f| vmap_elementwise(f, ctx)) {
+        ctx.set_output(Some(y));
+    } else {
+        vmap_by_compute(ctx);
+    }
+}
+
+// Rebuilds the elementwise function `f` on the batched inputs.
+//
+// Binary functions broadcast the inputs in the numpy way, so the batch axis is moved to the
+// front of the broadcast axes, which needs the static ranks of the inputs.
+fn vmap_elementwise<'g, F: Float>(
+    f: Elementwise<F>,
+    ctx: &VmapContext<'g, F>,
+) -> Option<Tensor<'g, F>> {
+    let g = ctx.graph;
+    if ctx.num_inputs() != f.arity() {
+        return None;
+    }
+    if f.arity() == 1 {
+        return Some(f.build(g, ctx.input(0), ctx.input(0)));
+    }
+    // Ranks without the batch axis
+    let ranks = (0..2)
+        .map(|i| {
+            let rank = ctx.input(i).static_shape()?.len();
+            rank.checked_sub(ctx.is_batched(i) as usize)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let rank = ranks[0].max(ranks[1]);
+    // Brings the inputs to the same rank, which the gradients of the binary ops need.
+    let xs: Vec<_> = (0..2)
+        .map(|i| {
+            let x = ctx.input(i);
+            // Axes of size 1 following the batch axis, or leading ones for unbatched inputs
+            let axes: Vec<_> = if ctx.is_batched(i) {
+                (1..=rank - ranks[i]).collect()
+            } else {
+                (0..=rank - ranks[i]).collect()
+            };
+            if axes.is_empty() {
+                x
+            } else {
+                let axes = axes.into_iter().map(|a| F::from(a).unwrap()).collect();
+                g.expand_dims(x, &g.convert_to_tensor(ndarray::Array1::from_vec(axes)))
+            }
+        })
+        .collect();
+    let op = crate::ops::fused_ops::FusedElementwise {
+        instructions: vec![(f, [0, 1])],
+        num_inputs: 2,
+    };
+    Some(
+        Tensor::builder()
+            .set_ro_inputs(&[&xs[0], &xs[1]])
+            .build(g, op),
+    )
+}
+
+// Builds a node computing the op of `ctx.output()` for each slice of the batch, which works
+// for any op.
+pub(crate) fn vmap_by_compute<F: Float>(ctx: &mut VmapContext<F>) {
+    let y = crate::ops::vmap_ops::map_over_batch(ctx.y, &ctx.xs, ctx.batch_size, ctx.graph);
+    ctx.set_output(Some(y));
+}
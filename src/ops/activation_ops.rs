@@ -1,5 +1,7 @@
 use crate::ndarray_ext::{NdArray, NdArrayView};
 use crate::op;
+use crate::op::Attribute;
+use crate::ops::math_ops::map_to_output;
 #[cfg(feature = "mkl")]
 use crate::ops::mkl_ffi::*;
 #[cfg(feature = "mkl")]
@@ -28,35 +30,36 @@ pub struct Softmax {
 }
 
 #[cfg(feature = "mkl")]
-fn fast_sigmoid_impl<F: Float>(x: &NdArrayView<F>) -> NdArray<F> {
+fn fast_sigmoid_impl<F: Float>(ctx: &op::ComputeContext<F>, x: &NdArrayView<F>) -> NdArray<F> {
     let half = F::from(0.5).unwrap();
+    let mut y = ctx.alloc_output(x.shape());
+    ndarray::Zip::from(&mut y)
+        .and(x)
+        .apply(move |y, &x| *y = x * half);
     unsafe {
         if same_type::<F, f32>() {
-            let mut y = x.mapv(move |x| x * half);
             vsTanh(
                 y.len() as MklInt,
                 y.as_ptr() as *const f32,
                 y.as_mut_ptr() as *mut f32,
             );
             y.mapv_inplace(move |x2| half * (x2 + F::one()));
-            return y;
         } else if same_type::<F, f64>() {
-            let mut y = x.mapv(move |x| x * half);
             vdTanh(
                 y.len() as MklInt,
                 y.as_ptr() as *const f64,
                 y.as_mut_ptr() as *mut f64,
             );
             y.mapv_inplace(move |x2| half * (x2 + F::one()));
-            return y;
         } else {
-            x.mapv(move |a| ((a * half).tanh() * half) + half)
+            y.mapv_inplace(move |a| (a.tanh() * half) + half);
         }
     }
+    y
 }
 
 #[inline]
-pub fn softmax_impl<T: Float>(x: &NdArrayView<T>, axis: isize) -> NdArray<T> {
+pub fn softmax_impl<T: Float>(x: &NdArrayView<T>, axis: isize, y: NdArray<T>) -> NdArray<T> {
     let axis = if axis < 0 {
         (x.ndim() as isize + axis) as usize
     } else {
@@ -75,7 +78,11 @@ pub fn softmax_impl<T: Float>(x: &NdArrayView<T>, axis: isize) -> NdArray<T> {
         .into_shape(ndarray::IxDyn(reduced_shape))
         .unwrap();
     // subtract `max` to prevent overflow
-    let mut tmp = x - max;
+    let mut tmp = y;
+    ndarray::Zip::from(&mut tmp)
+        .and(x)
+        .and(&max.broadcast(x.shape()).unwrap())
+        .apply(|y, &a, &m| *y = a - m);
     #[cfg(feature = "mkl")]
     {
         crate::ops::math_ops::fast_inplace_exp_impl(&mut tmp);
@@ -95,7 +102,10 @@ pub fn softmax_impl<T: Float>(x: &NdArrayView<T>, axis: isize) -> NdArray<T> {
 
 impl<T: Float> op::Op<T> for Softmax {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let ret = softmax_impl(&ctx.input(0), self.axis);
+        let x = ctx.input(0);
+        // The result is computed in an output taken from the buffer pool.
+        let y = ctx.alloc_output(x.shape());
+        let ret = softmax_impl(&x, self.axis, y);
         ctx.append_output(ret)
     }
 
@@ -106,12 +116,23 @@ impl<T: Float> op::Op<T> for Softmax {
         let sum = s.reduce_sum(y * gy, &[self.axis], true);
         ctx.append_input_grad(Some((gy - sum) * y))
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.axis))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Int(self.axis as i64)])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
+    }
 }
 
 impl<T: Float> op::Op<T> for Softplus {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let ret = ctx.input(0).map(move |a| (a.exp() + T::one()).ln());
-        ctx.append_output(ret)
+        map_to_output(ctx, move |a| (a.exp() + T::one()).ln());
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
@@ -122,23 +143,37 @@ impl<T: Float> op::Op<T> for Softplus {
         let gx = gy * (a / b);
         ctx.append_input_grad(Some(gx))
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Softplus)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Sigmoid {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let ret;
         #[cfg(feature = "mkl")]
         {
-            ret = fast_sigmoid_impl(&ctx.input(0));
+            let x = ctx.input(0);
+            let ret = fast_sigmoid_impl(ctx, &x);
+            ctx.append_output(ret);
         }
         #[cfg(not(feature = "mkl"))]
         {
             let half = T::from(0.5).unwrap();
-            ret = ctx
-                .input(0)
-                .mapv(move |a| ((a * half).tanh() * half) + half);
+            map_to_output(ctx, move |a| ((a * half).tanh() * half) + half);
         }
-        ctx.append_output(ret)
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
@@ -146,12 +181,35 @@ impl<T: Float> op::Op<T> for Sigmoid {
         let y = ctx.output();
         ctx.append_input_grad(Some(gy * (y - ctx.graph().square(y))));
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let y = ctx.output();
+        let ty = ctx
+            .input_tangent(0)
+            .map(|tx| tx * (y - ctx.graph().square(y)));
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Sigmoid)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for ReLU {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let ret = ctx.input(0).map(|a| a.max(T::zero()));
-        ctx.append_output(ret);
+        map_to_output(ctx, |a| a.max(T::zero()));
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
@@ -160,6 +218,31 @@ impl<T: Float> op::Op<T> for ReLU {
         let bin = s.greater(ctx.input(0), s.scalar(T::zero()));
         ctx.append_input_grad(Some(s.mul(bin, gy)))
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let s = ctx.graph();
+        let ty = ctx.input_tangent(0).map(|tx| {
+            let bin = s.greater(ctx.input(0), s.scalar(T::zero()));
+            s.mul(bin, tx)
+        });
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::ReLU)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Identity {
@@ -174,18 +257,34 @@ impl<T: Float> op::Op<T> for Identity {
         // use gy's array with rc increment.
         ctx.append_input_grad(Some(gy))
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let ty = ctx.input_tangent(0);
+        ctx.append_output_tangent(ty);
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
+    }
 }
 
 impl<T: Float> op::Op<T> for ELU<T> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let ret = ctx.input(0).mapv(move |a| {
+        map_to_output(ctx, move |a| {
             if a > T::zero() {
                 a
             } else {
                 self.alpha * (a.exp() - T::one())
             }
         });
-        ctx.append_output(ret)
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
@@ -196,24 +295,68 @@ impl<T: Float> op::Op<T> for ELU<T> {
             .build(ctx.graph(), ELUGrad { alpha: self.alpha });
         ctx.append_input_grad(Some(gx))
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.alpha))
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::ELU(self.alpha))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Float(self.alpha)])
+    }
 }
 
 impl<T: Float> op::Op<T> for ELUGrad<T> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let x = &ctx.input(0);
-        let a = x.mapv(move |a| {
-            if a > T::zero() {
-                T::one()
-            } else {
-                self.alpha * (a.exp() - T::one()) + self.alpha
-            }
-        });
-        let ret = a * &ctx.input(1);
-        ctx.append_output(ret)
+        let gy = &ctx.input(1);
+        let mut gx = ctx.alloc_output(x.shape());
+        ndarray::Zip::from(&mut gx)
+            .and(x)
+            .and(&gy.broadcast(x.shape()).unwrap())
+            .apply(move |gx, &a, &gy| {
+                let d = if a > T::zero() {
+                    T::one()
+                } else {
+                    self.alpha * (a.exp() - T::one()) + self.alpha
+                };
+                *gx = d * gy;
+            });
+        ctx.append_output(gx)
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
-        ctx.append_input_grad(None);
-        ctx.append_input_grad(None);
+        let s = ctx.graph();
+        let x = ctx.input(0);
+        let gy = ctx.input(1);
+        let ggx = ctx.output_grad();
+        // The derivative of `alpha * (exp(x) - 1)` for x <= 0 is differentiated again.
+        let negative = s.lesser_equal(x, s.scalar(T::zero()));
+        let gx = negative * s.exp(x) * s.scalar(self.alpha) * gy * ggx;
+        let g_gy = Tensor::builder()
+            .set_ro_inputs(&[&x, &ggx])
+            .set_shape(&s.shape(ggx))
+            .build(s, ELUGrad { alpha: self.alpha });
+        ctx.append_input_grad(Some(gx));
+        ctx.append_input_grad(Some(g_gy));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.alpha))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Float(self.alpha)])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
     }
 }
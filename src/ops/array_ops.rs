@@ -1,11 +1,12 @@
+use crate::ndarray;
 use crate::ndarray_ext;
+#[cfg(feature = "mkl")]
+use crate::ndarray_ext::NdArrayViewMut;
 use crate::ndarray_ext::{NdArray, NdArrayView};
 use crate::op;
-use crate::tensor::{Input, Tensor};
+use crate::op::Attribute;
+use crate::tensor::{DType, Input, Tensor};
 use crate::Float;
-#[cfg(feature = "mkl")]
-use crate::ndarray_ext::NdArrayViewMut;
-use crate::ndarray;
 use std::iter::FromIterator;
 
 pub struct ExpandDims;
@@ -20,6 +21,48 @@ pub struct SliceGrad {
     pub indices: Vec<ndarray::SliceOrIndex>,
 }
 
+// Encodes each of `indices` as 4 ints: [kind, start or index, end, step],
+// where kind is 0 for bounded slices, 1 for unbounded slices and 2 for indices.
+fn slice_indices_to_ints(indices: &[ndarray::SliceOrIndex]) -> Vec<i64> {
+    let mut ret = Vec::with_capacity(indices.len() * 4);
+    for &i in indices {
+        match i {
+            ndarray::SliceOrIndex::Slice {
+                start,
+                end: Some(end),
+                step,
+            } => ret.extend(&[0, start as i64, end as i64, step as i64]),
+            ndarray::SliceOrIndex::Slice {
+                start,
+                end: None,
+                step,
+            } => ret.extend(&[1, start as i64, 0, step as i64]),
+            ndarray::SliceOrIndex::Index(index) => ret.extend(&[2, index as i64, 0, 0]),
+        }
+    }
+    ret
+}
+
+// Inverse of `slice_indices_to_ints`
+pub(crate) fn slice_indices_from_ints(ints: &[i64]) -> Option<Vec<ndarray::SliceOrIndex>> {
+    ints.chunks(4)
+        .map(|c| match *c {
+            [0, start, end, step] => Some(ndarray::SliceOrIndex::Slice {
+                start: start as isize,
+                end: Some(end as isize),
+                step: step as isize,
+            }),
+            [1, start, _, step] => Some(ndarray::SliceOrIndex::Slice {
+                start: start as isize,
+                end: None,
+                step: step as isize,
+            }),
+            [2, index, _, _] => Some(ndarray::SliceOrIndex::Index(index as isize)),
+            _ => None,
+        })
+        .collect()
+}
+
 pub struct Split {
     pub axis: isize,
     pub start_index: isize,
@@ -37,6 +80,11 @@ pub struct Tile {
     pub num: usize,
 }
 
+pub struct TileGrad {
+    pub axis: isize,
+    pub num: usize,
+}
+
 pub struct Concat {
     pub axis: isize,
 }
@@ -67,6 +115,10 @@ pub struct GatherGrad {
     pub axis: isize,
 }
 
+pub struct Cast {
+    pub dtype: DType,
+}
+
 pub struct IndexOp {
     pub index: isize,
 }
@@ -77,11 +129,17 @@ pub struct IndexOpGrad {
 
 pub struct SetDiff1D;
 
-pub struct Shape;
+pub struct Shape {
+    pub dtype: DType,
+}
 
-pub struct Rank;
+pub struct Rank {
+    pub dtype: DType,
+}
 
-pub struct Size;
+pub struct Size {
+    pub dtype: DType,
+}
 
 pub struct Reshape;
 
@@ -121,42 +179,144 @@ impl<T: Float> op::Op<T> for InferBinOpShape {
         ctx.append_input_grad(None);
         ctx.append_input_grad(None);
     }
+
+    fn determinism(&self) -> op::Determinism {
+        op::Determinism::InputValues
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+}
+
+// Output dtype of the ops outputting dims or indices (e.g. `Shape`), which can be floats
+// or integers.
+pub(crate) fn float_or_int_dtype<T: Float>(
+    ctx: &op::ShapeContext<T>,
+    dtype: DType,
+) -> Result<DType, op::OpError> {
+    ctx.check_input_dtypes(&[])?;
+    match dtype {
+        DType::Bool => Err(op::OpError::TypeUnsupported(
+            "bool outputs are not supported".to_string(),
+        )),
+        _ => Ok(dtype),
+    }
 }
 
 impl<T: Float> op::Op<T> for Shape {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let x = &ctx.input(0);
-        let ret = ndarray_ext::shape_of_view(x);
-        ctx.append_output(ret);
+        if self.dtype == DType::Int {
+            let shape: Vec<i64> = x.shape().iter().map(|&a| a as i64).collect();
+            ctx.append_output_int(ndarray::Array1::from_vec(shape).into_dyn());
+        } else {
+            let ret = ndarray_ext::shape_of_view(x);
+            ctx.append_output(ret);
+        }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn determinism(&self) -> op::Determinism {
+        op::Determinism::InputShapes
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(self.dtype.to_string())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Int(self.dtype.index())])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| vec![s.len() as isize]))
+    }
+
+    fn output_dtype(&self, ctx: &op::ShapeContext<T>) -> Result<DType, op::OpError> {
+        float_or_int_dtype(ctx, self.dtype)
+    }
 }
 
 impl<T: Float> op::Op<T> for Rank {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let x = ctx.input(0);
-        let ret = NdArray::from_elem(ndarray::IxDyn(&[]), T::from(x.ndim()).unwrap());
-        ctx.append_output(ret);
+        if self.dtype == DType::Int {
+            let ret = ndarray::Array::from_elem(ndarray::IxDyn(&[]), x.ndim() as i64);
+            ctx.append_output_int(ret);
+        } else {
+            let ret = NdArray::from_elem(ndarray::IxDyn(&[]), T::from(x.ndim()).unwrap());
+            ctx.append_output(ret);
+        }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn determinism(&self) -> op::Determinism {
+        op::Determinism::InputShapes
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(self.dtype.to_string())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Int(self.dtype.index())])
+    }
+
+    fn infer_shape(&self, _: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(Some(Vec::new()))
+    }
+
+    fn output_dtype(&self, ctx: &op::ShapeContext<T>) -> Result<DType, op::OpError> {
+        float_or_int_dtype(ctx, self.dtype)
+    }
 }
 
 impl<T: Float> op::Op<T> for Size {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let x = ctx.input(0);
-        let ret = NdArray::from_elem(ndarray::IxDyn(&[]), T::from(x.len()).unwrap());
-        ctx.append_output(ret);
+        if self.dtype == DType::Int {
+            let ret = ndarray::Array::from_elem(ndarray::IxDyn(&[]), x.len() as i64);
+            ctx.append_output_int(ret);
+        } else {
+            let ret = NdArray::from_elem(ndarray::IxDyn(&[]), T::from(x.len()).unwrap());
+            ctx.append_output(ret);
+        }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn determinism(&self) -> op::Determinism {
+        op::Determinism::InputShapes
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(self.dtype.to_string())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Int(self.dtype.index())])
+    }
+
+    fn infer_shape(&self, _: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(Some(Vec::new()))
+    }
+
+    fn output_dtype(&self, ctx: &op::ShapeContext<T>) -> Result<DType, op::OpError> {
+        float_or_int_dtype(ctx, self.dtype)
+    }
 }
 
 impl<T: Float> op::Op<T> for Reshape {
@@ -210,6 +370,46 @@ impl<T: Float> op::Op<T> for Reshape {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let target = match ctx.input_ints(1) {
+            Some(target) => target,
+            None => return Ok(ctx.input_as_shape(1)),
+        };
+        let size = match ctx.input_shape(0) {
+            Some(s) if s.iter().all(|&a| a >= 0) => s.iter().product::<isize>(),
+            _ => return Ok(Some(target)),
+        };
+        let known: isize = target.iter().filter(|&&a| a != -1).product();
+        let num_unknown = target.iter().filter(|&&a| a == -1).count();
+        if (num_unknown == 0 && known != size)
+            || (num_unknown == 1 && known > 0 && size % known != 0)
+        {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "Can't reshape {:?} into {:?}",
+                ctx.input_shape(0).unwrap(),
+                target
+            )));
+        }
+        if num_unknown == 1 && known > 0 {
+            Ok(Some(
+                target
+                    .iter()
+                    .map(|&a| if a == -1 { size / known } else { a })
+                    .collect(),
+            ))
+        } else {
+            Ok(Some(target))
+        }
+    }
 }
 
 impl<T: Float> op::Op<T> for SetDiff1D {
@@ -249,6 +449,18 @@ impl<T: Float> op::Op<T> for SetDiff1D {
         ctx.append_input_grad(None);
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, _: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(Some(vec![-1]))
+    }
 }
 
 impl<T: Float> op::Op<T> for IndexOp {
@@ -279,6 +491,18 @@ impl<T: Float> op::Op<T> for IndexOp {
             .build(scope, op);
         ctx.append_input_grad(Some(gx));
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.index))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Int(self.index as i64)])
+    }
+
+    fn infer_shape(&self, _: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(Some(Vec::new()))
+    }
 }
 
 impl<T: Float> op::Op<T> for IndexOpGrad {
@@ -308,14 +532,30 @@ impl<T: Float> op::Op<T> for IndexOpGrad {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let gy = Tensor::builder()
+            .append_input(&ctx.output_grad())
+            .build(ctx.graph(), IndexOp { index: self.index });
         ctx.append_input_grad(None);
+        ctx.append_input_grad(Some(gy));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.index))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Int(self.index as i64)])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
     }
 }
 
 impl<T: Float> op::Op<T> for Gather {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let param = &ctx.input(1);
-        let indices = &ctx.input(0);
+        let indices = &ctx.input_as_ints(0);
         let indices_shape = indices.shape();
         let param_shape = param.shape();
         let axis = ndarray_ext::normalize_negative_axis(self.axis, param.ndim());
@@ -332,11 +572,16 @@ impl<T: Float> op::Op<T> for Gather {
                 .collect()
         };
 
-        let flat_indices = if self.should_normalize_negative_indices {
-            ndarray_ext::normalize_negative_axes(indices, param_shape[axis])
-        } else {
-            indices.map(|a| a.to_usize().unwrap()).into_raw_vec()
-        };
+        let flat_indices: Vec<usize> = indices
+            .iter()
+            .map(|&a| {
+                if self.should_normalize_negative_indices && a < 0 {
+                    (param_shape[axis] as i64 + a) as usize
+                } else {
+                    a as usize
+                }
+            })
+            .collect();
         let selected = param.select(ndarray::Axis(axis), flat_indices.as_slice());
         let ret = selected.into_shape(output_shape.as_slice()).unwrap();
         ctx.append_output(ret);
@@ -349,24 +594,54 @@ impl<T: Float> op::Op<T> for Gather {
         let gy = ctx.output_grad();
         let gx = Tensor::builder()
             .set_ro_inputs(&[&x, &x1, &gy])
-            .set_shape(&scope.shape(x))
+            .set_shape(&scope.shape(x1))
             .build(scope, GatherGrad { axis: self.axis });
         ctx.append_input_grad(None);
         ctx.append_input_grad(Some(gx));
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!(
+            "{:?}",
+            (self.axis, self.should_normalize_negative_indices)
+        ))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.axis as i64),
+            Attribute::Bool(self.should_normalize_negative_indices),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let (indices, param) = match (ctx.input_shape(0), ctx.input_shape(1)) {
+            (Some(indices), Some(param)) => (indices, param),
+            _ => return Ok(None),
+        };
+        let axis = static_axis(self.axis, param.len())?;
+        Ok(Some(
+            param[..axis]
+                .iter()
+                .chain(indices)
+                .chain(&param[axis + 1..])
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    fn output_dtype(&self, ctx: &op::ShapeContext<T>) -> Result<DType, op::OpError> {
+        ctx.check_input_dtypes(&[0]).map(|()| DType::Float)
+    }
 }
 
 impl<T: Float> op::Op<T> for GatherGrad {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let indices = ctx.input(0);
+        let indices = ctx.input_as_ints(0);
         let param = &ctx.input(1);
         let param_shape = param.shape();
         let gy = &ctx.input(2);
-        let axis = if self.axis == -1 {
-            param.ndim()
-        } else {
-            self.axis as usize
-        };
+        let axis = ndarray_ext::normalize_negative_axis(self.axis, param.ndim());
 
         // get read-only view of gy and reshape it
         let gy = {
@@ -383,8 +658,9 @@ impl<T: Float> op::Op<T> for GatherGrad {
 
         let mut gx = NdArray::zeros(param.shape());
 
-        for (gy_sub, &i) in gy.axis_iter(ndarray::Axis(axis)).zip(indices) {
-            let i = i.to_isize().unwrap();
+        for (gy_sub, &i) in gy.axis_iter(ndarray::Axis(axis)).zip(&indices) {
+            // Negative indices are those normalized by `Gather`.
+            let i = ndarray_ext::normalize_negative_axis(i as isize, param_shape[axis]) as isize;
             // get gx's sub view
             let gx_sliced = gx.slice_mut(
                 ndarray::SliceInfo::<_, ndarray::IxDyn>::new(
@@ -422,9 +698,110 @@ impl<T: Float> op::Op<T> for GatherGrad {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let gy = Tensor::builder()
+            .set_ro_inputs(&[&ctx.input(0), &ctx.output_grad()])
+            .build(
+                ctx.graph(),
+                Gather {
+                    axis: self.axis,
+                    should_normalize_negative_indices: false,
+                },
+            );
         ctx.append_input_grad(None);
         ctx.append_input_grad(None);
-        ctx.append_input_grad(None);
+        ctx.append_input_grad(Some(gy));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.axis))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Int(self.axis as i64)])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(1).map(|s| s.to_vec()))
+    }
+
+    fn output_dtype(&self, ctx: &op::ShapeContext<T>) -> Result<DType, op::OpError> {
+        ctx.check_input_dtypes(&[0]).map(|()| DType::Float)
+    }
+}
+
+impl<T: Float> op::Op<T> for Cast {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
+        match (ctx.input_dtype(0), self.dtype) {
+            (DType::Float, DType::Float) => {
+                let x = ctx.input(0);
+                ctx.append_output_view(x);
+            }
+            (DType::Float, DType::Int) => {
+                // Truncates towards zero, saturating at the bounds (NaN is 0).
+                let y = ctx.input(0).mapv(|a| a.to_f64().unwrap() as i64);
+                ctx.append_output_int(y);
+            }
+            (DType::Float, DType::Bool) => {
+                let y = ctx.input(0).mapv(|a| a != T::zero());
+                ctx.append_output_bool(y);
+            }
+            (DType::Int, DType::Float) => {
+                let y = ctx.input_int(0).mapv(|a| T::from(a).unwrap());
+                ctx.append_output(y);
+            }
+            (DType::Int, DType::Int) => {
+                let y = ctx.input_int(0).to_owned();
+                ctx.append_output_int(y);
+            }
+            (DType::Int, DType::Bool) => {
+                let y = ctx.input_int(0).mapv(|a| a != 0);
+                ctx.append_output_bool(y);
+            }
+            (DType::Bool, DType::Float) => {
+                let y = ctx
+                    .input_bool(0)
+                    .mapv(|a| if a { T::one() } else { T::zero() });
+                ctx.append_output(y);
+            }
+            (DType::Bool, DType::Int) => {
+                let y = ctx.input_bool(0).mapv(i64::from);
+                ctx.append_output_int(y);
+            }
+            (DType::Bool, DType::Bool) => {
+                let y = ctx.input_bool(0).to_owned();
+                ctx.append_output_bool(y);
+            }
+        }
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // Only float-to-float casts are on gradient paths since integer and boolean tensors
+        // are not differentiable.
+        ctx.append_input_grad(Some(ctx.output_grad()));
+    }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        ctx.append_output_tangent(ctx.input_tangent(0));
+    }
+
+    fn determinism(&self) -> op::Determinism {
+        op::Determinism::InputValues
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(self.dtype.to_string())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Int(self.dtype.index())])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
+    }
+
+    fn output_dtype(&self, _: &op::ShapeContext<T>) -> Result<DType, op::OpError> {
+        Ok(self.dtype)
     }
 }
 
@@ -486,6 +863,28 @@ impl<T: Float> op::Op<T> for AddN {
             ctx.append_input_grad(Some(ctx.output_grad()));
         }
     }
+
+    fn vmap(&self, ctx: &mut crate::op::VmapContext<T>) {
+        // The inputs have the same shape, so the unbatched ones are broadcast to the batched
+        // ones preceding them.
+        let (mut xs, unbatched): (Vec<_>, Vec<_>) =
+            (0..ctx.num_inputs()).partition(|&i| ctx.is_batched(i));
+        xs.extend(unbatched);
+        let xs: Vec<_> = xs.into_iter().map(|i| ctx.input(i)).collect();
+        ctx.set_output(Some(ctx.graph().add_n(&xs)));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        ctx.broadcast_input_shapes()
+    }
 }
 
 impl<T: Float> op::Op<T> for Clip<T> {
@@ -509,6 +908,18 @@ impl<T: Float> op::Op<T> for Clip<T> {
             );
         ctx.append_input_grad(Some(gx));
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.min, self.max)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Float(self.min), Attribute::Float(self.max)])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
+    }
 }
 
 impl<T: Float> op::Op<T> for ClipGrad<T> {
@@ -522,8 +933,31 @@ impl<T: Float> op::Op<T> for ClipGrad<T> {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let ggx = ctx.output_grad();
+        let gy = Tensor::builder()
+            .set_shape(&ctx.graph().shape(ggx))
+            .set_ro_inputs(&[&ctx.input(0), &ggx])
+            .build(
+                ctx.graph(),
+                ClipGrad {
+                    min: self.min,
+                    max: self.max,
+                },
+            );
         ctx.append_input_grad(None);
-        ctx.append_input_grad(None);
+        ctx.append_input_grad(Some(gy));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.min, self.max)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Float(self.min), Attribute::Float(self.max)])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
     }
 }
 
@@ -561,7 +995,7 @@ impl<T: Float> op::Op<T> for Concat {
 
         for i in 0..num_inputs {
             let gx = Tensor::builder()
-                .set_shape(&ctx.graph().shape(ctx.input(0)))
+                .set_shape(&ctx.graph().shape(ctx.input(i)))
                 .set_inputs(merged_inputs.as_slice())
                 .build(
                     ctx.graph(),
@@ -573,6 +1007,53 @@ impl<T: Float> op::Op<T> for Concat {
             ctx.append_input_grad(Some(gx));
         }
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.axis))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Int(self.axis as i64)])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let mut ret: Option<Vec<isize>> = None;
+        for i in 0..ctx.num_inputs() {
+            let shape = match ctx.input_shape(i) {
+                Some(shape) => shape,
+                None => continue,
+            };
+            let ret = ret.get_or_insert_with(|| vec![-1; shape.len()]);
+            let axis = static_axis(self.axis, shape.len())?;
+            if shape.len() != ret.len() {
+                return Err(op::OpError::IncompatibleShape(format!(
+                    "Can't concat tensors of ranks {} and {}",
+                    ret.len(),
+                    shape.len()
+                )));
+            }
+            for (j, (r, &a)) in ret.iter_mut().zip(shape).enumerate() {
+                if j == axis {
+                    continue;
+                }
+                *r = merge_dims(*r, a).ok_or_else(|| {
+                    op::OpError::IncompatibleShape(format!(
+                        "Can't concat tensors along axis {}: dim {} is {} vs {}",
+                        self.axis, j, r, a
+                    ))
+                })?;
+            }
+        }
+        if let Some(ref mut ret) = ret {
+            // The size along `axis` is known if all the inputs are.
+            let axis = static_axis(self.axis, ret.len())?;
+            let sizes = (0..ctx.num_inputs())
+                .map(|i| ctx.input_shape(i).map(|s| s[axis]).filter(|&a| a >= 0))
+                .collect::<Option<Vec<_>>>();
+            ret[axis] = sizes.map(|s| s.iter().sum()).unwrap_or(-1);
+        }
+        Ok(ret)
+    }
 }
 
 impl<T: Float> op::Op<T> for ConcatGrad {
@@ -587,7 +1068,7 @@ impl<T: Float> op::Op<T> for ConcatGrad {
 
         // make slice indices
         let mut start_idx = 0;
-        for i in 1..self.index {
+        for i in 1..self.index + 1 {
             start_idx += ctx.input(i).shape()[axis];
         }
         let region_len = ctx.input(self.index + 1).shape()[axis] as isize;
@@ -597,7 +1078,7 @@ impl<T: Float> op::Op<T> for ConcatGrad {
                     // partial region
                     ndarray::SliceOrIndex::Slice {
                         start: start_idx as isize,
-                        end: Some(region_len),
+                        end: Some(start_idx as isize + region_len),
                         step: 1,
                     }
                 } else {
@@ -625,10 +1106,34 @@ impl<T: Float> op::Op<T> for ConcatGrad {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
-        for _ in 0..ctx.num_inputs() {
+        // Puts the gradient back into its region, and zeros into the others.
+        let s = ctx.graph();
+        let ggx = ctx.output_grad();
+        let parts: Vec<_> = (1..ctx.num_inputs())
+            .map(|i| {
+                if i == self.index + 1 {
+                    ggx
+                } else {
+                    s.zeros(&s.shape(ctx.input(i)))
+                }
+            })
+            .collect();
+        ctx.append_input_grad(Some(s.concat(&parts, self.axis)));
+        for _ in 1..ctx.num_inputs() {
             ctx.append_input_grad(None);
         }
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.axis, self.index)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.axis as i64),
+            Attribute::Int(self.index as i64),
+        ])
+    }
 }
 
 impl<T: Float> op::Op<T> for Tile {
@@ -647,12 +1152,94 @@ impl<T: Float> op::Op<T> for Tile {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
-        let scope = ctx.graph();
-        ctx.append_input_grad(Some(scope.reduce_sum(
-            ctx.output_grad(),
-            &[self.axis],
-            true,
-        )));
+        let x = ctx.input(0);
+        let gx = Tensor::builder()
+            .set_ro_inputs(&[&ctx.output_grad()])
+            .set_shape(&ctx.graph().shape(x))
+            .build(
+                ctx.graph(),
+                TileGrad {
+                    axis: self.axis,
+                    num: self.num,
+                },
+            );
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.axis, self.num)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.axis as i64),
+            Attribute::Int(self.num as i64),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let mut ret = match ctx.input_shape(0) {
+            Some(shape) => shape.to_vec(),
+            None => return Ok(None),
+        };
+        let axis = static_axis(self.axis, ret.len())?;
+        if ret[axis] >= 0 {
+            ret[axis] *= self.num as isize;
+        }
+        Ok(Some(ret))
+    }
+}
+
+impl<T: Float> op::Op<T> for TileGrad {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
+        // Sums up the `num` tiles of gy.
+        let gy = ctx.input(0);
+        let axis = ndarray_ext::normalize_negative_axis(self.axis, gy.ndim());
+        let len = gy.shape()[axis] / self.num;
+        let mut gx = gy
+            .slice_axis(ndarray::Axis(axis), ndarray::Slice::from(0..len))
+            .to_owned();
+        for i in 1..self.num {
+            gx += &gy.slice_axis(
+                ndarray::Axis(axis),
+                ndarray::Slice::from(i * len..(i + 1) * len),
+            );
+        }
+        ctx.append_output(gx);
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let gy = Tensor::builder().append_input(&ctx.output_grad()).build(
+            ctx.graph(),
+            Tile {
+                axis: self.axis,
+                num: self.num,
+            },
+        );
+        ctx.append_input_grad(Some(gy));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.axis, self.num)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.axis as i64),
+            Attribute::Int(self.num as i64),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let mut ret = match ctx.input_shape(0) {
+            Some(shape) => shape.to_vec(),
+            None => return Ok(None),
+        };
+        let axis = static_axis(self.axis, ret.len())?;
+        if ret[axis] >= 0 {
+            ret[axis] /= self.num as isize;
+        }
+        Ok(Some(ret))
     }
 }
 
@@ -680,6 +1267,31 @@ impl<T: Float> op::Op<T> for Split {
             .build(ctx.graph(), op);
         ctx.append_input_grad(Some(gx));
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!(
+            "{:?}",
+            (self.axis, self.start_index, self.end_index)
+        ))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.axis as i64),
+            Attribute::Int(self.start_index as i64),
+            Attribute::Int(self.end_index as i64),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let mut ret = match ctx.input_shape(0) {
+            Some(shape) => shape.to_vec(),
+            None => return Ok(None),
+        };
+        let axis = static_axis(self.axis, ret.len())?;
+        ret[axis] = static_slice_len(ret[axis], self.start_index, Some(self.end_index), 1)?;
+        Ok(Some(ret))
+    }
 }
 
 impl<T: Float> op::Op<T> for SplitGrad {
@@ -700,7 +1312,35 @@ impl<T: Float> op::Op<T> for SplitGrad {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let op = Split {
+            axis: self.axis,
+            start_index: self.start_index,
+            end_index: self.end_index,
+        };
+        let gy = Tensor::builder()
+            .append_input(&ctx.output_grad())
+            .build(ctx.graph(), op);
         ctx.append_input_grad(None);
+        ctx.append_input_grad(Some(gy));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!(
+            "{:?}",
+            (self.axis, self.start_index, self.end_index)
+        ))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.axis as i64),
+            Attribute::Int(self.start_index as i64),
+            Attribute::Int(self.end_index as i64),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
     }
 }
 
@@ -733,6 +1373,55 @@ fn make_indices_for_split<T: Float>(
         .collect::<Vec<_>>()
 }
 
+// Normalizes `axis` for a static shape of rank `ndim`.
+pub(crate) fn static_axis(axis: isize, ndim: usize) -> Result<usize, op::OpError> {
+    let ret = if axis < 0 { axis + ndim as isize } else { axis };
+    if ret < 0 || ret >= ndim as isize {
+        return Err(op::OpError::OutOfBounds(format!(
+            "Axis {} is out of bounds for rank {}",
+            axis, ndim
+        )));
+    }
+    Ok(ret as usize)
+}
+
+// Merges two static dims which must be equal, or returns `None` if they differ.
+pub(crate) fn merge_dims(a: isize, b: isize) -> Option<isize> {
+    match (a, b) {
+        (-1, b) => Some(b),
+        (a, -1) => Some(a),
+        (a, b) if a == b => Some(a),
+        _ => None,
+    }
+}
+
+// Static size of a dim of size `dim` sliced in the same way as `ndarray`.
+fn static_slice_len(
+    dim: isize,
+    start: isize,
+    end: Option<isize>,
+    step: isize,
+) -> Result<isize, op::OpError> {
+    if dim < 0 {
+        return Ok(-1);
+    }
+    let start = if start < 0 { start + dim } else { start };
+    let end = match end {
+        Some(end) if end < 0 => end + dim,
+        Some(end) => end,
+        None => dim,
+    };
+    if start < 0 || start > dim || end < 0 || end > dim || step == 0 {
+        return Err(op::OpError::OutOfBounds(format!(
+            "Slice {}..{};{} is out of bounds for size {}",
+            start, end, step, dim
+        )));
+    }
+    let len = (end - start).max(0);
+    let step = step.abs();
+    Ok((len + step - 1) / step)
+}
+
 impl<T: Float> op::Op<T> for Slice {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let mut y = ctx.input(0);
@@ -752,6 +1441,35 @@ impl<T: Float> op::Op<T> for Slice {
             .build(ctx.graph(), op);
         ctx.append_input_grad(Some(gx));
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.indices))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Ints(slice_indices_to_ints(&self.indices))])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let shape = match ctx.input_shape(0) {
+            Some(shape) => shape,
+            None => return Ok(None),
+        };
+        if shape.len() != self.indices.len() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "Can't slice a tensor of shape {:?} with {} indices",
+                shape,
+                self.indices.len()
+            )));
+        }
+        let mut ret = Vec::with_capacity(shape.len());
+        for (&dim, &index) in shape.iter().zip(&self.indices) {
+            if let ndarray::SliceOrIndex::Slice { start, end, step } = index {
+                ret.push(static_slice_len(dim, start, end, step)?);
+            }
+        }
+        Ok(Some(ret))
+    }
 }
 
 impl<T: Float> op::Op<T> for SliceGrad {
@@ -769,9 +1487,26 @@ impl<T: Float> op::Op<T> for SliceGrad {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
-        // is this ok?
-        ctx.append_input_grad(None);
+        let op = Slice {
+            indices: self.indices.clone(),
+        };
+        let gy = Tensor::builder()
+            .append_input(&ctx.output_grad())
+            .build(ctx.graph(), op);
         ctx.append_input_grad(None);
+        ctx.append_input_grad(Some(gy));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.indices))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Ints(slice_indices_to_ints(&self.indices))])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
     }
 }
 impl<T: Float> op::Op<T> for Squeeze {
@@ -803,6 +1538,39 @@ impl<T: Float> op::Op<T> for Squeeze {
         ));
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let (mut ret, mut axes) = match (ctx.input_shape(0), ctx.input_ints(1)) {
+            (Some(shape), Some(axes)) => (shape.to_vec(), axes),
+            _ => return Ok(None),
+        };
+        // Same order as `compute`
+        axes.sort();
+        for (adjust, &i) in axes.iter().enumerate() {
+            let axis = if i < 0 { ret.len() as isize + i } else { i } - adjust as isize;
+            match ret.get(axis as usize) {
+                Some(&a) if axis >= 0 && (a == 1 || a == -1) => {
+                    ret.remove(axis as usize);
+                }
+                _ => {
+                    return Err(op::OpError::IncompatibleShape(format!(
+                        "Can't squeeze axis {} of shape {:?}",
+                        i,
+                        ctx.input_shape(0).unwrap()
+                    )))
+                }
+            }
+        }
+        Ok(Some(ret))
+    }
 }
 
 impl<T: Float> op::Op<T> for ExpandDims {
@@ -830,4 +1598,33 @@ impl<T: Float> op::Op<T> for ExpandDims {
         ctx.append_input_grad(Some(ctx.graph().squeeze(ctx.output_grad(), &ctx.input(1))));
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let (mut ret, mut axes) = match (ctx.input_shape(0), ctx.input_ints(1)) {
+            (Some(shape), Some(axes)) => (shape.to_vec(), axes),
+            _ => return Ok(None),
+        };
+        let ndim = ret.len();
+        axes.sort();
+        for &i in axes.iter() {
+            let axis = ndarray_ext::normalize_negative_axis(i, ndim);
+            if axis > ret.len() {
+                return Err(op::OpError::OutOfBounds(format!(
+                    "Can't expand axis {} of shape {:?}",
+                    i,
+                    ctx.input_shape(0).unwrap()
+                )));
+            }
+            ret.insert(axis, 1);
+        }
+        Ok(Some(ret))
+    }
 }
@@ -11,6 +11,10 @@ macro_rules! impl_op {
             fn grad(&self, _: &mut crate::op::GradientContext<T>) {
                 unreachable!()
             }
+
+            fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+                Some(Vec::new())
+            }
         }
     };
 }
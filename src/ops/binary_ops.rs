@@ -51,32 +51,26 @@ impl<T: Float> op::Op<T> for PreprocessBinOpGrad {
         } else {
             // Broadcast occurred. We need reduction of `gy`.
             // First, handle the case where x is scalar.
-            let x_is_scalar = crate::ndarray_ext::is_scalar_shape(x_shape);
-            let x_shape = if x_is_scalar {
-                vec![1; gy_shape.len()]
-            } else {
-                x_shape.to_vec()
-            };
+            if crate::ndarray_ext::is_scalar_shape(x_shape) {
+                ctx.append_output(ndarray::arr0(gy.sum()).into_dyn());
+                return;
+            }
             // Reduce each dim as necessary
             let mut folded: Option<NdArray<T>> = None;
             for (i, (x_axis, gy_axis)) in x_shape.iter().zip(gy_shape).enumerate() {
                 if x_axis < gy_axis {
                     if *x_axis == 1 {
                         // `fold_axis` squashes the axis automatically.
-                        let axis = ndarray::Axis(if x_is_scalar { 0 } else { i });
+                        let axis = ndarray::Axis(i);
                         let ret = match folded {
-                            Some(ref a) => a.fold_axis(axis.clone(), T::zero(), |&a, &b| a + b),
-                            None => gy.fold_axis(axis.clone(), T::zero(), |&a, &b| a + b),
+                            Some(ref a) => a.fold_axis(axis, T::zero(), |&a, &b| a + b),
+                            None => gy.fold_axis(axis, T::zero(), |&a, &b| a + b),
                         };
-                        if x_is_scalar {
-                            mem::swap(&mut folded, &mut Some(ret));
-                        } else {
-                            // Expands squashed axis.
-                            mem::swap(
-                                &mut folded,
-                                &mut Some(crate::ndarray_ext::expand_dims(ret, i)),
-                            );
-                        }
+                        // Expands squashed axis.
+                        mem::swap(
+                            &mut folded,
+                            &mut Some(crate::ndarray_ext::expand_dims(ret, i)),
+                        );
                     } else {
                         ctx.set_error(op::OpError::IncompatibleShape(
                             "Incorrect gradient shape".to_string(),
@@ -87,18 +81,70 @@ impl<T: Float> op::Op<T> for PreprocessBinOpGrad {
                 // case of x_axis < gy_axis: unreachable
                 // case of x_axis == gy_axis: nothing to do
             }
-            // TODO
-            ctx.append_output(folded.unwrap());
+            match folded {
+                Some(folded) => ctx.append_output(folded),
+                // Only the ranks differ, e.g. a scalar `x` and `gy` of shape [1].
+                None => match gy.into_shape(x_shape_.as_slice()) {
+                    Ok(gx) => ctx.append_output_view(gx),
+                    Err(e) => ctx.set_error(op::OpError::NdArrayError(
+                        "PreprocessBinOpGrad: ".to_string(),
+                        e,
+                    )),
+                },
+            }
         };
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // Broadcasts back into the shape of `gy`.
+        let gy_shape = ctx.graph().shape(ctx.input(0));
         let gx = Tensor::builder()
-            .set_ro_inputs(&[&ctx.output_grad(), &ctx.input(1)])
+            .set_ro_inputs(&[&ctx.output_grad(), &gy_shape])
             .build(ctx.graph(), PreprocessBinOpGradGrad);
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn vmap(&self, ctx: &mut crate::op::VmapContext<T>) {
+        // Reduces the batch of `gy` into the target shape with the batch axis, which is right
+        // unless the ranks differ.
+        let g = ctx.graph();
+        let (gy, target) = (ctx.input(0), ctx.input(1));
+        let gy_rank = gy.static_shape().map(|s| s.len());
+        let target_rank = target
+            .static_shape()
+            .and_then(|s| s.first().copied())
+            .filter(|&r| r >= 0);
+        if ctx.is_batched(1) || gy_rank != target_rank.map(|r| r as usize + 1) {
+            op::vmap_by_compute(ctx);
+            return;
+        }
+        let batch = T::from(ctx.batch_size()).unwrap();
+        let target = g.concat(&[g.convert_to_tensor(ndarray::arr1(&[batch])), target], 0);
+        let b = Tensor::builder().set_ro_inputs(&[&gy, &target]);
+        let b = match ctx.output().static_shape() {
+            Some(shape) => {
+                let batched: Vec<_> = std::iter::once(ctx.batch_size() as isize)
+                    .chain(shape)
+                    .collect();
+                b.set_inferred_shape(batched)
+            }
+            None => b,
+        };
+        ctx.set_output(Some(b.build(g, PreprocessBinOpGrad)));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(1))
+    }
 }
 
 // Do broadcast if necessary.
@@ -121,9 +167,9 @@ impl<T: Float> op::Op<T> for PreprocessBinOpGradGrad {
 
         // make broadcast dims if needed
         if gy_is_scalar {
-            for &axis in target_shape.iter() {
-                gy = crate::ndarray_ext::expand_dims_view(gy, axis);
-            }
+            let ones = vec![1; target_shape.len()];
+            // unwrap is safe since gy has only one element
+            gy = gy.into_shape(ones.as_slice()).unwrap();
         }
 
         // do broadcast
@@ -137,18 +183,31 @@ impl<T: Float> op::Op<T> for PreprocessBinOpGradGrad {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // Reduces into the shape of `gy`.
+        let gy_shape = ctx.graph().shape(ctx.input(0));
         let gx = Tensor::builder()
-            .set_ro_inputs(&[&ctx.input(0), &ctx.output_grad()])
+            .set_ro_inputs(&[&ctx.output_grad(), &gy_shape])
             .build(ctx.graph(), PreprocessBinOpGrad);
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(1))
+    }
 }
 
 impl<T: Float> op::Op<T> for AddOp {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let ret = add_forward(&ctx.input(0), &ctx.input(1));
-        ctx.append_output(ret);
+        bin_op_forward_in_place(ctx, |a, b| a + b, add_forward);
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
@@ -160,32 +219,32 @@ impl<T: Float> op::Op<T> for AddOp {
         ctx.append_input_grad(Some(gy1));
         ctx.append_input_grad(Some(gy2));
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let ty = add_tangents(ctx, false);
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Add)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for SubOp {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let x0 = &ctx.input(0);
-        let x1 = &ctx.input(1);
-        let shape0: &[usize] = x0.shape();
-        let shape1: &[usize] = x1.shape();
-        let ret = if shape0 == [] {
-            // is scalar
-            let x0_elem = x0[ndarray::IxDyn(&[])];
-            x1.map(move |&a| x0_elem - a)
-        } else if shape0 == shape1 {
-            #[cfg(feature = "mkl")]
-            {
-                use crate::{ops::mkl_ffi::*, same_type};
-                bin_op_same_shape!(vsSub, vdSub, -, x0, x1)
-            }
-            #[cfg(not(feature = "mkl"))]
-            {
-                x0 - x1
-            }
-        } else {
-            x0 - x1
-        };
-        ctx.append_output(ret);
+        bin_op_forward_in_place(ctx, |a, b| a - b, sub_forward);
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
@@ -197,12 +256,32 @@ impl<T: Float> op::Op<T> for SubOp {
         ctx.append_input_grad(Some(gy1));
         ctx.append_input_grad(Some(ctx.graph().neg(&gy2)));
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let ty = add_tangents(ctx, true);
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Sub)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for MulOp {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let ret = mul_forward(&ctx.input(0), &ctx.input(1));
-        ctx.append_output(ret);
+        bin_op_forward_in_place(ctx, |a, b| a * b, mul_forward);
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
@@ -210,43 +289,43 @@ impl<T: Float> op::Op<T> for MulOp {
         let x1 = ctx.input(1);
         let shape0 = &ctx.graph().shape(x0);
         let shape1 = &ctx.graph().shape(x1);
-        let (gy1, gy2) = preprocess_gy(shape0, shape1, &ctx.output_grad(), ctx.graph());
-        ctx.append_input_grad(Some(gy1 * x1));
-        ctx.append_input_grad(Some(gy2 * x0));
+        let gy = ctx.output_grad();
+        // Reduces the products, which have the broadcast shape.
+        ctx.append_input_grad(Some(reduce_gy(&(gy * x1), shape0, ctx.graph())));
+        ctx.append_input_grad(Some(reduce_gy(&(gy * x0), shape1, ctx.graph())));
+    }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let (x0, x1) = (ctx.input(0), ctx.input(1));
+        let ty = match (ctx.input_tangent(0), ctx.input_tangent(1)) {
+            (Some(t0), Some(t1)) => Some(t0 * x1 + x0 * t1),
+            (Some(t0), None) => Some(t0 * x1),
+            (None, Some(t1)) => Some(x0 * t1),
+            (None, None) => None,
+        };
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Mul)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
     }
 }
 
 impl<T: Float> op::Op<T> for DivOp {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let x0 = &ctx.input(0);
-        let x1 = &ctx.input(1);
-        let shape0: &[usize] = x0.shape();
-        let shape1: &[usize] = x1.shape();
-        let is_scalar0 = shape0 == [] || shape0 == [0];
-        let is_scalar1 = shape1 == [] || shape1 == [1];
-        let ret = if is_scalar0 {
-            // a is a scalar
-            let x0_elem = x0[ndarray::IxDyn(&[])];
-            x1.map(move |&a| x0_elem / a)
-        } else if is_scalar1 {
-            // b is a scalar
-            let x1_elem = x1[ndarray::IxDyn(&[])];
-            let rhs = T::one() / x1_elem;
-            x0.mapv(|x0_elem| x0_elem * rhs)
-        } else if shape0 == shape1 {
-            #[cfg(feature = "mkl")]
-            {
-                use crate::{ops::mkl_ffi::*, same_type};
-                bin_op_same_shape!(vsDiv, vdDiv, /, x0, x1)
-            }
-            #[cfg(not(feature = "mkl"))]
-            {
-                x0 / x1
-            }
-        } else {
-            x0 / x1
-        };
-        ctx.append_output(ret);
+        bin_op_forward_in_place(ctx, |a, b| a / b, div_forward);
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
@@ -255,30 +334,228 @@ impl<T: Float> op::Op<T> for DivOp {
         let x1 = ctx.input(1);
         let shape0 = &scope.shape(x0);
         let shape1 = &scope.shape(x1);
-        let (gy1, gy2) = preprocess_gy(shape0, shape1, &ctx.output_grad(), scope);
-        ctx.append_input_grad(Some(gy1 / x1));
-        ctx.append_input_grad(Some(
-            scope.neg(x0) * scope.pow(x1, T::from(-2.).unwrap()) * gy2,
-        ));
+        let gy = ctx.output_grad();
+        ctx.append_input_grad(Some(reduce_gy(&(gy / x1), shape0, scope)));
+        ctx.append_input_grad(Some(reduce_gy(
+            &(scope.neg(x0) * scope.pow(x1, T::from(-2.).unwrap()) * gy),
+            shape1,
+            scope,
+        )));
+    }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let (y, x1) = (ctx.output(), ctx.input(1));
+        let ty = match (ctx.input_tangent(0), ctx.input_tangent(1)) {
+            (Some(t0), Some(t1)) => Some((t0 - y * t1) / x1),
+            (Some(t0), None) => Some(t0 / x1),
+            (None, Some(t1)) => Some(ctx.graph().neg(y * t1) / x1),
+            (None, None) => None,
+        };
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Div)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+}
+
+fn sub_forward<'v, T: Float>(x0: &NdArrayView<'v, T>, x1: &NdArrayView<'v, T>) -> NdArray<T> {
+    let shape0: &[usize] = x0.shape();
+    let shape1: &[usize] = x1.shape();
+    if shape0 == [] {
+        // is scalar
+        let x0_elem = x0[ndarray::IxDyn(&[])];
+        x1.map(move |&a| x0_elem - a)
+    } else if shape0 == shape1 {
+        #[cfg(feature = "mkl")]
+        {
+            use crate::{ops::mkl_ffi::*, same_type};
+            bin_op_same_shape!(vsSub, vdSub, -, x0, x1)
+        }
+        #[cfg(not(feature = "mkl"))]
+        {
+            x0 - x1
+        }
+    } else {
+        x0 - x1
+    }
+}
+
+fn div_forward<'v, T: Float>(x0: &NdArrayView<'v, T>, x1: &NdArrayView<'v, T>) -> NdArray<T> {
+    let shape0: &[usize] = x0.shape();
+    let shape1: &[usize] = x1.shape();
+    let is_scalar0 = shape0 == [] || shape0 == [0];
+    let is_scalar1 = shape1 == [] || shape1 == [1];
+    if is_scalar0 {
+        // a is a scalar
+        let x0_elem = x0[ndarray::IxDyn(&[])];
+        x1.map(move |&a| x0_elem / a)
+    } else if is_scalar1 {
+        // b is a scalar
+        let x1_elem = x1[ndarray::IxDyn(&[])];
+        let rhs = T::one() / x1_elem;
+        x0.mapv(|x0_elem| x0_elem * rhs)
+    } else if shape0 == shape1 {
+        #[cfg(feature = "mkl")]
+        {
+            use crate::{ops::mkl_ffi::*, same_type};
+            bin_op_same_shape!(vsDiv, vdDiv, /, x0, x1)
+        }
+        #[cfg(not(feature = "mkl"))]
+        {
+            x0 / x1
+        }
+    } else {
+        x0 / x1
+    }
+}
+
+// Computes `f(x0, x1)` elementwise, writing the result into an input array taken over from
+// the runtime if the other input can be broadcast to its shape, or into an output taken from
+// the buffer pool otherwise.
+// Falls back to `forward` if neither input can be broadcast to the shape of the other.
+fn bin_op_forward_in_place<T, F, G>(ctx: &mut op::ComputeContext<T>, f: F, forward: G)
+where
+    T: Float,
+    F: Fn(T, T) -> T,
+    G: for<'v> Fn(&NdArrayView<'v, T>, &NdArrayView<'v, T>) -> NdArray<T>,
+{
+    let owned0 = ctx.input_owned(0);
+    let owned1 = ctx.input_owned(1);
+    let view0 = if owned0.is_none() {
+        Some(ctx.input(0))
+    } else {
+        None
+    };
+    let view1 = if owned1.is_none() {
+        Some(ctx.input(1))
+    } else {
+        None
+    };
+    let out_of_place = |x0: &NdArrayView<'_, T>, x1: &NdArrayView<'_, T>| {
+        // MKL computes the arrays of the same shape faster.
+        if cfg!(feature = "mkl") && x0.shape() == x1.shape() {
+            return forward(&x0.view(), &x1.view());
+        }
+        bin_op_to_output(ctx, x0, x1, &f).unwrap_or_else(|| forward(&x0.view(), &x1.view()))
+    };
+    let ret = match (owned0, owned1) {
+        (Some(mut x0), Some(mut x1)) => {
+            if apply_broadcast(&mut x0, &x1.view(), &f) {
+                x0
+            } else if apply_broadcast(&mut x1, &x0.view(), |b, a| f(a, b)) {
+                x1
+            } else {
+                out_of_place(&x0.view(), &x1.view())
+            }
+        }
+        (Some(mut x0), None) => {
+            let x1 = view1.unwrap();
+            if apply_broadcast(&mut x0, &x1, &f) {
+                x0
+            } else {
+                out_of_place(&x0.view(), &x1)
+            }
+        }
+        (None, Some(mut x1)) => {
+            let x0 = view0.unwrap();
+            if apply_broadcast(&mut x1, &x0, |b, a| f(a, b)) {
+                x1
+            } else {
+                out_of_place(&x0, &x1.view())
+            }
+        }
+        (None, None) => out_of_place(&view0.unwrap(), &view1.unwrap()),
+    };
+    ctx.append_output(ret);
+}
+
+// Computes `f(x0, x1)` broadcasting one of the inputs to the shape of the other, into an
+// output taken from the buffer pool. Returns `None` if neither can be broadcast.
+fn bin_op_to_output<T: Float, F: Fn(T, T) -> T>(
+    ctx: &op::ComputeContext<T>,
+    x0: &NdArrayView<T>,
+    x1: &NdArrayView<T>,
+    f: F,
+) -> Option<NdArray<T>> {
+    let (x0, x1) = match x1.broadcast(x0.shape()) {
+        Some(x1) => (x0.view(), x1),
+        None => (x0.broadcast(x1.shape())?, x1.view()),
+    };
+    let mut y = ctx.alloc_output(x0.shape());
+    ndarray::Zip::from(&mut y)
+        .and(&x0)
+        .and(&x1)
+        .apply(|y, &a, &b| *y = f(a, b));
+    Some(y)
+}
+
+// Performs `lhs = f(lhs, rhs)` broadcasting `rhs` to the shape of `lhs`.
+// Returns false if `rhs` can't be broadcast.
+fn apply_broadcast<T: Float, F: Fn(T, T) -> T>(
+    lhs: &mut NdArray<T>,
+    rhs: &NdArrayView<T>,
+    f: F,
+) -> bool {
+    match rhs.broadcast(lhs.shape()) {
+        Some(rhs) => {
+            ndarray::Zip::from(lhs)
+                .and(&rhs)
+                .apply(|l, &r| *l = f(*l, r));
+            true
+        }
+        None => false,
     }
 }
 
 // Reduce gy if broadcast occurred in the forward path.
+// Tangent of `x0 + x1`, or `x0 - x1` if `sub`.
+//
+// A single tangent is broadcast to the output shape by adding zeros of the other input's shape.
+fn add_tangents<'g, T: Float>(ctx: &op::JvpContext<'g, T>, sub: bool) -> Option<Tensor<'g, T>> {
+    let g = ctx.graph();
+    let zeros = |i: usize| g.zeros(&g.shape(ctx.input(i)));
+    match (ctx.input_tangent(0), ctx.input_tangent(1)) {
+        (Some(t0), Some(t1)) if sub => Some(t0 - t1),
+        (Some(t0), Some(t1)) => Some(t0 + t1),
+        (Some(t0), None) => Some(t0 + zeros(1)),
+        (None, Some(t1)) if sub => Some(zeros(0) - t1),
+        (None, Some(t1)) => Some(zeros(0) + t1),
+        (None, None) => None,
+    }
+}
+
 fn preprocess_gy<'b, T: Float>(
     shape0: &Tensor<'b, T>,
     shape1: &Tensor<'b, T>,
     gy: &Tensor<'b, T>,
     c: &'b Graph<T>,
 ) -> (Tensor<'b, T>, Tensor<'b, T>) {
-    let gy0 = Tensor::builder()
-        .set_ro_inputs(&[gy, shape0])
-        .set_shape(shape0)
-        .build(c, PreprocessBinOpGrad);
-    let gy1 = Tensor::builder()
-        .set_ro_inputs(&[gy, shape1])
-        .set_shape(shape1)
-        .build(c, PreprocessBinOpGrad);
-    (gy0, gy1)
+    (reduce_gy(gy, shape0, c), reduce_gy(gy, shape1, c))
+}
+
+// Sums up `gy` over the axes along which an input of `shape` was broadcast.
+fn reduce_gy<'b, T: Float>(
+    gy: &Tensor<'b, T>,
+    shape: &Tensor<'b, T>,
+    c: &'b Graph<T>,
+) -> Tensor<'b, T> {
+    Tensor::builder()
+        .set_ro_inputs(&[gy, shape])
+        .set_shape(shape)
+        .build(c, PreprocessBinOpGrad)
 }
 
 macro_rules! impl_bin_op_forward {
@@ -1,6 +1,7 @@
 use crate::ndarray_ext;
 use crate::ndarray_ext::NdArray;
 use crate::op;
+use crate::op::Attribute;
 use crate::Float;
 use ndarray;
 
@@ -21,6 +22,22 @@ impl<T: Float> op::Op<T> for Scalar<T> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn determinism(&self) -> op::Determinism {
+        op::Determinism::InputValues
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.val))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Float(self.val)])
+    }
+
+    fn infer_shape(&self, _: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(Some(Vec::new()))
+    }
 }
 
 impl<T: Float> op::Op<T> for Zeros {
@@ -48,6 +65,22 @@ impl<T: Float> op::Op<T> for Zeros {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn determinism(&self) -> op::Determinism {
+        op::Determinism::InputValues
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(0))
+    }
 }
 
 impl<T: Float> op::Op<T> for Ones {
@@ -75,6 +108,22 @@ impl<T: Float> op::Op<T> for Ones {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn determinism(&self) -> op::Determinism {
+        op::Determinism::InputValues
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(0))
+    }
 }
 
 impl<T: Float> op::Op<T> for ConvertToTensor<T> {
@@ -83,4 +132,16 @@ impl<T: Float> op::Op<T> for ConvertToTensor<T> {
     }
 
     fn grad(&self, _: &mut crate::op::GradientContext<T>) {}
+
+    fn determinism(&self) -> op::Determinism {
+        op::Determinism::InputValues
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Array(self.arr.clone())])
+    }
+
+    fn infer_shape(&self, _: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(Some(self.arr.shape().iter().map(|&a| a as isize).collect()))
+    }
 }
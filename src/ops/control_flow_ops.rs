@@ -0,0 +1,997 @@
+//! Ops evaluating subgraphs at run-time: `cond`, `while_loop`, `scan` and `checkpoint`.
+//!
+//! The closures given to `Graph::cond`, `Graph::while_loop`, `Graph::scan` and
+//! `Graph::checkpoint` build their subgraphs in the
+//! same graph as the other nodes. The outer tensors used in a subgraph are replaced with
+//! placeholders ("params"), and the control flow op takes the outer tensors as its inputs, so the
+//! outer evaluation never reaches the subgraph nodes. The op evaluates them on its own
+//! with the params filled with its input arrays.
+//!
+//! Thus the subgraph nodes depending on params can't be evaluated from outside: their
+//! evaluations fail with `EvalError::SubgraphTensor` instead of a missing feed of the param.
+use super::activation_ops::Identity;
+use super::basic_source_ops::Placeholder;
+use super::gradient_ops::unsupported_grads;
+use crate::ndarray;
+use crate::ndarray_ext::{NdArray, NdArrayView};
+use crate::op;
+use crate::runtime::{run_subgraph, Schedule};
+use crate::tensor::{Input, Tensor};
+use crate::{EvalError, Float, Graph};
+
+// Outer tensors used in subgraphs, and the placeholders replacing them.
+struct Captures {
+    outer: Vec<usize>,
+    params: Vec<usize>,
+}
+
+impl Captures {
+    // Returns the placeholder replacing the outer tensor `id`.
+    fn param<F: Float>(&mut self, g: &Graph<F>, id: usize) -> usize {
+        if let Some(i) = self.outer.iter().position(|&x| x == id) {
+            return self.params[i];
+        }
+        let param = new_param(g, g.access_node(id).known_shape.as_ref().map(|s| s.get())).id();
+        self.outer.push(id);
+        self.params.push(param);
+        param
+    }
+}
+
+// Creates a placeholder filled by a control flow op.
+fn new_param<'g, F: Float>(g: &'g Graph<F>, shape: Option<&[isize]>) -> Tensor<'g, F> {
+    let b = Tensor::builder()
+        .set_is_placeholder(true)
+        .set_is_subgraph_param(true);
+    let b = match shape {
+        Some(shape) => b.set_inferred_shape(shape.to_vec()),
+        None => b,
+    };
+    b.build(g, Placeholder)
+}
+
+// True if the subgraphs can use the node `id` as it is.
+fn is_constant<F: Float>(g: &Graph<F>, id: usize) -> bool {
+    g.access_node(id).constant_array.is_some()
+}
+
+// Replaces the outer tensors used by the nodes created since `start` with placeholders.
+//
+// Constants and the variables mutated in the subgraphs are left as they are.
+// `outputs` which are outer tensors are replaced as well.
+fn capture_outer_tensors<F: Float>(g: &Graph<F>, start: usize, outputs: &mut [usize]) -> Captures {
+    let mut captures = Captures {
+        outer: Vec::new(),
+        params: Vec::new(),
+    };
+    let end = g.num_nodes();
+    for id in start..end {
+        // Nodes are boxed, so creating placeholders doesn't move `node`.
+        let node = unsafe { g.access_node_mut(id) };
+        let edges = node
+            .in_edges
+            .iter_mut()
+            .chain(node.backprop_inputs.iter_mut().flatten());
+        for x in edges {
+            if x.id < start && !x.mut_usage && !is_constant(g, x.id) {
+                x.id = captures.param(g, x.id);
+                x.is_placeholder = true;
+            }
+        }
+        // The shape is computed again in the subgraph if needed.
+        if let Some(shape) = node.shape {
+            if shape < start && !is_constant(g, shape) {
+                node.shape = None;
+            }
+        }
+    }
+    for y in outputs {
+        if *y < start && !is_constant(g, *y) {
+            *y = captures.param(g, *y);
+        }
+    }
+    captures
+}
+
+// Static shape of the values of two tensors, one of which is taken at run-time.
+fn union_shape(a: Option<&[isize]>, b: Option<&[isize]>) -> Option<Vec<isize>> {
+    match (a, b) {
+        (Some(a), Some(b)) if a.len() == b.len() => Some(
+            a.iter()
+                .zip(b)
+                .map(|(&a, &b)| if a == b { a } else { -1 })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn static_shape<F: Float>(g: &Graph<F>, id: usize) -> Option<&[isize]> {
+    g.access_node(id).known_shape.as_ref().map(|s| s.get())
+}
+
+// Builds a node of `op` and returns its outputs, whose static shapes are `shapes`.
+fn build_outputs<'g, F: Float, O: op::Op<F> + 'static>(
+    g: &'g Graph<F>,
+    inputs: &[Input],
+    op: O,
+    shapes: Vec<Option<Vec<isize>>>,
+) -> Vec<Tensor<'g, F>> {
+    let node = Tensor::builder().set_inputs(inputs).build(g, op);
+    shapes
+        .into_iter()
+        .enumerate()
+        .map(|(k, shape)| {
+            let b = Tensor::builder()
+                .append_input(&node)
+                .set_input_indices(&[k]);
+            let b = match shape {
+                Some(shape) => b.set_inferred_shape(shape),
+                None => b,
+            };
+            b.build(g, Identity)
+        })
+        .collect()
+}
+
+// Symbolic gradients of the subgraph outputs `ys` with respect to `params`,
+// where `gys` are the placeholders of the gradients of `ys`.
+fn subgraph_grads<'g, F: Float>(
+    g: &'g Graph<F>,
+    ys: &[usize],
+    params: &[usize],
+    gys: &[usize],
+) -> Vec<Option<Tensor<'g, F>>> {
+    // The gradients of an output appearing more than once are summed up.
+    let mut distinct_ys = Vec::new();
+    let mut distinct_gys: Vec<Vec<Tensor<F>>> = Vec::new();
+    for (&y, &gy) in ys.iter().zip(gys) {
+        let gy = g.scoped(g.access_node(gy));
+        match distinct_ys.iter().position(|&x| x == y) {
+            Some(i) => distinct_gys[i].push(gy),
+            None => {
+                distinct_ys.push(y);
+                distinct_gys.push(vec![gy]);
+            }
+        }
+    }
+    let gys: Vec<_> = distinct_gys.iter().map(|gys| g.add_n(gys)).collect();
+    let ys: Vec<_> = distinct_ys.iter().map(|&y| g.access_node(y)).collect();
+    let gys: Vec<_> = gys.iter().map(|gy| gy.scoped_inner()).collect();
+    let params: Vec<_> = params.iter().map(|&p| g.access_node(p)).collect();
+    crate::gradient::symbolic_gradients_opt(&ys, &params, &gys, g)
+}
+
+fn zeros_like<'g, F: Float>(g: &'g Graph<F>, id: usize) -> Tensor<'g, F> {
+    let x = g.scoped(g.access_node(id));
+    g.zeros(&g.shape(x))
+}
+
+// Fails the op with an error in its subgraph.
+fn set_subgraph_error<F: Float>(ctx: &mut op::ComputeContext<F>, e: EvalError) {
+    match e {
+        EvalError::OpError(e) => ctx.set_error(e),
+        // The runtime reports this as `EvalError::Subgraph` of the control flow op.
+        e => ctx.set_subgraph_error(e),
+    }
+}
+
+// Gradient of an input of a loop's gradient op, which fails at evaluation.
+// Reads a predicate, which must have exactly one element.
+fn is_true<F: Float>(pred: &NdArrayView<F>) -> Result<bool, EvalError> {
+    if pred.len() != 1 {
+        return Err(EvalError::OpError(op::OpError::IncompatibleShape(format!(
+            "The predicate of a control flow op must have a single element, but got shape {:?}",
+            pred.shape()
+        ))));
+    }
+    Ok(pred.iter().all(|&x| x != F::zero()))
+}
+
+pub(crate) struct Cond {
+    // Placeholders of the inputs following the predicate
+    pub params: Vec<usize>,
+    // Outputs of the branch taken if the predicate is nonzero, and those of the other one
+    pub branches: [Vec<usize>; 2],
+}
+
+pub(crate) struct Checkpoint {
+    // Placeholders of the inputs
+    pub params: Vec<usize>,
+    // Outputs of the segment
+    pub outputs: Vec<usize>,
+}
+
+pub(crate) struct WhileLoop {
+    // Placeholders of the loop variables followed by those of the captured tensors
+    pub params: Vec<usize>,
+    // Output of `cond_fn`
+    pub cond: usize,
+    // Outputs of `body_fn`, i.e. the loop variables of the next iteration
+    pub body: Vec<usize>,
+}
+
+pub(crate) struct WhileLoopGrad {
+    // Same as `WhileLoop`
+    pub params: Vec<usize>,
+    pub cond: usize,
+    pub body: Vec<usize>,
+    // Placeholders of the gradients of `body`
+    pub gy_params: Vec<usize>,
+    // Gradients of `body` with respect to the loop variables and `grad_captures`
+    pub body_grads: Vec<usize>,
+    // `has_gy[k]` is true if the gradient of the `k` th output of `WhileLoop` is an input.
+    pub has_gy: Vec<bool>,
+    // Indices of the captured tensors in `params` which are differentiated
+    pub grad_captures: Vec<usize>,
+}
+
+pub(crate) struct Scan {
+    // Placeholders of the states, the slice of the sequence, and the captured tensors
+    pub params: Vec<usize>,
+    // Outputs of `f`, i.e. the states after a step
+    pub body: Vec<usize>,
+    // Axis of the sequence and the stacked states
+    pub axis: isize,
+}
+
+pub(crate) struct ScanGrad {
+    // Same as `Scan`
+    pub params: Vec<usize>,
+    pub body: Vec<usize>,
+    pub axis: isize,
+    // Placeholders of the gradients of `body`
+    pub gy_params: Vec<usize>,
+    // Gradients of `body` with respect to the states, the slice if `grad_sequence`,
+    // and `grad_captures`
+    pub body_grads: Vec<usize>,
+    // `has_gy[k]` is true if the gradient of the `k` th output of `Scan` is an input.
+    pub has_gy: Vec<bool>,
+    pub grad_sequence: bool,
+    // Indices of the captured tensors in `params` which are differentiated
+    pub grad_captures: Vec<usize>,
+}
+
+/// Builds `Cond` from the closures of `Graph::cond`.
+pub(crate) fn cond<'g, F, TF, FF>(
+    g: &'g Graph<F>,
+    pred: &Tensor<'g, F>,
+    true_fn: TF,
+    false_fn: FF,
+) -> Vec<Tensor<'g, F>>
+where
+    F: Float,
+    TF: FnOnce() -> Vec<Tensor<'g, F>>,
+    FF: FnOnce() -> Vec<Tensor<'g, F>>,
+{
+    let start = g.num_nodes();
+    let true_outputs: Vec<_> = true_fn().iter().map(|y| y.id()).collect();
+    let mut false_outputs: Vec<_> = false_fn().iter().map(|y| y.id()).collect();
+    assert_eq!(
+        true_outputs.len(),
+        false_outputs.len(),
+        "Graph::cond: the branches must return the same number of tensors"
+    );
+    let mut outputs = true_outputs.clone();
+    outputs.append(&mut false_outputs);
+    let captures = capture_outer_tensors(g, start, &mut outputs);
+    let false_outputs = outputs.split_off(true_outputs.len());
+    let true_outputs = outputs;
+
+    let shapes = true_outputs
+        .iter()
+        .zip(&false_outputs)
+        .map(|(&a, &b)| union_shape(static_shape(g, a), static_shape(g, b)))
+        .collect();
+    let mut inputs = vec![Input::new(pred)];
+    for &x in &captures.outer {
+        inputs.push(Input::new(&g.scoped(g.access_node(x))));
+    }
+    let op = Cond {
+        params: captures.params,
+        branches: [true_outputs, false_outputs],
+    };
+    build_outputs(g, &inputs, op, shapes)
+}
+
+/// Builds `Checkpoint` from the closure of `Graph::checkpoint`.
+pub(crate) fn checkpoint<'g, F, FN>(g: &'g Graph<F>, f: FN) -> Vec<Tensor<'g, F>>
+where
+    F: Float,
+    FN: FnOnce() -> Vec<Tensor<'g, F>>,
+{
+    let start = g.num_nodes();
+    let mut outputs: Vec<_> = f().iter().map(|y| y.id()).collect();
+    let captures = capture_outer_tensors(g, start, &mut outputs);
+
+    let shapes = outputs
+        .iter()
+        .map(|&y| static_shape(g, y).map(|s| s.to_vec()))
+        .collect();
+    let inputs: Vec<_> = captures
+        .outer
+        .iter()
+        .map(|&x| Input::new(&g.scoped(g.access_node(x))))
+        .collect();
+    let op = Checkpoint {
+        params: captures.params,
+        outputs,
+    };
+    build_outputs(g, &inputs, op, shapes)
+}
+
+/// Builds `WhileLoop` from the closures of `Graph::while_loop`.
+pub(crate) fn while_loop<'g, F, CF, BF>(
+    g: &'g Graph<F>,
+    cond_fn: CF,
+    body_fn: BF,
+    loop_vars: &[Tensor<'g, F>],
+) -> Vec<Tensor<'g, F>>
+where
+    F: Float,
+    CF: FnOnce(&[Tensor<'g, F>]) -> Tensor<'g, F>,
+    BF: FnOnce(&[Tensor<'g, F>]) -> Vec<Tensor<'g, F>>,
+{
+    let start = g.num_nodes();
+    // Only the ranks of the loop variables are fixed.
+    let vars: Vec<_> = loop_vars
+        .iter()
+        .map(|x| {
+            let shape = x.static_shape().map(|s| vec![-1; s.len()]);
+            new_param(g, shape.as_deref())
+        })
+        .collect();
+    let cond = cond_fn(&vars).id();
+    let body: Vec<_> = body_fn(&vars).iter().map(|y| y.id()).collect();
+    assert_eq!(
+        body.len(),
+        vars.len(),
+        "Graph::while_loop: body_fn must return as many tensors as the loop variables"
+    );
+    let mut outputs = body.clone();
+    outputs.push(cond);
+    let captures = capture_outer_tensors(g, start, &mut outputs);
+    let cond = outputs.pop().unwrap();
+    let body = outputs;
+
+    let shapes = loop_vars
+        .iter()
+        .zip(&body)
+        .map(|(x, &y)| union_shape(x.static_shape().as_deref(), static_shape(g, y)))
+        .collect();
+    let mut params: Vec<_> = vars.iter().map(|x| x.id()).collect();
+    params.extend_from_slice(&captures.params);
+    let mut inputs: Vec<_> = loop_vars.iter().map(Input::new).collect();
+    for &x in &captures.outer {
+        inputs.push(Input::new(&g.scoped(g.access_node(x))));
+    }
+    build_outputs(g, &inputs, WhileLoop { params, cond, body }, shapes)
+}
+
+/// Builds `Scan` from the closure of `Graph::scan`.
+pub(crate) fn scan<'g, F, FN>(
+    g: &'g Graph<F>,
+    f: FN,
+    initial_state: &[Tensor<'g, F>],
+    sequence: &Tensor<'g, F>,
+    axis: isize,
+) -> Vec<Tensor<'g, F>>
+where
+    F: Float,
+    FN: FnOnce(&[Tensor<'g, F>], &Tensor<'g, F>) -> Vec<Tensor<'g, F>>,
+{
+    let start = g.num_nodes();
+    let states: Vec<_> = initial_state
+        .iter()
+        .map(|x| new_param(g, x.static_shape().as_deref()))
+        .collect();
+    let seq_shape = sequence.static_shape();
+    let seq_axis = seq_shape
+        .as_ref()
+        .and_then(|s| normalize_axis(axis, s.len()).ok());
+    let slice_shape = seq_shape.as_ref().zip(seq_axis).map(|(s, axis)| {
+        let mut s = s.clone();
+        s.remove(axis);
+        s
+    });
+    let slice = new_param(g, slice_shape.as_deref());
+    let mut body: Vec<_> = f(&states, &slice).iter().map(|y| y.id()).collect();
+    assert_eq!(
+        body.len(),
+        states.len(),
+        "Graph::scan: f must return as many tensors as the states"
+    );
+    let captures = capture_outer_tensors(g, start, &mut body);
+
+    let len = match (&seq_shape, seq_axis) {
+        (Some(s), Some(axis)) => s[axis],
+        _ => -1,
+    };
+    let shapes = initial_state
+        .iter()
+        .zip(&body)
+        .map(|(x, &y)| {
+            let mut shape = union_shape(x.static_shape().as_deref(), static_shape(g, y))?;
+            shape.insert(normalize_axis(axis, shape.len() + 1).ok()?, len);
+            Some(shape)
+        })
+        .collect();
+    let mut params: Vec<_> = states.iter().map(|x| x.id()).collect();
+    params.push(slice.id());
+    params.extend_from_slice(&captures.params);
+    let mut inputs: Vec<_> = initial_state.iter().map(Input::new).collect();
+    inputs.push(Input::new(sequence));
+    for &x in &captures.outer {
+        inputs.push(Input::new(&g.scoped(g.access_node(x))));
+    }
+    build_outputs(g, &inputs, Scan { params, body, axis }, shapes)
+}
+
+// Runs a while loop from the initial loop variables and the captured tensors `xs`.
+//
+// Returns the loop variables before each iteration and after the last one if `keep_all`,
+// or only the last ones otherwise.
+fn run_loop<F: Float>(
+    g: &Graph<F>,
+    params: &[usize],
+    cond: usize,
+    body: &[usize],
+    xs: &[NdArrayView<F>],
+    keep_all: bool,
+) -> Result<Vec<Vec<NdArray<F>>>, EvalError> {
+    let n = body.len();
+    let cond = Schedule::new(&[cond], params, g)?;
+    let body = Schedule::new(body, params, g)?;
+    let mut states = vec![xs[..n].iter().map(|x| x.to_owned()).collect::<Vec<_>>()];
+    loop {
+        let next = {
+            let mut feeds: Vec<_> = states.last().unwrap().iter().map(|x| x.view()).collect();
+            feeds.extend(xs[n..].iter().map(|x| x.view()));
+            if !is_true(&run_subgraph(&cond, g, &feeds)?[0].view())? {
+                break;
+            }
+            run_subgraph(&body, g, &feeds)?
+        };
+        if keep_all {
+            states.push(next);
+        } else {
+            states[0] = next;
+        }
+    }
+    Ok(states)
+}
+
+impl<T: Float> op::Op<T> for Cond {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) {
+        let g = ctx.graph();
+        let taken = match is_true(&ctx.input(0)) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => return set_subgraph_error(ctx, e),
+        };
+        let xs: Vec<_> = (1..ctx.num_inputs()).map(|i| ctx.input(i)).collect();
+        let ys = Schedule::new(&self.branches[taken], &self.params, g)
+            .and_then(|schedule| run_subgraph(&schedule, g, &xs));
+        match ys {
+            Ok(ys) => {
+                for y in ys {
+                    ctx.append_output(y);
+                }
+            }
+            Err(e) => set_subgraph_error(ctx, e),
+        }
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let g = ctx.graph();
+        // Outputs having gradients, and the placeholders of the gradients in the new branches.
+        let mut gys = Vec::new();
+        let mut gy_params = Vec::new();
+        let mut live_outputs = Vec::new();
+        for k in 0..self.branches[0].len() {
+            if let Some(gy) = ctx.nth_output_grad(k) {
+                gy_params.push(new_param(g, gy.static_shape().as_deref()).id());
+                gys.push(gy);
+                live_outputs.push(k);
+            }
+        }
+        // Differentiates only with respect to the inputs on the gradient path.
+        let wrt: Vec<_> = (0..self.params.len())
+            .filter(|&j| ctx.requires_grad(j + 1))
+            .collect();
+        let wrt_params: Vec<_> = wrt.iter().map(|&j| self.params[j]).collect();
+        let grads: Vec<_> = self
+            .branches
+            .iter()
+            .map(|outputs| {
+                let ys: Vec<_> = live_outputs.iter().map(|&k| outputs[k]).collect();
+                subgraph_grads(g, &ys, &wrt_params, &gy_params)
+            })
+            .collect();
+
+        // The predicate has no gradient.
+        ctx.append_input_grad(None);
+        // Inputs having gradients in either of the branches
+        let mut grad_inputs = Vec::new();
+        let mut branches = [Vec::new(), Vec::new()];
+        for (i, (&j, &param)) in wrt.iter().zip(&wrt_params).enumerate() {
+            if grads[0][i].is_none() && grads[1][i].is_none() {
+                continue;
+            }
+            grad_inputs.push(j);
+            for (branch, grads) in branches.iter_mut().zip(&grads) {
+                branch.push(grads[i].unwrap_or_else(|| zeros_like(g, param)).id());
+            }
+        }
+        if grad_inputs.is_empty() {
+            for _ in &self.params {
+                ctx.append_input_grad(None);
+            }
+            return;
+        }
+
+        let mut inputs: Vec<_> = (0..ctx.num_inputs())
+            .map(|i| Input::new(&ctx.input(i)))
+            .collect();
+        inputs.extend(gys.iter().map(Input::new));
+        let shapes = grad_inputs
+            .iter()
+            .map(|&j| ctx.input(j + 1).static_shape())
+            .collect();
+        let mut params = self.params.clone();
+        params.extend_from_slice(&gy_params);
+        let gxs = build_outputs(g, &inputs, Cond { params, branches }, shapes);
+        for j in 0..self.params.len() {
+            ctx.append_input_grad(grad_inputs.iter().position(|&i| i == j).map(|i| gxs[i]));
+        }
+    }
+}
+
+impl<T: Float> op::Op<T> for Checkpoint {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) {
+        let g = ctx.graph();
+        let xs: Vec<_> = (0..ctx.num_inputs()).map(|i| ctx.input(i)).collect();
+        // The intermediate arrays of the segment are dropped here.
+        let ys = Schedule::new(&self.outputs, &self.params, g)
+            .and_then(|schedule| run_subgraph(&schedule, g, &xs));
+        match ys {
+            Ok(ys) => {
+                for y in ys {
+                    ctx.append_output(y);
+                }
+            }
+            Err(e) => set_subgraph_error(ctx, e),
+        }
+    }
+
+    // The gradients are computed by another `Checkpoint` sharing the params, so that
+    // the segment is computed again in it.
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let g = ctx.graph();
+        let mut gys = Vec::new();
+        let mut gy_params = Vec::new();
+        let mut ys = Vec::new();
+        for (k, &y) in self.outputs.iter().enumerate() {
+            if let Some(gy) = ctx.nth_output_grad(k) {
+                gy_params.push(new_param(g, gy.static_shape().as_deref()).id());
+                gys.push(gy);
+                ys.push(y);
+            }
+        }
+        let wrt: Vec<_> = (0..self.params.len())
+            .filter(|&j| ctx.requires_grad(j))
+            .collect();
+        let wrt_params: Vec<_> = wrt.iter().map(|&j| self.params[j]).collect();
+        let grads = subgraph_grads(g, &ys, &wrt_params, &gy_params);
+
+        let mut grad_inputs = Vec::new();
+        let mut outputs = Vec::new();
+        for (&j, gx) in wrt.iter().zip(grads) {
+            if let Some(gx) = gx {
+                grad_inputs.push(j);
+                outputs.push(gx.id());
+            }
+        }
+        if grad_inputs.is_empty() {
+            for _ in &self.params {
+                ctx.append_input_grad(None);
+            }
+            return;
+        }
+
+        let mut inputs: Vec<_> = (0..ctx.num_inputs())
+            .map(|i| Input::new(&ctx.input(i)))
+            .collect();
+        inputs.extend(gys.iter().map(Input::new));
+        let shapes = grad_inputs
+            .iter()
+            .map(|&j| ctx.input(j).static_shape())
+            .collect();
+        let mut params = self.params.clone();
+        params.extend_from_slice(&gy_params);
+        let gxs = build_outputs(g, &inputs, Checkpoint { params, outputs }, shapes);
+        for j in 0..self.params.len() {
+            ctx.append_input_grad(grad_inputs.iter().position(|&i| i == j).map(|i| gxs[i]));
+        }
+    }
+}
+
+impl<T: Float> op::Op<T> for WhileLoop {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) {
+        let g = ctx.graph();
+        let xs: Vec<_> = (0..ctx.num_inputs()).map(|i| ctx.input(i)).collect();
+        match run_loop(g, &self.params, self.cond, &self.body, &xs, false) {
+            Ok(mut states) => {
+                for y in states.pop().unwrap() {
+                    ctx.append_output(y);
+                }
+            }
+            Err(e) => set_subgraph_error(ctx, e),
+        }
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let g = ctx.graph();
+        let gys: Vec<_> = (0..self.body.len())
+            .map(|k| ctx.nth_output_grad(k))
+            .collect();
+        if gys.iter().all(|gy| gy.is_none()) {
+            for _ in 0..ctx.num_inputs() {
+                ctx.append_input_grad(None);
+            }
+            return;
+        }
+        let gy_params: Vec<_> = self
+            .body
+            .iter()
+            .map(|&y| new_param(g, static_shape(g, y)).id())
+            .collect();
+        // The loop variables are always differentiated as the gradients flow through them
+        // across the iterations, while the captured tensors only if on the gradient path.
+        let n = self.body.len();
+        let grad_captures: Vec<_> = (n..self.params.len())
+            .filter(|&j| ctx.requires_grad(j))
+            .collect();
+        let wrt: Vec<_> = (0..n)
+            .chain(grad_captures.iter().cloned())
+            .map(|j| self.params[j])
+            .collect();
+        let body_grads = subgraph_grads(g, &self.body, &wrt, &gy_params)
+            .into_iter()
+            .zip(&wrt)
+            .map(|(gx, &param)| gx.unwrap_or_else(|| zeros_like(g, param)).id())
+            .collect();
+
+        let mut inputs: Vec<_> = (0..ctx.num_inputs())
+            .map(|i| Input::new(&ctx.input(i)))
+            .collect();
+        inputs.extend(gys.iter().flatten().map(Input::new));
+        let shapes = (0..n)
+            .chain(grad_captures.iter().cloned())
+            .map(|i| ctx.input(i).static_shape())
+            .collect();
+        let op = WhileLoopGrad {
+            params: self.params.clone(),
+            cond: self.cond,
+            body: self.body.clone(),
+            gy_params,
+            body_grads,
+            has_gy: gys.iter().map(|gy| gy.is_some()).collect(),
+            grad_captures: grad_captures.clone(),
+        };
+        let gxs = build_outputs(g, &inputs, op, shapes);
+        for gx in &gxs[..n] {
+            ctx.append_input_grad(Some(*gx));
+        }
+        for j in n..self.params.len() {
+            let i = grad_captures.iter().position(|&c| c == j);
+            ctx.append_input_grad(i.map(|i| gxs[n + i]));
+        }
+    }
+}
+
+impl<T: Float> op::Op<T> for WhileLoopGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) {
+        let g = ctx.graph();
+        let xs: Vec<_> = (0..ctx.num_inputs()).map(|i| ctx.input(i)).collect();
+        let (xs, gys) = xs.split_at(self.params.len());
+        match self.backprop(g, xs, gys) {
+            Ok(gxs) => {
+                for gx in gxs {
+                    ctx.append_output(gx);
+                }
+            }
+            Err(e) => set_subgraph_error(ctx, e),
+        }
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        unsupported_grads(
+            ctx,
+            "Graph::while_loop: second-order gradients through loops are not supported",
+        );
+    }
+}
+
+impl WhileLoopGrad {
+    // Runs the loop, then backprops from the last iteration to the first one.
+    fn backprop<F: Float>(
+        &self,
+        g: &Graph<F>,
+        xs: &[NdArrayView<F>],
+        gys: &[NdArrayView<F>],
+    ) -> Result<Vec<NdArray<F>>, EvalError> {
+        let n = self.body.len();
+        let mut states = run_loop(g, &self.params, self.cond, &self.body, xs, true)?;
+        let last = states.pop().unwrap();
+        let mut gys = gys.iter();
+        // Gradients of the loop variables after the current iteration
+        let mut gvs: Vec<_> = self
+            .has_gy
+            .iter()
+            .zip(&last)
+            .map(|(&has_gy, y)| {
+                if has_gy {
+                    gys.next().unwrap().to_owned()
+                } else {
+                    NdArray::zeros(y.shape())
+                }
+            })
+            .collect();
+        // Gradients of the captured tensors accumulated over the iterations
+        let mut gcs: Vec<_> = self
+            .grad_captures
+            .iter()
+            .map(|&j| NdArray::zeros(xs[j].shape()))
+            .collect();
+
+        let mut params = self.params.clone();
+        params.extend_from_slice(&self.gy_params);
+        let schedule = Schedule::new(&self.body_grads, &params, g)?;
+        for state in states.iter().rev() {
+            let mut feeds: Vec<_> = state.iter().map(|x| x.view()).collect();
+            feeds.extend(xs[n..].iter().map(|x| x.view()));
+            feeds.extend(gvs.iter().map(|x| x.view()));
+            let mut grads = run_subgraph(&schedule, g, &feeds)?;
+            for (gc, grad) in gcs.iter_mut().zip(grads.split_off(n)) {
+                *gc += &grad;
+            }
+            gvs = grads;
+        }
+        gvs.append(&mut gcs);
+        Ok(gvs)
+    }
+}
+
+// Resolves `axis` of an array of rank `ndim`, which can be negative.
+fn normalize_axis(axis: isize, ndim: usize) -> Result<usize, EvalError> {
+    let ndim = ndim as isize;
+    if axis < -ndim || axis >= ndim {
+        return Err(EvalError::OpError(op::OpError::OutOfBounds(format!(
+            "Graph::scan: axis {} is out of bounds for rank {}",
+            axis, ndim
+        ))));
+    }
+    Ok(if axis < 0 { axis + ndim } else { axis } as usize)
+}
+
+// Runs a scan from the initial states, the sequence and the captured tensors `xs`.
+//
+// Returns the states before the first step and after each step.
+fn run_scan<F: Float>(
+    g: &Graph<F>,
+    params: &[usize],
+    body: &[usize],
+    axis: isize,
+    xs: &[NdArrayView<F>],
+) -> Result<Vec<Vec<NdArray<F>>>, EvalError> {
+    let n = body.len();
+    let axis = normalize_axis(axis, xs[n].ndim())?;
+    let body = Schedule::new(body, params, g)?;
+    let mut states = vec![xs[..n].iter().map(|x| x.to_owned()).collect::<Vec<_>>()];
+    for slice in xs[n].axis_iter(ndarray::Axis(axis)) {
+        let next = {
+            let mut feeds: Vec<_> = states.last().unwrap().iter().map(|x| x.view()).collect();
+            feeds.push(slice.view());
+            feeds.extend(xs[n + 1..].iter().map(|x| x.view()));
+            run_subgraph(&body, g, &feeds)?
+        };
+        states.push(next);
+    }
+    Ok(states)
+}
+
+// Stacks `arrays` along `axis`, or returns an empty array of the stacked shape of `empty` if
+// there are no arrays.
+fn stack<F: Float>(
+    arrays: &[&NdArray<F>],
+    axis: isize,
+    empty: &NdArrayView<F>,
+) -> Result<NdArray<F>, EvalError> {
+    let axis = normalize_axis(axis, empty.ndim() + 1)?;
+    if arrays.is_empty() {
+        let mut shape = empty.shape().to_vec();
+        shape.insert(axis, 0);
+        return Ok(NdArray::zeros(shape));
+    }
+    let views: Vec<_> = arrays
+        .iter()
+        .map(|x| x.view().insert_axis(ndarray::Axis(axis)))
+        .collect();
+    ndarray::stack(ndarray::Axis(axis), &views).map_err(|e| {
+        EvalError::OpError(op::OpError::NdArrayError(
+            "Graph::scan: the states must keep their shapes".to_string(),
+            e,
+        ))
+    })
+}
+
+impl<T: Float> op::Op<T> for Scan {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) {
+        let g = ctx.graph();
+        let xs: Vec<_> = (0..ctx.num_inputs()).map(|i| ctx.input(i)).collect();
+        let ys = run_scan(g, &self.params, &self.body, self.axis, &xs).and_then(|states| {
+            (0..self.body.len())
+                .map(|k| {
+                    let steps: Vec<_> = states[1..].iter().map(|s| &s[k]).collect();
+                    stack(&steps, self.axis, &xs[k])
+                })
+                .collect::<Result<Vec<_>, _>>()
+        });
+        match ys {
+            Ok(ys) => {
+                for y in ys {
+                    ctx.append_output(y);
+                }
+            }
+            Err(e) => set_subgraph_error(ctx, e),
+        }
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let g = ctx.graph();
+        let n = self.body.len();
+        let gys: Vec<_> = (0..n).map(|k| ctx.nth_output_grad(k)).collect();
+        if gys.iter().all(|gy| gy.is_none()) {
+            for _ in 0..ctx.num_inputs() {
+                ctx.append_input_grad(None);
+            }
+            return;
+        }
+        let gy_params: Vec<_> = self
+            .body
+            .iter()
+            .map(|&y| new_param(g, static_shape(g, y)).id())
+            .collect();
+        // The states are always differentiated as in `WhileLoop`.
+        let grad_sequence = ctx.requires_grad(n);
+        let grad_captures: Vec<_> = (n + 1..self.params.len())
+            .filter(|&j| ctx.requires_grad(j))
+            .collect();
+        let wrt: Vec<_> = (0..n)
+            .chain(if grad_sequence { Some(n) } else { None })
+            .chain(grad_captures.iter().cloned())
+            .collect();
+        let wrt_params: Vec<_> = wrt.iter().map(|&j| self.params[j]).collect();
+        let body_grads = subgraph_grads(g, &self.body, &wrt_params, &gy_params)
+            .into_iter()
+            .zip(&wrt_params)
+            .map(|(gx, &param)| gx.unwrap_or_else(|| zeros_like(g, param)).id())
+            .collect();
+
+        let mut inputs: Vec<_> = (0..ctx.num_inputs())
+            .map(|i| Input::new(&ctx.input(i)))
+            .collect();
+        inputs.extend(gys.iter().flatten().map(Input::new));
+        let shapes = wrt.iter().map(|&i| ctx.input(i).static_shape()).collect();
+        let op = ScanGrad {
+            params: self.params.clone(),
+            body: self.body.clone(),
+            axis: self.axis,
+            gy_params,
+            body_grads,
+            has_gy: gys.iter().map(|gy| gy.is_some()).collect(),
+            grad_sequence,
+            grad_captures,
+        };
+        let gxs = build_outputs(g, &inputs, op, shapes);
+        for j in 0..self.params.len() {
+            ctx.append_input_grad(wrt.iter().position(|&i| i == j).map(|i| gxs[i]));
+        }
+    }
+}
+
+impl<T: Float> op::Op<T> for ScanGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) {
+        let g = ctx.graph();
+        let xs: Vec<_> = (0..ctx.num_inputs()).map(|i| ctx.input(i)).collect();
+        let (xs, gys) = xs.split_at(self.params.len());
+        match self.backprop(g, xs, gys) {
+            Ok(gxs) => {
+                for gx in gxs {
+                    ctx.append_output(gx);
+                }
+            }
+            Err(e) => set_subgraph_error(ctx, e),
+        }
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        unsupported_grads(
+            ctx,
+            "Graph::scan: second-order gradients through loops are not supported",
+        );
+    }
+}
+
+impl ScanGrad {
+    // Runs the scan, then backprops from the last step to the first one.
+    fn backprop<F: Float>(
+        &self,
+        g: &Graph<F>,
+        xs: &[NdArrayView<F>],
+        gys: &[NdArrayView<F>],
+    ) -> Result<Vec<NdArray<F>>, EvalError> {
+        let n = self.body.len();
+        let states = run_scan(g, &self.params, &self.body, self.axis, xs)?;
+        let seq_axis = ndarray::Axis(normalize_axis(self.axis, xs[n].ndim())?);
+        let mut gys = gys.iter();
+        let gys: Vec<_> = self
+            .has_gy
+            .iter()
+            .map(|&has_gy| if has_gy { gys.next() } else { None })
+            .collect();
+        // Gradients of the states after the current step
+        let mut gvs: Vec<_> = states
+            .last()
+            .unwrap()
+            .iter()
+            .map(|s| NdArray::zeros(s.shape()))
+            .collect();
+        // Gradients of the slices of the sequence from the last step
+        let mut gslices = Vec::new();
+        // Gradients of the captured tensors accumulated over the steps
+        let mut gcs: Vec<_> = self
+            .grad_captures
+            .iter()
+            .map(|&j| NdArray::zeros(xs[j].shape()))
+            .collect();
+
+        let mut params = self.params.clone();
+        params.extend_from_slice(&self.gy_params);
+        let schedule = Schedule::new(&self.body_grads, &params, g)?;
+        for (t, state) in states[..states.len() - 1].iter().enumerate().rev() {
+            for (gv, gy) in gvs.iter_mut().zip(&gys) {
+                if let Some(gy) = gy {
+                    let axis = normalize_axis(self.axis, gy.ndim())?;
+                    *gv += &gy.index_axis(ndarray::Axis(axis), t);
+                }
+            }
+            let mut feeds: Vec<_> = state.iter().map(|x| x.view()).collect();
+            feeds.push(xs[n].index_axis(seq_axis, t));
+            feeds.extend(xs[n + 1..].iter().map(|x| x.view()));
+            feeds.extend(gvs.iter().map(|x| x.view()));
+            let mut grads = run_subgraph(&schedule, g, &feeds)?;
+            let captured = grads.split_off(n + self.grad_sequence as usize);
+            for (gc, grad) in gcs.iter_mut().zip(captured) {
+                *gc += &grad;
+            }
+            if self.grad_sequence {
+                gslices.push(grads.pop().unwrap());
+            }
+            gvs = grads;
+        }
+        if self.grad_sequence {
+            let gslices: Vec<_> = gslices.iter().rev().collect();
+            let gseq = if gslices.is_empty() {
+                NdArray::zeros(xs[n].shape())
+            } else {
+                let slice = xs[n].index_axis(seq_axis, 0);
+                stack(&gslices, seq_axis.index() as isize, &slice)?
+            };
+            gvs.push(gseq);
+        }
+        gvs.append(&mut gcs);
+        Ok(gvs)
+    }
+}
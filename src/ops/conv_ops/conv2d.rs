@@ -1,4 +1,5 @@
 use super::*;
+use crate::op::Attribute;
 use crate::tensor::Input;
 use ndarray::IxDyn;
 use std::slice;
@@ -192,90 +193,95 @@ fn conv2d_extract_params<F: Float>(
     })
 }
 
-/// Returns: (conv result, im2col result)
-#[allow(unused_assignments)]
-fn conv2d_impl<F: Float>(
-    x: &NdArrayView<F>,
-    w: &NdArrayView<F>,
-    pad_h: usize,
-    pad_w: usize,
-    stride_h: usize,
-    stride_w: usize,
-    dilation_h: usize,
-    dilation_w: usize,
-) -> Result<(NdArray<F>, NdArray<F>), op::OpError> {
-    let Conv2DParams {
-        batch_size,
-        xch,
-        xh,
-        xw,
-        ych,
-        yh,
-        yw,
-        kh,
-        kw,
-    } = conv2d_extract_params(
-        x, w, pad_h, pad_w, stride_h, stride_w, dilation_h, dilation_w,
-    )?;
-
-    let copied_x = ndarray_ext::copy_if_not_standard(x);
-    let copied_w = ndarray_ext::copy_if_not_standard(w);
-
-    // Prepare pointers to buffers
-    let x_p = copied_x.map(|inner| inner.as_ptr()).unwrap_or(x.as_ptr());
-    let w_p = copied_w.map(|inner| inner.as_ptr()).unwrap_or(w.as_ptr());
-    let x_p = unsafe { slice::from_raw_parts(x_p, x.len()) };
-    let w_p = unsafe { slice::from_raw_parts(w_p, w.len()) };
-
-    // move vectors into ndarrays
-    let cols = im2col_batch(
-        x_p,
-        batch_size,
-        xch as i32,
-        xh as i32,
-        xw as i32,
-        kh as i32,
-        kw as i32,
-        pad_h as i32,
-        pad_w as i32,
-        stride_h as i32,
-        stride_w as i32,
-        dilation_h as i32,
-        dilation_w as i32,
-    );
-
-    unsafe {
-        let size_per_batch_y = ych * yh * yw;
-        let mut y = uninitialized_vec(batch_size * size_per_batch_y);
-        let f;
-        #[cfg(feature = "mkl")]
-        {
-            f = fast_col_x_filter_kernel;
-        }
-        #[cfg(not(feature = "mkl"))]
-        {
-            f = slow_col_x_filter_kernel;
-        }
-        f(
-            cols.as_slice(),
-            w_p,
-            y.as_mut_slice(),
+impl Conv2D {
+    /// Returns: (conv result, im2col result)
+    #[allow(unused_assignments)]
+    fn conv2d_impl<F: Float>(
+        &self,
+        ctx: &crate::op::ComputeContext<F>,
+        x: &NdArrayView<F>,
+        w: &NdArrayView<F>,
+    ) -> Result<(NdArray<F>, NdArray<F>), op::OpError> {
+        let (pad_h, pad_w) = (self.pad, self.pad);
+        let (stride_h, stride_w) = (self.stride, self.stride);
+        let (dilation_h, dilation_w) = (self.dilation, self.dilation);
+        let Conv2DParams {
+            batch_size,
             xch,
+            xh,
+            xw,
             ych,
             yh,
             yw,
             kh,
             kw,
+        } = conv2d_extract_params(
+            x, w, pad_h, pad_w, stride_h, stride_w, dilation_h, dilation_w,
+        )?;
+
+        let copied_x = ndarray_ext::copy_if_not_standard(x);
+        let copied_w = ndarray_ext::copy_if_not_standard(w);
+
+        // Prepare pointers to buffers
+        let x_p = copied_x.map(|inner| inner.as_ptr()).unwrap_or(x.as_ptr());
+        let w_p = copied_w.map(|inner| inner.as_ptr()).unwrap_or(w.as_ptr());
+        let x_p = unsafe { slice::from_raw_parts(x_p, x.len()) };
+        let w_p = unsafe { slice::from_raw_parts(w_p, w.len()) };
+
+        // move vectors into ndarrays
+        let cols = im2col_batch(
+            x_p,
             batch_size,
+            xch as i32,
+            xh as i32,
+            xw as i32,
+            kh as i32,
+            kw as i32,
+            pad_h as i32,
+            pad_w as i32,
+            stride_h as i32,
+            stride_w as i32,
+            dilation_h as i32,
+            dilation_w as i32,
         );
-        let y = NdArray::from_shape_vec_unchecked(IxDyn(&[batch_size, ych, yh, yw]), y);
-        let cols =
-            NdArray::from_shape_vec_unchecked(IxDyn(&[batch_size, xch, kw, kh, yh, yw]), cols);
-        Ok((y, cols))
+
+        unsafe {
+            let size_per_batch_y = ych * yh * yw;
+            let mut y = ctx.alloc_vec(batch_size * size_per_batch_y);
+            let f;
+            #[cfg(feature = "mkl")]
+            {
+                f = fast_col_x_filter_kernel;
+            }
+            #[cfg(not(feature = "mkl"))]
+            {
+                f = slow_col_x_filter_kernel;
+            }
+            f(
+                cols.as_slice(),
+                w_p,
+                y.as_mut_slice(),
+                xch,
+                ych,
+                yh,
+                yw,
+                kh,
+                kw,
+                batch_size,
+            );
+            let y = NdArray::from_shape_vec_unchecked(IxDyn(&[batch_size, ych, yh, yw]), y);
+            let cols =
+                NdArray::from_shape_vec_unchecked(IxDyn(&[batch_size, xch, kw, kh, yh, yw]), cols);
+            Ok((y, cols))
+        }
     }
 }
 
-fn conv2d_with_cols_impl<F: Float>(cols: &NdArrayView<F>, w: &NdArrayView<F>) -> NdArray<F> {
+fn conv2d_with_cols_impl<F: Float>(
+    ctx: &crate::op::ComputeContext<F>,
+    cols: &NdArrayView<F>,
+    w: &NdArrayView<F>,
+) -> NdArray<F> {
     // Extract size params
     let cols_shape = cols.shape();
     let k_shape = w.shape();
@@ -292,7 +298,7 @@ fn conv2d_with_cols_impl<F: Float>(cols: &NdArrayView<F>, w: &NdArrayView<F>) ->
         w.as_slice().unwrap()
     };
     unsafe {
-        let mut y = uninitialized_vec(batch_size * size_per_batch_y);
+        let mut y = ctx.alloc_vec(batch_size * size_per_batch_y);
         let f;
         #[cfg(feature = "mkl")]
         {
@@ -323,16 +329,7 @@ impl<T: Float> crate::op::Op<T> for Conv2D {
         // Grab inputs
         let x = &ctx.input(0);
         let w = &ctx.input(1);
-        let result = conv2d_impl(
-            x,
-            w,
-            self.pad,
-            self.pad,
-            self.stride,
-            self.stride,
-            self.dilation,
-            self.dilation,
-        );
+        let result = self.conv2d_impl(ctx, x, w);
         match result {
             Ok((y, cols)) => {
                 ctx.append_output(y);
@@ -376,6 +373,28 @@ impl<T: Float> crate::op::Op<T> for Conv2D {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(Some(gw));
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.pad as i64),
+            Attribute::Int(self.stride as i64),
+            Attribute::Int(self.dilation as i64),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let [batch, xch, xh, xw] = static_4d_shape(ctx, 0, "conv2d: lhs input")?;
+        let [ych, wch, kh, kw] = static_4d_shape(ctx, 1, "conv2d: filter")?;
+        if crate::ops::array_ops::merge_dims(xch, wch).is_none() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "conv2d: input channel dim ({:?}) must match filter's second dim ({:?})",
+                xch, wch
+            )));
+        }
+        let yh = static_conv_out_size(xh, kh, self.pad, self.stride, self.dilation)?;
+        let yw = static_conv_out_size(xw, kw, self.pad, self.stride, self.dilation)?;
+        Ok(Some(vec![batch, ych, yh, yw]))
+    }
 }
 
 impl<T: Float> crate::op::Op<T> for Conv2DWithCols {
@@ -384,7 +403,7 @@ impl<T: Float> crate::op::Op<T> for Conv2DWithCols {
         // Grab inputs
         let cols = &ctx.input(0);
         let w = &ctx.input(1);
-        let y = conv2d_with_cols_impl(cols, w);
+        let y = conv2d_with_cols_impl(ctx, cols, w);
         ctx.append_output(y);
     }
 
@@ -422,6 +441,14 @@ impl<T: Float> crate::op::Op<T> for Conv2DWithCols {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(Some(gw));
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.pad as i64),
+            Attribute::Int(self.stride as i64),
+            Attribute::Int(self.dilation as i64),
+        ])
+    }
 }
 
 fn conv2d_filter_grad_impl<F: Float>(
@@ -562,4 +589,16 @@ impl<T: Float> crate::op::Op<T> for Conv2DFilterGrad {
         ctx.append_input_grad(Some(ggy));
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.pad as i64),
+            Attribute::Int(self.stride as i64),
+            Attribute::Int(self.dilation as i64),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(2).map(|s| s.to_vec()))
+    }
 }
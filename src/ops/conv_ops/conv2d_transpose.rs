@@ -1,4 +1,5 @@
 use super::*;
+use crate::op::Attribute;
 
 pub struct Conv2DTranspose {
     pub pad: usize,
@@ -279,6 +280,28 @@ impl<T: Float> crate::op::Op<T> for Conv2DTranspose {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(Some(gw));
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.pad as i64),
+            Attribute::Int(self.stride as i64),
+            Attribute::Int(self.dilation as i64),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let [batch, ych, yh, yw] = static_4d_shape(ctx, 0, "conv2d_transpose: input")?;
+        let [wch, xch, kh, kw] = static_4d_shape(ctx, 1, "conv2d_transpose: filter")?;
+        if crate::ops::array_ops::merge_dims(ych, wch).is_none() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "conv2d_transpose: Number of input channels ({:?}) must match second filter dim ({:?})",
+                ych, wch
+            )));
+        }
+        let xh = static_conv_transpose_out_size(yh, kh, self.pad, self.stride, self.dilation);
+        let xw = static_conv_transpose_out_size(yw, kw, self.pad, self.stride, self.dilation);
+        Ok(Some(vec![batch, xch, xh, xw]))
+    }
 }
 
 fn conv2d_transpose_filter_grad_impl<F: Float>(
@@ -454,6 +477,18 @@ impl<T: Float> crate::op::Op<T> for Conv2DTransposeFilterGrad {
         ctx.append_input_grad(Some(ggx));
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.pad as i64),
+            Attribute::Int(self.stride as i64),
+            Attribute::Int(self.dilation as i64),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(2).map(|s| s.to_vec()))
+    }
 }
 
 #[test]
@@ -1,4 +1,5 @@
 use super::*;
+use crate::op::Attribute;
 
 pub struct MaxPool2D {
     pub pad: usize,
@@ -7,15 +8,15 @@ pub struct MaxPool2D {
 }
 
 pub struct MaxPool2DGrad {
-    pad: usize,
-    stride: usize,
-    size: usize,
+    pub pad: usize,
+    pub stride: usize,
+    pub size: usize,
 }
 
 pub struct MaxPool2DGradGrad {
-    pad: usize,
-    stride: usize,
-    size: usize,
+    pub pad: usize,
+    pub stride: usize,
+    pub size: usize,
 }
 
 macro_rules! impl_max_pool {
@@ -233,6 +234,22 @@ impl<T: Float> crate::op::Op<T> for MaxPool2D {
         );
         ctx.append_input_grad(Some(gx));
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.pad as i64),
+            Attribute::Int(self.stride as i64),
+            Attribute::Int(self.size as i64),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let [batch, c, xh, xw] = static_4d_shape(ctx, 0, "max_pool2d: input")?;
+        let size = self.size as isize;
+        let yh = static_conv_out_size(xh, size, self.pad, self.stride, 1)?;
+        let yw = static_conv_out_size(xw, size, self.pad, self.stride, 1)?;
+        Ok(Some(vec![batch, c, yh, yw]))
+    }
 }
 
 impl<T: Float> crate::op::Op<T> for MaxPool2DGrad {
@@ -280,6 +297,22 @@ impl<T: Float> crate::op::Op<T> for MaxPool2DGrad {
         ctx.append_input_grad(Some(ggy));
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.pad as i64),
+            Attribute::Int(self.stride as i64),
+            Attribute::Int(self.size as i64),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let [batch, c, yh, yw] = static_4d_shape(ctx, 0, "max_pool2d_grad: input")?;
+        let size = self.size as isize;
+        let xh = static_conv_transpose_out_size(yh, size, self.pad, self.stride, 1);
+        let xw = static_conv_transpose_out_size(yw, size, self.pad, self.stride, 1);
+        Ok(Some(vec![batch, c, xh, xw]))
+    }
 }
 
 impl<T: Float> crate::op::Op<T> for MaxPool2DGradGrad {
@@ -314,7 +347,28 @@ impl<T: Float> crate::op::Op<T> for MaxPool2DGradGrad {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // Linear in ggx, so this scatters back with the same argmax.
+        let s = ctx.graph();
+        let argmax = &ctx.input(1);
+        let gggx = Tensor::builder()
+            .set_ro_inputs(&[&ctx.output_grad(), argmax])
+            .build(
+                s,
+                MaxPool2DGrad {
+                    pad: self.pad,
+                    stride: self.stride,
+                    size: self.size,
+                },
+            );
+        ctx.append_input_grad(Some(gggx));
         ctx.append_input_grad(None);
-        ctx.append_input_grad(None);
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.pad as i64),
+            Attribute::Int(self.stride as i64),
+            Attribute::Int(self.size as i64),
+        ])
     }
 }
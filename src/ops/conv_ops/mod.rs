@@ -24,6 +24,58 @@ use crate::ops::mkl_ffi::{
     CblasTranspose::CblasTrans, MklInt, CBLAS_ROW_MAJOR,
 };
 
+// Static shape of the `i` th input of conv ops, which must be 4D.
+fn static_4d_shape<T: Float>(
+    ctx: &op::ShapeContext<T>,
+    i: usize,
+    name: &str,
+) -> Result<[isize; 4], op::OpError> {
+    match ctx.input_shape(i) {
+        Some(&[a, b, c, d]) => Ok([a, b, c, d]),
+        Some(shape) => Err(op::OpError::IncompatibleShape(format!(
+            "{} must be 4D (got {:?})",
+            name, shape
+        ))),
+        None => Ok([-1; 4]),
+    }
+}
+
+// Static size of a spatial dim of the output of a convolution or pooling.
+fn static_conv_out_size(
+    x: isize,
+    k: isize,
+    pad: usize,
+    stride: usize,
+    dilation: usize,
+) -> Result<isize, op::OpError> {
+    if x < 0 || k < 0 {
+        return Ok(-1);
+    }
+    let padded = x + 2 * pad as isize;
+    let window = dilation as isize * (k - 1) + 1;
+    if padded < window {
+        return Err(op::OpError::IncompatibleShape(format!(
+            "Window of size {} is larger than the padded input of size {}",
+            window, padded
+        )));
+    }
+    Ok((padded - window) / stride as isize + 1)
+}
+
+// Inverse of `static_conv_out_size`
+fn static_conv_transpose_out_size(
+    y: isize,
+    k: isize,
+    pad: usize,
+    stride: usize,
+    dilation: usize,
+) -> isize {
+    if y < 0 || k < 0 {
+        return -1;
+    }
+    stride as isize * (y - 1) - 2 * pad as isize + dilation as isize * (k - 1) + 1
+}
+
 #[test]
 fn test_im2col_batch() {
     let op = conv2d::Conv2D {
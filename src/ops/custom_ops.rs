@@ -0,0 +1,52 @@
+use crate::op;
+use crate::Float;
+
+type ComputeFn<T> = Box<dyn Fn(&mut op::ComputeContext<T>) + Send + Sync>;
+type GradFn<T> = Box<dyn Fn(&mut op::GradientContext<T>) + Send + Sync>;
+
+/// Op defined by closures (see `Graph::custom_op`).
+pub struct CustomOp<T: Float> {
+    pub compute: ComputeFn<T>,
+    pub grad: GradFn<T>,
+}
+
+/// Passes through the last input, of which the gradient is replaced with the given one
+/// (see `Graph::custom_gradient`).
+///
+/// The first inputs are those of the subgraph computing the last one, to which
+/// the gradients are propagated.
+pub struct CustomGradient<T: Float> {
+    pub grad: GradFn<T>,
+}
+
+impl<T: Float> op::Op<T> for CustomOp<T> {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) {
+        (self.compute)(ctx)
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        (self.grad)(ctx)
+    }
+}
+
+impl<T: Float> op::Op<T> for CustomGradient<T> {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) {
+        let ret = ctx.input(ctx.num_inputs() - 1);
+        ctx.append_output_view(ret);
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        (self.grad)(ctx);
+        // The gradients are propagated to `xs`, i.e. all the inputs but the result of the subgraph.
+        let num_xs = ctx.num_inputs() - 1;
+        assert_eq!(
+            ctx.num_input_grads(),
+            num_xs,
+            "Graph::custom_gradient: `grad` must append one gradient for each of `xs`"
+        );
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(ctx.num_inputs() - 1).map(|s| s.to_vec()))
+    }
+}
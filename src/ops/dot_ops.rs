@@ -1,7 +1,8 @@
-/// Some gemm kernel usages are ported from ndarray
 use crate::ndarray_ext::NdArray;
 #[cfg(feature = "mkl")]
 use crate::ndarray_ext::{get_batch_ptrs, get_batch_ptrs_mut};
+/// Some gemm kernel usages are ported from ndarray
+use crate::op::Attribute;
 #[cfg(feature = "mkl")]
 use crate::ops::mkl_ffi::*;
 #[cfg(feature = "mkl")]
@@ -523,6 +524,162 @@ pub struct BatchMatMul {
     pub transpose_b: bool,
 }
 
+// Static shape of the (batched) matrix product of `a` and `b` of the same rank.
+fn infer_dot_shape(
+    a: &[isize],
+    b: &[isize],
+    transpose_a: bool,
+    transpose_b: bool,
+) -> Result<Vec<isize>, op::OpError> {
+    let rank = a.len();
+    let (m, k) = if transpose_a {
+        (a[rank - 1], a[rank - 2])
+    } else {
+        (a[rank - 2], a[rank - 1])
+    };
+    let (k2, n) = if transpose_b {
+        (b[rank - 1], b[rank - 2])
+    } else {
+        (b[rank - 2], b[rank - 1])
+    };
+    let mismatch = || {
+        op::OpError::IncompatibleShape(format!(
+            "Incompatible shapes for matrix product: {:?}{} vs {:?}{}",
+            a,
+            if transpose_a { "^T" } else { "" },
+            b,
+            if transpose_b { "^T" } else { "" }
+        ))
+    };
+    crate::ops::array_ops::merge_dims(k, k2).ok_or_else(mismatch)?;
+    let mut ret = Vec::with_capacity(rank);
+    for (&x, &y) in a[..rank - 2].iter().zip(&b[..rank - 2]) {
+        ret.push(crate::ops::array_ops::merge_dims(x, y).ok_or_else(mismatch)?);
+    }
+    ret.push(m);
+    ret.push(n);
+    Ok(ret)
+}
+
+// Gradients of `op(a) op(b)` with respect to `a` and `b`, where `op` transposes its operand
+// if `transpose_a` (`transpose_b`) is set.
+// `dot(x, y, transpose_x, transpose_y)` builds the product of `x` and `y`.
+fn dot_grads<'g, T: Float, D>(
+    a: &Tensor<'g, T>,
+    b: &Tensor<'g, T>,
+    gy: &Tensor<'g, T>,
+    transpose_a: bool,
+    transpose_b: bool,
+    dot: D,
+) -> (Tensor<'g, T>, Tensor<'g, T>)
+where
+    D: Fn(&Tensor<'g, T>, &Tensor<'g, T>, bool, bool) -> Tensor<'g, T>,
+{
+    let ga = if transpose_a {
+        dot(b, gy, transpose_b, true)
+    } else {
+        dot(gy, b, false, !transpose_b)
+    };
+    let gb = if transpose_b {
+        dot(gy, a, true, transpose_a)
+    } else {
+        dot(a, gy, !transpose_a, false)
+    };
+    (ga, gb)
+}
+
+// Returns the `axis` th dim of `x` if it's known statically.
+fn static_dim<T: Float>(x: &Tensor<T>, axis: usize) -> Option<isize> {
+    x.static_shape()?.get(axis).copied().filter(|&d| d >= 0)
+}
+
+impl MatMul {
+    // Multiplies the matrices of the batched inputs (see `Op::vmap`) in a single product with
+    // the unbatched input.
+    fn batched_matmul<'g, T: Float>(
+        &self,
+        ctx: &crate::op::VmapContext<'g, T>,
+    ) -> Option<Tensor<'g, T>> {
+        let g = ctx.graph();
+        let (a, b) = (ctx.input(0), ctx.input(1));
+        let batch_size = ctx.batch_size() as isize;
+        let matmul = |a: &Tensor<'g, T>, b: &Tensor<'g, T>, transpose_a, transpose_b| {
+            Tensor::builder().set_ro_inputs(&[a, b]).build(
+                g,
+                MatMul {
+                    transpose_a,
+                    transpose_b,
+                },
+            )
+        };
+        // The `axis` th dim of `x`, or a tensor of it if it's not known statically.
+        let dim = |x: &Tensor<'g, T>, axis: isize| {
+            static_dim(x, axis as usize).ok_or_else(|| g.slice(g.shape(x), &[axis], &[axis + 1]))
+        };
+        let shape = |dims: &[Result<isize, Tensor<'g, T>>]| {
+            let known: Option<Vec<_>> = dims.iter().map(|d| d.as_ref().ok()).collect();
+            match known {
+                Some(dims) => {
+                    let dims = dims.into_iter().map(|&d| T::from(d).unwrap());
+                    g.convert_to_tensor(ndarray::Array1::from_iter(dims))
+                }
+                None => {
+                    let dims: Vec<_> = dims
+                        .iter()
+                        .map(|d| match d {
+                            Ok(d) => g.convert_to_tensor(ndarray::arr1(&[T::from(*d).unwrap()])),
+                            Err(d) => *d,
+                        })
+                        .collect();
+                    g.concat(&dims, 0)
+                }
+            }
+        };
+        let n = static_dim(&ctx.output(), 1);
+        match (ctx.is_batched(0), ctx.is_batched(1)) {
+            (true, true) => Some(Tensor::builder().set_ro_inputs(&[&a, &b]).build(
+                g,
+                BatchMatMul {
+                    transpose_a: self.transpose_a,
+                    transpose_b: self.transpose_b,
+                },
+            )),
+            (true, false) => {
+                // Stacks the rows of the batch of `a`: (batch * m, k) x (k, n)
+                let a = if self.transpose_a {
+                    g.transpose(a, &[0, 2, 1])
+                } else {
+                    a
+                };
+                let n = n
+                    .ok_or(())
+                    .or_else(|_| dim(&b, if self.transpose_b { 0 } else { 1 }));
+                let a = g.reshape(a, &shape(&[Ok(-1), dim(&a, 2)]));
+                let y = matmul(&a, &b, false, self.transpose_b);
+                Some(g.reshape(y, &shape(&[Ok(batch_size), Ok(-1), n])))
+            }
+            (false, true) => {
+                // Stacks the columns of the batch of `b`: (m, k) x (k, batch * n)
+                let b = if self.transpose_b {
+                    g.transpose(b, &[0, 2, 1])
+                } else {
+                    b
+                };
+                let n = n.ok_or(()).or_else(|_| dim(&b, 2));
+                let bn = match n {
+                    Ok(n) => Ok(batch_size * n),
+                    Err(ref n) => Err(*n * g.scalar(T::from(batch_size).unwrap())),
+                };
+                let b = g.reshape(g.transpose(b, &[1, 0, 2]), &shape(&[Ok(-1), bn]));
+                let y = matmul(&a, &b, self.transpose_a, false);
+                let y = g.reshape(y, &shape(&[Ok(-1), Ok(batch_size), n]));
+                Some(g.transpose(y, &[1, 0, 2]))
+            }
+            (false, false) => None,
+        }
+    }
+}
+
 impl<T: Float> op::Op<T> for MatMul {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let mut a = ctx
@@ -549,10 +706,9 @@ impl<T: Float> op::Op<T> for MatMul {
         let rhs_s0 = b.strides()[0];
         let column_major = lhs_s0 == 1 && rhs_s0 == 1;
         // A is Copy so this is safe
-        let mut v = Vec::with_capacity(m * n);
         let mut c;
         unsafe {
-            v.set_len(m * n);
+            let v = ctx.alloc_vec(m * n);
             c = ndarray::Array::from_shape_vec_unchecked((m, n).set_f(column_major), v);
         }
 
@@ -569,25 +725,75 @@ impl<T: Float> op::Op<T> for MatMul {
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         let s = ctx.graph();
-        let gy = &ctx.output_grad();
-        let opa = Tensor::builder().set_ro_inputs(&[gy, &ctx.input(1)]).build(
-            s,
-            MatMul {
-                transpose_a: false,
-                transpose_b: true,
+        let (ga, gb) = dot_grads(
+            &ctx.input(0),
+            &ctx.input(1),
+            &ctx.output_grad(),
+            self.transpose_a,
+            self.transpose_b,
+            |x, y, transpose_a, transpose_b| {
+                Tensor::builder().set_ro_inputs(&[x, y]).build(
+                    s,
+                    MatMul {
+                        transpose_a,
+                        transpose_b,
+                    },
+                )
             },
         );
+        ctx.append_input_grad(Some(ga));
+        ctx.append_input_grad(Some(gb));
+    }
 
-        let opb = Tensor::builder().set_ro_inputs(&[&ctx.input(0), gy]).build(
-            s,
-            MatMul {
-                transpose_a: true,
-                transpose_b: false,
-            },
-        );
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let s = ctx.graph();
+        // The product is bilinear in the inputs.
+        let matmul = |a: &Tensor<'_, T>, b: &Tensor<'_, T>| {
+            Tensor::builder().set_ro_inputs(&[a, b]).build(
+                s,
+                MatMul {
+                    transpose_a: self.transpose_a,
+                    transpose_b: self.transpose_b,
+                },
+            )
+        };
+        let ta = ctx.input_tangent(0).map(|ta| matmul(&ta, &ctx.input(1)));
+        let tb = ctx.input_tangent(1).map(|tb| matmul(&ctx.input(0), &tb));
+        let ty = match (ta, tb) {
+            (Some(ta), Some(tb)) => Some(ta + tb),
+            (ta, tb) => ta.or(tb),
+        };
+        ctx.append_output_tangent(ty);
+    }
 
-        ctx.append_input_grad(Some(opa));
-        ctx.append_input_grad(Some(opb));
+    fn vmap(&self, ctx: &mut crate::op::VmapContext<T>) {
+        match self.batched_matmul(ctx) {
+            Some(y) => ctx.set_output(Some(y)),
+            None => op::vmap_by_compute(ctx),
+        }
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.transpose_a, self.transpose_b)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Bool(self.transpose_a),
+            Attribute::Bool(self.transpose_b),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let a = ctx.input_shape(0).unwrap_or(&[-1, -1]);
+        let b = ctx.input_shape(1).unwrap_or(&[-1, -1]);
+        if a.len() != 2 || b.len() != 2 {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "MatMul: inputs must be 2D, actual: {:?} vs {:?}",
+                a, b
+            )));
+        }
+        infer_dot_shape(a, b, self.transpose_a, self.transpose_b).map(Some)
     }
 }
 
@@ -639,10 +845,9 @@ impl<T: Float> op::Op<T> for BatchMatMul {
         };
         // A is Copy so this is safe
         let size: usize = ret_shape.iter().product();
-        let mut v = Vec::with_capacity(size);
         let mut c;
         unsafe {
-            v.set_len(size);
+            let v = ctx.alloc_vec(size);
             // BatchMatMul's ret val is a c-order array.
             c = ndarray::Array::from_shape_vec_unchecked(ret_shape, v);
         }
@@ -660,25 +865,50 @@ impl<T: Float> op::Op<T> for BatchMatMul {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
-        let gy = &ctx.output_grad();
-        let opa = Tensor::builder().set_ro_inputs(&[gy, &ctx.input(1)]).build(
-            ctx.graph(),
-            BatchMatMul {
-                transpose_a: false,
-                transpose_b: true,
+        let s = ctx.graph();
+        let (ga, gb) = dot_grads(
+            &ctx.input(0),
+            &ctx.input(1),
+            &ctx.output_grad(),
+            self.transpose_a,
+            self.transpose_b,
+            |x, y, transpose_a, transpose_b| {
+                Tensor::builder().set_ro_inputs(&[x, y]).build(
+                    s,
+                    BatchMatMul {
+                        transpose_a,
+                        transpose_b,
+                    },
+                )
             },
         );
+        ctx.append_input_grad(Some(ga));
+        ctx.append_input_grad(Some(gb));
+    }
 
-        let opb = Tensor::builder().set_ro_inputs(&[&ctx.input(0), gy]).build(
-            ctx.graph(),
-            BatchMatMul {
-                transpose_a: true,
-                transpose_b: false,
-            },
-        );
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.transpose_a, self.transpose_b)))
+    }
 
-        ctx.append_input_grad(Some(opa));
-        ctx.append_input_grad(Some(opb));
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Bool(self.transpose_a),
+            Attribute::Bool(self.transpose_b),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let (a, b) = match (ctx.input_shape(0), ctx.input_shape(1)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Ok(None),
+        };
+        if a.len() < 2 || a.len() != b.len() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "BatchMatMul: inputs must have the same rank >= 2, actual: {:?} vs {:?}",
+                a, b
+            )));
+        }
+        infer_dot_shape(a, b, self.transpose_a, self.transpose_b).map(Some)
     }
 }
 
@@ -760,4 +990,12 @@ impl<T: Float> op::Op<T> for TensordotPreprocess {
         ctx.append_input_grad(None);
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
@@ -0,0 +1,297 @@
+use crate::ndarray_ext::NdArrayView;
+use crate::op;
+use crate::tensor::Tensor;
+use crate::Float;
+use crate::Graph;
+
+/// Elementwise functions which can be fused into a single pass.
+///
+/// See `Op::elementwise` and `Graph::fuse_elementwise_ops`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Elementwise<T: Float> {
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Asinh,
+    Acosh,
+    Atanh,
+    Exp,
+    Exp2,
+    Exp10,
+    Sqrt,
+    Neg,
+    Floor,
+    Ceil,
+    Sign,
+    Inv,
+    InvSqrt,
+    Square,
+    Abs,
+    Log2,
+    Log10,
+    Ln,
+    Pow(T),
+    Sigmoid,
+    ReLU,
+    Softplus,
+    ELU(T),
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl<T: Float> Elementwise<T> {
+    /// Returns the number of the arguments.
+    #[inline]
+    pub fn arity(&self) -> usize {
+        match self {
+            Elementwise::Add | Elementwise::Sub | Elementwise::Mul | Elementwise::Div => 2,
+            _ => 1,
+        }
+    }
+
+    /// Applies this function to `a` (and `b` if binary).
+    #[inline]
+    pub fn apply(&self, a: T, b: T) -> T {
+        match *self {
+            Elementwise::Sin => a.sin(),
+            Elementwise::Cos => a.cos(),
+            Elementwise::Tan => a.tan(),
+            Elementwise::Asin => a.asin(),
+            Elementwise::Acos => a.acos(),
+            Elementwise::Atan => a.atan(),
+            Elementwise::Sinh => a.sinh(),
+            Elementwise::Cosh => a.cosh(),
+            Elementwise::Tanh => a.tanh(),
+            Elementwise::Asinh => a.asinh(),
+            Elementwise::Acosh => a.acosh(),
+            Elementwise::Atanh => a.atanh(),
+            Elementwise::Exp => a.exp(),
+            Elementwise::Exp2 => a.exp2(),
+            Elementwise::Exp10 => T::from(10.).unwrap().powf(a),
+            Elementwise::Sqrt => a.sqrt(),
+            Elementwise::Neg => -a,
+            Elementwise::Floor => a.floor(),
+            Elementwise::Ceil => a.ceil(),
+            Elementwise::Sign => {
+                if a == T::zero() {
+                    T::zero()
+                } else {
+                    a.signum()
+                }
+            }
+            Elementwise::Inv => a.recip(),
+            Elementwise::InvSqrt => a.sqrt().recip(),
+            Elementwise::Square => a * a,
+            Elementwise::Abs => a.abs(),
+            Elementwise::Log2 => a.log2(),
+            Elementwise::Log10 => a.log10(),
+            Elementwise::Ln => a.ln(),
+            Elementwise::Pow(p) => a.powf(p),
+            Elementwise::Sigmoid => {
+                let half = T::from(0.5).unwrap();
+                ((a * half).tanh() * half) + half
+            }
+            Elementwise::ReLU => a.max(T::zero()),
+            Elementwise::Softplus => (a.exp() + T::one()).ln(),
+            Elementwise::ELU(alpha) => {
+                if a > T::zero() {
+                    a
+                } else {
+                    alpha * (a.exp() - T::one())
+                }
+            }
+            Elementwise::Add => a + b,
+            Elementwise::Sub => a - b,
+            Elementwise::Mul => a * b,
+            Elementwise::Div => a / b,
+        }
+    }
+
+    // Builds the unfused tensor computing this function.
+    pub(crate) fn build<'g>(
+        &self,
+        g: &'g Graph<T>,
+        a: Tensor<'g, T>,
+        b: Tensor<'g, T>,
+    ) -> Tensor<'g, T> {
+        match *self {
+            Elementwise::Sin => g.sin(a),
+            Elementwise::Cos => g.cos(a),
+            Elementwise::Tan => g.tan(a),
+            Elementwise::Asin => g.asin(a),
+            Elementwise::Acos => g.acos(a),
+            Elementwise::Atan => g.atan(a),
+            Elementwise::Sinh => g.sinh(a),
+            Elementwise::Cosh => g.cosh(a),
+            Elementwise::Tanh => g.tanh(a),
+            Elementwise::Asinh => g.asinh(a),
+            Elementwise::Acosh => g.acosh(a),
+            Elementwise::Atanh => g.atanh(a),
+            Elementwise::Exp => g.exp(a),
+            Elementwise::Exp2 => g.exp2(a),
+            Elementwise::Exp10 => g.exp10(a),
+            Elementwise::Sqrt => g.sqrt(a),
+            Elementwise::Neg => g.neg(a),
+            Elementwise::Floor => g.floor(a),
+            Elementwise::Ceil => g.ceil(a),
+            Elementwise::Sign => g.sign(a),
+            Elementwise::Inv => g.inv(a),
+            Elementwise::InvSqrt => g.inv_sqrt(a),
+            Elementwise::Square => g.square(a),
+            Elementwise::Abs => g.abs(a),
+            Elementwise::Log2 => g.log2(a),
+            Elementwise::Log10 => g.log10(a),
+            Elementwise::Ln => g.ln(a),
+            Elementwise::Pow(p) => g.pow(a, p),
+            Elementwise::Sigmoid => g.sigmoid(a),
+            Elementwise::ReLU => g.relu(a),
+            Elementwise::Softplus => g.softplus(a),
+            Elementwise::ELU(alpha) => g.elu(a, alpha),
+            Elementwise::Add => g.add(a, b),
+            Elementwise::Sub => g.sub(a, b),
+            Elementwise::Mul => g.mul(a, b),
+            Elementwise::Div => g.div(a, b),
+        }
+    }
+}
+
+/// Evaluates an expression of elementwise functions in a single pass over the inputs.
+///
+/// Register `i < num_inputs` holds an element of the `i` th input, and register `num_inputs + j`
+/// holds the result of `instructions[j]` applied to the registers of its arguments.
+/// The last register is the output.
+pub struct FusedElementwise<T: Float> {
+    pub instructions: Vec<(Elementwise<T>, [usize; 2])>,
+    pub num_inputs: usize,
+}
+
+// Returns the shape to which all of `shapes` are broadcast, if any.
+fn broadcast_shape(shapes: &[&[usize]]) -> Option<Vec<usize>> {
+    let ndim = shapes.iter().map(|s| s.len()).max().unwrap_or(0);
+    let mut ret = vec![1; ndim];
+    for shape in shapes {
+        for (r, &d) in ret[ndim - shape.len()..].iter_mut().zip(*shape) {
+            if *r == 1 {
+                *r = d;
+            } else if d != 1 && d != *r {
+                return None;
+            }
+        }
+    }
+    Some(ret)
+}
+
+impl<T: Float> FusedElementwise<T> {
+    #[inline]
+    fn eval(&self, regs: &mut [T]) -> T {
+        let n = self.num_inputs;
+        for (j, (f, args)) in self.instructions.iter().enumerate() {
+            regs[n + j] = f.apply(regs[args[0]], regs[args[1]]);
+        }
+        regs[regs.len() - 1]
+    }
+}
+
+impl<T: Float> op::Op<T> for FusedElementwise<T> {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
+        let xs: Vec<NdArrayView<T>> = (0..self.num_inputs).map(|i| ctx.input(i)).collect();
+        let shapes: Vec<&[usize]> = xs.iter().map(|x| x.shape()).collect();
+        let shape = match broadcast_shape(&shapes) {
+            Some(shape) => shape,
+            None => {
+                ctx.set_error(op::OpError::IncompatibleShape(format!(
+                    "FusedElementwise: can't broadcast the inputs of shapes {:?}",
+                    shapes
+                )));
+                return;
+            }
+        };
+        let xs: Vec<_> = xs
+            .iter()
+            .map(|x| x.broadcast(shape.as_slice()).unwrap())
+            .collect();
+        let mut y = ctx.alloc_output(&shape);
+        let mut regs = vec![T::zero(); self.num_inputs + self.instructions.len()];
+
+        let slices: Option<Vec<&[T]>> = xs.iter().map(|x| x.as_slice()).collect();
+        if let Some(slices) = slices {
+            // All the inputs are contiguous and not broadcast.
+            for (i, y) in y.as_slice_mut().unwrap().iter_mut().enumerate() {
+                for (reg, x) in regs.iter_mut().zip(&slices) {
+                    *reg = x[i];
+                }
+                *y = self.eval(&mut regs);
+            }
+        } else {
+            let mut iters: Vec<_> = xs.iter().map(|x| x.iter()).collect();
+            for y in y.iter_mut() {
+                for (reg, x) in regs.iter_mut().zip(iters.iter_mut()) {
+                    *reg = *x.next().unwrap();
+                }
+                *y = self.eval(&mut regs);
+            }
+        }
+        ctx.append_output(y);
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let g = ctx.graph();
+        let n = self.num_inputs;
+
+        // Rebuild the unfused expression, then backprop through it using the grads of the
+        // unfused ops.
+        let mut values: Vec<Tensor<T>> = (0..n).map(|i| ctx.input(i)).collect();
+        for (f, args) in &self.instructions {
+            let y = f.build(g, values[args[0]], values[args[1]]);
+            values.push(y);
+        }
+        let mut grads: Vec<Vec<Tensor<T>>> = vec![Vec::new(); values.len()];
+        grads[values.len() - 1].push(ctx.output_grad());
+        for (j, (f, args)) in self.instructions.iter().enumerate().rev() {
+            let gy = match grads[n + j].len() {
+                0 => continue,
+                1 => grads[n + j][0],
+                _ => g.add_n(&grads[n + j]),
+            };
+            let mut y_ctx = op::GradientContext::new(gy, values[n + j], g);
+            values[n + j].inner().op.grad(&mut y_ctx);
+            let gxs = y_ctx.extract_input_grads();
+            for (gx, &arg) in gxs.into_iter().zip(&args[..f.arity()]) {
+                if let Some(gx) = gx {
+                    grads[arg].push(gx);
+                }
+            }
+        }
+        for gxs in &grads[..n] {
+            let gx = match gxs.len() {
+                0 => None,
+                1 => Some(gxs[0]),
+                _ => Some(g.add_n(gxs)),
+            };
+            ctx.append_input_grad(gx);
+        }
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.instructions))
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        ctx.broadcast_input_shapes()
+    }
+}
+
+#[test]
+fn test_broadcast_shape() {
+    assert_eq!(broadcast_shape(&[&[2, 3], &[3], &[]]), Some(vec![2, 3]));
+    assert_eq!(broadcast_shape(&[&[2, 1], &[1, 4]]), Some(vec![2, 4]));
+    assert_eq!(broadcast_shape(&[&[2, 3], &[2]]), None);
+}
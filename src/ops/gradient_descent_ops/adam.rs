@@ -1,3 +1,4 @@
+use crate::op::Attribute;
 use crate::optimizers::adam::StaticParams;
 use crate::Float;
 
@@ -55,4 +56,13 @@ impl<F: Float> crate::op::Op<F> for AdamOp<F> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<F>) {
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<F>>> {
+        Some(vec![
+            Attribute::Float(self.static_params.alpha),
+            Attribute::Float(self.static_params.eps),
+            Attribute::Float(self.static_params.b1),
+            Attribute::Float(self.static_params.b2),
+        ])
+    }
 }
@@ -1,3 +1,4 @@
+use crate::op::Attribute;
 use crate::Float;
 
 pub(crate) struct SGDOp<T: Float> {
@@ -19,4 +20,8 @@ impl<T: Float> crate::op::Op<T> for SGDOp<T> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Float(self.lr)])
+    }
 }
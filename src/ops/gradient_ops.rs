@@ -1,8 +1,14 @@
 use crate::op;
+use crate::tensor::Tensor;
 use crate::Float;
 
 pub struct StopGradient;
 
+// Gradient which fails the evaluations with `OpError::Unsupported`.
+struct UnsupportedGrad {
+    reason: &'static str,
+}
+
 impl<T: Float> op::Op<T> for StopGradient {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let ret = ctx.input(0);
@@ -12,4 +18,46 @@ impl<T: Float> op::Op<T> for StopGradient {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
+    }
+}
+
+impl<T: Float> op::Op<T> for UnsupportedGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) {
+        ctx.set_error(op::OpError::Unsupported(self.reason.to_string()));
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
+    }
+}
+
+// Fails the gradients of the inputs on the gradient path, instead of making them silently
+// zero, for the ops that can't be differentiated (again).
+pub(crate) fn unsupported_grads<T: Float>(ctx: &mut op::GradientContext<T>, reason: &'static str) {
+    let g = ctx.graph();
+    for i in 0..ctx.num_inputs() {
+        let gx = if ctx.requires_grad(i) {
+            let x = ctx.input(i);
+            let op = UnsupportedGrad { reason };
+            Some(Tensor::builder().append_input(&x).build(g, op))
+        } else {
+            None
+        };
+        ctx.append_input_grad(gx);
+    }
 }
@@ -27,4 +27,8 @@ impl<T: Float, H: crate::hook::Hook<T>> op::Op<T> for HookOp<T, H> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(Some(ctx.output_grad()));
     }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
+    }
 }
@@ -1,10 +1,11 @@
 use crate::ndarray_ext::{NdArray, NdArrayView};
 use crate::op;
+use crate::op::Attribute;
 #[cfg(feature = "mkl")]
 use crate::ops::mkl_ffi::*;
 #[cfg(feature = "mkl")]
 use crate::same_type;
-use crate::tensor::Tensor;
+use crate::tensor::{DType, Tensor};
 use crate::Float;
 use crate::Graph;
 use ndarray;
@@ -81,77 +82,83 @@ fn minimum<T: Float>(a: T, b: T) -> T {
     a.min(b)
 }
 
+// Computes the elementwise `$assign` of the two inputs, broadcasting either of them.
+macro_rules! binary_op_compute {
+    ($ctx:expr, $name:expr, $assign:expr) => {{
+        let x0 = $ctx.input(0);
+        let x1 = &$ctx.input(1);
+        let shape0 = x0.shape();
+        let shape1 = x1.shape();
+
+        let x0_is_scalar = crate::ndarray_ext::is_scalar_shape(shape0);
+        let x1_is_scalar = crate::ndarray_ext::is_scalar_shape(shape1);
+
+        if x0_is_scalar && x1_is_scalar {
+            let x1_elem = x1[ndarray::IxDyn(&[])];
+            x0.mapv(move |a| $assign(a, x1_elem))
+        } else if x0_is_scalar && !x1_is_scalar {
+            let x0_elem = x0[ndarray::IxDyn(&[])];
+            x1.mapv(move |a| $assign(x0_elem, a))
+        } else if !x0_is_scalar && x1_is_scalar {
+            let x1_elem = x1[ndarray::IxDyn(&[])];
+            x0.mapv(move |a| $assign(a, x1_elem))
+        } else {
+            // case that scalar is not involved
+            // Check the input ranks.
+            // op couldn't we catch here cause ndarray's panics.
+
+            // rank check
+            if shape0.len() != shape1.len() {
+                panic!(
+                    "Tensor ranks mismatch: {}({}'s lhs input) vs {}({}'s rhs input)",
+                    shape0.len(),
+                    $name,
+                    shape1.len(),
+                    $name,
+                )
+            }
+
+            let size0: usize = shape0.iter().product();
+            let size1: usize = shape1.iter().product();
+
+            // Whether broadcast of x0 and x1 is needed or not is depends on
+            // their shapes.
+            // FIXME: Is this cond branch ok?
+            if size0 < size1 {
+                let mut result = NdArray::zeros(shape1);
+                Zip::from(&mut result)
+                    .and_broadcast(x0)
+                    .and(x1)
+                    .apply(|r, a, b| *r = $assign(a.clone(), b.clone()));
+                result
+            } else if size0 > size1 {
+                panic!(
+                    "Tensor ranks mismatch: {}({}'s lhs input) vs {}({}'s rhs input)",
+                    shape0.len(),
+                    $name,
+                    shape1.len(),
+                    $name
+                );
+            } else {
+                // same
+                let mut result = NdArray::zeros(shape0);
+                Zip::from(&mut result)
+                    .and(x0)
+                    .and(x1)
+                    .apply(|r, a, b| *r = $assign(a.clone(), b.clone()));
+                result
+            }
+        }
+    }};
+}
+
 macro_rules! impl_cmp_op {
     ($struct_name:ident, $name:expr, $assign:expr, $grad_fn:expr) => {
         pub struct $struct_name;
 
         impl<T: Float> op::Op<T> for $struct_name {
             fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-                let x0 = ctx.input(0);
-                let x1 = &ctx.input(1);
-                let shape0 = x0.shape();
-                let shape1 = x1.shape();
-
-                let x0_is_scalar = crate::ndarray_ext::is_scalar_shape(shape0);
-                let x1_is_scalar = crate::ndarray_ext::is_scalar_shape(shape1);
-
-                let ret = if x0_is_scalar && x1_is_scalar {
-                    let x1_elem = x1[ndarray::IxDyn(&[])];
-                    x0.mapv(move |a| $assign(a, x1_elem))
-                } else if x0_is_scalar && !x1_is_scalar {
-                    let x0_elem = x0[ndarray::IxDyn(&[])];
-                    x1.mapv(move |a| $assign(x0_elem, a))
-                } else if !x0_is_scalar && x1_is_scalar {
-                    let x1_elem = x1[ndarray::IxDyn(&[])];
-                    x0.mapv(move |a| $assign(a, x1_elem))
-                } else {
-                    // case that scalar is not involved
-                    // Check the input ranks.
-                    // op couldn't we catch here cause ndarray's panics.
-
-                    // rank check
-                    if shape0.len() != shape1.len() {
-                        panic!(
-                            "Tensor ranks mismatch: {}({}'s lhs input) vs {}({}'s rhs input)",
-                            shape0.len(),
-                            $name,
-                            shape1.len(),
-                            $name,
-                        )
-                    }
-
-                    let size0: usize = shape0.iter().product();
-                    let size1: usize = shape1.iter().product();
-
-                    // Whether broadcast of x0 and x1 is needed or not is depends on
-                    // their shapes.
-                    // FIXME: Is this cond branch ok?
-                    if size0 < size1 {
-                        let mut result = NdArray::zeros(shape1);
-                        Zip::from(&mut result)
-                            .and_broadcast(x0)
-                            .and(x1)
-                            .apply(|r, a, b| *r = $assign(a.clone(), b.clone()));
-                        result
-                    } else if size0 > size1 {
-                        panic!(
-                            "Tensor ranks mismatch: {}({}'s lhs input) vs {}({}'s rhs input)",
-                            shape0.len(),
-                            $name,
-                            shape1.len(),
-                            $name
-                        );
-                    } else {
-                        // same
-                        let mut result = NdArray::zeros(shape0);
-                        Zip::from(&mut result)
-                            .and(x0)
-                            .and(x1)
-                            .apply(|r, a, b| *r = $assign(a.clone(), b.clone()));
-                        result
-                    }
-                };
-
+                let ret = binary_op_compute!(ctx, $name, $assign);
                 ctx.append_output(ret);
             }
 
@@ -165,31 +172,77 @@ macro_rules! impl_cmp_op {
                     ctx,
                 );
             }
+
+            fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+                Some(Vec::new())
+            }
+
+            fn infer_shape(
+                &self,
+                ctx: &op::ShapeContext<T>,
+            ) -> Result<Option<Vec<isize>>, op::OpError> {
+                ctx.broadcast_input_shapes()
+            }
         }
     };
 }
 
-impl_cmp_op!(Equal, "Equal", equal, none_grad);
-impl_cmp_op!(NotEqual, "NotEqual", not_equal, none_grad);
-impl_cmp_op!(Greater, "Greater", greater, none_grad);
-impl_cmp_op!(Lesser, "Lesser", lesser, none_grad);
-impl_cmp_op!(GreaterEqual, "GreaterEqual", greater_equal, none_grad);
-impl_cmp_op!(LesserEqual, "LesserEqual", lesser_equal, none_grad);
 impl_cmp_op!(Maximum, "Maximum", maximum, min_max_grad);
 impl_cmp_op!(Minimum, "Minimum", minimum, min_max_grad);
 
-#[inline]
-fn none_grad<'g, T: Float>(
-    _: Tensor<'g, T>,
-    _: Tensor<'g, T>,
-    _: Tensor<'g, T>,
-    _: Tensor<'g, T>,
-    _: &'g Graph<T>,
-    ctx: &mut crate::op::GradientContext<T>,
-) {
-    ctx.append_input_grad(None);
+// Comparison ops, which output `1` or `0` as floats, or `true` or `false` as booleans.
+macro_rules! impl_comparison_op {
+    ($struct_name:ident, $name:expr, $assign:expr) => {
+        pub struct $struct_name {
+            pub dtype: DType,
+        }
+
+        impl<T: Float> op::Op<T> for $struct_name {
+            fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
+                let ret: NdArray<T> = binary_op_compute!(ctx, $name, $assign);
+                if self.dtype == DType::Bool {
+                    ctx.append_output_bool(ret.mapv(|a| a != T::zero()));
+                } else {
+                    ctx.append_output(ret);
+                }
+            }
+
+            fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+                ctx.append_input_grad(None);
+                ctx.append_input_grad(None);
+            }
+
+            fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+                Some(vec![Attribute::Int(self.dtype.index())])
+            }
+
+            fn infer_shape(
+                &self,
+                ctx: &op::ShapeContext<T>,
+            ) -> Result<Option<Vec<isize>>, op::OpError> {
+                ctx.broadcast_input_shapes()
+            }
+
+            fn output_dtype(&self, ctx: &op::ShapeContext<T>) -> Result<DType, op::OpError> {
+                ctx.check_input_dtypes(&[])?;
+                match self.dtype {
+                    DType::Int => Err(op::OpError::TypeUnsupported(
+                        "int outputs are not supported".to_string(),
+                    )),
+                    dtype => Ok(dtype),
+                }
+            }
+        }
+    };
 }
 
+impl_comparison_op!(Equal, "Equal", equal);
+impl_comparison_op!(NotEqual, "NotEqual", not_equal);
+impl_comparison_op!(Greater, "Greater", greater);
+impl_comparison_op!(Lesser, "Lesser", lesser);
+impl_comparison_op!(GreaterEqual, "GreaterEqual", greater_equal);
+impl_comparison_op!(LesserEqual, "LesserEqual", lesser_equal);
+
 #[inline]
 fn min_max_grad<'g, T: Float>(
     gy: Tensor<'g, T>,
@@ -205,13 +258,30 @@ fn min_max_grad<'g, T: Float>(
     ctx.append_input_grad(Some(c.mul(selected_b, gy)));
 }
 
+// Applies `f` to each element of the input.
+//
+// The result is written into the input itself if the op can take it over, otherwise into
+// an output taken from the buffer pool.
+#[inline]
+pub(crate) fn map_to_output<T: Float, F: Fn(T) -> T>(ctx: &mut op::ComputeContext<T>, f: F) {
+    if let Some(mut x) = ctx.input_owned(0) {
+        x.mapv_inplace(f);
+        ctx.append_output(x);
+        return;
+    }
+    let x = ctx.input(0);
+    let mut y = ctx.alloc_output(x.shape());
+    Zip::from(&mut y).and(&x).apply(|y, &x| *y = f(x));
+    ctx.append_output(y);
+}
+
 #[cfg(feature = "mkl")]
 macro_rules! elem_wise_vm_or_std {
     ($vms_op:ident, $vmd_op:ident, $closure:expr, $ctx:expr) => {
         let x = $ctx.input(0);
         let ret = unsafe {
             if same_type::<T, f32>() {
-                let mut y = crate::uninitialized_vec(x.len());
+                let mut y = $ctx.alloc_vec(x.len());
                 $vms_op(
                     x.len() as MklInt,
                     x.as_ptr() as *const f32,
@@ -219,7 +289,7 @@ macro_rules! elem_wise_vm_or_std {
                 );
                 NdArray::from_shape_vec_unchecked(x.shape(), y)
             } else if same_type::<T, f64>() {
-                let mut y = crate::uninitialized_vec(x.len());
+                let mut y = $ctx.alloc_vec(x.len());
                 $vmd_op(
                     x.len() as MklInt,
                     x.as_ptr() as *const f64,
@@ -240,7 +310,7 @@ macro_rules! elem_wise_vm_with_param_or_std {
         let x = $ctx.input(0);
         let ret = unsafe {
             if same_type::<T, f32>() {
-                let mut y = crate::uninitialized_vec(x.len());
+                let mut y = $ctx.alloc_vec(x.len());
                 let p = $param.to_f32().unwrap();
                 $vms_op(
                     x.len() as MklInt,
@@ -250,7 +320,7 @@ macro_rules! elem_wise_vm_with_param_or_std {
                 );
                 NdArray::from_shape_vec_unchecked(x.shape(), y)
             } else if same_type::<T, f64>() {
-                let mut y = crate::uninitialized_vec(x.len());
+                let mut y = $ctx.alloc_vec(x.len());
                 let p = $param.to_f64().unwrap();
                 $vmd_op(
                     x.len() as MklInt,
@@ -275,25 +345,60 @@ impl<T: Float> op::Op<T> for Abs {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.abs());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.abs());
         }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(Some(ctx.output_grad() * ctx.graph().sign(ctx.input(0))));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Abs)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for NegOp {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let ret = ctx.input(0).map(|x| x.neg());
-        ctx.append_output(ret);
+        map_to_output(ctx, |x| x.neg());
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(Some(ctx.graph().neg(ctx.output_grad())));
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let ty = ctx.input_tangent(0).map(|tx| ctx.graph().neg(tx));
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Neg)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Square {
@@ -304,8 +409,7 @@ impl<T: Float> op::Op<T> for Square {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).mapv(|a| a * a);
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a * a);
         }
     }
 
@@ -313,6 +417,28 @@ impl<T: Float> op::Op<T> for Square {
         let two = ctx.graph().scalar(T::one() + T::one());
         ctx.append_input_grad(Some(two * ctx.input(0) * ctx.output_grad()));
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let two = ctx.graph().scalar(T::one() + T::one());
+        let ty = ctx.input_tangent(0).map(|tx| two * ctx.input(0) * tx);
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Square)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Inv {
@@ -323,8 +449,7 @@ impl<T: Float> op::Op<T> for Inv {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.recip());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.recip());
         }
     }
 
@@ -333,6 +458,22 @@ impl<T: Float> op::Op<T> for Inv {
             ctx.graph().neg(&ctx.graph().square(ctx.output())) * ctx.output_grad(),
         ));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Inv)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for InvSqrt {
@@ -343,8 +484,7 @@ impl<T: Float> op::Op<T> for InvSqrt {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.sqrt().recip());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.sqrt().recip());
         }
     }
 
@@ -354,23 +494,54 @@ impl<T: Float> op::Op<T> for InvSqrt {
         let b = g.pow(ctx.input(0), T::from(-1.5).unwrap());
         ctx.append_input_grad(Some(a * b * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::InvSqrt)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Sign {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let ret = ctx.input(0).mapv(|x| {
+        map_to_output(ctx, |x| {
             if x == T::zero() {
                 T::zero()
             } else {
                 x.signum()
             }
         });
-        ctx.append_output(ret);
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Sign)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Floor {
@@ -381,14 +552,29 @@ impl<T: Float> op::Op<T> for Floor {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.floor());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.floor());
         }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Floor)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Ceil {
@@ -399,14 +585,29 @@ impl<T: Float> op::Op<T> for Ceil {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.ceil());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.ceil());
         }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None)
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Ceil)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Transpose {
@@ -450,6 +651,37 @@ impl<T: Float> op::Op<T> for Transpose {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.invert_axes))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Bool(self.invert_axes)])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let (shape, perm) = match (ctx.input_shape(0), ctx.input_ints(1)) {
+            (Some(shape), Some(perm)) => (shape, perm),
+            (_, Some(perm)) => return Ok(Some(vec![-1; perm.len()])),
+            _ => return Ok(None),
+        };
+        if shape.len() != perm.len() || perm.iter().any(|&d| d < 0 || d as usize >= shape.len()) {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "transpose: axes {:?} don't match the shape {:?}",
+                perm, shape
+            )));
+        }
+        let mut ret = vec![-1; shape.len()];
+        for (i, &d) in perm.iter().enumerate() {
+            if self.invert_axes {
+                ret[d as usize] = shape[i];
+            } else {
+                ret[i] = shape[d as usize];
+            }
+        }
+        Ok(Some(ret))
+    }
 }
 
 #[cfg(feature = "mkl")]
@@ -531,12 +763,9 @@ pub fn logsumexp_forward<T: Float>(x: &NdArrayView<T>, axis: isize, keep_dims: b
         axis as usize
     };
 
+    // Computes with the reduced axis kept so that `max` broadcasts over `x`.
     let mut a = x.shape().to_vec();
-    if keep_dims {
-        a[axis] = 1;
-    } else {
-        a.remove(axis);
-    }
+    a[axis] = 1;
     let reduced_shape = a.as_slice();
 
     let max_fn = T::max;
@@ -567,15 +796,20 @@ pub fn logsumexp_forward<T: Float>(x: &NdArrayView<T>, axis: isize, keep_dims: b
         .unwrap();
 
     #[cfg(feature = "mkl")]
-    {
+    let ret = {
         fast_inplace_ln_impl(&mut sum);
         inplace_add_impl(sum, max)
-    }
+    };
     #[cfg(not(feature = "mkl"))]
-    {
+    let ret = {
         sum.mapv_inplace(move |a| a.ln());
         sum += max;
         sum
+    };
+    if keep_dims {
+        ret
+    } else {
+        ret.index_axis_move(ndarray::Axis(axis), 0)
     }
 }
 
@@ -589,9 +823,50 @@ impl<T: Float> op::Op<T> for LogSumExp {
         // let ref sum = c.exp(output);
         // let ref exp = c.exp(ctx.input(0));
         // let gx = gy * exp / sum;
-        let gx = ctx.graph().softmax(ctx.input(0), self.axis) * ctx.output_grad();
+        let s = ctx.graph();
+        let x = ctx.input(0);
+        // Broadcast gy into x's shape
+        let gy = Tensor::builder()
+            .set_ro_inputs(&[
+                &ctx.output_grad(),
+                &s.shape(x),
+                &s.scalar(T::from(self.axis).unwrap()),
+            ])
+            .build(
+                s,
+                crate::ops::reduction_ops::ReduceGradCommon {
+                    should_make_broadcast_dims: !self.keep_dims,
+                    sparse_axes: false,
+                },
+            );
+        let gx = s.softmax(x, self.axis) * gy;
         ctx.append_input_grad(Some(gx))
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.axis, self.keep_dims)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.axis as i64),
+            Attribute::Bool(self.keep_dims),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let mut ret = match ctx.input_shape(0) {
+            Some(shape) => shape.to_vec(),
+            None => return Ok(None),
+        };
+        let axis = crate::ops::array_ops::static_axis(self.axis, ret.len())?;
+        if self.keep_dims {
+            ret[axis] = 1;
+        } else {
+            ret.remove(axis);
+        }
+        Ok(Some(ret))
+    }
 }
 
 impl<T: Float> op::Op<T> for Pow<T> {
@@ -602,8 +877,7 @@ impl<T: Float> op::Op<T> for Pow<T> {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.powf(self.a));
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.powf(self.a));
         }
     }
 
@@ -613,6 +887,22 @@ impl<T: Float> op::Op<T> for Pow<T> {
             ctx.output_grad() * ctx.graph().scalar(self.a) * ctx.graph().pow(x, self.a - T::one());
         ctx.append_input_grad(Some(gx))
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.a))
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Pow(self.a))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Float(self.a)])
+    }
 }
 
 impl<T: Float> op::Op<T> for Sqrt {
@@ -623,8 +913,7 @@ impl<T: Float> op::Op<T> for Sqrt {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.sqrt());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.sqrt());
         }
     }
 
@@ -634,6 +923,22 @@ impl<T: Float> op::Op<T> for Sqrt {
         let ret = half * ctx.graph().pow(x, T::one().neg());
         ctx.append_input_grad(Some(ctx.output_grad() * ret));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Sqrt)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Log10 {
@@ -644,8 +949,7 @@ impl<T: Float> op::Op<T> for Log10 {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.log10());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.log10());
         }
     }
 
@@ -653,6 +957,22 @@ impl<T: Float> op::Op<T> for Log10 {
         let log10 = ctx.graph().scalar(T::from(10.).unwrap().ln());
         ctx.append_input_grad(Some(ctx.output_grad() / (log10 * ctx.input(0))));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Log10)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Log2 {
@@ -663,8 +983,7 @@ impl<T: Float> op::Op<T> for Log2 {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.log2());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.log2());
         }
     }
 
@@ -672,6 +991,22 @@ impl<T: Float> op::Op<T> for Log2 {
         let log2 = ctx.graph().scalar((T::one() + T::one()).ln());
         ctx.append_input_grad(Some(ctx.output_grad() / (log2 * ctx.input(0))));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Log2)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Ln {
@@ -682,14 +1017,34 @@ impl<T: Float> op::Op<T> for Ln {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.ln());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.ln());
         }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(Some(ctx.output_grad() / ctx.input(0)));
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let ty = ctx.input_tangent(0).map(|tx| tx / ctx.input(0));
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Ln)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Exp {
@@ -700,14 +1055,34 @@ impl<T: Float> op::Op<T> for Exp {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.exp());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.exp());
         }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(Some(ctx.output() * ctx.output_grad()));
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let ty = ctx.input_tangent(0).map(|tx| ctx.output() * tx);
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Exp)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Exp2 {
@@ -718,8 +1093,7 @@ impl<T: Float> op::Op<T> for Exp2 {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.exp2());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.exp2());
         }
     }
 
@@ -729,6 +1103,22 @@ impl<T: Float> op::Op<T> for Exp2 {
         let log2 = g.scalar(log2);
         ctx.append_input_grad(Some(log2 * ctx.output() * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Exp2)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Exp10 {
@@ -740,8 +1130,7 @@ impl<T: Float> op::Op<T> for Exp10 {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(move |&a| _10.powf(a));
-            ctx.append_output(ret);
+            map_to_output(ctx, move |a| _10.powf(a));
         }
     }
 
@@ -749,6 +1138,22 @@ impl<T: Float> op::Op<T> for Exp10 {
         let log10 = ctx.graph().scalar(T::from(10.).unwrap().ln());
         ctx.append_input_grad(Some(log10 * ctx.output() * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Exp10)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Atanh {
@@ -759,8 +1164,7 @@ impl<T: Float> op::Op<T> for Atanh {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.atanh());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.atanh());
         }
     }
 
@@ -770,6 +1174,22 @@ impl<T: Float> op::Op<T> for Atanh {
         let y = g.inv(1. - g.square(x));
         ctx.append_input_grad(Some(y * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Atanh)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Acosh {
@@ -780,8 +1200,7 @@ impl<T: Float> op::Op<T> for Acosh {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.acosh());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.acosh());
         }
     }
 
@@ -791,6 +1210,22 @@ impl<T: Float> op::Op<T> for Acosh {
         let y = g.inv(g.sqrt(g.square(x) - g.scalar(T::one())));
         ctx.append_input_grad(Some(y * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Acosh)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Asinh {
@@ -801,8 +1236,7 @@ impl<T: Float> op::Op<T> for Asinh {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.asinh());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.asinh());
         }
     }
 
@@ -812,6 +1246,22 @@ impl<T: Float> op::Op<T> for Asinh {
         let y = g.inv(g.sqrt(g.square(x) + g.scalar(T::one())));
         ctx.append_input_grad(Some(y * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Asinh)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Tanh {
@@ -822,8 +1272,7 @@ impl<T: Float> op::Op<T> for Tanh {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.tanh());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.tanh());
         }
     }
 
@@ -832,6 +1281,30 @@ impl<T: Float> op::Op<T> for Tanh {
             ctx.output_grad() * (ctx.graph().scalar(T::one()) - ctx.graph().square(ctx.output())),
         ));
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let g = ctx.graph();
+        let ty = ctx
+            .input_tangent(0)
+            .map(|tx| tx * (g.scalar(T::one()) - g.square(ctx.output())));
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Tanh)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Cosh {
@@ -842,14 +1315,29 @@ impl<T: Float> op::Op<T> for Cosh {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.cosh());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.cosh());
         }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(Some(ctx.graph().sinh(ctx.input(0)) * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Cosh)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Sinh {
@@ -860,14 +1348,29 @@ impl<T: Float> op::Op<T> for Sinh {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.sinh());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.sinh());
         }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(Some(ctx.graph().cosh(ctx.input(0)) * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Sinh)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Atan {
@@ -878,8 +1381,7 @@ impl<T: Float> op::Op<T> for Atan {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.atan());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.atan());
         }
     }
 
@@ -889,6 +1391,22 @@ impl<T: Float> op::Op<T> for Atan {
         let y = g.inv(g.square(x) + g.scalar(T::one()));
         ctx.append_input_grad(Some(y * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Atan)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Acos {
@@ -899,8 +1417,7 @@ impl<T: Float> op::Op<T> for Acos {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.acos());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.acos());
         }
     }
 
@@ -910,6 +1427,22 @@ impl<T: Float> op::Op<T> for Acos {
         let y = g.neg(g.inv_sqrt(1. - g.square(x)));
         ctx.append_input_grad(Some(y * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Acos)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Asin {
@@ -920,8 +1453,7 @@ impl<T: Float> op::Op<T> for Asin {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.asin());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.asin());
         }
     }
 
@@ -931,6 +1463,22 @@ impl<T: Float> op::Op<T> for Asin {
         let y = g.inv_sqrt(1. - g.square(x));
         ctx.append_input_grad(Some(y * ctx.output_grad()));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Asin)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Sin {
@@ -941,14 +1489,36 @@ impl<T: Float> op::Op<T> for Sin {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.sin());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.sin());
         }
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(Some(ctx.graph().cos(ctx.input(0)) * ctx.output_grad()));
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let ty = ctx
+            .input_tangent(0)
+            .map(|tx| ctx.graph().cos(ctx.input(0)) * tx);
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Sin)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Cos {
@@ -959,8 +1529,7 @@ impl<T: Float> op::Op<T> for Cos {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.cos());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.cos());
         }
     }
 
@@ -968,6 +1537,30 @@ impl<T: Float> op::Op<T> for Cos {
         let g = ctx.graph();
         ctx.append_input_grad(Some(g.neg(&(g.sin(ctx.input(0)) * ctx.output_grad()))));
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let g = ctx.graph();
+        let ty = ctx
+            .input_tangent(0)
+            .map(|tx| g.neg(g.sin(ctx.input(0)) * tx));
+        ctx.append_output_tangent(ty);
+    }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Cos)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
 
 impl<T: Float> op::Op<T> for Tan {
@@ -978,8 +1571,7 @@ impl<T: Float> op::Op<T> for Tan {
         }
         #[cfg(not(feature = "mkl"))]
         {
-            let ret = ctx.input(0).map(|a| a.tan());
-            ctx.append_output(ret);
+            map_to_output(ctx, |a| a.tan());
         }
     }
 
@@ -988,4 +1580,20 @@ impl<T: Float> op::Op<T> for Tan {
         let cos = g.cos(&ctx.input(0));
         ctx.append_input_grad(Some(ctx.output_grad() / g.square(cos)));
     }
+
+    fn supports_in_place(&self, _i: usize) -> bool {
+        true
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn elementwise(&self) -> Option<op::Elementwise<T>> {
+        Some(op::Elementwise::Tan)
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
 }
@@ -2,7 +2,9 @@
 use ndarray;
 
 use crate::ndarray_ext::{ArrayRng, NdArray};
-use crate::tensor::{AsTensor, Tensor, TensorInternal};
+use crate::op::{self, Attribute};
+use crate::serialization::OpRegistry;
+use crate::tensor::{AsTensor, DType, Tensor, TensorInternal};
 use crate::Float;
 use rand::Rng;
 
@@ -11,8 +13,11 @@ mod array_ops;
 pub(crate) mod basic_source_ops;
 pub(crate) mod binary_ops;
 mod const_gen_ops;
+mod control_flow_ops;
 mod conv_ops;
+mod custom_ops;
 pub(crate) mod dot_ops;
+pub(crate) mod fused_ops;
 pub mod gradient_descent_ops;
 mod gradient_ops;
 pub(crate) mod hook_ops;
@@ -20,8 +25,11 @@ mod math_ops;
 mod mkl_ffi;
 mod random_ops;
 mod reduction_ops;
+pub(crate) mod vmap_ops;
 mod xent_ops;
 
+pub(crate) use array_ops::{slice_indices_from_ints, Slice};
+
 // ---------------------------------------
 // -- Ops to manipulate `Tensor` object --
 // ---------------------------------------
@@ -41,6 +49,7 @@ impl<'tensor, 'graph, F: Float> Tensor<'graph, F> {
     ///    assert_eq!(b.eval(&[]).unwrap()[ndarray::IxDyn(&[])], 4.);
     /// });
     /// ```
+    #[track_caller]
     pub fn access_elem(self, i: isize) -> Tensor<'graph, F> {
         let op = array_ops::IndexOp { index: i };
         Tensor::builder().append_input(&self).build(self.graph, op)
@@ -90,6 +99,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///     assert_eq!(8., gx.eval(&[x.given(ndarray::arr0(2.).view())]).unwrap()[ndarray::IxDyn(&[])]);
     /// });
     /// ```
+    #[track_caller]
     pub fn grad<A, B>(&'graph self, ys_: &[A], xs: &[B]) -> Vec<Tensor<'graph, F>>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -133,7 +143,15 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
         let xs: Vec<_> = xs.iter().map(|x| x.as_ref().scoped_inner()).collect();
         let ys: Vec<_> = ys.iter().map(|y| y.as_ref().scoped_inner()).collect();
         let ys_grads: Vec<_> = ys_grads.iter().map(|x| x.as_ref().scoped_inner()).collect();
-        crate::gradient::symbolic_gradients(ys.as_slice(), xs.as_slice(), ys_grads.as_slice(), self)
+        let start = self.num_nodes();
+        let ret = crate::gradient::symbolic_gradients(
+            ys.as_slice(),
+            xs.as_slice(),
+            ys_grads.as_slice(),
+            self,
+        );
+        self.mark_gradient_nodes(start);
+        ret
     }
 
     /// Computes jacobians for variables.
@@ -145,8 +163,9 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// # Returns
     /// Jacobians for each variable. Each one is a matrix of shape `(y_size, x size)`.
+    /// Jacobians of `xs` that `y` doesn't depend on are zeros.
     ///
-    /// Note: the current implementation works correctly but is unoptimized for serious use.
+    /// This is computed by the reverse-mode; see `jacobians_with_mode` for the others.
     ///
     /// ```
     /// use autograd as ag;
@@ -163,71 +182,211 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(j[1].eval(&[]).unwrap().shape(), &[4*3, 2*3]);
     /// });
     /// ```
-    pub fn jacobians<A: 'tensor, B: 'tensor>(
-        &'graph self,
-        y_: A,
-        xs_: &[B],
-        objective_len: usize,
-    ) -> Vec<Tensor<'graph, F>>
+    #[track_caller]
+    pub fn jacobians<A, B>(&'graph self, y: A, xs: &[B], y_size: usize) -> Vec<Tensor<'graph, F>>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
         B: AsRef<Tensor<'graph, F>> + Copy,
     {
-        let y = y_.as_ref();
-        let xs: Vec<_> = xs_.iter().map(|x| x.as_ref().scoped_inner()).collect();
-        let mut vec_vec = Vec::with_capacity(objective_len);
-        let gy = self.scalar(F::one()).scoped_inner();
-        for i in 0..objective_len as isize {
-            vec_vec.push({
-                crate::gradient::symbolic_gradients(
-                    &[y.access_elem(i).scoped_inner()],
-                    xs.as_slice(),
-                    &[gy],
-                    self,
-                )
-            });
-        }
-
-        let len = xs.len();
-        let mut ret = Vec::with_capacity(len);
-        // post process gradients
-        for i in 0..len {
-            // jac is matrix
-            let mut jac = Vec::with_capacity(objective_len);
-            for vec in &vec_vec {
-                jac.push(self.expand_dims(self.flatten(&vec[i]), &[0]));
-            }
-            // (y size, x size)
-            ret.push(self.concat(&jac, 0));
-        }
-        ret
+        self.jacobians_with_mode(y, xs, y_size, crate::JacobianMode::Reverse)
     }
 
-    /// (Experimental) Computes hessian vector product
-    pub fn _hessian_vector_product<A, B, C: 'tensor>(
+    /// Computes jacobians for variables with the given mode of differentiation.
+    ///
+    /// Same as `jacobians` except that `mode` chooses how: the reverse-mode backpropagates
+    /// a one-hot cotangent for each element of `y`, while the forward-mode propagates a one-hot
+    /// tangent for each element of `xs`, so the forward-mode is cheaper when `xs` are smaller
+    /// than `y`. `JacobianMode::Auto` chooses one of them with the static shapes of `xs`.
+    ///
+    /// Either way, all the (co)tangents are pushed at once through a single differentiated
+    /// graph with an extra leading batch axis (see `Op::vmap`), so the graph doesn't grow with
+    /// `y_size` or the sizes of `xs`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor::Variable;
+    /// use ag::JacobianMode;
+    ///
+    /// ag::with(|g| {
+    ///    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    ///    let a = g.variable(rng.standard_normal(&[2]));
+    ///    let b = g.variable(rng.standard_normal(&[2, 30]));
+    ///    // 30 outputs from 2 + 60 inputs
+    ///    let c = g.sin(g.matmul(g.expand_dims(a, &[0]), b));
+    ///    let reverse = g.jacobians_with_mode(c, &[a, b], 30, JacobianMode::Reverse);
+    ///    let forward = g.jacobians_with_mode(c, &[a, b], 30, JacobianMode::Forward);
+    ///
+    ///    for (r, f) in reverse.iter().zip(&forward) {
+    ///        let ret = g.eval(&[r, f], &[]);
+    ///        let (r, f) = (ret[0].as_ref().unwrap(), ret[1].as_ref().unwrap());
+    ///        assert_eq!(r.shape(), f.shape());
+    ///        assert!(r.all_close(f, 1e-12));
+    ///    }
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn jacobians_with_mode<A, B>(
         &'graph self,
-        ys: &[A],
+        y: A,
         xs: &[B],
-        vectors: &[C],
+        y_size: usize,
+        mode: crate::JacobianMode,
     ) -> Vec<Tensor<'graph, F>>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        B: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        let xs: Vec<_> = xs.iter().map(|x| *x.as_ref()).collect();
+        let start = self.num_nodes();
+        let ret = crate::gradient::batched_jacobians(y.as_ref(), &xs, y_size, mode, self);
+        self.mark_gradient_nodes(start);
+        ret
+    }
+
+    /// Computes the directional derivatives of `ys` along `tangents` of `xs`, i.e.
+    /// the Jacobian-vector products, by the forward-mode differentiation.
+    ///
+    /// `tangents[i]` must have the same shape as `xs[i]`, and the results have the same shapes
+    /// as `ys`. Tangents are propagated from `xs` to `ys` with `Op::jvp`, so the cost doesn't
+    /// depend on the sizes of `ys`: this is cheaper than `grad` for functions having more
+    /// outputs than inputs.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ndarray::array;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f64>| {
+    ///    let x = g.placeholder(&[2]);
+    ///    let y = g.sin(x) * 2.;
+    ///    // Directional derivative along `t`
+    ///    let t = g.convert_to_tensor(array![1., 0.5]);
+    ///    let ty = g.jvp(&[y], &[x], &[t])[0];
+    ///
+    ///    let x_val = array![0., 0.].into_dyn();
+    ///    assert_eq!(ty.eval(&[x.given(x_val.view())]), Ok(array![2., 1.].into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn jvp<A, B, C>(&'graph self, ys: &[A], xs: &[B], tangents: &[C]) -> Vec<Tensor<'graph, F>>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        B: AsRef<Tensor<'graph, F>> + Copy,
+        C: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        let ys: Vec<_> = ys.iter().map(|y| *y.as_ref()).collect();
+        let xs: Vec<_> = xs.iter().map(|x| *x.as_ref()).collect();
+        let tangents: Vec<_> = tangents.iter().map(|t| *t.as_ref()).collect();
+        crate::gradient::symbolic_jvp(&ys, &xs, &tangents, self)
+    }
+
+    /// Computes the Hessian-vector products of `ys` with respect to `xs`.
+    ///
+    /// Returns `H v` for each `x` in `xs`, where `H` is the Hessian of `sum(ys)` and `v` is the
+    /// concatenation of `vectors`. `vectors[i]` must have the same shape as `xs[i]`.
+    /// This is computed by differentiating `<grad(ys, xs), vectors>`, without materializing `H`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor::Variable;
+    /// use ndarray::array;
+    ///
+    /// ag::with(|g| {
+    ///     let x = g.variable(array![1., 2.]);
+    ///     // y = x0^2 * x1, whose Hessian is [[2x1, 2x0], [2x0, 0]] = [[4, 2], [2, 0]]
+    ///     let y = g.square(g.slice(x, &[0], &[1])) * g.slice(x, &[1], &[2]);
+    ///     let v = g.convert_to_tensor(array![1., 1.]);
+    ///     let hv = g.hvp(&[y], &[x], &[v])[0];
+    ///     assert_eq!(hv.eval(&[]).unwrap(), array![6., 2.].into_dyn());
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn hvp<A, B, C>(&'graph self, ys: &[A], xs: &[B], vectors: &[C]) -> Vec<Tensor<'graph, F>>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
         B: AsRef<Tensor<'graph, F>> + Copy,
         C: AsRef<Tensor<'graph, F>> + Copy,
     {
+        assert_eq!(
+            xs.len(),
+            vectors.len(),
+            "`xs.len()` must match `vectors.len()`"
+        );
         let grads = self.grad(ys, xs);
         let products = grads
             .into_iter()
             .zip(vectors)
-            .map(|(g, &v)| *g.as_ref() * *v.as_ref())
+            .map(|(g, &v)| self.reduce_sum_to_scalar(g * v.as_ref()))
             .collect::<Vec<_>>();
-        self.grad(products.as_slice(), xs)
+        self.grad(&[self.add_n(&products)], xs)
+    }
+
+    /// Computes the Hessian of `y` with respect to `xs`.
+    ///
+    /// The Hessian is returned in blocks: `ret[i][j]` is the matrix of shape `(size of xs[i], size of xs[j])`
+    /// holding the second derivatives `d^2 sum(y) / dxs[i] dxs[j]` of the flattened `xs`.
+    /// Blocks for pairs not interacting with each other are zeros.
+    ///
+    /// The shapes of `xs` must be known statically (e.g. variables).
+    ///
+    /// This is built as the Jacobians of the gradients (see `jacobians`), so the graph doesn't
+    /// grow with the sizes of `xs`. Use `hvp` when only the products with some vectors are
+    /// required.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor::Variable;
+    /// use ndarray::array;
+    ///
+    /// ag::with(|g| {
+    ///     let a = g.variable(array![1., 2.]);
+    ///     let b = g.variable(array![3.]);
+    ///     // y = a0 * a1 * b
+    ///     let y = g.reduce_prod(a, &[0], false) * b;
+    ///     let h = g.hessian(y, &[a, b]);
+    ///     assert_eq!(h[0][0].eval(&[]).unwrap(), array![[0., 3.], [3., 0.]].into_dyn());
+    ///     assert_eq!(h[0][1].eval(&[]).unwrap(), array![[2.], [1.]].into_dyn());
+    ///     assert_eq!(h[1][0].eval(&[]).unwrap(), array![[2., 1.]].into_dyn());
+    ///     assert_eq!(h[1][1].eval(&[]).unwrap(), array![[0.]].into_dyn());
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn hessian<A, B>(&'graph self, y: A, xs: &[B]) -> Vec<Vec<Tensor<'graph, F>>>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        B: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        let sizes: Vec<usize> = xs
+            .iter()
+            .map(|x| {
+                x.as_ref()
+                    .static_shape()
+                    .filter(|s| s.iter().all(|&d| d >= 0))
+                    .map(|s| s.iter().product::<isize>() as usize)
+                    .expect("Graph::hessian: the shapes of `xs` must be known statically")
+            })
+            .collect();
+        let grads = self.grad(&[y], xs);
+        let xs: Vec<_> = xs.iter().map(|x| *x.as_ref()).collect();
+
+        // The blocks of each row are the Jacobians of the gradient of each x.
+        let start = self.num_nodes();
+        let ret = grads
+            .iter()
+            .zip(sizes)
+            .map(|(gx, size)| {
+                let mode = crate::JacobianMode::Reverse;
+                crate::gradient::batched_jacobians(gx, &xs, size, mode, self)
+            })
+            .collect();
+        self.mark_gradient_nodes(start);
+        ret
     }
 
     /// Stops gradient propagation.
     ///
     /// Guarantees that the gradient is not propagated to the tensors behind this
     /// during gradient computation.
+    #[track_caller]
     pub fn stop_gradient<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -238,6 +397,123 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
             .build(self, gradient_ops::StopGradient)
     }
 
+    /// Creates a tensor of an op defined by closures, instead of implementing `op::Op`.
+    ///
+    /// `compute` and `grad` work as `Op::compute` and `Op::grad` respectively: the former
+    /// computes the outputs from the input arrays, and the latter provides the symbolic
+    /// gradients of all the `inputs`. They are called each time the op is evaluated
+    /// or differentiated, so they must use the contexts to access the tensors.
+    /// They must be `Send + Sync` since parallel evaluations call them from worker threads.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ndarray::array;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f64>| {
+    ///    let x = g.placeholder(&[-1]);
+    ///    // x^3
+    ///    let y = g.custom_op(
+    ///        &[x],
+    ///        |ctx| {
+    ///            let y = ctx.input(0).mapv(|a| a * a * a);
+    ///            ctx.append_output(y);
+    ///        },
+    ///        |ctx| {
+    ///            let x = ctx.input(0);
+    ///            let gx = ctx.output_grad() * ctx.graph().square(x) * 3.;
+    ///            ctx.append_input_grad(Some(gx));
+    ///        },
+    ///    );
+    ///    let gx = g.grad(&[y], &[x])[0];
+    ///
+    ///    let x_val = array![1., 2.].into_dyn();
+    ///    let ret = g.eval(&[y, gx], &[x.given(x_val.view())]);
+    ///    assert_eq!(ret[0], Ok(array![1., 8.].into_dyn()));
+    ///    assert_eq!(ret[1], Ok(array![3., 12.].into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn custom_op<A, FC, FG>(
+        &'graph self,
+        inputs: &[A],
+        compute: FC,
+        grad: FG,
+    ) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        FC: Fn(&mut op::ComputeContext<F>) + Send + Sync + 'static,
+        FG: Fn(&mut op::GradientContext<F>) + Send + Sync + 'static,
+    {
+        let mut builder = Tensor::builder();
+        for x in inputs {
+            builder = builder.append_input(x.as_ref());
+        }
+        builder.build(
+            self,
+            custom_ops::CustomOp {
+                compute: Box::new(compute),
+                grad: Box::new(grad),
+            },
+        )
+    }
+
+    /// Computes the tensor returned by `f`, of which the gradient is given by `grad`.
+    ///
+    /// `f` is called once with `xs` to build the subgraph, through which gradients are not
+    /// propagated. Instead, `grad` works as `Op::grad` to provide the symbolic gradients of `xs`,
+    /// appending exactly one for each of them: `ctx.input(i)` is the `i` th of `xs`,
+    /// `ctx.output()` is the result of `f`, and `ctx.output_grad()` is its gradient. Useful for
+    /// straight-through estimators, gradient clipping in backward, or numerically stable
+    /// gradients.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ndarray::array;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f64>| {
+    ///    let x = g.placeholder(&[-1]);
+    ///    // Straight-through estimator of `sign`
+    ///    let y = g.custom_gradient(
+    ///        &[x],
+    ///        |xs| g.sign(xs[0]),
+    ///        |ctx| {
+    ///            let gy = ctx.output_grad();
+    ///            ctx.append_input_grad(Some(gy));
+    ///        },
+    ///    );
+    ///    let gx = g.grad(&[y * 2.], &[x])[0];
+    ///
+    ///    let x_val = array![-0.5, 3.].into_dyn();
+    ///    let ret = g.eval(&[y, gx], &[x.given(x_val.view())]);
+    ///    assert_eq!(ret[0], Ok(array![-1., 1.].into_dyn()));
+    ///    assert_eq!(ret[1], Ok(array![2., 2.].into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn custom_gradient<A, FN, FG>(&'graph self, xs: &[A], f: FN, grad: FG) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        FN: FnOnce(&[Tensor<'graph, F>]) -> Tensor<'graph, F>,
+        FG: Fn(&mut op::GradientContext<F>) + Send + Sync + 'static,
+    {
+        let xs: Vec<_> = xs.iter().map(|x| *x.as_ref()).collect();
+        let y = f(&xs);
+        let backprop_inputs: Vec<_> = xs.iter().map(crate::tensor::Input::new).collect();
+        let mut builder = Tensor::builder();
+        for x in &xs {
+            builder = builder.append_input(x);
+        }
+        builder
+            .append_input(&y)
+            .set_backprop_inputs(&backprop_inputs)
+            .build(
+                self,
+                custom_ops::CustomGradient {
+                    grad: Box::new(grad),
+                },
+            )
+    }
+
     /// Creates a placeholder tensor.
     ///
     /// Behaves like TensorFlow's placeholder object.
@@ -256,8 +532,45 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// });
     /// ```
     #[inline]
+    #[track_caller]
     pub fn placeholder(&'graph self, shape_: &[isize]) -> Tensor<'graph, F> {
-        let b = Tensor::builder().set_is_placeholder(true);
+        self.typed_placeholder(shape_, DType::Float)
+    }
+
+    /// Creates a placeholder of `DType::Int`, which is fed with `Tensor::given_int`.
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let param = g.convert_to_tensor(array![[0., 1.], [2., 3.], [4., 5.]]);
+    ///     let indices = g.placeholder_int(&[-1]);
+    ///     let y = g.gather(param, indices, 0);
+    ///
+    ///     let value = array![2, 0];
+    ///     assert_eq!(
+    ///         y.eval(&[indices.given_int(value.view())]),
+    ///         Ok(array![[4., 5.], [0., 1.]].into_dyn())
+    ///     );
+    /// });
+    /// ```
+    #[inline]
+    #[track_caller]
+    pub fn placeholder_int(&'graph self, shape_: &[isize]) -> Tensor<'graph, F> {
+        self.typed_placeholder(shape_, DType::Int)
+    }
+
+    /// Creates a placeholder of `DType::Bool`, which is fed with `Tensor::given_bool`.
+    #[inline]
+    #[track_caller]
+    pub fn placeholder_bool(&'graph self, shape_: &[isize]) -> Tensor<'graph, F> {
+        self.typed_placeholder(shape_, DType::Bool)
+    }
+
+    #[track_caller]
+    fn typed_placeholder(&'graph self, shape_: &[isize], dtype: DType) -> Tensor<'graph, F> {
+        let b = Tensor::builder().set_is_placeholder(true).set_dtype(dtype);
         let rank = shape_.len();
         let b = if rank == 0 || -1 != shape_[0] {
             b.set_shape(
@@ -279,6 +592,27 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
         b.build(self, basic_source_ops::Placeholder)
     }
 
+    /// Creates a placeholder tensor named `name`.
+    ///
+    /// Same as `placeholder(shape_).with_name(name)`, so the placeholder can be fed by name.
+    ///
+    /// ```
+    /// use ndarray;
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g| {
+    ///     let x = g.placeholder_named("x", &[2]);
+    ///
+    ///     let arr = ndarray::array![1., 1.].into_dyn();
+    ///     assert_eq!(x.eval(&[ag::Feed::named("x", arr.view())]), Ok(arr));
+    /// });
+    /// ```
+    #[inline]
+    #[track_caller]
+    pub fn placeholder_named(&'graph self, name: &str, shape_: &[isize]) -> Tensor<'graph, F> {
+        self.placeholder(shape_).with_name(name)
+    }
+
     /// Returns a `Tensor` representation of the input tensor's shape
     ///
     /// ```
@@ -290,6 +624,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(&[2., 3.], s.eval(&[]).unwrap().as_slice().unwrap());
     /// });
     /// ```
+    #[track_caller]
     pub fn shape<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -304,7 +639,12 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
             Tensor::builder()
                 .append_input(x.as_ref())
                 .set_differentiable(false)
-                .build(self, array_ops::Shape)
+                .build(
+                    self,
+                    array_ops::Shape {
+                        dtype: DType::Float,
+                    },
+                )
         }
     }
 
@@ -321,6 +661,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(12., b.eval(&[]).unwrap()[ndarray::IxDyn(&[])]);
     /// });
     /// ```
+    #[track_caller]
     pub fn size<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -328,7 +669,12 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
         Tensor::builder()
             .append_input(x.as_ref())
             .set_differentiable(false)
-            .build(self, array_ops::Size)
+            .build(
+                self,
+                array_ops::Size {
+                    dtype: DType::Float,
+                },
+            )
     }
 
     /// Returns the (symbolic) rank of the input tensor
@@ -343,6 +689,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(3., r.eval(&[]).unwrap()[ndarray::IxDyn(&[])]);
     /// });
     /// ```
+    #[track_caller]
     pub fn rank<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -350,10 +697,73 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
         Tensor::builder()
             .append_input(x.as_ref())
             .set_differentiable(false)
-            .build(self, array_ops::Rank)
+            .build(
+                self,
+                array_ops::Rank {
+                    dtype: DType::Float,
+                },
+            )
+    }
+
+    /// Same as `shape` but outputs integers (`DType::Int`), which are exact for any dims.
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g| {
+    ///    let x: ag::Tensor<f32> = g.zeros(&[2, 3]);
+    ///    assert_eq!(g.shape_int(x).eval_int(&[]), Ok(array![2, 3].into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn shape_int<A>(&'graph self, x: A) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        Tensor::builder()
+            .append_input(x.as_ref())
+            .set_differentiable(false)
+            .build(self, array_ops::Shape { dtype: DType::Int })
+    }
+
+    /// Same as `size` but outputs an integer (`DType::Int`), which is exact for any size.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g| {
+    ///    let x: ag::Tensor<f32> = g.placeholder(&[-1]);
+    ///    let value = ndarray::Array1::zeros(16777217);  // 2^24 + 1 is not representable in f32
+    ///    let y = g.size_int(x).eval_int(&[x.given(value.view())]);
+    ///    assert_eq!(y, Ok(ndarray::arr0(16777217).into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn size_int<A>(&'graph self, x: A) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        Tensor::builder()
+            .append_input(x.as_ref())
+            .set_differentiable(false)
+            .build(self, array_ops::Size { dtype: DType::Int })
+    }
+
+    /// Same as `rank` but outputs an integer (`DType::Int`).
+    #[track_caller]
+    pub fn rank_int<A>(&'graph self, x: A) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        Tensor::builder()
+            .append_input(x.as_ref())
+            .set_differentiable(false)
+            .build(self, array_ops::Rank { dtype: DType::Int })
     }
 
     /// Elementwise sine
+    #[track_caller]
     pub fn sin<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -365,6 +775,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise cosine
+    #[track_caller]
     pub fn cos<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -376,6 +787,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise tangent
+    #[track_caller]
     pub fn tan<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -387,6 +799,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise arcsin
+    #[track_caller]
     pub fn asin<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -398,6 +811,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise arccos
+    #[track_caller]
     pub fn acos<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -409,6 +823,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise arctan
+    #[track_caller]
     pub fn atan<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -420,6 +835,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise hyperbolic sine
+    #[track_caller]
     pub fn sinh<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -431,6 +847,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise hyperbolic cosine
+    #[track_caller]
     pub fn cosh<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -442,6 +859,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise hyperbolic tangent
+    #[track_caller]
     pub fn tanh<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -453,6 +871,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise hyperbolic arcsin
+    #[track_caller]
     pub fn asinh<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -464,6 +883,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise hyperbolic arccos
+    #[track_caller]
     pub fn acosh<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -475,6 +895,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise hyperbolic arctan
+    #[track_caller]
     pub fn atanh<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -490,6 +911,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// `x` must be a result of a multi-outputs op;
     /// otherwise index-out-of-bounds error may happen.
+    #[track_caller]
     pub fn nth_tensor<A>(&'graph self, x: A, n: usize) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -501,6 +923,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Identity function without copy.
+    #[track_caller]
     pub fn identity<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -526,6 +949,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// This can be replaced with `+` operation of Tensor.
     #[inline]
+    #[track_caller]
     pub fn add<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -541,6 +965,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// This can be replaced with `-` operation of Tensor.
     #[inline]
+    #[track_caller]
     pub fn sub<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -556,6 +981,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// This can be replaced with `*` operation of Tensor.
     #[inline]
+    #[track_caller]
     pub fn mul<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -571,6 +997,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// This can be replaced with `/` operation of Tensor.
     #[inline]
+    #[track_caller]
     pub fn div<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -584,6 +1011,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
 
     /// Elementwise sqrt
     #[inline]
+    #[track_caller]
     pub fn sqrt<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -595,6 +1023,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise pow
+    #[track_caller]
     pub fn pow<A>(&'graph self, x: A, a: F) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -606,6 +1035,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise base e (napier) logarithm
+    #[track_caller]
     pub fn ln<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -617,6 +1047,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise base 2 logarithm
+    #[track_caller]
     pub fn log2<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -628,6 +1059,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise base 10 logarithm
+    #[track_caller]
     pub fn log10<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -639,6 +1071,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise base e (napier) exponential
+    #[track_caller]
     pub fn exp<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -650,6 +1083,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise base 2 exponential
+    #[track_caller]
     pub fn exp2<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -661,6 +1095,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise base 10 exponential
+    #[track_caller]
     pub fn exp10<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -685,6 +1120,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(c.eval(&[]), Ok(array![3., 2., 3.].into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn maximum<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -709,6 +1145,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(c.eval(&[]), Ok(array![1., 2., 1.].into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn minimum<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -737,6 +1174,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(d.eval(&[]), Ok(array![[3., 3.], [3., 3.]].into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn add_n<A>(&'graph self, xs: &[A]) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -773,6 +1211,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(c.eval(&[]), Ok(ndarray::arr1(&[0., 1., 0.]).into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn equal<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -780,7 +1219,36 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     {
         Tensor::builder()
             .set_ro_inputs(&[a.as_ref(), b.as_ref()])
-            .build(self, math_ops::Equal)
+            .build(
+                self,
+                math_ops::Equal {
+                    dtype: DType::Float,
+                },
+            )
+    }
+
+    /// Same as `equal` but outputs booleans (`DType::Bool`).
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///    let a = g.convert_to_tensor(array![1., 2., 3.]);
+    ///    let b = g.convert_to_tensor(array![3., 2., 1.]);
+    ///    let c = g.equal_bool(a, b);
+    ///    assert_eq!(c.eval_bool(&[]), Ok(array![false, true, false].into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn equal_bool<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        B: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        Tensor::builder()
+            .set_ro_inputs(&[a.as_ref(), b.as_ref()])
+            .build(self, math_ops::Equal { dtype: DType::Bool })
     }
 
     /// Compares a couple of tensors and returns a binary tensor.
@@ -802,6 +1270,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(c.eval(&[]), Ok(array![1., 0., 1.].into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn not_equal<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -809,7 +1278,24 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     {
         Tensor::builder()
             .set_ro_inputs(&[a.as_ref(), b.as_ref()])
-            .build(self, math_ops::NotEqual)
+            .build(
+                self,
+                math_ops::NotEqual {
+                    dtype: DType::Float,
+                },
+            )
+    }
+
+    /// Same as `not_equal` but outputs booleans (`DType::Bool`).
+    #[track_caller]
+    pub fn not_equal_bool<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        B: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        Tensor::builder()
+            .set_ro_inputs(&[a.as_ref(), b.as_ref()])
+            .build(self, math_ops::NotEqual { dtype: DType::Bool })
     }
 
     /// Takes argmax along specified axis.
@@ -828,11 +1314,74 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]), Ok(array![1., 0.].into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn argmax<A>(&'graph self, x: A, axis: isize, keep_dim: bool) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
     {
-        let op = reduction_ops::ArgMax { axis, keep_dim };
+        let op = reduction_ops::ArgMax {
+            axis,
+            keep_dim,
+            dtype: DType::Float,
+        };
+        Tensor::builder().append_input(x.as_ref()).build(self, op)
+    }
+
+    /// Same as `argmax` but outputs integers (`DType::Int`), which are exact for any length
+    /// of `axis`.
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///    let x = g.convert_to_tensor(array![[3., 4.], [6., 5.]]);
+    ///    assert_eq!(g.argmax_int(x, 1, false).eval_int(&[]), Ok(array![1, 0].into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn argmax_int<A>(&'graph self, x: A, axis: isize, keep_dim: bool) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        let op = reduction_ops::ArgMax {
+            axis,
+            keep_dim,
+            dtype: DType::Int,
+        };
+        Tensor::builder().append_input(x.as_ref()).build(self, op)
+    }
+
+    /// Converts the elements of `x` into `dtype`.
+    ///
+    /// Floats are truncated towards zero when cast to integers, and nonzero values are
+    /// `true` as booleans. Only casts from floats to floats are differentiable.
+    ///
+    /// Note that integers above 2^24 (2^53 for `f64`) aren't exact in floats, so indices
+    /// and dims should be computed by the integer ops (e.g. `argmax_int`, `shape_int`)
+    /// rather than cast from the float ones. Likewise, the boolean comparisons
+    /// (e.g. `greater_bool`) output masks directly.
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///    let x = g.convert_to_tensor(array![[3., 4.], [6., 5.]]);
+    ///    let i = g.cast(x, ag::DType::Int);
+    ///    let mask = g.greater_bool(x, g.scalar(4.));
+    ///
+    ///    assert_eq!(i.eval_int(&[]), Ok(array![[3, 4], [6, 5]].into_dyn()));
+    ///    assert_eq!(mask.eval_bool(&[]), Ok(array![[false, false], [true, true]].into_dyn()));
+    ///    assert_eq!(g.cast(mask, ag::DType::Float).eval(&[]), Ok(array![[0., 0.], [1., 1.]].into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn cast<A>(&'graph self, x: A, dtype: DType) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        let op = array_ops::Cast { dtype };
         Tensor::builder().append_input(x.as_ref()).build(self, op)
     }
 
@@ -849,6 +1398,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(b.eval(&[]).unwrap().shape(), &[1, 3, 1]);
     /// });
     /// ```
+    #[track_caller]
     pub fn expand_dims<A, AT>(&'graph self, x: A, axes: &AT) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -872,6 +1422,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(b.eval(&[]).unwrap().shape(), &[3]);
     /// })
     /// ```
+    #[track_caller]
     pub fn squeeze<A, AT>(&'graph self, x: A, axes: &AT) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -902,6 +1453,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    );
     /// });
     /// ```
+    #[track_caller]
     pub fn tile<A>(&'graph self, x: A, axis: isize, num: usize) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -923,6 +1475,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]), Ok(ndarray::arr1(&[3., 4., 5.]).into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn clip<A>(&'graph self, x: A, min: F, max: F) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -946,6 +1499,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]), Ok(array![3., 4.].into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn reduce_max<A, AT>(&'graph self, x: A, axes: &AT, keep_dims: bool) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -975,6 +1529,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]), Ok(array![2., 1.].into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn reduce_min<A, AT>(&'graph self, x: A, axes: &AT, keep_dims: bool) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1002,6 +1557,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]), Ok(ndarray::arr0(10.).into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn reduce_sum_to_scalar<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1027,6 +1583,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]), Ok(array![6., 4.].into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn reduce_sum<A, AT>(&'graph self, x: A, axes: &AT, keep_dims: bool) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1056,6 +1613,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]), Ok(array![3., 2.].into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn reduce_mean<A, AT>(&'graph self, x: A, axes: &AT, keep_dims: bool) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1085,6 +1643,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]), Ok(array![8., 3.].into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn reduce_prod<A, AT>(&'graph self, x: A, axes: &AT, keep_dims: bool) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1113,6 +1672,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]), Ok(ag::ndarray_ext::zeros::<f32>(&[3, 4])));
     /// });
     /// ```
+    #[track_caller]
     pub fn reshape<A, AT>(&'graph self, x: A, shape: &AT) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1134,6 +1694,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(z.eval(&[]).unwrap().shape(), &[12]);
     /// });
     /// ```
+    #[track_caller]
     pub fn flatten<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1160,6 +1721,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    );
     /// });
     /// ```
+    #[track_caller]
     pub fn sign<A>(&'graph self, a: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1186,6 +1748,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    );
     /// });
     /// ```
+    #[track_caller]
     pub fn abs<A>(&'graph self, a: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1212,6 +1775,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    );
     /// });
     /// ```
+    #[track_caller]
     pub fn floor<A>(&'graph self, a: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1238,6 +1802,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    );
     /// });
     /// ```
+    #[track_caller]
     pub fn neg<A>(&'graph self, a: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1263,6 +1828,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    );
     /// });
     /// ```
+    #[track_caller]
     pub fn square<A>(&'graph self, a: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1289,6 +1855,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    );
     /// });
     /// ```
+    #[track_caller]
     pub fn inv<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1315,6 +1882,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    );
     /// });
     /// ```
+    #[track_caller]
     pub fn inv_sqrt<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1342,6 +1910,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// });
     /// ```
+    #[track_caller]
     pub fn ceil<A>(&'graph self, a: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1356,6 +1925,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// # Panics
     /// When broadcast is impossible
+    #[track_caller]
     pub fn greater<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1363,13 +1933,31 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     {
         Tensor::builder()
             .set_ro_inputs(&[a.as_ref(), b.as_ref()])
-            .build(self, math_ops::Greater)
+            .build(
+                self,
+                math_ops::Greater {
+                    dtype: DType::Float,
+                },
+            )
+    }
+
+    /// Same as `greater` but outputs booleans (`DType::Bool`).
+    #[track_caller]
+    pub fn greater_bool<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        B: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        Tensor::builder()
+            .set_ro_inputs(&[a.as_ref(), b.as_ref()])
+            .build(self, math_ops::Greater { dtype: DType::Bool })
     }
 
     /// Compares a couple of tensors and returns a binary tensor.
     ///
     /// # Panics
     /// When broadcast is impossible
+    #[track_caller]
     pub fn greater_equal<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1377,13 +1965,31 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     {
         Tensor::builder()
             .set_ro_inputs(&[a.as_ref(), b.as_ref()])
-            .build(self, math_ops::GreaterEqual)
+            .build(
+                self,
+                math_ops::GreaterEqual {
+                    dtype: DType::Float,
+                },
+            )
+    }
+
+    /// Same as `greater_equal` but outputs booleans (`DType::Bool`).
+    #[track_caller]
+    pub fn greater_equal_bool<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        B: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        Tensor::builder()
+            .set_ro_inputs(&[a.as_ref(), b.as_ref()])
+            .build(self, math_ops::GreaterEqual { dtype: DType::Bool })
     }
 
     /// Compares a couple of tensors and returns a binary tensor.
     ///
     /// # Panics
     /// When broadcast is impossible
+    #[track_caller]
     pub fn lesser<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1391,13 +1997,31 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     {
         Tensor::builder()
             .set_ro_inputs(&[a.as_ref(), b.as_ref()])
-            .build(self, math_ops::Lesser)
+            .build(
+                self,
+                math_ops::Lesser {
+                    dtype: DType::Float,
+                },
+            )
+    }
+
+    /// Same as `lesser` but outputs booleans (`DType::Bool`).
+    #[track_caller]
+    pub fn lesser_bool<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        B: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        Tensor::builder()
+            .set_ro_inputs(&[a.as_ref(), b.as_ref()])
+            .build(self, math_ops::Lesser { dtype: DType::Bool })
     }
 
     /// Compares a couple of tensors and returns a binary tensor.
     ///
     /// # Panics
     /// When broadcast is impossible
+    #[track_caller]
     pub fn lesser_equal<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1405,10 +2029,28 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     {
         Tensor::builder()
             .set_ro_inputs(&[a.as_ref(), b.as_ref()])
-            .build(self, math_ops::LesserEqual)
+            .build(
+                self,
+                math_ops::LesserEqual {
+                    dtype: DType::Float,
+                },
+            )
+    }
+
+    /// Same as `lesser_equal` but outputs booleans (`DType::Bool`).
+    #[track_caller]
+    pub fn lesser_equal_bool<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        B: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        Tensor::builder()
+            .set_ro_inputs(&[a.as_ref(), b.as_ref()])
+            .build(self, math_ops::LesserEqual { dtype: DType::Bool })
     }
 
     /// Elementwise logistic sigmoid function.
+    #[track_caller]
     pub fn sigmoid<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1422,6 +2064,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Elementwise exponential linear unit.
     ///
     /// See https://arxiv.org/abs/1511.07289
+    #[track_caller]
     pub fn elu<A>(&'graph self, x: A, alpha: F) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1433,6 +2076,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise rectified linear unit.
+    #[track_caller]
     pub fn relu<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1448,6 +2092,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// In common, `alpha` is around 0.1 ~ 0.2.
     ///
     /// See http://web.stanford.edu/~awni/papers/relu_hybrid_icml2013_final.pdf
+    #[track_caller]
     pub fn leaky_relu<A: 'tensor>(&'graph self, x: A, alpha: F) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1456,6 +2101,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Elementwise softplus.
+    #[track_caller]
     pub fn softplus<A>(&'graph self, x: A) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1469,6 +2115,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Computes `log(sum(exp(x)))` along specified axis.
     ///
     /// `axis` can be negative.
+    #[track_caller]
     pub fn reduce_logsumexp<A>(&'graph self, x: A, axis: isize, keep_dim: bool) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>>,
@@ -1485,6 +2132,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Computes `softmax(x)` along specified axis and
     /// takes logarithm of it.
     /// `axis` can be negative.
+    #[track_caller]
     pub fn log_softmax<A>(&'graph self, x: A, axis: isize) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1498,6 +2146,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Computes softmax along specified axis
     ///
     /// `axis` can be negative.
+    #[track_caller]
     pub fn softmax<A>(&'graph self, x: A, axis: isize) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1520,6 +2169,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// # Returns
     /// Loss tensor with same shape as inputs'graph shapes
+    #[track_caller]
     pub fn sigmoid_cross_entropy<A, B>(&'graph self, y: A, t: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1543,6 +2193,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// # Returns
     /// Loss tensor with shape (batch_size, 1)
+    #[track_caller]
     pub fn softmax_cross_entropy<A, B>(&'graph self, y: A, t: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1561,10 +2212,11 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// # Arguments
     /// * `y` - Tensor with shape (batch_size, num_classes)
-    /// * `t` - Tensor with shape (batch_size,) or (batch_size, 1)
+    /// * `t` - Tensor with shape (batch_size,) or (batch_size, 1), which can be an integer tensor
     ///
     /// # Returns
     /// Loss tensor with shape (batch_size, 1)
+    #[track_caller]
     pub fn sparse_softmax_cross_entropy<A, B>(&'graph self, y: A, t: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1592,6 +2244,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// ```
     ///
     /// This function supports only f32 and f64.
+    #[track_caller]
     pub fn matmul<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1635,6 +2288,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// For detailed description,
     /// see https://docs.scipy.org/doc/numpy/reference/generated/numpy.tensordot.html.
+    #[track_caller]
     pub fn tensordot<A, B, AT1, AT2>(
         &'graph self,
         a: A,
@@ -1688,6 +2342,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// This function supports only f32 and f64.
     /// For detailed description, see https://www.tensorflow.org/api_docs/python/tf/matmul
+    #[track_caller]
     pub fn batch_matmul_t<A, B>(
         &'graph self,
         a: A,
@@ -1725,6 +2380,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// This function supports only f32 and f64.
     /// For detailed description, see https://www.tensorflow.org/api_docs/python/tf/matmul
+    #[track_caller]
     pub fn batch_matmul<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1759,6 +2415,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// });
     /// ```
     ///
+    #[track_caller]
     pub fn setdiff1d<A, B>(&'graph self, a: A, b: B) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1785,6 +2442,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(b.eval(&[]).unwrap().shape(), &[5, 3, 4, 1, 2]);
     /// });
     /// ```
+    #[track_caller]
     pub fn transpose<A, AT>(&'graph self, x: A, axes: &AT) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1820,6 +2478,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(e2.as_ref().unwrap().shape(), &[3, 2, 5]);
     /// });
     /// ```
+    #[track_caller]
     pub fn split<A>(&'graph self, x: A, sizes: &[usize], axis: isize) -> Vec<Tensor<'graph, F>>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1862,6 +2521,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(b.eval(&[]).unwrap().shape(), &[4, 2]);
     /// });
     /// ```
+    #[track_caller]
     pub fn slice<A>(&'graph self, x: A, starts: &[isize], ends: &[isize]) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1898,6 +2558,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(d.eval(&[]).unwrap().shape(), &[9, 2]);
     /// });
     /// ```
+    #[track_caller]
     pub fn concat<A>(&'graph self, tensors: &[A], axis: isize) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1915,6 +2576,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///
     /// Same spec as https://www.tensorflow.org/api_docs/python/tf/gather.
     /// For example, this can be used for embedding vectors lookup etc.
+    /// `indices` can be an integer tensor (see `placeholder_int`).
     ///
     /// Unlike `ag::gather`, `indices` can contain negative elements.
     ///
@@ -1934,6 +2596,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]).unwrap().shape(), &[5, 4, 2, 3, 2])
     /// });
     /// ```
+    #[track_caller]
     pub fn gather_common<A, B>(&'graph self, param: A, indices: B, axis: isize) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1969,6 +2632,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(y.eval(&[]).unwrap().shape(), &[5, 4, 2, 3, 2])
     /// });
     /// ```
+    #[track_caller]
     pub fn gather<A, B>(&'graph self, param: A, indices: B, axis: isize) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -2000,6 +2664,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(e1.as_ref().unwrap().shape(), &[3, 4]);
     /// });
     /// ```
+    #[track_caller]
     pub fn normalize<A: 'tensor, AT>(&'graph self, _x: A, _axes: &AT) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -2033,6 +2698,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(norm.eval(&[]).unwrap().shape(), &[3, 4]);
     /// });
     /// ```
+    #[track_caller]
     pub fn batch_norm<A: 'tensor, B: 'tensor, C: 'tensor>(
         &'graph self,
         x: A,
@@ -2058,6 +2724,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(a.eval(&[]).unwrap().shape(), &[]);
     /// });
     /// ```
+    #[track_caller]
     pub fn scalar(&'graph self, val: F) -> Tensor<'graph, F> {
         let op = const_gen_ops::Scalar { val };
         Tensor::builder()
@@ -2066,6 +2733,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Outputs values sampled from the normal distribution.
+    #[track_caller]
     pub fn random_normal<A>(&'graph self, shape: &A, mean: f64, stddev: f64) -> Tensor<'graph, F>
     where
         A: AsTensor<'graph, F>,
@@ -2076,7 +2744,8 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Outputs values sampled from the normal distribution.
     ///
     /// Pre-instantiated [ArrayRng](ndarray_ext/array_gen/struct.ArrayRng.html) is acceptable.
-    pub fn random_normal_rng<A, R: Rng + 'static>(
+    #[track_caller]
+    pub fn random_normal_rng<A, R: Rng + Send + 'static>(
         &'graph self,
         arr_rng: ArrayRng<F, R>,
         shape: &A,
@@ -2094,6 +2763,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Outputs values sampled from the uniform distribution.
+    #[track_caller]
     pub fn random_uniform<A>(&'graph self, shape: &A, min: f64, max: f64) -> Tensor<'graph, F>
     where
         A: AsTensor<'graph, F>,
@@ -2104,7 +2774,8 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Outputs values sampled from the uniform distribution.
     ///
     /// Pre-instantiated [ArrayRng](ndarray_ext/array_gen/struct.ArrayRng.html) is acceptable.
-    pub fn random_uniform_rng<A, R: Rng + 'static>(
+    #[track_caller]
+    pub fn random_uniform_rng<A, R: Rng + Send + 'static>(
         &'graph self,
         arr_rng: ArrayRng<F, R>,
         shape: &A,
@@ -2122,6 +2793,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Outputs values sampled from the standard normal distribution.
+    #[track_caller]
     pub fn standard_normal<A>(&'graph self, shape: &A) -> Tensor<'graph, F>
     where
         A: AsTensor<'graph, F>,
@@ -2132,7 +2804,8 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Outputs values sampled from the standard normal distribution.
     ///
     /// Pre-instantiated [ArrayRng](ndarray_ext/array_gen/struct.ArrayRng.html) is acceptable.
-    pub fn standard_normal_rng<A, R: Rng + 'static>(
+    #[track_caller]
+    pub fn standard_normal_rng<A, R: Rng + Send + 'static>(
         &'graph self,
         arr_rng: ArrayRng<F, R>,
         shape: &A,
@@ -2149,6 +2822,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Outputs values sampled from the standard uniform distribution.
+    #[track_caller]
     pub fn standard_uniform<A>(&'graph self, shape: &A) -> Tensor<'graph, F>
     where
         A: AsTensor<'graph, F>,
@@ -2159,7 +2833,8 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Outputs values sampled from the standard uniform distribution.
     ///
     /// Pre-instantiated [ArrayRng](ndarray_ext/array_gen/struct.ArrayRng.html) is acceptable.
-    pub fn standard_uniform_rng<A, R: Rng + 'static>(
+    #[track_caller]
+    pub fn standard_uniform_rng<A, R: Rng + Send + 'static>(
         &'graph self,
         arr_rng: ArrayRng<F, R>,
         shape: &A,
@@ -2175,6 +2850,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Outputs values sampled from the bernoulli distribution.
+    #[track_caller]
     pub fn bernoulli<A>(&'graph self, shape: &A, p: f64) -> Tensor<'graph, F>
     where
         A: AsTensor<'graph, F>,
@@ -2185,7 +2861,8 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Outputs values sampled from the bernoulli distribution.
     ///
     /// Pre-instantiated [ArrayRng](ndarray_ext/array_gen/struct.ArrayRng.html) is acceptable.
-    pub fn bernoulli_rng<A, R: Rng + 'static>(
+    #[track_caller]
+    pub fn bernoulli_rng<A, R: Rng + Send + 'static>(
         &'graph self,
         arr_rng: ArrayRng<F, R>,
         shape: &A,
@@ -2202,6 +2879,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Outputs values sampled from the exponential distribution.
+    #[track_caller]
     pub fn random_exp<A>(&'graph self, shape: &A, lambda: f64) -> Tensor<'graph, F>
     where
         A: AsTensor<'graph, F>,
@@ -2212,7 +2890,8 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Outputs values sampled from the exponential distribution.
     ///
     /// Pre-instantiated [ArrayRng](ndarray_ext/array_gen/struct.ArrayRng.html) is acceptable.
-    pub fn random_exp_rng<A, R: Rng + 'static>(
+    #[track_caller]
+    pub fn random_exp_rng<A, R: Rng + Send + 'static>(
         &'graph self,
         arr_rng: ArrayRng<F, R>,
         shape: &A,
@@ -2229,6 +2908,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Outputs values sampled from the gamma distribution.
+    #[track_caller]
     pub fn random_gamma<A>(
         &'graph self,
         shape: &A,
@@ -2244,7 +2924,8 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Outputs values sampled from the gamma distribution.
     ///
     /// Pre-instantiated [ArrayRng](ndarray_ext/array_gen/struct.ArrayRng.html) is acceptable.
-    pub fn random_gamma_rng<A, R: Rng + 'static>(
+    #[track_caller]
+    pub fn random_gamma_rng<A, R: Rng + Send + 'static>(
         &'graph self,
         arr_rng: ArrayRng<F, R>,
         shape: &A,
@@ -2262,6 +2943,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     }
 
     /// Outputs values sampled from the log-normal distribution.
+    #[track_caller]
     pub fn log_normal<A>(&'graph self, shape: &A, mean: f64, stddev: f64) -> Tensor<'graph, F>
     where
         A: AsTensor<'graph, F>,
@@ -2272,7 +2954,8 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     /// Outputs values sampled from the log-normal distribution.
     ///
     /// Pre-instantiated [ArrayRng](ndarray_ext/array_gen/struct.ArrayRng.html) is acceptable.
-    pub fn log_normal_rng<A, R: Rng + 'static>(
+    #[track_caller]
+    pub fn log_normal_rng<A, R: Rng + Send + 'static>(
         &'graph self,
         arr_rng: ArrayRng<F, R>,
         shape: &A,
@@ -2301,6 +2984,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(tensor.eval(&[]), Ok(arr.into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn convert_to_tensor<D>(&'graph self, arr: ndarray::Array<F, D>) -> Tensor<'graph, F>
     where
         D: ndarray::Dimension,
@@ -2328,6 +3012,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(a.eval(&[]), Ok(ndarray::Array2::<f32>::zeros((4, 2)).into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn zeros<A>(&'graph self, shape: &A) -> Tensor<'graph, F>
     where
         A: AsTensor<'graph, F>,
@@ -2348,6 +3033,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///    assert_eq!(a.eval(&[]), Ok(ndarray::Array2::<f32>::ones((4, 2)).into_dyn()));
     /// });
     /// ```
+    #[track_caller]
     pub fn ones<A>(&'graph self, shape: &A) -> Tensor<'graph, F>
     where
         A: AsTensor<'graph, F>,
@@ -2370,6 +3056,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///   * `out_w` = `(w + 2 * pad - filter_w) / stride + 1`
     ///
     /// This function supports only f32 and f64.
+    #[track_caller]
     pub fn conv2d<A, B>(&'graph self, x: A, w: B, pad: usize, stride: usize) -> Tensor<'graph, F>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
@@ -2400,6 +3087,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///   * `out_w` = `(w + 2 * pad - (dilate * (filter - 1) + 1)) / stride + 1`
     ///
     /// This function supports only f32 and f64.
+    #[track_caller]
     pub fn dilated_conv2d<A, B>(
         &'graph self,
         x: A,
@@ -2437,6 +3125,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///   * `out_w` = `stride * (w - 1) - pad + filter_w`
     ///
     /// This function supports only f32 and f64.
+    #[track_caller]
     pub fn conv2d_transpose<A, B>(
         &'graph self,
         x: A,
@@ -2473,6 +3162,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///   * `out_w` = `stride * (w - 1) - pad + (dilate * (filter_w - 1) + 1)`
     ///
     /// This function supports only f32 and f64.
+    #[track_caller]
     pub fn dilated_conv2d_transpose<A, B>(
         &'graph self,
         x: A,
@@ -2509,6 +3199,7 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
     ///   * `out_w` = `(w + 2 * pad - pool_size) / stride + 1`
     ///
     /// This function supports only f32 and f64.
+    #[track_caller]
     pub fn max_pool2d<A>(
         &'graph self,
         x: A,
@@ -2528,4 +3219,569 @@ impl<'tensor, 'graph: 'tensor, F: Float> crate::graph::Graph<F> {
             },
         )
     }
+
+    /// Evaluates the tensors returned by `true_fn` if `pred` is nonzero, and those returned by
+    /// `false_fn` otherwise.
+    ///
+    /// Both closures are called once to build the branches, but only the taken one is
+    /// evaluated at run-time. The branches must return the same number of tensors, and can use
+    /// any tensors of the graph. Gradients flow into the tensors used in the taken branch.
+    ///
+    /// The tensors created in the closures belong to the branches: use them outside only
+    /// through the returned tensors, since evaluating them directly fails with
+    /// [EvalError::SubgraphTensor](enum.EvalError.html#variant.SubgraphTensor).
+    ///
+    /// * `pred` - Tensor with a single element
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ndarray::arr0;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f64>| {
+    ///    let x = g.placeholder(&[]);
+    ///    // abs(x)
+    ///    let y = g.cond(g.greater_equal(x, g.scalar(0.)), || vec![x], || vec![x * -1.])[0];
+    ///    let gx = g.grad(&[y], &[x])[0];
+    ///
+    ///    let x_val = arr0(-2.);
+    ///    let ret = g.eval(&[y, gx], &[x.given(x_val.view())]);
+    ///    assert_eq!(ret[0], Ok(arr0(2.).into_dyn()));
+    ///    assert_eq!(ret[1], Ok(arr0(-1.).into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn cond<A, TF, FF>(
+        &'graph self,
+        pred: A,
+        true_fn: TF,
+        false_fn: FF,
+    ) -> Vec<Tensor<'graph, F>>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        TF: FnOnce() -> Vec<Tensor<'graph, F>>,
+        FF: FnOnce() -> Vec<Tensor<'graph, F>>,
+    {
+        control_flow_ops::cond(self, pred.as_ref(), true_fn, false_fn)
+    }
+
+    /// Computes the tensors returned by `f` without keeping their intermediate values
+    /// for the backward pass (a.k.a. gradient checkpointing).
+    ///
+    /// `f` is called once to build the segment, which is evaluated on its own: the arrays
+    /// computed in it are freed once its outputs are computed. The gradients of the outputs are
+    /// computed by evaluating the segment again, which trades compute for memory in
+    /// deep networks. `f` can use any tensors of the graph, but the tensors it creates can be
+    /// used outside only through its outputs (see [cond](#method.cond)).
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ndarray::array;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f64>| {
+    ///    let x = g.placeholder(&[-1]);
+    ///    let y = g.checkpoint(|| vec![g.sigmoid(g.tanh(x) * 2.)])[0];
+    ///    let gx = g.grad(&[y], &[x])[0];
+    ///
+    ///    let expected = g.sigmoid(g.tanh(x) * 2.);
+    ///    let expected_gx = g.grad(&[expected], &[x])[0];
+    ///    let x_val = array![-1., 0., 1.].into_dyn();
+    ///    let feeds = &[x.given(x_val.view())];
+    ///    assert_eq!(y.eval(feeds), expected.eval(feeds));
+    ///    assert_eq!(gx.eval(feeds), expected_gx.eval(feeds));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn checkpoint<FN>(&'graph self, f: FN) -> Vec<Tensor<'graph, F>>
+    where
+        FN: FnOnce() -> Vec<Tensor<'graph, F>>,
+    {
+        control_flow_ops::checkpoint(self, f)
+    }
+
+    /// Repeats `body_fn` on the loop variables while `cond_fn` returns a nonzero value,
+    /// and returns the final values of the loop variables.
+    ///
+    /// The closures are called once with the symbolic loop variables to build the loop,
+    /// which is run at run-time without unrolling it into the graph.
+    /// `body_fn` must return the next values of all the loop variables, whose shapes may change
+    /// over the iterations. Both closures can use any tensors of the graph, and the tensors
+    /// created in them are reachable only through the loop outputs (see [cond](#method.cond)).
+    ///
+    /// Gradients flow into `loop_vars` and the tensors used in `body_fn`; they are computed by
+    /// running the loop again and backpropagating through the iterations in reverse order.
+    /// Second-order gradients are not supported: differentiating these gradients again builds
+    /// tensors whose evaluations fail with `OpError::Unsupported`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ndarray::arr0;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f64>| {
+    ///    let x = g.placeholder(&[]);
+    ///    let i = g.scalar(0.);
+    ///    // Squares `x` three times.
+    ///    let ys = g.while_loop(
+    ///        |vars| g.lesser(vars[0], g.scalar(3.)),
+    ///        |vars| vec![vars[0] + 1., vars[1] * vars[1]],
+    ///        &[i, x],
+    ///    );
+    ///    let gx = g.grad(&[ys[1]], &[x])[0];
+    ///
+    ///    let x_val = arr0(2.);
+    ///    let ret = g.eval(&[ys[1], gx], &[x.given(x_val.view())]);
+    ///    assert_eq!(ret[0], Ok(arr0(256.).into_dyn()));
+    ///    // d(x^8)/dx = 8x^7
+    ///    assert_eq!(ret[1], Ok(arr0(1024.).into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn while_loop<A, CF, BF>(
+        &'graph self,
+        cond_fn: CF,
+        body_fn: BF,
+        loop_vars: &[A],
+    ) -> Vec<Tensor<'graph, F>>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        CF: FnOnce(&[Tensor<'graph, F>]) -> Tensor<'graph, F>,
+        BF: FnOnce(&[Tensor<'graph, F>]) -> Vec<Tensor<'graph, F>>,
+    {
+        let loop_vars: Vec<_> = loop_vars.iter().map(|x| *x.as_ref()).collect();
+        control_flow_ops::while_loop(self, cond_fn, body_fn, &loop_vars)
+    }
+
+    /// Applies `f` to the slices of `sequence` along `axis` in order, threading the states
+    /// through the steps, and returns the states after each step stacked along `axis`.
+    ///
+    /// `f` is called once with the symbolic states and slice to build the step, which is run
+    /// at run-time without unrolling it into the graph, so the length of the sequence can vary
+    /// between evaluations. `f` must return the next values of all the states, whose shapes must
+    /// not change. `axis` can be negative. The tensors created in `f` are reachable only through
+    /// the stacked states (see [cond](#method.cond)).
+    ///
+    /// Gradients flow into `initial_state`, `sequence` and the tensors used in `f`;
+    /// they are computed by backpropagation through time. As in [while_loop](#method.while_loop),
+    /// the gradients can't be differentiated again.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ndarray::array;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f64>| {
+    ///    let x = g.placeholder(&[-1]);
+    ///    // Cumulative sum
+    ///    let ys = g.scan(|s, x| vec![s[0] + x], &[g.scalar(0.)], x, 0);
+    ///    let gx = g.grad(&[ys[0]], &[x])[0];
+    ///
+    ///    let x_val = array![1., 2., 3.];
+    ///    let ret = g.eval(&[ys[0], gx], &[x.given(x_val.view().into_dyn())]);
+    ///    assert_eq!(ret[0], Ok(array![1., 3., 6.].into_dyn()));
+    ///    assert_eq!(ret[1], Ok(array![3., 2., 1.].into_dyn()));
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn scan<A, B, FN>(
+        &'graph self,
+        f: FN,
+        initial_state: &[A],
+        sequence: B,
+        axis: isize,
+    ) -> Vec<Tensor<'graph, F>>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+        B: AsRef<Tensor<'graph, F>> + Copy,
+        FN: FnOnce(&[Tensor<'graph, F>], &Tensor<'graph, F>) -> Vec<Tensor<'graph, F>>,
+    {
+        let initial_state: Vec<_> = initial_state.iter().map(|x| *x.as_ref()).collect();
+        control_flow_ops::scan(self, f, &initial_state, sequence.as_ref(), axis)
+    }
+}
+
+// Helpers to read attributes in `register_builtin_ops`
+fn int<F: Float>(attrs: &[Attribute<F>], i: usize) -> Option<i64> {
+    attrs.get(i)?.as_int()
+}
+
+fn float<F: Float>(attrs: &[Attribute<F>], i: usize) -> Option<F> {
+    attrs.get(i)?.as_float()
+}
+
+fn double<F: Float>(attrs: &[Attribute<F>], i: usize) -> Option<f64> {
+    attrs.get(i)?.as_float()?.to_f64()
+}
+
+fn boolean<F: Float>(attrs: &[Attribute<F>], i: usize) -> Option<bool> {
+    attrs.get(i)?.as_bool()
+}
+
+// Graphs saved before the attribute was added have only float outputs.
+fn dtype_or_float<F: Float>(attrs: &[Attribute<F>], i: usize) -> Option<DType> {
+    match attrs.get(i) {
+        Some(a) => DType::from_index(a.as_int()?),
+        None => Some(DType::Float),
+    }
+}
+
+fn boxed<F: Float, O: op::Op<F> + 'static>(op: O) -> Option<Box<dyn op::Op<F>>> {
+    Some(Box::new(op))
+}
+
+macro_rules! register_ops_without_attributes {
+    ($registry:expr, $num_inputs:expr; $($module:ident::$op:ident),* $(,)?) => {
+        $($registry.register(stringify!($op), $num_inputs, |_| boxed($module::$op));)*
+    };
+}
+
+/// Registers the constructors of all the built-in ops.
+pub(crate) fn register_builtin_ops<F: Float>(r: &mut OpRegistry<F>) {
+    register_ops_without_attributes!(r, 0;
+        basic_source_ops::Const,
+        basic_source_ops::Placeholder,
+        basic_source_ops::Variable,
+    );
+    register_ops_without_attributes!(r, 1;
+        activation_ops::Identity,
+        activation_ops::ReLU,
+        activation_ops::Sigmoid,
+        activation_ops::Softplus,
+        const_gen_ops::Ones,
+        const_gen_ops::Zeros,
+        gradient_ops::StopGradient,
+        math_ops::Abs,
+        math_ops::Acos,
+        math_ops::Acosh,
+        math_ops::Asin,
+        math_ops::Asinh,
+        math_ops::Atan,
+        math_ops::Atanh,
+        math_ops::Ceil,
+        math_ops::Cos,
+        math_ops::Cosh,
+        math_ops::Exp,
+        math_ops::Exp10,
+        math_ops::Exp2,
+        math_ops::Floor,
+        math_ops::Inv,
+        math_ops::InvSqrt,
+        math_ops::Ln,
+        math_ops::Log10,
+        math_ops::Log2,
+        math_ops::NegOp,
+        math_ops::Sign,
+        math_ops::Sin,
+        math_ops::Sinh,
+        math_ops::Sqrt,
+        math_ops::Square,
+        math_ops::Tan,
+        math_ops::Tanh,
+        reduction_ops::ReduceSumToScalar,
+    );
+    register_ops_without_attributes!(r, 2;
+        array_ops::ExpandDims,
+        array_ops::InferBinOpShape,
+        array_ops::Reshape,
+        array_ops::SetDiff1D,
+        array_ops::Squeeze,
+        binary_ops::AddOp,
+        binary_ops::DivOp,
+        binary_ops::MulOp,
+        binary_ops::PreprocessBinOpGrad,
+        binary_ops::PreprocessBinOpGradGrad,
+        binary_ops::SubOp,
+        math_ops::Maximum,
+        math_ops::Minimum,
+        reduction_ops::ReduceSumToScalarGrad,
+        xent_ops::SigmoidCrossEntropy,
+        xent_ops::SoftmaxCrossEntropy,
+        xent_ops::SparseSoftmaxCrossEntropy,
+    );
+    register_ops_without_attributes!(r, 3;
+        xent_ops::SparseSoftmaxCrossEntropyGrad,
+    );
+    register_ops_without_attributes!(r, 4;
+        dot_ops::TensordotPreprocess,
+    );
+    r.register_variadic("AddN", 1, |_| boxed(array_ops::AddN));
+
+    // activation_ops
+    r.register("ELU", 1, |a| {
+        boxed(activation_ops::ELU {
+            alpha: float(a, 0)?,
+        })
+    });
+    r.register("ELUGrad", 2, |a| {
+        boxed(activation_ops::ELUGrad {
+            alpha: float(a, 0)?,
+        })
+    });
+    r.register("Softmax", 1, |a| {
+        boxed(activation_ops::Softmax {
+            axis: int(a, 0)? as isize,
+        })
+    });
+
+    // array_ops
+    r.register("Clip", 1, |a| {
+        boxed(array_ops::Clip {
+            min: float(a, 0)?,
+            max: float(a, 1)?,
+        })
+    });
+    r.register("ClipGrad", 2, |a| {
+        boxed(array_ops::ClipGrad {
+            min: float(a, 0)?,
+            max: float(a, 1)?,
+        })
+    });
+    r.register_variadic("Concat", 1, |a| {
+        boxed(array_ops::Concat {
+            axis: int(a, 0)? as isize,
+        })
+    });
+    r.register_variadic("ConcatGrad", 2, |a| {
+        boxed(array_ops::ConcatGrad {
+            axis: int(a, 0)? as isize,
+            index: int(a, 1)? as usize,
+        })
+    });
+    macro_rules! register_ops_with_dtype {
+        ($num_inputs:expr; $($module:ident::$op:ident),*) => {
+            $(r.register(stringify!($op), $num_inputs, |a| {
+                boxed($module::$op {
+                    dtype: dtype_or_float(a, 0)?,
+                })
+            });)*
+        };
+    }
+    register_ops_with_dtype!(1; array_ops::Rank, array_ops::Shape, array_ops::Size);
+    register_ops_with_dtype!(2;
+        math_ops::Equal,
+        math_ops::Greater,
+        math_ops::GreaterEqual,
+        math_ops::Lesser,
+        math_ops::LesserEqual,
+        math_ops::NotEqual
+    );
+    r.register("Cast", 1, |a| {
+        boxed(array_ops::Cast {
+            dtype: DType::from_index(int(a, 0)?)?,
+        })
+    });
+    r.register("Gather", 2, |a| {
+        boxed(array_ops::Gather {
+            axis: int(a, 0)? as isize,
+            should_normalize_negative_indices: boolean(a, 1)?,
+        })
+    });
+    r.register("GatherGrad", 3, |a| {
+        boxed(array_ops::GatherGrad {
+            axis: int(a, 0)? as isize,
+        })
+    });
+    r.register("IndexOp", 1, |a| {
+        boxed(array_ops::IndexOp {
+            index: int(a, 0)? as isize,
+        })
+    });
+    r.register("IndexOpGrad", 2, |a| {
+        boxed(array_ops::IndexOpGrad {
+            index: int(a, 0)? as isize,
+        })
+    });
+    r.register("Slice", 1, |a| {
+        boxed(array_ops::Slice {
+            indices: array_ops::slice_indices_from_ints(a.first()?.as_ints()?)?,
+        })
+    });
+    r.register("SliceGrad", 2, |a| {
+        boxed(array_ops::SliceGrad {
+            indices: array_ops::slice_indices_from_ints(a.first()?.as_ints()?)?,
+        })
+    });
+    r.register("Split", 1, |a| {
+        boxed(array_ops::Split {
+            axis: int(a, 0)? as isize,
+            start_index: int(a, 1)? as isize,
+            end_index: int(a, 2)? as isize,
+        })
+    });
+    r.register("SplitGrad", 2, |a| {
+        boxed(array_ops::SplitGrad {
+            axis: int(a, 0)? as isize,
+            start_index: int(a, 1)? as isize,
+            end_index: int(a, 2)? as isize,
+        })
+    });
+    r.register("Tile", 1, |a| {
+        boxed(array_ops::Tile {
+            axis: int(a, 0)? as isize,
+            num: int(a, 1)? as usize,
+        })
+    });
+    r.register("TileGrad", 1, |a| {
+        boxed(array_ops::TileGrad {
+            axis: int(a, 0)? as isize,
+            num: int(a, 1)? as usize,
+        })
+    });
+
+    // const_gen_ops
+    r.register("ConvertToTensor", 0, |a| {
+        boxed(const_gen_ops::ConvertToTensor {
+            arr: a.first()?.as_array()?.clone(),
+        })
+    });
+    r.register("Scalar", 0, |a| {
+        boxed(const_gen_ops::Scalar { val: float(a, 0)? })
+    });
+
+    // conv_ops
+    macro_rules! register_conv_ops {
+        ($last:ident; $($module:ident::$op:ident: $num_inputs:expr),*) => {
+            $(r.register(stringify!($op), $num_inputs, |a| {
+                boxed(conv_ops::$module::$op {
+                    pad: int(a, 0)? as usize,
+                    stride: int(a, 1)? as usize,
+                    $last: int(a, 2)? as usize,
+                })
+            });)*
+        };
+    }
+    register_conv_ops!(dilation;
+        conv2d::Conv2D: 2,
+        conv2d::Conv2DFilterGrad: 3,
+        conv2d::Conv2DWithCols: 2,
+        conv2d_transpose::Conv2DTranspose: 2,
+        conv2d_transpose::Conv2DTransposeFilterGrad: 3
+    );
+    register_conv_ops!(size;
+        max_pool2d::MaxPool2D: 1,
+        max_pool2d::MaxPool2DGrad: 2,
+        max_pool2d::MaxPool2DGradGrad: 2
+    );
+
+    // dot_ops
+    r.register("MatMul", 2, |a| {
+        boxed(dot_ops::MatMul {
+            transpose_a: boolean(a, 0)?,
+            transpose_b: boolean(a, 1)?,
+        })
+    });
+    r.register("BatchMatMul", 2, |a| {
+        boxed(dot_ops::BatchMatMul {
+            transpose_a: boolean(a, 0)?,
+            transpose_b: boolean(a, 1)?,
+        })
+    });
+
+    // gradient_descent_ops
+    r.register("SGDOp", 2, |a| {
+        boxed(gradient_descent_ops::sgd::SGDOp::new(float(a, 0)?))
+    });
+    r.register("AdamOp", 5, |a| {
+        boxed(gradient_descent_ops::adam::AdamOp {
+            static_params: crate::optimizers::adam::StaticParams {
+                alpha: float(a, 0)?,
+                eps: float(a, 1)?,
+                b1: float(a, 2)?,
+                b2: float(a, 3)?,
+            },
+        })
+    });
+
+    // math_ops
+    r.register("LogSumExp", 1, |a| {
+        boxed(math_ops::LogSumExp {
+            axis: int(a, 0)? as isize,
+            keep_dims: boolean(a, 1)?,
+        })
+    });
+    r.register("Pow", 1, |a| boxed(math_ops::Pow { a: float(a, 0)? }));
+    r.register("Transpose", 2, |a| {
+        boxed(math_ops::Transpose {
+            invert_axes: boolean(a, 0)?,
+        })
+    });
+
+    // random_ops
+    r.register("StandardNormal", 1, |_| {
+        boxed(random_ops::StandardNormal::new(ArrayRng::default()))
+    });
+    r.register("StandardUniform", 1, |_| {
+        boxed(random_ops::StandardUniform::new(ArrayRng::default()))
+    });
+    r.register("RandomNormal", 1, |a| {
+        boxed(random_ops::RandomNormal::new(
+            ArrayRng::default(),
+            double(a, 0)?,
+            double(a, 1)?,
+        ))
+    });
+    r.register("RandomUniform", 1, |a| {
+        boxed(random_ops::RandomUniform::new(
+            ArrayRng::default(),
+            double(a, 0)?,
+            double(a, 1)?,
+        ))
+    });
+    r.register("Bernoulli", 1, |a| {
+        boxed(random_ops::Bernoulli::new(
+            ArrayRng::default(),
+            double(a, 0)?,
+        ))
+    });
+    r.register("Exponential", 1, |a| {
+        boxed(random_ops::Exponential::new(
+            ArrayRng::default(),
+            double(a, 0)?,
+        ))
+    });
+    r.register("LogNormal", 1, |a| {
+        boxed(random_ops::LogNormal::new(
+            ArrayRng::default(),
+            double(a, 0)?,
+            double(a, 1)?,
+        ))
+    });
+    r.register("Gamma", 1, |a| {
+        boxed(random_ops::Gamma::new(
+            ArrayRng::default(),
+            double(a, 0)?,
+            double(a, 1)?,
+        ))
+    });
+
+    // reduction_ops
+    macro_rules! register_reduce_ops {
+        ($($op:ident),*) => {
+            // The input and the axes
+            $(r.register(stringify!($op), 2, |a| {
+                boxed(reduction_ops::$op {
+                    keep_dims: boolean(a, 0)?,
+                    sparse_axes: boolean(a, 1)?,
+                })
+            });)*
+        };
+    }
+    register_reduce_ops!(ReduceSum, ReduceMean, ReduceProd, ReduceMin, ReduceMax);
+    r.register("ArgMax", 1, |a| {
+        boxed(reduction_ops::ArgMax {
+            axis: int(a, 0)? as isize,
+            keep_dim: boolean(a, 1)?,
+            dtype: dtype_or_float(a, 2)?,
+        })
+    });
+    r.register("ReduceGradCommon", 3, |a| {
+        boxed(reduction_ops::ReduceGradCommon {
+            should_make_broadcast_dims: boolean(a, 0)?,
+            sparse_axes: boolean(a, 1)?,
+        })
+    });
+
+    // xent_ops
+    r.register("LogSoftmax", 1, |a| {
+        boxed(xent_ops::LogSoftmax {
+            axis: int(a, 0)? as isize,
+        })
+    });
 }
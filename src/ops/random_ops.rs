@@ -1,5 +1,6 @@
 use crate::ndarray_ext::{self, ArrayRng};
 use crate::op;
+use crate::op::Attribute;
 use crate::Float;
 use rand::Rng;
 
@@ -105,7 +106,7 @@ impl<'a, T: Float, R: Rng> Gamma<T, R> {
     }
 }
 
-impl<T: Float, R: Rng> op::Op<T> for RandomNormal<T, R> {
+impl<T: Float, R: Rng + Send> op::Op<T> for RandomNormal<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(
@@ -117,9 +118,20 @@ impl<T: Float, R: Rng> op::Op<T> for RandomNormal<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Float(T::from(self.mean).unwrap()),
+            Attribute::Float(T::from(self.stddev).unwrap()),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(0))
+    }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for RandomUniform<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for RandomUniform<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(
@@ -131,9 +143,20 @@ impl<R: Rng, T: Float> op::Op<T> for RandomUniform<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Float(T::from(self.min).unwrap()),
+            Attribute::Float(T::from(self.max).unwrap()),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(0))
+    }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for StandardNormal<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for StandardNormal<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(self.arr_rng.standard_normal(shape.as_slice()));
@@ -142,9 +165,17 @@ impl<R: Rng, T: Float> op::Op<T> for StandardNormal<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(0))
+    }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for StandardUniform<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for StandardUniform<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(self.arr_rng.standard_uniform(shape.as_slice()));
@@ -153,9 +184,17 @@ impl<R: Rng, T: Float> op::Op<T> for StandardUniform<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(0))
+    }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for Bernoulli<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for Bernoulli<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(self.arr_rng.bernoulli(shape.as_slice(), self.p));
@@ -164,9 +203,17 @@ impl<R: Rng, T: Float> op::Op<T> for Bernoulli<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Float(T::from(self.p).unwrap())])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(0))
+    }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for Exponential<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for Exponential<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(self.arr_rng.exponential(shape.as_slice(), self.lambda));
@@ -175,9 +222,17 @@ impl<R: Rng, T: Float> op::Op<T> for Exponential<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Float(T::from(self.lambda).unwrap())])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(0))
+    }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for LogNormal<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for LogNormal<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(
@@ -189,9 +244,20 @@ impl<R: Rng, T: Float> op::Op<T> for LogNormal<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Float(T::from(self.mean).unwrap()),
+            Attribute::Float(T::from(self.stddev).unwrap()),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(0))
+    }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for Gamma<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for Gamma<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(
@@ -203,4 +269,15 @@ impl<R: Rng, T: Float> op::Op<T> for Gamma<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Float(T::from(self.shape_param).unwrap()),
+            Attribute::Float(T::from(self.scale).unwrap()),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_as_shape(0))
+    }
 }
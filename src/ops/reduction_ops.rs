@@ -1,8 +1,9 @@
 use crate::ndarray_ext;
 use crate::ndarray_ext::{NdArray, NdArrayView};
 use crate::op;
+use crate::op::Attribute;
 use crate::ops;
-use crate::tensor::Tensor;
+use crate::tensor::{DType, Tensor};
 use crate::Float;
 use crate::Graph;
 use ndarray;
@@ -39,6 +40,7 @@ pub struct ReduceMean {
 pub struct ArgMax {
     pub axis: isize,
     pub keep_dim: bool,
+    pub dtype: DType,
 }
 
 pub struct ReduceGradCommon {
@@ -115,6 +117,51 @@ fn preprocess_axes<T: Float>(
     }
 }
 
+// Static shape of the output of reduction ops whose inputs are `[x, axes]`.
+fn infer_reduced_shape<T: Float>(
+    ctx: &op::ShapeContext<T>,
+    keep_dims: bool,
+    sparse_axes: bool,
+) -> Result<Option<Vec<isize>>, op::OpError> {
+    let mut ret = match ctx.input_shape(0) {
+        Some(shape) if !shape.is_empty() && shape != [0] => shape.to_vec(),
+        shape => return Ok(shape.map(|s| s.to_vec())),
+    };
+    let axes = match ctx.input_ints(1) {
+        Some(axes) => axes,
+        None if keep_dims => return Ok(Some(vec![-1; ret.len()])),
+        None => return Ok(None),
+    };
+    let mut axes = if sparse_axes {
+        axes.iter()
+            .enumerate()
+            .filter(|&(_, &a)| a == 1)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>()
+    } else {
+        axes.iter()
+            .map(|&a| ops::array_ops::static_axis(a, ret.len()))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    axes.sort();
+    axes.dedup();
+    for &axis in axes.iter().rev() {
+        if axis >= ret.len() {
+            return Err(op::OpError::OutOfBounds(format!(
+                "Can't reduce axis {} of rank {}",
+                axis,
+                ret.len()
+            )));
+        }
+        if keep_dims {
+            ret[axis] = 1;
+        } else {
+            ret.remove(axis);
+        }
+    }
+    Ok(Some(ret))
+}
+
 impl<T: Float> op::Op<T> for ReduceSumToScalar {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let x = &ctx.input(0);
@@ -127,9 +174,28 @@ impl<T: Float> op::Op<T> for ReduceSumToScalar {
             .build(ctx.graph(), ReduceSumToScalarGrad);
         ctx.append_input_grad(Some(gx))
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        let ty = ctx
+            .input_tangent(0)
+            .map(|tx| ctx.graph().reduce_sum_to_scalar(tx));
+        ctx.append_output_tangent(ty);
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, _: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(Some(Vec::new()))
+    }
 }
 
-struct ReduceSumToScalarGrad;
+pub struct ReduceSumToScalarGrad;
 
 impl<T: Float> op::Op<T> for ReduceSumToScalarGrad {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
@@ -148,6 +214,14 @@ impl<T: Float> op::Op<T> for ReduceSumToScalarGrad {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_ints(1))
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceSum {
@@ -175,6 +249,37 @@ impl<T: Float> op::Op<T> for ReduceSum {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn jvp(&self, ctx: &mut crate::op::JvpContext<T>) {
+        // Sums up the tangent over the same axes.
+        let ty = ctx.input_tangent(0).map(|tx| {
+            Tensor::builder()
+                .set_ro_inputs(&[&tx, &ctx.input(1)])
+                .build(
+                    ctx.graph(),
+                    ReduceSum {
+                        keep_dims: self.keep_dims,
+                        sparse_axes: self.sparse_axes,
+                    },
+                )
+        });
+        ctx.append_output_tangent(ty);
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.keep_dims, self.sparse_axes)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Bool(self.keep_dims),
+            Attribute::Bool(self.sparse_axes),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        infer_reduced_shape(ctx, self.keep_dims, self.sparse_axes)
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceMean {
@@ -229,6 +334,21 @@ impl<T: Float> op::Op<T> for ReduceMean {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.keep_dims, self.sparse_axes)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Bool(self.keep_dims),
+            Attribute::Bool(self.sparse_axes),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        infer_reduced_shape(ctx, self.keep_dims, self.sparse_axes)
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceProd {
@@ -261,6 +381,21 @@ impl<T: Float> op::Op<T> for ReduceProd {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.keep_dims, self.sparse_axes)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Bool(self.keep_dims),
+            Attribute::Bool(self.sparse_axes),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        infer_reduced_shape(ctx, self.keep_dims, self.sparse_axes)
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceMin {
@@ -289,6 +424,21 @@ impl<T: Float> op::Op<T> for ReduceMin {
             ctx,
         );
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.keep_dims, self.sparse_axes)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Bool(self.keep_dims),
+            Attribute::Bool(self.sparse_axes),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        infer_reduced_shape(ctx, self.keep_dims, self.sparse_axes)
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceMax {
@@ -317,6 +467,21 @@ impl<T: Float> op::Op<T> for ReduceMax {
             ctx,
         );
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.keep_dims, self.sparse_axes)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Bool(self.keep_dims),
+            Attribute::Bool(self.sparse_axes),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        infer_reduced_shape(ctx, self.keep_dims, self.sparse_axes)
+    }
 }
 
 fn min_max_grad<'g, T: Float>(
@@ -356,6 +521,23 @@ impl<T: Float> op::Op<T> for ArgMax {
         let axis = ndarray_ext::normalize_negative_axis(self.axis, x.ndim());
         let x_shape = x.shape();
 
+        if self.dtype == DType::Int {
+            // Indices of the first maximums, which are exact unlike the float ones below.
+            let mut ret = x.map_axis(ndarray::Axis(axis), |lane| {
+                let mut argmax = 0;
+                for (i, &a) in lane.iter().enumerate() {
+                    if a > lane[argmax] {
+                        argmax = i;
+                    }
+                }
+                argmax as i64
+            });
+            if self.keep_dim {
+                ret = ret.insert_axis(ndarray::Axis(axis));
+            }
+            return ctx.append_output_int(ret);
+        }
+
         // 1. Make binary mask tensor (maximums are 1s)
         let mut mask = {
             let max_fn = T::max;
@@ -418,6 +600,36 @@ impl<T: Float> op::Op<T> for ArgMax {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None)
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", (self.axis, self.keep_dim, self.dtype)))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Int(self.axis as i64),
+            Attribute::Bool(self.keep_dim),
+            Attribute::Int(self.dtype.index()),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let mut ret = match ctx.input_shape(0) {
+            Some(shape) => shape.to_vec(),
+            None => return Ok(None),
+        };
+        let axis = ops::array_ops::static_axis(self.axis, ret.len())?;
+        if self.keep_dim {
+            ret[axis] = 1;
+        } else {
+            ret.remove(axis);
+        }
+        Ok(Some(ret))
+    }
+
+    fn output_dtype(&self, ctx: &op::ShapeContext<T>) -> Result<DType, op::OpError> {
+        ops::array_ops::float_or_int_dtype(ctx, self.dtype)
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceGradCommon {
@@ -470,8 +682,9 @@ impl<T: Float> op::Op<T> for ReduceGradCommon {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // Broadcasting is linear in `gy`, so its gradient sums up `gy`'s gradient again.
         let sum = ops::reduction_ops::ReduceSum {
-            keep_dims: self.should_make_broadcast_dims,
+            keep_dims: !self.should_make_broadcast_dims,
             sparse_axes: self.sparse_axes,
         };
         let axes = &ctx.input(2);
@@ -482,4 +695,22 @@ impl<T: Float> op::Op<T> for ReduceGradCommon {
         ctx.append_input_grad(None);
         ctx.append_input_grad(None);
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!(
+            "{:?}",
+            (self.should_make_broadcast_dims, self.sparse_axes)
+        ))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![
+            Attribute::Bool(self.should_make_broadcast_dims),
+            Attribute::Bool(self.sparse_axes),
+        ])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_ints(1))
+    }
 }
@@ -0,0 +1,281 @@
+//! Op computing another node's op for each slice of a batch (see `Op::vmap`).
+use super::gradient_ops::unsupported_grads;
+use crate::ndarray;
+use crate::ndarray_ext::NdArray;
+use crate::op::{self, InputArray, OpInput, OpOutput};
+use crate::tensor::{Input, Tensor};
+use crate::{ArrRepr, Float, Graph};
+
+/// Computes the op of `node` for each slice of the batch along the first axis of the batched
+/// inputs, and stacks the outputs along a new first axis.
+pub(crate) struct MapOverBatch {
+    pub node: usize,
+    // Whether each input is batched
+    pub batched: Vec<bool>,
+    pub batch_size: usize,
+}
+
+/// Builds `MapOverBatch` of the op of `y` for its inputs replaced with `xs`.
+pub(crate) fn map_over_batch<'g, F: Float>(
+    y: Tensor<'g, F>,
+    xs: &[(Tensor<'g, F>, bool)],
+    batch_size: usize,
+    g: &'g Graph<F>,
+) -> Tensor<'g, F> {
+    let node = y.inner();
+    let inputs: Vec<_> = xs
+        .iter()
+        .zip(&node.in_edges)
+        .map(|((x, _), orig)| Input {
+            id: x.id(),
+            mut_usage: orig.mut_usage,
+            is_placeholder: x.is_placeholder(),
+        })
+        .collect();
+    let b = Tensor::builder()
+        .set_inputs(&inputs)
+        .set_input_indices(&node.input_indices)
+        .set_dtype(node.dtype)
+        .set_differentiable(node.is_differentiable);
+    let b = match node.known_shape {
+        Some(ref shape) => {
+            let mut batched_shape = vec![batch_size as isize];
+            batched_shape.extend_from_slice(shape.get());
+            b.set_inferred_shape(batched_shape)
+        }
+        None => b,
+    };
+    let op = MapOverBatch {
+        node: y.id(),
+        batched: xs.iter().map(|&(_, batched)| batched).collect(),
+        batch_size,
+    };
+    b.build(g, op)
+}
+
+// The `b` th slice of a batched input array, or the whole array otherwise.
+fn slice<'v, A>(
+    x: &ndarray::ArrayView<'v, A, ndarray::IxDyn>,
+    batched: bool,
+    b: usize,
+) -> ndarray::ArrayView<'v, A, ndarray::IxDyn> {
+    if batched {
+        x.clone().index_axis_move(ndarray::Axis(0), b)
+    } else {
+        x.clone()
+    }
+}
+
+// Stacks the arrays along a new first axis.
+fn stack<A: Copy>(arrays: &[ndarray::ArrayD<A>]) -> Result<ndarray::ArrayD<A>, op::OpError> {
+    let views: Vec<_> = arrays
+        .iter()
+        .map(|a| a.view().insert_axis(ndarray::Axis(0)))
+        .collect();
+    ndarray::stack(ndarray::Axis(0), &views)
+        .map_err(|e| op::OpError::NdArrayError("MapOverBatch: ".to_string(), e))
+}
+
+impl MapOverBatch {
+    // Slices of the input arrays for the `b` th computation.
+    fn slices<'v, T: Float>(
+        &self,
+        xs: &[OpInput<'v, T>],
+        b: usize,
+    ) -> Result<InputArray<OpInput<'v, T>>, op::OpError> {
+        xs.iter()
+            .zip(&self.batched)
+            .map(|(x, &batched)| match x {
+                OpInput::RO(Some(a)) | OpInput::Reusable(Some(a), _) => {
+                    Ok(OpInput::RO(Some(slice(a, batched, b))))
+                }
+                OpInput::Int(Some(a)) => Ok(OpInput::Int(Some(slice(a, batched, b)))),
+                OpInput::Bool(Some(a)) => Ok(OpInput::Bool(Some(slice(a, batched, b)))),
+                _ => Err(op::OpError::Unsupported(
+                    "MapOverBatch: mutable inputs are not supported".to_string(),
+                )),
+            })
+            .collect()
+    }
+}
+
+impl<T: Float> op::Op<T> for MapOverBatch {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) {
+        let g = ctx.graph();
+        let node = g.access_node(self.node);
+        let xs = ctx.take_inputs();
+        for (x, &batched) in xs.iter().zip(&self.batched) {
+            let batch_size = match x {
+                OpInput::RO(Some(a)) | OpInput::Reusable(Some(a), _) => a.shape().first(),
+                OpInput::Int(Some(a)) => a.shape().first(),
+                OpInput::Bool(Some(a)) => a.shape().first(),
+                _ => None,
+            };
+            if batched && batch_size != Some(&self.batch_size) {
+                ctx.set_error(op::OpError::IncompatibleShape(format!(
+                    "MapOverBatch: the batched inputs of {} must have the first axis of size {}",
+                    node.op.name(),
+                    self.batch_size
+                )));
+                return;
+            }
+        }
+
+        // outputs[i][b] is the `i` th output of the `b` th computation.
+        let mut outputs: Vec<Vec<OpOutput<T>>> = Vec::new();
+        for b in 0..self.batch_size {
+            let slices = match self.slices(&xs, b) {
+                Ok(slices) => slices,
+                Err(e) => {
+                    ctx.set_error(e);
+                    return;
+                }
+            };
+            let mut slice_ctx = op::ComputeContext::new(node, g, slices);
+            node.op.compute(&mut slice_ctx);
+            if let Some(e) = slice_ctx.take_subgraph_error() {
+                ctx.set_subgraph_error(e);
+                return;
+            }
+            let ys = slice_ctx.extract_outputs();
+            outputs.resize_with(ys.len(), Vec::new);
+            for (y, output) in ys.into_iter().zip(&mut outputs) {
+                let y = match y {
+                    Some(Ok(y)) => y,
+                    Some(Err(e)) => {
+                        ctx.set_error(e);
+                        return;
+                    }
+                    None => {
+                        ctx.set_error(op::OpError::Unsupported(format!(
+                            "MapOverBatch: {} has an empty output",
+                            node.op.name()
+                        )));
+                        return;
+                    }
+                };
+                output.push(y);
+            }
+        }
+
+        for output in outputs {
+            let mut floats: Vec<NdArray<T>> = Vec::new();
+            let mut ints = Vec::new();
+            let mut bools = Vec::new();
+            for y in output {
+                match y {
+                    OpOutput::Float(ArrRepr::Owned(y)) => floats.push(y),
+                    OpOutput::Float(ArrRepr::View(y)) => floats.push(y.to_owned()),
+                    OpOutput::Int(y) => ints.push(y),
+                    OpOutput::Bool(y) => bools.push(y),
+                }
+            }
+            let ret = if !ints.is_empty() {
+                stack(&ints).map(|y| ctx.append_output_int(y))
+            } else if !bools.is_empty() {
+                stack(&bools).map(|y| ctx.append_output_bool(y))
+            } else {
+                stack(&floats).map(|y| ctx.append_output(y))
+            };
+            if let Err(e) = ret {
+                ctx.set_error(e);
+                return;
+            }
+        }
+    }
+
+    // The gradients are those of the op, batched.
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let g = ctx.graph();
+        let node = g.access_node(self.node);
+        if !ctx.only_first_output_grad() {
+            unsupported_grads(
+                ctx,
+                "MapOverBatch: gradients of the outputs following the first are not supported",
+            );
+            return;
+        }
+        let y = g.scoped(node);
+        // Any tensor of the shape of `y` works, which is replaced with the batched one.
+        let gy = g.zeros(&g.shape(y));
+        let mut y_ctx = op::GradientContext::new(gy, y, g);
+        y_ctx.set_requires_grads(
+            (0..ctx.num_inputs())
+                .map(|i| ctx.requires_grad(i))
+                .collect(),
+        );
+        node.op.grad(&mut y_ctx);
+        let gxs = y_ctx.extract_input_grads();
+
+        let mut xs = vec![gy, y];
+        let mut batched_xs = vec![ctx.output_grad(), ctx.output()];
+        for (i, (x, &batched)) in node.in_edges.iter().zip(&self.batched).enumerate() {
+            if batched {
+                xs.push(x.get(g));
+                batched_xs.push(ctx.input(i));
+            }
+        }
+        let targets: Vec<_> = gxs.iter().flatten().copied().collect();
+        let mut batched_gxs =
+            crate::gradient::symbolic_vmap(&targets, &xs, &batched_xs, self.batch_size, g)
+                .into_iter();
+        for (gx, &batched) in gxs.iter().zip(&self.batched) {
+            let gx = gx.map(|_| match (batched_gxs.next().unwrap(), batched) {
+                ((gx, true), true) | ((gx, false), false) => gx,
+                // The input is shared by the slices.
+                ((gx, true), false) => g.reduce_sum(gx, &[0], false),
+                ((gx, false), true) => g.tile(g.expand_dims(gx, &[0]), 0, self.batch_size),
+            });
+            ctx.append_input_grad(gx);
+        }
+    }
+}
+
+#[test]
+fn test_map_over_batch() {
+    use crate::tensor::Variable;
+    use crate::JacobianMode;
+    crate::with(|g: &mut Graph<f64>| {
+        let rng = crate::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.placeholder(&[-1, 3]);
+        let w = g.variable(rng.standard_normal(&[3, 4]));
+        let x_value = rng.standard_normal(&[2, 3]);
+        let feeds = &[x.given(x_value.view())];
+        let num_maps = |start: usize, g: &Graph<f64>| {
+            (start..g.num_nodes())
+                .filter(|&i| op::short_op_name(g.access_node(i).op.name()) == "MapOverBatch")
+                .count()
+        };
+
+        // Ops having their own rules are batched without `MapOverBatch`, and the others
+        // (softmax here) are mapped over the batch.
+        let ys = [g.tanh(g.matmul(x, w)), g.softmax(g.matmul(x, w), 1)];
+        for (&y, &maps) in ys.iter().zip(&[false, true]) {
+            let expected: Vec<_> = (0..8)
+                .map(|i| g.reshape(g.grad(&[g.flatten(y).access_elem(i)], &[w])[0], &[1, -1]))
+                .collect();
+            let expected = g.concat(&expected, 0);
+            for &mode in &[JacobianMode::Reverse, JacobianMode::Forward] {
+                let start = g.num_nodes();
+                let j = g.jacobians_with_mode(y, &[w], 8, mode)[0];
+                assert_eq!(num_maps(start, g) > 0, maps);
+                let ret = g.eval(&[j, expected], feeds);
+                assert!(ret[0]
+                    .as_ref()
+                    .unwrap()
+                    .all_close(ret[1].as_ref().unwrap(), 1e-10));
+            }
+
+            // Differentiating the Jacobians goes through the batched gradients.
+            let x = g.variable(x_value.clone());
+            let y = if maps {
+                g.softmax(g.matmul(x, w), 1)
+            } else {
+                g.tanh(g.matmul(x, w))
+            };
+            let j = g.jacobians(y, &[w], 8)[0];
+            let gw = g.grad(&[j], &[w]);
+            crate::test_helper::check_theoretical_grads(j, &gw, &[w], &[], 1e-5, 1e-4);
+        }
+    });
+}
@@ -1,7 +1,8 @@
 use crate::ndarray_ext::{NdArray, NdArrayView};
 use crate::op;
+use crate::op::Attribute;
 use crate::ops;
-use crate::tensor::Tensor;
+use crate::tensor::{DType, Tensor};
 use crate::Float;
 use ndarray;
 
@@ -13,6 +14,17 @@ pub struct LogSoftmax {
     pub axis: isize,
 }
 
+// Gradient of `x` for `log_x = log_softmax(x)` along the last axis of 2D `x`.
+fn log_softmax_grad<'g, T: Float>(
+    s: &'g crate::Graph<T>,
+    log_x: Tensor<'g, T>,
+    gy: Tensor<'g, T>,
+) -> Tensor<'g, T> {
+    let sm = s.exp(log_x);
+    let sum = s.reduce_sum(gy, &[1], true);
+    gy - sm * sum
+}
+
 impl<T: Float> op::Op<T> for LogSoftmax {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let x = &ctx.input(0);
@@ -20,12 +32,20 @@ impl<T: Float> op::Op<T> for LogSoftmax {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
-        let s = ctx.graph();
-        let gy = ctx.output_grad();
-        let sm = s.exp(ctx.output());
-        let sum = s.reduce_sum(gy, &[1], true);
-        let mul = sm * sum;
-        ctx.append_input_grad(Some(gy - mul));
+        let gx = log_softmax_grad(ctx.graph(), ctx.output(), ctx.output_grad());
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(format!("{:?}", self.axis))
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(vec![Attribute::Int(self.axis as i64)])
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        Ok(ctx.input_shape(0).map(|s| s.to_vec()))
     }
 }
 
@@ -56,11 +76,37 @@ impl<T: Float> op::Op<T> for SigmoidCrossEntropy {
         ctx.append_input_grad(Some(gx1));
         ctx.append_input_grad(Some(gx2));
     }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        let x = ctx.input_shape(0);
+        match (x, ctx.input_shape(1)) {
+            (Some(x), Some(t))
+                if x.len() != t.len()
+                    || x.iter()
+                        .zip(t)
+                        .any(|(&a, &b)| crate::ops::array_ops::merge_dims(a, b).is_none()) =>
+            {
+                Err(op::OpError::IncompatibleShape(format!(
+                    "sigmoid_cross_entropy: shapes mismatch: {:?} vs {:?}",
+                    x, t
+                )))
+            }
+            _ => Ok(x.map(|s| s.to_vec())),
+        }
+    }
 }
 
 impl<T: Float> op::Op<T> for SparseSoftmaxCrossEntropy {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
-        let (x, t) = (&ctx.input(0), &ctx.input(1));
+        let (x, t) = (&ctx.input(0), &ctx.input_as_ints(1));
         let log_x: NdArray<T> = x - &ops::math_ops::logsumexp_forward(x, 1, true);
 
         // validation
@@ -91,7 +137,7 @@ impl<T: Float> op::Op<T> for SparseSoftmaxCrossEntropy {
         let mut t_iter = t.iter();
         let ret = log_x
             .map_axis(ndarray::Axis(1), move |row| {
-                -row[t_iter.next().unwrap().to_usize().unwrap()]
+                -row[*t_iter.next().unwrap() as usize]
             })
             .into_shape(ndarray::IxDyn(&[log_x.shape()[0], 1]))
             .unwrap();
@@ -103,22 +149,53 @@ impl<T: Float> op::Op<T> for SparseSoftmaxCrossEntropy {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         let s = ctx.graph();
         let t = ctx.input(1);
-        let gy = ctx.output_grad();
         let log_x = s.nth_tensor(ctx.output(), 1);
 
-        let gx1 = Tensor::builder()
-            .set_ro_inputs(&[&log_x, &t, &gy])
-            .build(s, SparseSoftmaxCrossEntropyGrad);
+        let (mut gx1, mut gx2) = (None, None);
+        if let Some(gy) = ctx.nth_output_grad(0) {
+            gx1 = Some(
+                Tensor::builder()
+                    .set_ro_inputs(&[&log_x, &t, &gy])
+                    .build(s, SparseSoftmaxCrossEntropyGrad),
+            );
+            // gx2 won't be used in most cases.
+            gx2 = Some({
+                let x = s.exp(log_x);
+                let sum = s.reduce_sum(x * log_x, &[1], true);
+                x * gy * (sum - log_x)
+            });
+        }
+        // The second output is log_softmax(x), which is differentiated when `gx1` is.
+        if let Some(g_log_x) = ctx.nth_output_grad(1) {
+            let g = log_softmax_grad(s, log_x, g_log_x);
+            gx1 = Some(gx1.map_or(g, |gx1| gx1 + g));
+        }
 
-        // gx2 won't be used in most cases.
-        let gx2 = {
-            let x = s.exp(log_x);
-            let sum = s.reduce_sum(x * log_x, &[1], true);
-            x * gy * (sum - log_x)
-        };
+        ctx.append_input_grad(gx1);
+        ctx.append_input_grad(gx2);
+    }
 
-        ctx.append_input_grad(Some(gx1));
-        ctx.append_input_grad(Some(gx2));
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        match ctx.input_shape(0) {
+            Some(&[batch, _]) => Ok(Some(vec![batch, 1])),
+            Some(shape) => Err(op::OpError::IncompatibleShape(format!(
+                "sparse_softmax_cross_entropy: x must be 2D (got {:?})",
+                shape
+            ))),
+            None => Ok(Some(vec![-1, 1])),
+        }
+    }
+
+    fn output_dtype(&self, ctx: &op::ShapeContext<T>) -> Result<DType, op::OpError> {
+        ctx.check_input_dtypes(&[1]).map(|()| DType::Float)
     }
 }
 
@@ -126,9 +203,9 @@ impl<T: Float> op::Op<T> for SparseSoftmaxCrossEntropyGrad {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) {
         let log_x = &ctx.input(0); // x is softmax
         let mut x = log_x.map(|a| a.exp());
-        let t = &ctx.input(1);
+        let t = &ctx.input_as_ints(1);
         for (mut row, &t_) in x.axis_iter_mut(ndarray::Axis(0)).zip(t) {
-            row[t_.to_usize().unwrap()] -= T::one();
+            row[t_ as usize] -= T::one();
         }
 
         let gy = &ctx.input(2);
@@ -137,8 +214,29 @@ impl<T: Float> op::Op<T> for SparseSoftmaxCrossEntropyGrad {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let s = ctx.graph();
+        let (log_x, t, gy) = (ctx.input(0), ctx.input(1), ctx.input(2));
+        let ggx = ctx.output_grad();
+        // The output is (softmax(x) - onehot(t)) * gy, whose softmax(x) is exp(log_x).
+        let g_log_x = s.exp(log_x) * gy * ggx;
+        let g_gy = Tensor::builder()
+            .set_ro_inputs(&[&log_x, &t, &ggx])
+            .build(s, SparseSoftmaxCrossEntropyGrad);
+        ctx.append_input_grad(Some(g_log_x));
         ctx.append_input_grad(None);
-        ctx.append_input_grad(None);
+        ctx.append_input_grad(Some(s.reduce_sum(g_gy, &[1], true)));
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn output_dtype(&self, ctx: &op::ShapeContext<T>) -> Result<DType, op::OpError> {
+        ctx.check_input_dtypes(&[1]).map(|()| DType::Float)
     }
 }
 
@@ -162,26 +260,49 @@ impl<T: Float> op::Op<T> for SoftmaxCrossEntropy {
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         let s = ctx.graph();
-        let output = ctx.output();
-        let log_x = s.nth_tensor(output, 1);
-        let gy = ctx.output_grad();
+        let log_x = s.nth_tensor(ctx.output(), 1);
         let x = s.exp(&log_x);
         let t = ctx.input(1);
 
-        // x = softmax, gy = dy/dx
-        // = {gy - Σ(x * gy)} * x
-        // = {-t/x - Σ(x * -t/x)} * x
-        // = {-t/x + Σt} * x
-        // = -t + x
-        let gx1 = (x - t) * gy;
-
-        // gx2 won't be used in most cases
-        let gx2 = {
-            let sum = s.reduce_sum(x * log_x, &[-1], true);
-            gy * (sum - log_x) * output
-        };
+        let (mut gx1, mut gx2) = (None, None);
+        if let Some(gy) = ctx.nth_output_grad(0) {
+            // (batch,) => (batch, 1)
+            let gy = s.expand_dims(gy, &[1]);
+            // x = softmax, gy = dy/dx
+            // = {gy - Σ(x * gy)} * x
+            // = {-t/x - Σ(x * -t/x)} * x
+            // = {-t/x + Σt} * x
+            // = -t + x
+            gx1 = Some((x - t) * gy);
+            // gx2 won't be used in most cases
+            gx2 = Some(s.neg(log_x * gy));
+        }
+        // The second output is log_softmax(x), which is differentiated when `gx1` is.
+        if let Some(g_log_x) = ctx.nth_output_grad(1) {
+            let g = log_softmax_grad(s, log_x, g_log_x);
+            gx1 = Some(gx1.map_or(g, |gx1| gx1 + g));
+        }
 
-        ctx.append_input_grad(Some(gx1));
-        ctx.append_input_grad(Some(gx2));
+        ctx.append_input_grad(gx1);
+        ctx.append_input_grad(gx2);
+    }
+
+    fn cse_key(&self) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn attributes(&self) -> Option<Vec<crate::op::Attribute<T>>> {
+        Some(Vec::new())
+    }
+
+    fn infer_shape(&self, ctx: &op::ShapeContext<T>) -> Result<Option<Vec<isize>>, op::OpError> {
+        match ctx.input_shape(0) {
+            Some(&[batch, _]) => Ok(Some(vec![batch])),
+            Some(shape) => Err(op::OpError::IncompatibleShape(format!(
+                "softmax_cross_entropy: x must be 2D (got {:?})",
+                shape
+            ))),
+            None => Ok(Some(vec![-1])),
+        }
     }
 }
@@ -0,0 +1,183 @@
+//! Defining the per-op profiling reports of evaluations.
+//!
+//! Enable profiling with [Eval::profiling](../struct.Eval.html#method.profiling), then get the
+//! report of the last run with [Eval::profile](../struct.Eval.html#method.profile).
+use crate::FxHashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Profile of a node computed in an evaluation.
+#[derive(Clone, Debug)]
+pub struct NodeProfile {
+    /// Id of the tensor.
+    pub id: usize,
+    /// `Op::name` of the tensor.
+    pub op_name: String,
+    /// Time from the beginning of the evaluation to the start of `Op::compute`.
+    pub start: Duration,
+    /// Wall-clock time spent in `Op::compute`.
+    pub duration: Duration,
+    /// Shapes of the outputs (empty if the computation failed).
+    pub output_shapes: Vec<Vec<usize>>,
+    /// Bytes of the arrays newly allocated for the outputs.
+    ///
+    /// Outputs that are views, or computed in place of the inputs, don't count.
+    pub bytes: usize,
+    /// Index of the thread that computed the node (always 0 in the sequential execution).
+    pub thread: usize,
+}
+
+/// Aggregated profile of an op in an evaluation.
+#[derive(Clone, Debug)]
+pub struct OpProfile {
+    /// `Op::name`
+    pub op_name: String,
+    /// Number of the nodes computed.
+    pub count: usize,
+    /// Total wall-clock time spent in `Op::compute`.
+    pub total: Duration,
+    /// Total bytes of the arrays newly allocated for the outputs.
+    pub bytes: usize,
+}
+
+/// Per-op profiling report of an evaluation.
+///
+/// `Display` prints the table of [by_op](#method.by_op).
+///
+/// ```
+/// use autograd as ag;
+///
+/// ag::with(|g: &mut ag::Graph<f32>| {
+///     let a = g.ones(&[64, 64]);
+///     let b = g.matmul(g.sigmoid(a), a);
+///
+///     let mut eval = ag::Eval::new(g);
+///     eval.push(&b).profiling(true).run();
+///     let profile = eval.profile().unwrap();
+///     assert!(profile.by_op().iter().any(|op| op.op_name.ends_with("MatMul")));
+///     println!("{}", profile);
+///     // std::fs::write("trace.json", profile.to_chrome_trace()).unwrap();
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub(crate) nodes: Vec<NodeProfile>,
+    pub(crate) total: Duration,
+}
+
+impl Profile {
+    /// Returns the profiles of the computed nodes in the order of their starts.
+    pub fn nodes(&self) -> &[NodeProfile] {
+        &self.nodes
+    }
+
+    /// Returns the wall-clock time of the whole evaluation.
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Aggregates the node profiles by `Op::name`, sorted by the total time in descending order.
+    pub fn by_op(&self) -> Vec<OpProfile> {
+        let mut map = FxHashMap::<&str, OpProfile>::default();
+        for node in &self.nodes {
+            let entry = map.entry(&node.op_name).or_insert_with(|| OpProfile {
+                op_name: node.op_name.clone(),
+                count: 0,
+                total: Duration::default(),
+                bytes: 0,
+            });
+            entry.count += 1;
+            entry.total += node.duration;
+            entry.bytes += node.bytes;
+        }
+        let mut ret: Vec<_> = map.into_iter().map(|(_, v)| v).collect();
+        ret.sort_by(|a, b| b.total.cmp(&a.total).then(a.op_name.cmp(&b.op_name)));
+        ret
+    }
+
+    /// Exports the node profiles in the Chrome trace event format.
+    ///
+    /// Load the returned JSON in `chrome://tracing` or [Perfetto](https://ui.perfetto.dev).
+    pub fn to_chrome_trace(&self) -> String {
+        let mut buf = String::from("{\"traceEvents\":[");
+        for (i, node) in self.nodes.iter().enumerate() {
+            if i > 0 {
+                buf.push(',');
+            }
+            buf += &format!(
+                "{{\"name\":\"{}\",\"cat\":\"op\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":0,\"tid\":{},\
+                 \"args\":{{\"id\":{},\"output_shapes\":{:?},\"bytes\":{}}}}}",
+                escape_json(&node.op_name),
+                micros(node.start),
+                micros(node.duration),
+                node.thread,
+                node.id,
+                node.output_shapes,
+                node.bytes
+            );
+        }
+        buf += "]}";
+        buf
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total.as_secs_f64();
+        writeln!(
+            f,
+            "{:>12} {:>7} {:>8} {:>14}  op",
+            "time (ms)", "%", "count", "bytes"
+        )?;
+        for op in self.by_op() {
+            let time = op.total.as_secs_f64();
+            let percent = if total > 0. { time / total * 100. } else { 0. };
+            writeln!(
+                f,
+                "{:>12.3} {:>7.2} {:>8} {:>14}  {}",
+                time * 1e3,
+                percent,
+                op.count,
+                op.bytes,
+                op.op_name
+            )?;
+        }
+        write!(f, "total: {:.3} ms", total * 1e3)
+    }
+}
+
+// Duration in microseconds with the fraction.
+fn micros(d: Duration) -> f64 {
+    d.as_secs_f64() * 1e6
+}
+
+fn escape_json(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[test]
+fn test_by_op() {
+    let node = |id, op_name: &str, micros| NodeProfile {
+        id,
+        op_name: op_name.to_string(),
+        start: Duration::default(),
+        duration: Duration::from_micros(micros),
+        output_shapes: vec![vec![2, 2]],
+        bytes: 16,
+        thread: 0,
+    };
+    let profile = Profile {
+        nodes: vec![node(0, "A", 1), node(1, "B", 5), node(2, "A", 2)],
+        total: Duration::from_micros(10),
+    };
+    let by_op = profile.by_op();
+    assert_eq!(by_op.len(), 2);
+    assert_eq!(by_op[0].op_name, "B");
+    assert_eq!(by_op[1].op_name, "A");
+    assert_eq!(by_op[1].count, 2);
+    assert_eq!(by_op[1].total, Duration::from_micros(3));
+    assert_eq!(by_op[1].bytes, 32);
+    assert!(profile.to_chrome_trace().starts_with(
+        "{\"traceEvents\":[{\"name\":\"A\",\"cat\":\"op\",\"ph\":\"X\",\"ts\":0,\"dur\":1,"
+    ));
+}
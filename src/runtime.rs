@@ -1,12 +1,17 @@
-use crate::ndarray_ext::{NdArray, NdArrayView};
-use crate::op::{self, ComputeContext, InputArray, OpInput};
+use crate::anomaly::{Anomaly, ArrayStats};
+use crate::ndarray_ext::{BoolArray, BoolArrayView, IntArray, IntArrayView, NdArray, NdArrayView};
+use crate::op::{ComputeContext, InputArray, OpInput, OpOutput};
+use crate::profiler::{NodeProfile, Profile};
 use crate::smallvec::SmallVec;
-use crate::tensor::{Tensor, TensorInternal};
+use crate::tensor::{DType, Tensor, TensorInternal};
 use crate::FxHashMap;
 use crate::{Float, Graph};
-use std::cell::UnsafeCell;
+use std::cell::{Cell, RefCell, UnsafeCell};
 use std::mem;
-use std::sync::{RwLockReadGuard, RwLockWriteGuard};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
 
 const NUM_MAX_EVAL_BUF: usize = 8;
 
@@ -36,10 +41,17 @@ type EvalBuf<T> = SmallVec<[T; NUM_MAX_EVAL_BUF]>;
 ///        .run();  // Do eval
 ///    });
 /// ```
+///
+/// Independent branches of the graph can be computed concurrently on the rayon's thread pool
+/// with [parallel](#method.parallel), and the time spent in each op can be measured
+/// with [profiling](#method.profiling).
 pub struct Eval<'view, 'feed, 'graph, F: Float> {
     scope: &'graph Graph<F>,
     buf: EvalBuf<Tensor<'graph, F>>,
     feeds: Option<&'feed [crate::runtime::Feed<'view, F>]>,
+    options: ExecOptions,
+    peak_memory: Cell<usize>,
+    profile: RefCell<Option<Profile>>,
 }
 
 impl<'feed, 'tensor, 'view, 'graph, F: Float> Eval<'view, 'feed, 'graph, F> {
@@ -50,6 +62,9 @@ impl<'feed, 'tensor, 'view, 'graph, F: Float> Eval<'view, 'feed, 'graph, F> {
             feeds: None,
             scope,
             buf: EvalBuf::new(),
+            options: scope.exec_options(),
+            peak_memory: Cell::new(0),
+            profile: RefCell::new(None),
         }
     }
 
@@ -79,17 +94,132 @@ impl<'feed, 'tensor, 'view, 'graph, F: Float> Eval<'view, 'feed, 'graph, F> {
         self
     }
 
+    #[inline]
+    /// Chooses between the sequential (default) and the parallel execution.
+    ///
+    /// In the parallel mode, each op is scheduled onto the rayon's thread pool as soon as
+    /// all of its inputs are computed, so that independent branches run concurrently.
+    /// Ops that mutate variables are never run at the same time as each other.
+    ///
+    /// This is sound since `Op` requires `Send + Sync`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///    let a = g.ones(&[2, 2]);
+    ///    let b = g.sigmoid(a);
+    ///    let c = g.tanh(a);
+    ///
+    ///    let ret = ag::Eval::new(g).extend(&[b, c]).parallel(true).run();
+    ///    assert_eq!(ret[0], b.eval(&[]));
+    ///    assert_eq!(ret[1], c.eval(&[]));
+    /// });
+    /// ```
+    pub fn parallel(&mut self, parallel: bool) -> &mut Self {
+        self.options.parallel = parallel;
+        self
+    }
+
+    #[inline]
+    /// Enables or disables the per-op profiling (disabled by default).
+    ///
+    /// If enabled, `run` records the wall-clock time, the output shapes and the allocated bytes
+    /// of each computed node, which can be obtained with [profile](#method.profile).
+    pub fn profiling(&mut self, profiling: bool) -> &mut Self {
+        self.options.profiling = profiling;
+        self
+    }
+
+    #[inline]
+    /// Enables or disables the anomaly detection mode.
+    ///
+    /// The default is the graph's setting of [Graph::detect_anomaly](struct.Graph.html#method.detect_anomaly).
+    /// If enabled, the outputs of each computed op, including those of gradient ops, are checked
+    /// for NaN and infinity, and the first op outputting them fails with
+    /// [EvalError::NonFinite](enum.EvalError.html#variant.NonFinite). The tensors depending on
+    /// it fail with the same error, which reports the op with the summaries of its inputs and
+    /// where the tensor was created. This slows down the evaluation.
+    ///
+    /// The ops in the subgraphs of control flow ops (e.g. `Graph::scan`) are not checked
+    /// individually; the control flow op is reported instead.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///    let x = g.zeros(&[2]);
+    ///    let y = g.ln(x);  // -inf
+    ///    let z = y * 2.;
+    ///
+    ///    let ret = ag::Eval::new(g).push(&z).detect_anomaly(true).run();
+    ///    match &ret[0] {
+    ///        Err(ag::EvalError::NonFinite(anomaly)) => {
+    ///            assert_eq!(anomaly.id, y.id());
+    ///            assert_eq!(anomaly.inputs[0].as_ref().unwrap().shape, vec![2]);
+    ///            assert_eq!(anomaly.location, y.location());
+    ///            println!("{}", anomaly);
+    ///        }
+    ///        _ => unreachable!(),
+    ///    }
+    /// });
+    /// ```
+    pub fn detect_anomaly(&mut self, detect_anomaly: bool) -> &mut Self {
+        self.options.detect_anomaly = detect_anomaly;
+        self
+    }
+
     #[inline]
     /// Evaluates the buffered tensors.
+    ///
+    /// The values of integer and boolean tensors are converted into `F`.
     pub fn run(&'tensor self) -> Vec<Result<NdArray<F>, crate::EvalError>> {
-        self.scope
-            .eval(self.buf.as_slice(), self.feeds.unwrap_or(&[]))
+        let (ret, report) =
+            self.scope
+                .eval_internal(self.buf.as_slice(), self.feeds.unwrap_or(&[]), self.options);
+        self.peak_memory.set(report.peak_memory);
+        *self.profile.borrow_mut() = report.profile;
+        into_floats(ret)
+    }
+
+    /// Returns the profiling report of the last `run`.
+    ///
+    /// This is `None` unless [profiling](#method.profiling) is enabled.
+    /// See [Profile](profiler/struct.Profile.html) for the usage.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile.borrow().clone()
+    }
+
+    #[inline]
+    /// Returns the peak memory usage (in bytes) of the last `run`.
+    ///
+    /// This counts the arrays allocated by ops during the evaluation, including the returned ones,
+    /// and the buffers that the graph keeps for reuse (see
+    /// [Graph::set_buffer_pool_capacity](struct.Graph.html#method.set_buffer_pool_capacity)).
+    /// Intermediate arrays are freed as soon as all of their consumers have been computed,
+    /// so this is usually much smaller than the total size of the activations.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///    let a = g.ones(&[4, 4]);
+    ///    let b = g.matmul(g.matmul(g.matmul(a, a), a), a);
+    ///
+    ///    let mut eval = ag::Eval::new(g);
+    ///    eval.push(&b).run();
+    ///    // `a` and two of the products are alive at a time.
+    ///    assert_eq!(eval.peak_memory(), 3 * 16 * 4);
+    /// });
+    /// ```
+    pub fn peak_memory(&self) -> usize {
+        self.peak_memory.get()
     }
 }
 
 /// Links a placeholder tensor and its value at run-time.
 ///
-/// Use `Tensor::given` to instanciate, and
+/// Use `Tensor::given` or `Feed::named` to instanciate, and
 /// ensure that this is passed to `ag::Eval`, `ag::eval` or `Tensor::eval`.
 ///
 /// ```
@@ -106,134 +236,470 @@ impl<'feed, 'tensor, 'view, 'graph, F: Float> Eval<'view, 'feed, 'graph, F> {
 /// });
 /// ```
 pub struct Feed<'feed, T: Float> {
-    /// The id of the placeholder tensor
-    placeholder_id: usize,
+    /// The placeholder tensor
+    placeholder: FeedTarget,
     /// A run-time value of the placeholder
-    value: NdArrayView<'feed, T>,
+    value: FeedValue<'feed, T>,
+}
+
+/// Run-time value of a placeholder of any dtype, which is given to
+/// [ExecutionPlan::run](struct.ExecutionPlan.html#method.run).
+///
+/// This is converted from the `ArrayView` of `F`, and from those of `i64` and `bool` when `F`
+/// is `f32` or `f64`.
+#[derive(Clone)]
+pub enum FeedValue<'v, F: Float> {
+    /// Value of the placeholders of `DType::Float`
+    Float(NdArrayView<'v, F>),
+    /// Value of the placeholders of `DType::Int`
+    Int(IntArrayView<'v>),
+    /// Value of the placeholders of `DType::Bool`
+    Bool(BoolArrayView<'v>),
+}
+
+impl<'v, F: Float, D: ndarray::Dimension> From<ndarray::ArrayView<'v, F, D>> for FeedValue<'v, F> {
+    fn from(value: ndarray::ArrayView<'v, F, D>) -> Self {
+        FeedValue::Float(value.into_dyn())
+    }
+}
+
+// The integer and boolean views are converted for each `F`, since the compiler doesn't
+// rule out that `i64` and `bool` implement `Float`.
+macro_rules! impl_from_int_and_bool_views {
+    ($($float:ty),*) => {
+        $(impl<'v, D: ndarray::Dimension> From<ndarray::ArrayView<'v, i64, D>>
+            for FeedValue<'v, $float>
+        {
+            fn from(value: ndarray::ArrayView<'v, i64, D>) -> Self {
+                FeedValue::Int(value.into_dyn())
+            }
+        }
+
+        impl<'v, D: ndarray::Dimension> From<ndarray::ArrayView<'v, bool, D>>
+            for FeedValue<'v, $float>
+        {
+            fn from(value: ndarray::ArrayView<'v, bool, D>) -> Self {
+                FeedValue::Bool(value.into_dyn())
+            }
+        })*
+    };
+}
+
+impl_from_int_and_bool_views!(f32, f64);
+
+impl<'v, F: Float> FeedValue<'v, F> {
+    #[inline]
+    pub(crate) fn dtype(&self) -> DType {
+        match self {
+            FeedValue::Float(_) => DType::Float,
+            FeedValue::Int(_) => DType::Int,
+            FeedValue::Bool(_) => DType::Bool,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn shape(&self) -> &[usize] {
+        match self {
+            FeedValue::Float(a) => a.shape(),
+            FeedValue::Int(a) => a.shape(),
+            FeedValue::Bool(a) => a.shape(),
+        }
+    }
+
+    #[inline]
+    fn view(&self) -> FeedValue<'_, F> {
+        match self {
+            FeedValue::Float(a) => FeedValue::Float(a.view()),
+            FeedValue::Int(a) => FeedValue::Int(a.view()),
+            FeedValue::Bool(a) => FeedValue::Bool(a.view()),
+        }
+    }
+
+    fn to_owned(&self) -> AnyArray<F> {
+        match self {
+            FeedValue::Float(a) => AnyArray::Float(a.to_owned()),
+            FeedValue::Int(a) => AnyArray::Int(a.to_owned()),
+            FeedValue::Bool(a) => AnyArray::Bool(a.to_owned()),
+        }
+    }
+}
+
+// Evaluated array of any dtype.
+#[derive(Clone)]
+pub(crate) enum AnyArray<F: Float> {
+    Float(NdArray<F>),
+    Int(IntArray),
+    Bool(BoolArray),
+}
+
+impl<F: Float> AnyArray<F> {
+    // Converts the elements into `F` (`0` or `1` for booleans).
+    fn into_float(self) -> NdArray<F> {
+        match self {
+            AnyArray::Float(arr) => arr,
+            AnyArray::Int(arr) => arr.mapv(|a| F::from(a).unwrap()),
+            AnyArray::Bool(arr) => arr.mapv(|a| if a { F::one() } else { F::zero() }),
+        }
+    }
+}
+
+fn into_floats<F: Float>(
+    values: Vec<Result<AnyArray<F>, crate::EvalError>>,
+) -> Vec<Result<NdArray<F>, crate::EvalError>> {
+    values
+        .into_iter()
+        .map(|v| v.map(AnyArray::into_float))
+        .collect()
+}
+
+// Placeholder filled by a `Feed`.
+enum FeedTarget {
+    Id(usize),
+    Name(String),
 }
 
 impl<'feed, F: Float> Feed<'feed, F> {
     #[inline]
-    pub(crate) fn new(placeholder_id: usize, value: NdArrayView<'feed, F>) -> Self {
+    pub(crate) fn new(placeholder_id: usize, value: FeedValue<'feed, F>) -> Self {
         Feed {
-            placeholder_id,
+            placeholder: FeedTarget::Id(placeholder_id),
             value,
         }
     }
+
+    /// Returns a `Feed` assigning `value` to the placeholder named `name`.
+    ///
+    /// The placeholder is looked up when evaluated, so feeds can be made without
+    /// the tensor handles. See [Tensor::with_name](tensor/struct.Tensor.html#method.with_name).
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// let value = array![1., 2.];
+    /// let feeds = [ag::Feed::named("encoder/x", value.view())];
+    ///
+    /// ag::with(|g| {
+    ///     let x = g.name_scope("encoder", || g.placeholder_named("x", &[2]));
+    ///     assert_eq!(g.reduce_sum_to_scalar(x).eval(&feeds), Ok(ndarray::arr0(3.).into_dyn()));
+    /// });
+    /// ```
+    pub fn named<D>(name: &str, value: ndarray::ArrayView<'feed, F, D>) -> Self
+    where
+        D: ndarray::Dimension,
+    {
+        Feed {
+            placeholder: FeedTarget::Name(name.to_string()),
+            value: FeedValue::Float(value.into_dyn()),
+        }
+    }
+
+    /// Same as `named` but for the placeholders of `DType::Int`.
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// let value = array![16777217, -1];
+    /// let feeds = [ag::Feed::named_int("i", value.view())];
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let i = g.placeholder_int(&[2]).with_name("i");
+    ///     assert_eq!(i.eval_int(&feeds), Ok(value.clone().into_dyn()));
+    /// });
+    /// ```
+    pub fn named_int<D>(name: &str, value: ndarray::ArrayView<'feed, i64, D>) -> Self
+    where
+        D: ndarray::Dimension,
+    {
+        Feed {
+            placeholder: FeedTarget::Name(name.to_string()),
+            value: FeedValue::Int(value.into_dyn()),
+        }
+    }
+
+    /// Same as `named` but for the placeholders of `DType::Bool`.
+    pub fn named_bool<D>(name: &str, value: ndarray::ArrayView<'feed, bool, D>) -> Self
+    where
+        D: ndarray::Dimension,
+    {
+        Feed {
+            placeholder: FeedTarget::Name(name.to_string()),
+            value: FeedValue::Bool(value.into_dyn()),
+        }
+    }
+
+    // Id of the placeholder to fill in `g`.
+    fn placeholder_id(&self, g: &Graph<F>) -> Result<usize, crate::EvalError> {
+        match self.placeholder {
+            FeedTarget::Id(id) => Ok(id),
+            FeedTarget::Name(ref name) => match g.id_by_name(name) {
+                Some(id) if g.access_node(id).is_placeholder => Ok(id),
+                _ => Err(crate::EvalError::UnknownFeed(name.clone())),
+            },
+        }
+    }
 }
 
-enum ValueType {
-    Owned,
-    View,
+// Run-time value of an op's output.
+enum Value<'v, F: Float> {
+    Owned(NdArray<F>),
+    View(NdArrayView<'v, F>),
+    Int(IntArray),
+    Bool(BoolArray),
     Empty,
 }
 
-struct ValueInfo {
-    ty: ValueType,
-    // key to lookup output
-    key: usize,
+// Compute result of a node: all of its outputs, or the error that occurred.
+//
+// `Vec` is used rather than `OutputArray` since the storage must be droppable while
+// holding views of itself.
+type NodeValue<'v, F> = Result<Vec<Value<'v, F>>, crate::EvalError>;
+
+// Where a run-time value comes from.
+#[derive(Clone, Copy, PartialEq)]
+enum ValueSource {
+    // Outputs of the scheduled node at the position.
+    Node(usize),
+    // The feed at the position.
+    Feed(usize),
+    // Persistent array of a variable or a constant.
+    Persistent,
+}
+
+/// Evaluation order of the nodes needed to compute the targets.
+///
+/// Placeholders and persistent (variable/constant) tensors are not scheduled since
+/// their values are available from the beginning.
+/// Nodes are referred by their ids so that the graph can grow while a schedule is kept.
+pub(crate) struct Schedule {
+    // Ids of the nodes to be computed, in topological order.
+    nodes: Vec<usize>,
+    // `inputs[i][j]` is the source of the `j` th input of `nodes[i]`.
+    inputs: Vec<InputArray<ValueSource>>,
+    // Positions of the nodes that consume the outputs of `nodes[i]` (no duplicates).
+    consumers: Vec<Vec<usize>>,
+    // Positions of the nodes whose outputs `nodes[i]` consumes (no duplicates).
+    producers: Vec<Vec<usize>>,
+    // Number of distinct scheduled nodes that `nodes[i]` waits for.
+    num_producers: Vec<usize>,
+    // Ids and sources of the targets.
+    targets: Vec<(usize, ValueSource)>,
 }
 
-impl ValueInfo {
+impl Schedule {
+    // Collects the nodes reachable from `targets` with depth-first-search.
+    //
+    // `placeholders[k]` is the id of the placeholder that is filled with the `k` th feed.
+    // Fails if a placeholder needed is not in `placeholders`.
+    pub(crate) fn new<F: Float>(
+        targets: &[usize],
+        placeholders: &[usize],
+        g: &Graph<F>,
+    ) -> Result<Self, crate::EvalError> {
+        let mut slot_map = FxHashMap::<usize, usize>::default();
+        let mut nodes = Vec::new();
+
+        let mut dfs_stack = Vec::<(&TensorInternal<F>, bool)>::with_capacity(100);
+        for &t in targets.iter().rev() {
+            dfs_stack.push((g.access_node(t), false));
+        }
+        while let Some((node, is_parent)) = dfs_stack.pop() {
+            if is_parent {
+                if !slot_map.contains_key(&node.id()) {
+                    slot_map.insert(node.id(), nodes.len());
+                    nodes.push(node.id());
+                }
+            } else if !is_source_value(node) && !slot_map.contains_key(&node.id()) {
+                // Update dfs stack
+                dfs_stack.push((node, true));
+                // Push children if needed
+                for child in node.in_edges.iter().rev() {
+                    let child = child.get_inner(g);
+                    if !is_source_value(child) && !slot_map.contains_key(&child.id()) {
+                        dfs_stack.push((child, false));
+                    }
+                }
+            }
+        }
+
+        let source_of = |id: usize| {
+            if let Some(&slot) = slot_map.get(&id) {
+                Ok(ValueSource::Node(slot))
+            } else if g.access_node(id).is_placeholder {
+                match placeholders.iter().position(|&p| p == id) {
+                    Some(k) => Ok(ValueSource::Feed(k)),
+                    // Reached from a tensor created in a control flow closure.
+                    None if g.access_node(id).is_subgraph_param => {
+                        Err(crate::EvalError::SubgraphTensor { id })
+                    }
+                    None => Err(crate::EvalError::MissingFeed {
+                        id,
+                        name: g.access_node(id).name.clone(),
+                    }),
+                }
+            } else {
+                Ok(ValueSource::Persistent)
+            }
+        };
+
+        let mut inputs = Vec::with_capacity(nodes.len());
+        let mut consumers = vec![Vec::new(); nodes.len()];
+        let mut producers = vec![Vec::new(); nodes.len()];
+        let mut num_producers = vec![0; nodes.len()];
+        for (i, &node) in nodes.iter().enumerate() {
+            let sources: InputArray<ValueSource> = g
+                .access_node(node)
+                .in_edges
+                .iter()
+                .map(|x| source_of(x.id))
+                .collect::<Result<_, _>>()?;
+            for source in &sources {
+                if let ValueSource::Node(slot) = *source {
+                    if !consumers[slot].contains(&i) {
+                        consumers[slot].push(i);
+                        producers[i].push(slot);
+                        num_producers[i] += 1;
+                    }
+                }
+            }
+            inputs.push(sources);
+        }
+        let targets = targets
+            .iter()
+            .map(|&t| Ok((t, source_of(t)?)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Schedule {
+            nodes,
+            inputs,
+            consumers,
+            producers,
+            num_producers,
+            targets,
+        })
+    }
+
     #[inline]
-    fn new(ty: ValueType, key: usize) -> Self {
-        ValueInfo { ty, key }
+    fn len(&self) -> usize {
+        self.nodes.len()
     }
 }
 
-struct OutputStorage<'view, F: Float> {
-    inner: UnsafeCell<OutputStorageInner<'view, F>>,
+// True if the value of `node` is available without computation.
+#[inline]
+fn is_source_value<F: Float>(node: &TensorInternal<F>) -> bool {
+    node.is_placeholder || node.has_persistent_array
 }
 
-struct OutputStorageInner<'view, F: Float> {
-    // Each of NdArray is Some right up until eval's ret-val extraction phase.
-    // In that phase, each of entry is replaced with None to avoid copying the entire vector.
-    owned: Vec<Option<NdArray<F>>>,
-    borrowed: Vec<NdArrayView<'view, F>>,
+// Storage in which compute results are stored, one slot per scheduled node.
+//
+// Each slot is written exactly once (by the node itself), read by the consumers after that,
+// and freed after all of them have run, so no locking is required even in the parallel execution.
+struct OutputStorage<'view, F: Float> {
+    slots: Vec<UnsafeCell<Option<NodeValue<'view, F>>>>,
 }
 
-impl<'tensor, 'view, 'lock, F: Float> OutputStorage<'view, F> {
+unsafe impl<'view, F: Float> Sync for OutputStorage<'view, F> {}
+
+impl<'view, F: Float> OutputStorage<'view, F> {
     #[inline]
-    fn new() -> Self {
+    fn new(len: usize) -> Self {
         OutputStorage {
-            inner: UnsafeCell::new(OutputStorageInner {
-                owned: Vec::new(),
-                borrowed: Vec::new(),
-            }),
+            slots: (0..len).map(|_| UnsafeCell::new(None)).collect(),
         }
     }
 
     #[inline]
-    fn owned_mut(&self) -> &mut Vec<Option<NdArray<F>>> {
-        unsafe { &mut (*self.inner.get()).owned }
+    fn get(&self, slot: usize) -> &NodeValue<'view, F> {
+        unsafe {
+            (*self.slots[slot].get())
+                .as_ref()
+                .expect("Attempting to use a value which is not computed yet or already freed")
+        }
     }
 
     #[inline]
-    fn owned(&self) -> &[Option<NdArray<F>>] {
-        unsafe { &(*self.inner.get()).owned }
+    fn get_mut(&self, slot: usize) -> &mut NodeValue<'view, F> {
+        unsafe {
+            (*self.slots[slot].get())
+                .as_mut()
+                .expect("Attempting to use a value which is not computed yet or already freed")
+        }
     }
 
     #[inline]
-    fn view_mut(&self) -> &mut Vec<NdArrayView<'view, F>> {
-        unsafe { &mut (*self.inner.get()).borrowed }
+    fn set(&self, slot: usize, value: NodeValue<'view, F>) {
+        unsafe {
+            *self.slots[slot].get() = Some(value);
+        }
     }
 
+    // Takes the value out of the slot, so that it is dropped.
     #[inline]
-    fn view(&self) -> &[NdArrayView<'view, F>] {
-        unsafe { &(*self.inner.get()).borrowed }
+    fn free(&self, slot: usize) -> Option<NodeValue<'view, F>> {
+        unsafe { (*self.slots[slot].get()).take() }
     }
 }
 
-fn validate_feed_shapes<F: Float>(feeds: &[Feed<F>], g: &Graph<F>) {
-    for feed in feeds {
-        let shape = feed.value.shape();
-        g.access_node(feed.placeholder_id)
-            .validate_feed_shape(shape);
+fn validate_feeds<F: Float>(
+    placeholders: &[usize],
+    feeds: &[FeedValue<F>],
+    g: &Graph<F>,
+) -> Result<(), crate::EvalError> {
+    for (&id, value) in placeholders.iter().zip(feeds) {
+        g.access_node(id).validate_feed(value)?;
     }
+    Ok(())
 }
 
-#[inline]
-// search the feed using `in_node_id`
-fn retrieve_feed<'feeds, 'feed, F: Float>(
-    feeds: &'feeds [Feed<'feed, F>],
-    in_node_id: usize,
-) -> NdArrayView<'feeds, F> {
-    // linear search is tolerable for feeds in most cases.
-    for feed in feeds {
-        if feed.placeholder_id == in_node_id {
-            return feed.value.view();
-        }
-    }
-    panic!("Placeholder unfilled");
-}
-
-// Extract output arrays from `results` and stores into `storage`.
-fn install_compute_results<'view, F: Float>(
-    results: crate::op::Results<'view, F>,
-    storage: &OutputStorage<'view, F>,
-) -> Result<op::OutputArray<ValueInfo>, op::OpError> {
-    let mut value_info_list = op::OutputArray::new();
+// Message of a caught panic.
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+// Extract output arrays from `results`.
+fn install_compute_results<F: Float>(results: crate::op::Results<F>) -> NodeValue<F> {
+    let mut values = Vec::with_capacity(results.len());
     for y in results {
         match y {
-            Some(Ok(crate::ArrRepr::Owned(val))) => {
-                storage.owned_mut().push(Some(val));
-                value_info_list.push(ValueInfo::new(ValueType::Owned, storage.owned().len() - 1));
-            }
-            Some(Ok(crate::ArrRepr::View(val))) => {
-                storage.view_mut().push(val);
-                value_info_list.push(ValueInfo::new(ValueType::View, storage.view().len() - 1));
-            }
-            Some(Err(e)) => {
-                // error is set at the head of list
-                // value_info_list[0] = ValueInfo::new(ValueType::ComputeFailed(e), /*dummy = */ 0);
-                return Err(e);
-            }
-            None => {
-                value_info_list.push(ValueInfo::new(ValueType::Empty, /*dummy = */ 0))
-            }
+            Some(Ok(OpOutput::Float(crate::ArrRepr::Owned(val)))) => values.push(Value::Owned(val)),
+            Some(Ok(OpOutput::Float(crate::ArrRepr::View(val)))) => values.push(Value::View(val)),
+            Some(Ok(OpOutput::Int(val))) => values.push(Value::Int(val)),
+            Some(Ok(OpOutput::Bool(val))) => values.push(Value::Bool(val)),
+            Some(Err(e)) => return Err(crate::EvalError::OpError(e)),
+            None => values.push(Value::Empty),
         };
     }
-    Ok(value_info_list)
+    Ok(values)
+}
+
+// Per-node state of `aggregate_op_inputs`: where each input comes from and whether the op may
+// take it over.
+struct InputSources<'a> {
+    sources: &'a [ValueSource],
+    reusable: &'a [bool],
+}
+
+// Guard storage for the variable arrays read or mutated by a node.
+struct VariableGuards<'tensor, F: Float> {
+    read: InputArray<RwLockReadGuard<'tensor, NdArray<F>>>,
+    write: InputArray<RwLockWriteGuard<'tensor, NdArray<F>>>,
+}
+
+impl<'tensor, F: Float> VariableGuards<'tensor, F> {
+    fn new() -> Self {
+        VariableGuards {
+            read: InputArray::new(),
+            write: InputArray::new(),
+        }
+    }
 }
 
 // aggregated ones are pushed in `input_values`.
@@ -241,78 +707,480 @@ fn install_compute_results<'view, F: Float>(
 #[inline]
 fn aggregate_op_inputs<'ret, 'tensor: 'ret, 'slice: 'ret, 'feed: 'slice, F: Float>(
     node: &'tensor TensorInternal<F>,
+    inputs: &InputSources,
     g: &Graph<F>,
-    node_info_map: &FxHashMap<usize, Result<op::OutputArray<ValueInfo>, op::OpError>>,
-    feeds: &'slice [Feed<'feed, F>],
+    feeds: &'slice [FeedValue<'feed, F>],
     storage: &'ret OutputStorage<'ret, F>,
     input_values: &mut InputArray<OpInput<'ret, F>>,
-    read_guards: &mut InputArray<RwLockReadGuard<'tensor, NdArray<F>>>, // guard storage for variable arrays
-    write_guards: &mut InputArray<RwLockWriteGuard<'tensor, NdArray<F>>>, // guard storage for variable arrays
-) -> Result<(), op::OpError> {
-    let mut input_status = Ok(());
-
-    for (in_node, &in_idx) in node.in_edges.iter().zip(&node.input_indices) {
+    guards: &mut VariableGuards<'tensor, F>,
+) -> Result<(), crate::EvalError> {
+    let (read_guards, write_guards) = (&mut guards.read, &mut guards.write);
+    for (k, ((in_node, &in_idx), &source)) in node
+        .in_edges
+        .iter()
+        .zip(&node.input_indices)
+        .zip(inputs.sources)
+        .enumerate()
+    {
         // `in_idx` is not 0 only when `in_node` is multi-output op and `node` selects nth value from it using `Graph::nth_tensor`.
         let input_inner: &TensorInternal<F> = in_node.get_inner(g);
-        let x = if input_inner.is_placeholder {
-            Ok(OpInput::new(retrieve_feed(feeds, in_node.id)))
+        let x = if let ValueSource::Feed(k) = source {
+            match &feeds[k] {
+                FeedValue::Float(arr) => OpInput::new(arr.view()),
+                FeedValue::Int(arr) => OpInput::Int(Some(arr.view())),
+                FeedValue::Bool(arr) => OpInput::Bool(Some(arr.view())),
+            }
         } else if let Some(ref lock) = input_inner.variable_array {
             unsafe {
                 if in_node.mut_usage {
                     write_guards.push(lock.write().unwrap());
                     let inserted = write_guards.len() - 1;
-                    Ok(OpInput::new_mut(
+                    OpInput::new_mut(
                         (*(&mut write_guards[inserted] as *mut RwLockWriteGuard<NdArray<F>>))
                             .view_mut(),
-                    ))
+                    )
                 } else {
                     read_guards.push(lock.read().unwrap());
                     let inserted = read_guards.len() - 1;
-                    Ok(OpInput::new(
+                    OpInput::new(
                         (*(&mut read_guards[inserted] as *mut RwLockReadGuard<NdArray<F>>)).view(),
-                    ))
+                    )
                 }
             }
         } else if let Some(ref arr) = input_inner.get_constant_array_inner() {
-            Ok(OpInput::new(arr.view()))
-        } else {
+            OpInput::new(arr.view())
+        } else if let ValueSource::Node(slot) = source {
             // Search the value of input nodes.
-            match &node_info_map.get(&in_node.id).unwrap() {
-                Err(e) => Err(e.clone()),
-                Ok(vi_list) => {
-                    let vi = &vi_list[in_idx];
-                    match vi.ty {
-                        ValueType::Owned => Ok(OpInput::new(
-                            storage.owned()[vi.key].as_ref().unwrap().view(),
-                        )),
-                        ValueType::View => Ok(OpInput::new(storage.view()[vi.key].clone())),
-                        ValueType::Empty => {
-                            panic!(
-                                "Attempting to use {}'s output which is empty.",
-                                input_inner.op.name()
-                            );
-                        }
+            if inputs.reusable[k] {
+                if let Ok(values) = storage.get_mut(slot) {
+                    if let Value::Owned(arr) = &mut values[in_idx] {
+                        input_values.push(unsafe { OpInput::new_reusable(arr) });
+                        continue;
                     }
                 }
             }
+            match storage.get(slot) {
+                Err(e) => return Err(e.clone()),
+                Ok(values) => match &values[in_idx] {
+                    Value::Owned(arr) => OpInput::new(arr.view()),
+                    Value::View(view) => OpInput::new(view.clone()),
+                    Value::Int(arr) => OpInput::Int(Some(arr.view())),
+                    Value::Bool(arr) => OpInput::Bool(Some(arr.view())),
+                    Value::Empty => return Err(crate::EvalError::Empty),
+                },
+            }
+        } else {
+            unreachable!()
         };
-        match x {
-            Ok(x) => input_values.push(x),
-            Err(e) => {
-                input_status = Err(e);
-                break;
+        input_values.push(x);
+    }
+    Ok(())
+}
+
+// Options of an evaluation.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ExecOptions {
+    // Runs the independent nodes concurrently.
+    pub(crate) parallel: bool,
+    // Records `NodeProfile`s.
+    pub(crate) profiling: bool,
+    // Fails the nodes outputting non-finite values.
+    pub(crate) detect_anomaly: bool,
+}
+
+// Statistics of an evaluation.
+#[derive(Default)]
+pub(crate) struct EvalReport {
+    // Peak total bytes of the owned arrays computed in the evaluation.
+    pub(crate) peak_memory: usize,
+    // Per-node profile if enabled.
+    pub(crate) profile: Option<Profile>,
+}
+
+// Number of bytes of the owned arrays in `value`.
+fn owned_bytes<F: Float>(value: &NodeValue<F>) -> usize {
+    match value {
+        Ok(values) => values
+            .iter()
+            .map(|v| match v {
+                Value::Owned(arr) => arr.len() * mem::size_of::<F>(),
+                Value::Int(arr) => arr.len() * mem::size_of::<i64>(),
+                Value::Bool(arr) => arr.len() * mem::size_of::<bool>(),
+                _ => 0,
+            })
+            .sum(),
+        Err(_) => 0,
+    }
+}
+
+// Shapes of the arrays in `value`.
+fn output_shapes<F: Float>(value: &NodeValue<F>) -> Vec<Vec<usize>> {
+    match value {
+        Ok(values) => values
+            .iter()
+            .map(|v| match v {
+                Value::Owned(arr) => arr.shape().to_vec(),
+                Value::View(view) => view.shape().to_vec(),
+                Value::Int(arr) => arr.shape().to_vec(),
+                Value::Bool(arr) => arr.shape().to_vec(),
+                Value::Empty => Vec::new(),
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Runs the nodes of a schedule, and frees the intermediate arrays as soon as they become unnecessary.
+// The buffers of freed arrays are returned to the graph's `BufferPool`.
+//
+// Each scheduled node has a count of remaining uses: one for each distinct consumer, and one more
+// if the node is a target. When it reaches zero, the node's outputs are dropped.
+// Since an `ArrayView` output may borrow from the op's inputs, a node that returned views keeps
+// its inputs alive until its own outputs are freed.
+struct Executor<'s, 'feed: 's, F: Float> {
+    schedule: &'s Schedule,
+    graph: &'s Graph<F>,
+    feeds: &'s [FeedValue<'feed, F>],
+    storage: &'s OutputStorage<'s, F>,
+    // Remaining number of producers for each node.
+    pending: Vec<AtomicUsize>,
+    // Remaining number of uses for each node's outputs.
+    uses: Vec<AtomicUsize>,
+    // True if the node returned views, that is, it keeps its inputs alive.
+    holds_inputs: Vec<AtomicBool>,
+    // Total bytes of the owned arrays alive in `storage`.
+    memory: AtomicUsize,
+    peak_memory: AtomicUsize,
+    // Serializes the nodes that mutate variables, so that two of them never wait for each other's locks.
+    mut_lock: Mutex<()>,
+    // Start time of the evaluation and the profiles of the computed nodes, if profiling.
+    profile: Option<(Instant, Mutex<Vec<NodeProfile>>)>,
+    // `Some` in the anomaly detection mode, with the flags of the gradient nodes.
+    // They are collected beforehand since `Graph` can't be borrowed from other threads.
+    gradient_nodes: Option<Vec<bool>>,
+}
+
+// `Op`s are `Send + Sync`. `Graph` is not `Sync` because of the cells used while building,
+// which evaluations don't touch, and each node is computed by exactly one worker and its
+// results are published through `pending`.
+unsafe impl<'s, 'feed: 's, F: Float> Sync for Executor<'s, 'feed, F> {}
+
+impl<'s, 'feed: 's, F: Float> Executor<'s, 'feed, F> {
+    fn new(
+        schedule: &'s Schedule,
+        graph: &'s Graph<F>,
+        feeds: &'s [FeedValue<'feed, F>],
+        storage: &'s OutputStorage<'s, F>,
+        profiling: bool,
+        detect_anomaly: bool,
+    ) -> Self {
+        let mut uses: Vec<usize> = schedule.consumers.iter().map(|c| c.len()).collect();
+        for &(_, source) in &schedule.targets {
+            if let ValueSource::Node(slot) = source {
+                uses[slot] += 1;
+            }
+        }
+        Executor {
+            schedule,
+            graph,
+            feeds,
+            storage,
+            pending: schedule
+                .num_producers
+                .iter()
+                .map(|&n| AtomicUsize::new(n))
+                .collect(),
+            uses: uses.into_iter().map(AtomicUsize::new).collect(),
+            holds_inputs: (0..schedule.len())
+                .map(|_| AtomicBool::new(false))
+                .collect(),
+            memory: AtomicUsize::new(0),
+            peak_memory: AtomicUsize::new(0),
+            mut_lock: Mutex::new(()),
+            profile: if profiling {
+                Some((Instant::now(), Mutex::new(Vec::new())))
+            } else {
+                None
+            },
+            gradient_nodes: if detect_anomaly {
+                Some(
+                    schedule
+                        .nodes
+                        .iter()
+                        .map(|&id| graph.is_gradient_node(id))
+                        .collect(),
+                )
+            } else {
+                None
+            },
+        }
+    }
+
+    // Runs all the nodes of the schedule.
+    //
+    // In the parallel mode, each node is spawned onto the rayon's thread pool as soon as
+    // all of its inputs are computed.
+    fn run(&self, parallel: bool) {
+        if !parallel {
+            for i in 0..self.schedule.len() {
+                self.compute(i);
+            }
+            return;
+        }
+        rayon::scope(move |scope| {
+            for (i, &n) in self.schedule.num_producers.iter().enumerate() {
+                if n == 0 {
+                    self.spawn(scope, i);
+                }
+            }
+        });
+    }
+
+    fn spawn<'scope>(&'scope self, scope: &rayon::Scope<'scope>, i: usize) {
+        scope.spawn(move |scope| {
+            {
+                let node = self.graph.access_node(self.schedule.nodes[i]);
+                let _guard = if node.in_edges.iter().any(|x| x.mut_usage) {
+                    Some(self.mut_lock.lock().unwrap())
+                } else {
+                    None
+                };
+                self.compute(i);
+            }
+            for &consumer in &self.schedule.consumers[i] {
+                if self.pending[consumer].fetch_sub(1, Ordering::AcqRel) == 1 {
+                    self.spawn(scope, consumer);
+                }
+            }
+        });
+    }
+
+    // Runs `Op::compute` of the `i` th node and stores the result in `storage`.
+    fn compute(&self, i: usize) {
+        let node = self.graph.access_node(self.schedule.nodes[i]);
+
+        let reusable = self.reusable_inputs(i, node);
+        let reusable_bytes = self.input_bytes(i, &reusable);
+
+        // Aggregate input values for `node`. if any of the inputs failed, it's a total failure.
+        let mut xs = InputArray::new();
+        let mut guards = VariableGuards::new();
+        let sources = InputSources {
+            sources: &self.schedule.inputs[i],
+            reusable: &reusable,
+        };
+        let input_status = aggregate_op_inputs(
+            node,
+            &sources,
+            self.graph,
+            self.feeds,
+            self.storage,
+            &mut xs,
+            &mut guards,
+        );
+
+        // run compute if `node`'s inputs were not failed
+        let mut timer = None;
+        let input_status = match node.build_error {
+            Some(ref e) => Err(crate::EvalError::OpError(e.clone())),
+            None => input_status,
+        };
+        let value = input_status.and_then(|()| {
+            // Summaries of the inputs are taken before the op may overwrite them.
+            let input_stats: Option<Vec<_>> = self.gradient_nodes.as_ref().map(|_| {
+                xs.iter()
+                    .map(|x| x.view().map(|x| ArrayStats::new(&x)))
+                    .collect()
+            });
+            let mut ctx = ComputeContext::new(node, self.graph, xs);
+            let start = Instant::now();
+            // Ops panic on e.g. invalid shapes, which fails only the tensors depending on them.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| node.op.compute(&mut ctx)));
+            timer = Some((start, start.elapsed()));
+            if let Err(payload) = result {
+                return Err(crate::EvalError::OpPanicked {
+                    op_name: node.op.name().to_string(),
+                    id: node.id,
+                    message: panic_message(payload),
+                });
+            }
+            if let Some(e) = ctx.take_subgraph_error() {
+                return Err(crate::EvalError::Subgraph {
+                    op_name: node.op.name().to_string(),
+                    id: node.id,
+                    error: Box::new(e),
+                });
+            }
+            let ys = ctx.extract_outputs();
+            if ys.is_empty() {
+                return Err(crate::EvalError::OpPanicked {
+                    op_name: node.op.name().to_string(),
+                    id: node.id,
+                    message: "Bad op implementation: empty return value".to_string(),
+                });
+            }
+            let values = install_compute_results(ys)?;
+            match input_stats {
+                Some(inputs) => self.check_finite(i, node, values, inputs),
+                None => Ok(values),
+            }
+        });
+
+        let has_view = match &value {
+            Ok(values) => values.iter().any(|v| matches!(v, Value::View(_))),
+            Err(_) => false,
+        };
+        // Inputs taken over by the op no longer count.
+        let taken = reusable_bytes - self.input_bytes(i, &reusable);
+        let bytes = owned_bytes(&value);
+        let current = if bytes >= taken {
+            self.memory.fetch_add(bytes - taken, Ordering::AcqRel) + bytes - taken
+        } else {
+            self.memory.fetch_sub(taken - bytes, Ordering::AcqRel) + bytes - taken
+        };
+        // The buffers kept for reuse are held as well.
+        let pooled = self.graph.pool.num_bytes();
+        self.peak_memory
+            .fetch_max(current + pooled, Ordering::AcqRel);
+        if let (Some((eval_start, profiles)), Some((start, duration))) = (&self.profile, timer) {
+            profiles.lock().unwrap().push(NodeProfile {
+                id: node.id,
+                op_name: node.op.name().to_string(),
+                start: start.duration_since(*eval_start),
+                duration,
+                output_shapes: output_shapes(&value),
+                bytes: bytes.saturating_sub(taken),
+                thread: rayon::current_thread_index().unwrap_or(0),
+            });
+        }
+        self.storage.set(i, value);
+
+        if has_view {
+            self.holds_inputs[i].store(true, Ordering::Release);
+        } else {
+            for &slot in &self.schedule.producers[i] {
+                self.release(slot);
+            }
+        }
+    }
+
+    // Fails the `i` th node with the report if any of its outputs has non-finite values.
+    fn check_finite<'v>(
+        &self,
+        i: usize,
+        node: &TensorInternal<F>,
+        values: Vec<Value<'v, F>>,
+        inputs: Vec<Option<ArrayStats>>,
+    ) -> NodeValue<'v, F> {
+        let non_finite = values.iter().enumerate().find_map(|(k, v)| {
+            // Integers and booleans are always finite.
+            let view = match v {
+                Value::Owned(arr) => arr.view(),
+                Value::View(view) => view.view(),
+                _ => return None,
+            };
+            if view.iter().all(|a| a.is_finite()) {
+                None
+            } else {
+                Some((k, ArrayStats::new(&view)))
+            }
+        });
+        match non_finite {
+            None => Ok(values),
+            Some((output_index, output)) => Err(crate::EvalError::NonFinite(Box::new(Anomaly {
+                id: node.id,
+                op_name: node.op.name().to_string(),
+                name: node.name.clone(),
+                is_gradient: self.gradient_nodes.as_ref().map_or(false, |g| g[i]),
+                location: node.location,
+                output_index,
+                output,
+                inputs,
+            }))),
+        }
+    }
+
+    // Returns which inputs of the `i` th node can be taken over by its op.
+    //
+    // An input is reusable if the op supports it and the `i` th node is the last reader of
+    // the array, i.e. the producer has no other consumers left and isn't a target.
+    // Consumers outputting views of the array keep it in use until they are freed.
+    fn reusable_inputs(&self, i: usize, node: &TensorInternal<F>) -> InputArray<bool> {
+        let sources = &self.schedule.inputs[i];
+        sources
+            .iter()
+            .enumerate()
+            .map(|(k, &source)| match source {
+                ValueSource::Node(slot) => {
+                    node.op.supports_in_place(k)
+                        && !node.in_edges[k].mut_usage
+                        && sources.iter().filter(|&&s| s == source).count() == 1
+                        && self.uses[slot].load(Ordering::Acquire) == 1
+                }
+                _ => false,
+            })
+            .collect()
+    }
+
+    // Total bytes of the reusable inputs of the `i` th node.
+    fn input_bytes(&self, i: usize, reusable: &[bool]) -> usize {
+        let node = self.graph.access_node(self.schedule.nodes[i]);
+        self.schedule.inputs[i]
+            .iter()
+            .zip(&node.input_indices)
+            .zip(reusable)
+            .map(|((&source, &in_idx), &reusable)| match source {
+                ValueSource::Node(slot) if reusable => match self.storage.get(slot) {
+                    Ok(values) => match &values[in_idx] {
+                        Value::Owned(arr) => arr.len() * mem::size_of::<F>(),
+                        _ => 0,
+                    },
+                    Err(_) => 0,
+                },
+                _ => 0,
+            })
+            .sum()
+    }
+
+    // Decrements the remaining uses of the `i` th node's outputs, and frees them if unused.
+    fn release(&self, i: usize) {
+        let mut stack = vec![i];
+        while let Some(i) = stack.pop() {
+            if self.uses[i].fetch_sub(1, Ordering::AcqRel) == 1 {
+                if let Some(value) = self.storage.free(i) {
+                    self.memory.fetch_sub(owned_bytes(&value), Ordering::AcqRel);
+                    // Recycle the buffers in the following computations.
+                    for v in value.into_iter().flatten() {
+                        if let Value::Owned(arr) = v {
+                            self.graph.pool.put(arr.into_raw_vec());
+                        }
+                    }
+                }
+                if self.holds_inputs[i].load(Ordering::Acquire) {
+                    stack.extend_from_slice(&self.schedule.producers[i]);
+                }
             }
         }
     }
-    input_status
 }
 
 impl<F: Float> Graph<F> {
+    // Default options of the evaluations of this graph.
+    pub(crate) fn exec_options(&self) -> ExecOptions {
+        ExecOptions {
+            detect_anomaly: self.detect_anomaly.get(),
+            ..ExecOptions::default()
+        }
+    }
+
     /// Evaluates given symbolic tensors as a list of `ndarray::Array<F, ndarray::IxDyn>`.
     ///
     /// Unlike [Tensor::eval](tensor/struct.Tensor.html#method.eval), this function
     /// supports batched evaluation.
     ///
+    /// The values of integer and boolean tensors are converted into `F`; use
+    /// [Tensor::eval_int](tensor/struct.Tensor.html#method.eval_int) to get them exactly.
+    ///
     /// See also [Eval](struct.Eval.html).
     /// ```
     /// use ndarray::array;
@@ -336,102 +1204,287 @@ impl<F: Float> Graph<F> {
     where
         A: AsRef<Tensor<'scope, F>> + Copy,
     {
-        validate_feed_shapes(feeds, self);
-
-        let mut node_info_map: FxHashMap<usize, Result<op::OutputArray<ValueInfo>, op::OpError>> =
-            FxHashMap::default();
-
-        // Storage in which compute results are stored. Accessed through UnsafeCell.
-        let storage = OutputStorage::new();
+        into_floats(self.eval_internal(tensors, feeds, self.exec_options()).0)
+    }
 
-        let mut dfs_stack = Vec::<(&TensorInternal<F>, bool)>::with_capacity(100);
-        for t in tensors.iter() {
-            dfs_stack.push((t.as_ref().inner(), false));
+    pub(crate) fn eval_internal<'feed, 'tensor, 'scope, A>(
+        &'scope self,
+        tensors: &'tensor [A],
+        feeds: &[Feed<'feed, F>],
+        options: ExecOptions,
+    ) -> (Vec<Result<AnyArray<F>, crate::EvalError>>, EvalReport)
+    where
+        A: AsRef<Tensor<'scope, F>> + Copy,
+    {
+        let targets: Vec<_> = tensors.iter().map(|t| t.as_ref().id()).collect();
+        let values: Vec<_> = feeds.iter().map(|f| f.value.view()).collect();
+        let schedule = feeds
+            .iter()
+            .map(|f| f.placeholder_id(self))
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|placeholders| {
+                validate_feeds(&placeholders, &values, self)?;
+                Schedule::new(&targets, &placeholders, self)
+            });
+        match schedule {
+            Ok(schedule) => execute(&schedule, self, &values, options),
+            Err(e) => (vec![Err(e); targets.len()], EvalReport::default()),
         }
+    }
 
-        while let Some((node, is_parent)) = dfs_stack.pop() {
-            if is_parent {
-                if would_not_visit(node, &node_info_map) {
-                    continue;
-                }
-
-                // Aggregate input values for `node`. if any of the inputs failed, it's a total failure.
-                let mut xs = InputArray::new();
-                let (mut write_guards, mut read_guards) = (InputArray::new(), InputArray::new());
-                let input_status = aggregate_op_inputs(
-                    node,
-                    self,
-                    &node_info_map,
-                    feeds,
-                    &storage,
-                    &mut xs,
-                    &mut read_guards,
-                    &mut write_guards,
+    /// Compiles an execution plan that evaluates `targets` with the values of `placeholders`.
+    ///
+    /// The plan keeps the evaluation order of the nodes and the bindings of the placeholders,
+    /// so running it skips the graph traversal that [eval](#method.eval) performs on each call.
+    /// This is useful for training loops where the same tensors are evaluated repeatedly
+    /// with different feeds.
+    ///
+    /// Fails if `targets` depend on a placeholder that is not in `placeholders`.
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f64>| {
+    ///     let x = g.placeholder(&[-1]);
+    ///     let y = g.placeholder(&[]);
+    ///     let z = g.reduce_sum(x * y, &[0], false);
+    ///     assert!(g.compile(&[z], &[x]).is_err());
+    ///
+    ///     let plan = g.compile(&[z], &[x, y]).unwrap();
+    ///     for i in 0..3 {
+    ///         let xv = array![1., 2., 3.];
+    ///         let yv = ndarray::arr0(i as f64);
+    ///         let ret = plan.run(&[xv.view().into_dyn(), yv.view().into_dyn()]);
+    ///         assert_eq!(ret[0], Ok(ndarray::arr0(6. * i as f64).into_dyn()));
+    ///     }
+    /// });
+    /// ```
+    pub fn compile<'scope, A, B>(
+        &'scope self,
+        targets: &[A],
+        placeholders: &[B],
+    ) -> Result<ExecutionPlan<'scope, F>, crate::EvalError>
+    where
+        A: AsRef<Tensor<'scope, F>> + Copy,
+        B: AsRef<Tensor<'scope, F>> + Copy,
+    {
+        let targets: Vec<_> = targets.iter().map(|t| t.as_ref().id()).collect();
+        let placeholders: Vec<_> = placeholders
+            .iter()
+            .map(|p| {
+                let p = p.as_ref();
+                assert!(
+                    p.is_placeholder(),
+                    "Graph::compile: {} is not a placeholder",
+                    p.inner().label()
                 );
+                p.id()
+            })
+            .collect();
+        Ok(ExecutionPlan {
+            graph: self,
+            schedule: Schedule::new(&targets, &placeholders, self)?,
+            placeholders,
+            options: self.exec_options(),
+            peak_memory: Cell::new(0),
+            profile: RefCell::new(None),
+        })
+    }
+}
 
-                // run compute if `node`'s inputs were not failed
-                let installed_node_info = input_status.and_then(|()| {
-                    let mut ctx = ComputeContext::new(node, xs);
-                    node.op.compute(&mut ctx);
-                    let ys = ctx.extract_outputs();
-                    if ys.is_empty() {
-                        panic!("Bad op implementation: empty return value");
-                    }
-                    // register compute result
-                    install_compute_results(ys, &storage)
-                });
-                node_info_map.insert(node.id(), installed_node_info);
-            } else {
-                // Update dfs stack
-                dfs_stack.push((node, true));
-                // Push children if needed
-                for child in &node.in_edges {
-                    let child = child.get(self).scoped_inner();
-                    if !would_not_visit(child, &node_info_map) {
-                        dfs_stack.push((child, false));
-                    }
-                }
-            }
-        }
+/// Reusable execution plan created by [Graph::compile](struct.Graph.html#method.compile).
+///
+/// A plan holds the evaluation order of the nodes, the positions of their results and
+/// the bindings of the placeholders, so it can be run many times with different feed values.
+pub struct ExecutionPlan<'graph, F: Float> {
+    graph: &'graph Graph<F>,
+    schedule: Schedule,
+    placeholders: Vec<usize>,
+    options: ExecOptions,
+    peak_memory: Cell<usize>,
+    profile: RefCell<Option<Profile>>,
+}
 
-        // Aggregate return values
-        let mut ret = Vec::with_capacity(tensors.len());
-        for t in tensors {
-            let t = t.as_ref();
-            let arr = if let Some(per) = t.clone_persistent_array() {
-                Ok(per)
-            } else if t.is_placeholder() {
-                Ok(retrieve_feed(feeds, t.id()).to_owned())
-            } else {
-                match &node_info_map.get(&t.id()).unwrap() {
-                    Ok(value_info_list) => {
-                        let info = &value_info_list[0];
-                        match &info.ty {
-                            ValueType::Owned => {
-                                Ok(mem::replace(&mut storage.owned_mut()[info.key], None).unwrap())
-                            }
-                            ValueType::View => Ok(storage.view()[info.key].to_owned()),
-                            ValueType::Empty => Err(crate::EvalError::Empty),
-                        }
-                    }
-                    Err(e) => {
-                        // convert to EvalError
-                        Err(crate::EvalError::OpError(e.clone()))
-                    }
-                }
+impl<'graph, F: Float> ExecutionPlan<'graph, F> {
+    /// Runs the plan.
+    ///
+    /// `feeds[i]` is the value of the `i` th placeholder given to `Graph::compile`, which is
+    /// an `ArrayView` of `F`, `i64` or `bool` as the dtype of the placeholder (see `FeedValue`).
+    /// The values of integer and boolean targets are converted into `F`.
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.placeholder(&[-1, 2]);
+    ///     let i = g.placeholder_int(&[-1]);
+    ///     let plan = g.compile(&[g.gather(x, i, 0)], &[x, i]).unwrap();
+    ///
+    ///     let xv = array![[1., 2.], [3., 4.]];
+    ///     let iv = array![1, 0];
+    ///     let feeds: [ag::FeedValue<f32>; 2] = [xv.view().into(), iv.view().into()];
+    ///     assert_eq!(plan.run(&feeds)[0], Ok(array![[3., 4.], [1., 2.]].into_dyn()));
+    /// });
+    /// ```
+    pub fn run<'v, V>(&self, feeds: &[V]) -> Vec<Result<NdArray<F>, crate::EvalError>>
+    where
+        V: Clone + Into<FeedValue<'v, F>>,
+    {
+        if feeds.len() != self.placeholders.len() {
+            let e = crate::EvalError::FeedCountMismatch {
+                expected: self.placeholders.len(),
+                actual: feeds.len(),
             };
-            ret.push(arr);
+            return vec![Err(e); self.schedule.targets.len()];
+        }
+        let feeds: Vec<FeedValue<F>> = feeds.iter().cloned().map(Into::into).collect();
+        if let Err(e) = validate_feeds(&self.placeholders, &feeds, self.graph) {
+            return vec![Err(e); self.schedule.targets.len()];
         }
-        ret
+        let (ret, report) = execute(&self.schedule, self.graph, &feeds, self.options);
+        self.peak_memory.set(report.peak_memory);
+        *self.profile.borrow_mut() = report.profile;
+        into_floats(ret)
+    }
+
+    #[inline]
+    /// Chooses between the sequential (default) and the parallel execution.
+    ///
+    /// See also [Eval::parallel](struct.Eval.html#method.parallel).
+    pub fn parallel(&mut self, parallel: bool) -> &mut Self {
+        self.options.parallel = parallel;
+        self
+    }
+
+    #[inline]
+    /// Enables or disables the per-op profiling (disabled by default).
+    ///
+    /// See also [Eval::profiling](struct.Eval.html#method.profiling).
+    pub fn profiling(&mut self, profiling: bool) -> &mut Self {
+        self.options.profiling = profiling;
+        self
+    }
+
+    #[inline]
+    /// Enables or disables the anomaly detection mode.
+    ///
+    /// See also [Eval::detect_anomaly](struct.Eval.html#method.detect_anomaly).
+    pub fn detect_anomaly(&mut self, detect_anomaly: bool) -> &mut Self {
+        self.options.detect_anomaly = detect_anomaly;
+        self
+    }
+
+    #[inline]
+    /// Returns the peak memory usage (in bytes) of the last `run`.
+    ///
+    /// See also [Eval::peak_memory](struct.Eval.html#method.peak_memory).
+    pub fn peak_memory(&self) -> usize {
+        self.peak_memory.get()
+    }
+
+    /// Returns the profiling report of the last `run` if profiling is enabled.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile.borrow().clone()
+    }
+
+    #[inline]
+    /// Returns the number of ops that are computed in a run.
+    pub fn num_ops(&self) -> usize {
+        self.schedule.len()
     }
 }
 
-#[inline]
-fn would_not_visit<F: Float>(
-    node: &TensorInternal<F>,
-    info_map: &FxHashMap<usize, Result<op::OutputArray<ValueInfo>, op::OpError>>,
-) -> bool {
-    node.is_placeholder || node.has_persistent_array || info_map.contains_key(&node.id())
+// Runs `schedule` and collects the values of its targets.
+fn execute<F: Float>(
+    schedule: &Schedule,
+    g: &Graph<F>,
+    feeds: &[FeedValue<F>],
+    options: ExecOptions,
+) -> (Vec<Result<AnyArray<F>, crate::EvalError>>, EvalReport) {
+    let ret = run_schedule(schedule, g, feeds, options);
+    g.pool.finish_eval();
+    ret
+}
+
+/// Runs `schedule` in `Op::compute`, e.g. for the branches of `Graph::cond`.
+///
+/// `feeds[k]` is the value of the `k` th placeholder given to `Schedule::new`.
+/// Returns the values of the targets, or the first error among them.
+pub(crate) fn run_subgraph<F: Float>(
+    schedule: &Schedule,
+    g: &Graph<F>,
+    feeds: &[NdArrayView<F>],
+) -> Result<Vec<NdArray<F>>, crate::EvalError> {
+    let feeds: Vec<_> = feeds.iter().map(|f| FeedValue::Float(f.view())).collect();
+    // The buffer pool is left as is since the outer evaluation is in progress.
+    into_floats(run_schedule(schedule, g, &feeds, ExecOptions::default()).0)
+        .into_iter()
+        .collect()
+}
+
+// Same as `execute` but doesn't mark the end of the evaluation in the buffer pool.
+fn run_schedule<F: Float>(
+    schedule: &Schedule,
+    g: &Graph<F>,
+    feeds: &[FeedValue<F>],
+    options: ExecOptions,
+) -> (Vec<Result<AnyArray<F>, crate::EvalError>>, EvalReport) {
+    let storage = OutputStorage::new(schedule.len());
+    let executor = Executor::new(
+        schedule,
+        g,
+        feeds,
+        &storage,
+        options.profiling,
+        options.detect_anomaly,
+    );
+    executor.run(options.parallel);
+    let report = EvalReport {
+        peak_memory: executor.peak_memory.load(Ordering::Acquire),
+        profile: executor.profile.map(|(eval_start, profiles)| {
+            let mut nodes = profiles.into_inner().unwrap();
+            nodes.sort_by_key(|node| node.start);
+            Profile {
+                nodes,
+                total: eval_start.elapsed(),
+            }
+        }),
+    };
+
+    // Aggregate return values
+    let mut ret = Vec::with_capacity(schedule.targets.len());
+    for (i, &(id, source)) in schedule.targets.iter().enumerate() {
+        let arr = match source {
+            ValueSource::Persistent => Ok(AnyArray::Float(
+                g.access_node(id)
+                    .clone_persistent_array()
+                    .expect("Not a persistent tensor"),
+            )),
+            ValueSource::Feed(k) => Ok(feeds[k].to_owned()),
+            ValueSource::Node(slot) => {
+                // Owned arrays are moved out unless the same target appears again later.
+                let is_last = !schedule.targets[i + 1..].iter().any(|&(_, s)| s == source);
+                match storage.get_mut(slot) {
+                    Ok(values) => match &mut values[0] {
+                        Value::Owned(arr) if is_last => Ok(AnyArray::Float(mem::replace(
+                            arr,
+                            NdArray::zeros(ndarray::IxDyn(&[])),
+                        ))),
+                        Value::Owned(arr) => Ok(AnyArray::Float(arr.clone())),
+                        Value::View(view) => Ok(AnyArray::Float(view.to_owned())),
+                        Value::Int(arr) => Ok(AnyArray::Int(arr.clone())),
+                        Value::Bool(arr) => Ok(AnyArray::Bool(arr.clone())),
+                        Value::Empty => Err(crate::EvalError::Empty),
+                    },
+                    Err(e) => Err(e.clone()),
+                }
+            }
+        };
+        ret.push(arr);
+    }
+    (ret, report)
 }
 
 #[test]
@@ -481,3 +1534,444 @@ fn test_placeholder_eval() {
         assert_eq!(Ok(arr), eval_result);
     });
 }
+
+#[test]
+fn test_parallel_eval() {
+    use crate::tensor::Variable;
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let rng = crate::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.placeholder(&[-1, 4]);
+        let w = g.variable(rng.standard_normal(&[4, 8]));
+        let h = g.matmul(x, w);
+        let gates: Vec<_> = (0..4)
+            .map(|i| g.sigmoid(g.slice(h, &[0, 2 * i], &[-1, 2 * i + 2])))
+            .collect();
+        let y = g.add_n(&gates);
+        let z = g.reduce_sum(y, &[0, 1], false);
+        let gw = g.grad(&[z], &[w])[0];
+
+        let x_val = rng.standard_normal(&[3, 4]);
+        let feeds = &[x.given(x_val.view())];
+        let seq = crate::Eval::new(g).extend(&[y, z, gw, y]).feed(feeds).run();
+        let par = crate::Eval::new(g)
+            .extend(&[y, z, gw, y])
+            .feed(feeds)
+            .parallel(true)
+            .run();
+        assert_eq!(seq, par);
+        assert_eq!(par[0], par[3]);
+    });
+}
+
+#[test]
+fn test_parallel_eval_error() {
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let a = g.ones(&[2, 3]);
+        let b = g.ones(&[4, 5]);
+        let c = g.matmul(a, b);
+        let d = g.exp(c);
+        let ret = crate::Eval::new(g).extend(&[d, a]).parallel(true).run();
+        assert!(ret[0].is_err());
+        assert!(ret[1].is_ok());
+    });
+}
+
+#[test]
+fn test_parallel_eval_error_with_feeds() {
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let a = g.ones(&[2, 3]);
+        // The shape mismatch is unknown until `b` is fed.
+        let b = g.placeholder(&[-1, 5]);
+        let c = g.matmul(a, b);
+        let d = g.exp(c);
+        let b_val = crate::ndarray_ext::ones(&[4, 5]);
+        let feeds = [b.given(b_val.view())];
+        let ret = crate::Eval::new(g)
+            .extend(&[d, a])
+            .feed(&feeds)
+            .parallel(true)
+            .run();
+        assert!(ret[0].is_err());
+        assert!(ret[1].is_ok());
+    });
+}
+
+#[test]
+fn test_views_keep_inputs_alive() {
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let a = g.exp(g.ones(&[2, 3]));
+        // `b` and `c` are views of `a`'s array.
+        let b = g.reshape(a, &[3, 2]).show_shape();
+        let c = g.reshape(b, &[6]);
+        let d = g.exp(g.exp(c));
+        let e = g.add(d, c);
+        for &parallel in &[false, true] {
+            let ret = crate::Eval::new(g).extend(&[e, b]).parallel(parallel).run();
+            let expected = crate::ndarray_ext::ones::<f32>(&[6]).mapv(|x| x.exp());
+            assert_eq!(ret[1].as_ref().unwrap().shape(), &[3, 2]);
+            assert_eq!(ret[0], Ok(expected.mapv(|x| x.exp().exp()) + &expected));
+        }
+    });
+}
+
+#[test]
+fn test_peak_memory() {
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let a = g.ones(&[10, 10]);
+        let mut x = g.matmul(a, a);
+        for _ in 0..10 {
+            x = g.matmul(x, a);
+        }
+        let mut eval = crate::Eval::new(g);
+        eval.push(x);
+        let ret = eval.run();
+        assert_eq!(ret[0].as_ref().unwrap().shape(), &[10, 10]);
+        // Only `a` and two of the products are alive at a time.
+        assert_eq!(eval.peak_memory(), 3 * 100 * 8);
+    });
+}
+
+#[test]
+fn test_execution_plan() {
+    use crate::tensor::Variable;
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let rng = crate::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.placeholder(&[-1, 3]);
+        let w = g.variable(rng.standard_normal(&[3, 2]));
+        let y = g.matmul(x, w);
+        let z = g.reduce_sum(g.sigmoid(y), &[0, 1], false);
+        let gw = g.grad(&[z], &[w])[0];
+
+        let mut plan = g.compile(&[z, gw, w, x], &[x]).unwrap();
+        // Nodes created after compilation don't affect the plan.
+        let _ = g.tanh(g.exp(z) * gw);
+        for &parallel in &[false, true] {
+            plan.parallel(parallel);
+            for batch in 1..4 {
+                let x_val = rng.standard_normal(&[batch, 3]);
+                let expected = g.eval(&[z, gw, w, x], &[x.given(x_val.view())]);
+                assert_eq!(plan.run(&[x_val.view()]), expected);
+            }
+        }
+    });
+}
+
+#[test]
+fn test_execution_plan_typed_feeds() {
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let i = g.placeholder_int(&[-1]);
+        let mask = g.placeholder_bool(&[-1]);
+        let plan = g.compile(
+            &[g.cast(i, DType::Float), g.cast(mask, DType::Float)],
+            &[i, mask],
+        );
+        let plan = plan.unwrap();
+
+        let iv = ndarray::arr1(&[3, -1]);
+        let mask_v = ndarray::arr1(&[true, false]);
+        let feeds: [FeedValue<f32>; 2] = [iv.view().into(), mask_v.view().into()];
+        let ret = plan.run(&feeds);
+        assert_eq!(ret[0], Ok(ndarray::arr1(&[3., -1.]).into_dyn()));
+        assert_eq!(ret[1], Ok(ndarray::arr1(&[1., 0.]).into_dyn()));
+
+        let xv = ndarray::arr1(&[3., -1.]);
+        let feeds: [FeedValue<f32>; 2] = [xv.view().into(), mask_v.view().into()];
+        let mismatch = crate::EvalError::FeedTypeMismatch {
+            id: i.id(),
+            name: None,
+            expected: DType::Int,
+            actual: DType::Float,
+        };
+        assert_eq!(plan.run(&feeds), vec![Err(mismatch.clone()), Err(mismatch)]);
+    });
+}
+
+#[test]
+fn test_execution_plan_bad_feed() {
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let x = g.placeholder(&[2]);
+        let plan = g.compile(&[g.exp(x)], &[x]).unwrap();
+        let ret = plan.run(&[crate::ndarray_ext::zeros::<f32>(&[3]).view()]);
+        assert!(matches!(
+            ret[0],
+            Err(crate::EvalError::FeedShapeMismatch { .. })
+        ));
+    });
+}
+
+#[test]
+fn test_buffer_pool_reuse() {
+    use crate::tensor::Variable;
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let rng = crate::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.variable(rng.standard_normal(&[2, 3, 5, 5]));
+        let w = g.variable(rng.standard_normal(&[4, 3, 2, 2]));
+        let a = g.variable(rng.standard_normal(&[6, 16]));
+        let y = g.conv2d(x, w, 0, 1);
+        let y = g.reshape(y, &[-1, 16]);
+        let z = g.tanh(g.matmul(g.relu(y), g.transpose(a, &[1, 0])));
+        let z = g.sigmoid(g.exp(z));
+
+        let first = z.eval(&[]).unwrap();
+        let pooled = g.buffer_pool_bytes();
+        assert!(pooled > 0);
+        for _ in 0..3 {
+            assert_eq!(first, z.eval(&[]).unwrap());
+            // The pool doesn't grow over repeated evaluations.
+            assert_eq!(pooled, g.buffer_pool_bytes());
+        }
+    });
+}
+
+#[test]
+fn test_buffer_pool_limits() {
+    use crate::tensor::Variable;
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let rng = crate::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.variable(rng.standard_normal(&[5, 4]));
+
+        // Buffers of the lengths that no op asks for are not kept.
+        let y = g.reduce_sum(g.concat(&[x, x], 0), &[0], false);
+        y.eval(&[]).unwrap();
+        assert_eq!(g.buffer_pool_bytes(), 0);
+
+        let a = g.ones(&[10, 10]);
+        let mut z = g.matmul(a, a);
+        for _ in 0..10 {
+            z = g.matmul(z, a);
+        }
+        let expected = z.eval(&[]).unwrap();
+        // `a` and the second last product are left.
+        assert_eq!(g.buffer_pool_bytes(), 2 * 100 * 8);
+
+        // The pooled buffers count in the peak memory.
+        let mut eval = crate::Eval::new(g);
+        eval.push(g.exp(x));
+        eval.run();
+        assert_eq!(eval.peak_memory(), 20 * 8 + 2 * 100 * 8);
+
+        // The pool doesn't exceed its capacity.
+        g.set_buffer_pool_capacity(0);
+        assert_eq!(z.eval(&[]), Ok(expected));
+        assert_eq!(g.buffer_pool_bytes(), 0);
+    });
+}
+
+#[test]
+fn test_in_place_ops() {
+    use crate::tensor::Variable;
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let rng = crate::ndarray_ext::ArrayRng::<f64>::default();
+        let x_arr = rng.standard_normal(&[4, 3]);
+        let w_arr = rng.standard_normal(&[3, 5]);
+        let b_arr = rng.standard_normal(&[5]);
+        let x = g.variable(x_arr.clone());
+        let w = g.variable(w_arr.clone());
+        let b = g.variable(b_arr.clone());
+        let y = g.relu(g.matmul(x, w) + b);
+
+        let x_arr = x_arr.into_dimensionality::<ndarray::Ix2>().unwrap();
+        let w_arr = w_arr.into_dimensionality::<ndarray::Ix2>().unwrap();
+        let expected = (x_arr.dot(&w_arr) + &b_arr).mapv(|a| a.max(0.)).into_dyn();
+        for &parallel in &[false, true] {
+            let mut eval = crate::Eval::new(g);
+            eval.push(y).parallel(parallel);
+            let ret = eval.run();
+            assert!(ret[0].as_ref().unwrap().all_close(&expected, 1e-12));
+            // `+` and `relu` overwrite the output of `matmul`.
+            assert_eq!(eval.peak_memory(), 4 * 5 * 8);
+        }
+    });
+}
+
+#[test]
+fn test_in_place_ops_shared_input() {
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let a = g.exp(g.ones(&[3]));
+        // `a` is read by both `tanh` and `+`, so `tanh` must not overwrite it.
+        let b = a + g.tanh(a);
+        // `c` is a target, so `sin` must not overwrite it.
+        let c = g.exp(g.zeros(&[3]));
+        let d = g.sin(c);
+        let e = std::f64::consts::E;
+        let ret = g.eval(&[b, c, d], &[]);
+        assert_eq!(ret[0], Ok(ndarray::arr1(&[e + e.tanh(); 3]).into_dyn()));
+        assert_eq!(ret[1], Ok(ndarray::arr1(&[1.; 3]).into_dyn()));
+        assert_eq!(ret[2], Ok(ndarray::arr1(&[1f64.sin(); 3]).into_dyn()));
+    });
+}
+
+#[test]
+fn test_profiling() {
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let a = g.placeholder(&[4, 4]);
+        let b = g.matmul(a, a);
+        let c = g.sigmoid(b);
+
+        let a_arr = crate::ndarray_ext::ones(&[4, 4]);
+        let feeds = [a.given(a_arr.view())];
+        let mut eval = crate::Eval::new(g);
+        eval.push(&c).feed(&feeds);
+        eval.run();
+        assert!(eval.profile().is_none());
+        for &parallel in &[false, true] {
+            eval.profiling(true).parallel(parallel).run();
+            let profile = eval.profile().unwrap();
+            let ids: Vec<_> = profile.nodes().iter().map(|node| node.id).collect();
+            assert_eq!(ids, vec![b.id(), c.id()]);
+            let matmul = &profile.nodes()[0];
+            assert_eq!(matmul.output_shapes, vec![vec![4, 4]]);
+            assert_eq!(matmul.bytes, 4 * 4 * 4);
+            // `sigmoid` is computed in place of `b`.
+            assert_eq!(profile.nodes()[1].bytes, 0);
+            let by_op = profile.by_op();
+            assert_eq!(by_op.len(), 2);
+            assert!(by_op.iter().all(|op| op.count == 1));
+            assert!(profile.to_chrome_trace().contains("\"ph\":\"X\""));
+        }
+    });
+}
+
+#[test]
+fn test_eval_errors() {
+    use crate::EvalError;
+
+    struct Panic;
+
+    impl crate::op::Op<f32> for Panic {
+        fn compute(&self, _: &mut ComputeContext<f32>) {
+            panic!("bad input");
+        }
+
+        fn grad(&self, _: &mut crate::op::GradientContext<f32>) {}
+    }
+
+    struct NoOutput;
+
+    impl crate::op::Op<f32> for NoOutput {
+        fn compute(&self, _: &mut ComputeContext<f32>) {}
+
+        fn grad(&self, _: &mut crate::op::GradientContext<f32>) {}
+    }
+
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let x = g.placeholder_named("x", &[-1, 2]);
+        let y = x * 2.;
+        let z = g.ones(&[2]);
+
+        let missing = EvalError::MissingFeed {
+            id: x.id(),
+            name: Some("x".to_string()),
+        };
+        assert_eq!(
+            g.eval(&[y, z], &[]),
+            vec![Err(missing.clone()), Err(missing)]
+        );
+
+        let bad = crate::ndarray_ext::zeros(&[3]);
+        let mismatch = EvalError::FeedShapeMismatch {
+            id: x.id(),
+            name: Some("x".to_string()),
+            expected: vec![-1, 2],
+            actual: vec![3],
+        };
+        assert_eq!(y.eval(&[x.given(bad.view())]), Err(mismatch.clone()));
+        let plan = g.compile(&[y], &[x]).unwrap();
+        assert_eq!(plan.run(&[bad.view()]), vec![Err(mismatch)]);
+        let count = EvalError::FeedCountMismatch {
+            expected: 1,
+            actual: 0,
+        };
+        assert_eq!(plan.run(&[] as &[NdArrayView<f32>]), vec![Err(count)]);
+        let missing = EvalError::MissingFeed {
+            id: x.id(),
+            name: Some("x".to_string()),
+        };
+        assert_eq!(g.compile(&[y], &[] as &[Tensor<f32>]).err(), Some(missing));
+
+        let feeds = [crate::Feed::named("w", bad.view())];
+        assert_eq!(y.eval(&feeds), Err(EvalError::UnknownFeed("w".to_string())));
+
+        // Only the tensors depending on the panicked op fail.
+        let p = Tensor::builder().append_input(&z).build(g, Panic);
+        let ret = g.eval(&[g.exp(p), z], &[]);
+        match ret[0] {
+            Err(EvalError::OpPanicked {
+                ref op_name,
+                id,
+                ref message,
+            }) => {
+                assert!(op_name.ends_with("Panic"));
+                assert_eq!(id, p.id());
+                assert_eq!(message, "bad input");
+            }
+            _ => panic!("expected OpPanicked"),
+        }
+        assert!(ret[1].is_ok());
+
+        // An op without outputs fails as well.
+        let empty = Tensor::builder().append_input(&z).build(g, NoOutput);
+        match empty.eval(&[]) {
+            Err(EvalError::OpPanicked {
+                id, ref message, ..
+            }) => {
+                assert_eq!(id, empty.id());
+                assert!(message.contains("empty return value"));
+            }
+            _ => panic!("expected OpPanicked"),
+        }
+    });
+}
+
+#[test]
+fn test_detect_anomaly() {
+    use crate::EvalError;
+
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let x = g.placeholder(&[-1]);
+        let y = g.ln(x).with_name("log_x");
+        let line = line!() - 1;
+        let z = g.exp(y) * 2.;
+        let w = g.ones(&[2]) + 1.;
+
+        let x_arr = ndarray::arr1(&[0., 2., -1.]).into_dyn();
+        let feeds = [x.given(x_arr.view())];
+        // Disabled by default.
+        assert!(z.eval(&feeds).is_ok());
+
+        let mut eval = crate::Eval::new(g);
+        eval.extend(&[z, w]).feed(&feeds);
+        for &parallel in &[false, true] {
+            let ret = eval.detect_anomaly(true).parallel(parallel).run();
+            // The downstream tensors fail with the first op outputting non-finite values.
+            let anomaly = match &ret[0] {
+                Err(EvalError::NonFinite(anomaly)) => anomaly,
+                _ => panic!("expected NonFinite"),
+            };
+            assert_eq!(anomaly.id, y.id());
+            assert!(anomaly.op_name.ends_with("Ln"));
+            assert_eq!(anomaly.name.as_deref(), Some("log_x"));
+            assert!(!anomaly.is_gradient);
+            let location = anomaly.location.unwrap();
+            assert_eq!((location.file(), location.line()), (file!(), line));
+            assert_eq!(anomaly.output_index, 0);
+            assert_eq!((anomaly.output.num_nan, anomaly.output.num_inf), (1, 1));
+            let input = anomaly.inputs[0].as_ref().unwrap();
+            assert_eq!(input.shape, vec![3]);
+            assert_eq!((input.min, input.max, input.mean), (-1., 2., 1. / 3.));
+            assert!(input.is_finite());
+            assert!(format!("{}", anomaly).contains("log_x"));
+            assert!(ret[1].is_ok());
+        }
+
+        // Also in the execution plans and the graph-wide setting.
+        let mut plan = g.compile(&[z], &[x]).unwrap();
+        assert!(plan.run(&[x_arr.view()])[0].is_ok());
+        plan.detect_anomaly(true);
+        assert!(plan.run(&[x_arr.view()])[0].is_err());
+        g.detect_anomaly(true);
+        assert!(z.eval(&feeds).is_err());
+        let ok = ndarray::arr1(&[1., 2.]).into_dyn();
+        assert!(z.eval(&[x.given(ok.view())]).is_ok());
+    });
+}
@@ -0,0 +1,849 @@
+//! Defining the saving and loading of graphs.
+//!
+//! A graph is saved as text, one node per line:
+//!
+//! ```text
+//! autograd-graph 1
+//! node 0 ConvertToTensor shape=1 @a:2;-1.0,3.0
+//! node 1 ConvertToTensor @a:1;2.0
+//! node 2 Placeholder shape=0 known=-1,3 placeholder name=x
+//! node 3 Variable var=3,2;1.0,1.0,1.0,1.0,1.0,1.0
+//! node 4 MatMul in=2,3 @b:false @b:false
+//! targets 4
+//! ```
+//!
+//! Each node line has the op name (see [OpRegistry](struct.OpRegistry.html)) and the
+//! following fields, of which only the non-default ones are written:
+//!
+//! - `in=`: input node ids; `!` marks mutable inputs
+//! - `idx=`: output indices of the inputs
+//! - `bp=`: backprop inputs
+//! - `shape=`: id of the node computing the shape
+//! - `known=`: known shape (`-1` for unknown dims)
+//! - `dtype=`: dtype of placeholders other than float (`int` or `bool`)
+//! - `placeholder`, `nondiff`: flags
+//! - `name=`: name given with [Tensor::with_name](../tensor/struct.Tensor.html#method.with_name)
+//! - `var=`, `const=`: variable and constant arrays
+//! - `@`: the op's [attributes](../op/trait.Op.html#method.attributes), in order
+//!
+//! Arrays are written as `<dims>;<elements>` in the standard layout.
+use crate::op::{short_op_name, Op};
+use crate::tensor::{DType, Input, Tensor};
+use crate::{Float, FxHashMap, FxHashSet, Graph, NdArray};
+use std::fmt::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+const HEADER: &str = "autograd-graph 1";
+
+/// Parameter of an op.
+///
+/// See [Op::attributes](../op/trait.Op.html#method.attributes).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Attribute<F: Float> {
+    Int(i64),
+    Float(F),
+    Bool(bool),
+    Ints(Vec<i64>),
+    Array(NdArray<F>),
+}
+
+impl<F: Float> Attribute<F> {
+    #[inline]
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            Attribute::Int(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn as_float(&self) -> Option<F> {
+        match *self {
+            Attribute::Float(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Attribute::Bool(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn as_ints(&self) -> Option<&[i64]> {
+        match self {
+            Attribute::Ints(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn as_array(&self) -> Option<&NdArray<F>> {
+        match self {
+            Attribute::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+/// Error in saving or loading graphs and ONNX models.
+#[derive(Debug)]
+pub enum SerializationError {
+    Io(std::io::Error),
+    /// The op doesn't implement `Op::attributes`.
+    UnsupportedOp(String),
+    /// The op isn't registered in the `OpRegistry`.
+    UnknownOp(String),
+    /// The registered constructor rejected the attributes of the op.
+    InvalidAttributes(String),
+    /// Malformed line.
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// Malformed ONNX model.
+    InvalidModel(String),
+}
+
+impl std::error::Error for SerializationError {}
+
+impl fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SerializationError::Io(e) => e.fmt(f),
+            SerializationError::UnsupportedOp(s) => write!(f, "{} can't be serialized", s),
+            SerializationError::UnknownOp(s) => write!(f, "{} is not registered", s),
+            SerializationError::InvalidAttributes(s) => write!(f, "Invalid attributes of {}", s),
+            SerializationError::Parse { line, message } => {
+                write!(f, "Parse error at line {}: {}", line, message)
+            }
+            SerializationError::InvalidModel(s) => write!(f, "Invalid ONNX model: {}", s),
+        }
+    }
+}
+
+impl From<std::io::Error> for SerializationError {
+    fn from(e: std::io::Error) -> Self {
+        SerializationError::Io(e)
+    }
+}
+
+type OpConstructor<F> = dyn Fn(&[Attribute<F>]) -> Option<Box<dyn Op<F>>>;
+
+/// Constructors of ops used to load graphs.
+///
+/// Ops are keyed by `Op::name` without the module path and the generic parameters,
+/// e.g. `MatMul`. `OpRegistry::new` registers all the built-in ops.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::op::Attribute;
+///
+/// struct Scale(f32);
+///
+/// impl ag::op::Op<f32> for Scale {
+///     fn compute(&self, ctx: &mut ag::op::ComputeContext<f32>) {
+///         let y = ctx.input(0).mapv(|a| a * self.0);
+///         ctx.append_output(y);
+///     }
+///
+///     fn grad(&self, ctx: &mut ag::op::GradientContext<f32>) {
+///         let gx = ctx.output_grad() * self.0;
+///         ctx.append_input_grad(Some(gx));
+///     }
+///
+///     fn attributes(&self) -> Option<Vec<Attribute<f32>>> {
+///         Some(vec![Attribute::Float(self.0)])
+///     }
+/// }
+///
+/// let mut registry = ag::serialization::OpRegistry::new();
+/// // `Scale` takes one input.
+/// registry.register("Scale", 1, |attrs| {
+///     let op = Scale(attrs.first()?.as_float()?);
+///     Some(Box::new(op) as Box<dyn ag::op::Op<f32>>)
+/// });
+/// assert!(registry.contains("Scale"));
+/// ```
+pub struct OpRegistry<F: Float> {
+    constructors: FxHashMap<String, (Arity, Box<OpConstructor<F>>)>,
+}
+
+// Number of inputs an op takes.
+#[derive(Clone, Copy)]
+enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    fn accepts(self, num_inputs: usize) -> bool {
+        match self {
+            Arity::Exact(n) => num_inputs == n,
+            Arity::AtLeast(n) => num_inputs >= n,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+        }
+    }
+}
+
+impl<F: Float> Default for OpRegistry<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: Float> OpRegistry<F> {
+    /// Creates a registry of the built-in ops.
+    pub fn new() -> Self {
+        let mut ret = Self::empty();
+        crate::ops::register_builtin_ops(&mut ret);
+        ret
+    }
+
+    /// Creates a registry without any ops.
+    pub fn empty() -> Self {
+        OpRegistry {
+            constructors: FxHashMap::default(),
+        }
+    }
+
+    /// Registers the constructor of an op taking `num_inputs` inputs, replacing the existing
+    /// one with the same name.
+    ///
+    /// `constructor` takes the attributes returned by `Op::attributes`, and returns `None`
+    /// if they are invalid.
+    pub fn register<C>(&mut self, name: &str, num_inputs: usize, constructor: C)
+    where
+        C: Fn(&[Attribute<F>]) -> Option<Box<dyn Op<F>>> + 'static,
+    {
+        self.constructors.insert(
+            name.to_string(),
+            (Arity::Exact(num_inputs), Box::new(constructor)),
+        );
+    }
+
+    /// Same as `register` but for an op taking `min_inputs` or more inputs, e.g. `Concat`.
+    pub fn register_variadic<C>(&mut self, name: &str, min_inputs: usize, constructor: C)
+    where
+        C: Fn(&[Attribute<F>]) -> Option<Box<dyn Op<F>>> + 'static,
+    {
+        self.constructors.insert(
+            name.to_string(),
+            (Arity::AtLeast(min_inputs), Box::new(constructor)),
+        );
+    }
+
+    /// Returns true if an op named `name` is registered.
+    pub fn contains(&self, name: &str) -> bool {
+        self.constructors.contains_key(name)
+    }
+
+    // Returns the error message if the op doesn't take `num_inputs` inputs.
+    fn check_arity(&self, name: &str, num_inputs: usize) -> Result<(), String> {
+        match self.constructors.get(name) {
+            Some(&(arity, _)) if !arity.accepts(num_inputs) => Err(format!(
+                "{} takes {} inputs, not {}",
+                name, arity, num_inputs
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn construct(
+        &self,
+        name: &str,
+        attributes: &[Attribute<F>],
+    ) -> Result<Box<dyn Op<F>>, SerializationError> {
+        let (_, constructor) = self
+            .constructors
+            .get(name)
+            .ok_or_else(|| SerializationError::UnknownOp(name.to_string()))?;
+        constructor(attributes)
+            .ok_or_else(|| SerializationError::InvalidAttributes(name.to_string()))
+    }
+}
+
+/// Tensors of a graph loaded by [Graph::load](../struct.Graph.html#method.load).
+pub struct LoadedGraph<'g, F: Float> {
+    /// Tensors passed to `Graph::save`, in the same order.
+    pub targets: Vec<Tensor<'g, F>>,
+    /// Placeholders in the order of creation.
+    pub placeholders: Vec<Tensor<'g, F>>,
+    /// Variables in the order of creation.
+    pub variables: Vec<Tensor<'g, F>>,
+}
+
+// Key of an op in `OpRegistry`
+pub(crate) fn op_key(name: &str) -> &str {
+    let name = short_op_name(name);
+    &name[..name.find('<').unwrap_or(name.len())]
+}
+
+fn write_float<F: Float>(buf: &mut String, a: F) {
+    write!(buf, "{:?}", a.to_f64().unwrap()).unwrap();
+}
+
+fn write_array<F: Float>(buf: &mut String, arr: &NdArray<F>) {
+    write_list(buf, arr.shape());
+    buf.push(';');
+    for (i, &a) in arr.iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        write_float(buf, a);
+    }
+}
+
+fn write_list<T: fmt::Display>(buf: &mut String, items: &[T]) {
+    for (i, a) in items.iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        write!(buf, "{}", a).unwrap();
+    }
+}
+
+fn write_inputs(buf: &mut String, inputs: &[Input], new_ids: &FxHashMap<usize, usize>) {
+    for (i, x) in inputs.iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        write!(buf, "{}", new_ids[&x.id]).unwrap();
+        if x.mut_usage {
+            buf.push('!');
+        }
+    }
+}
+
+fn parse<T: FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid number `{}`", s))
+}
+
+fn parse_list<T: FromStr>(s: &str) -> Result<Vec<T>, String> {
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
+    s.split(',').map(parse).collect()
+}
+
+fn parse_float<F: Float>(s: &str) -> Result<F, String> {
+    parse::<f64>(s).map(|a| F::from(a).unwrap())
+}
+
+fn parse_array<F: Float>(s: &str) -> Result<NdArray<F>, String> {
+    let mut parts = s.splitn(2, ';');
+    let shape: Vec<usize> = parse_list(parts.next().unwrap())?;
+    let data = match parts.next() {
+        Some("") => Vec::new(),
+        Some(data) => data.split(',').map(parse_float).collect::<Result<_, _>>()?,
+        None => return Err(format!("invalid array `{}`", s)),
+    };
+    NdArray::from_shape_vec(ndarray::IxDyn(&shape), data)
+        .map_err(|_| format!("invalid array `{}`", s))
+}
+
+fn parse_attribute<F: Float>(s: &str) -> Result<Attribute<F>, String> {
+    let mut parts = s.splitn(2, ':');
+    let (kind, value) = (parts.next().unwrap(), parts.next().unwrap_or(""));
+    match kind {
+        "i" => parse(value).map(Attribute::Int),
+        "f" => parse_float(value).map(Attribute::Float),
+        "b" => parse(value).map(Attribute::Bool),
+        "is" => parse_list(value).map(Attribute::Ints),
+        "a" => parse_array(value).map(Attribute::Array),
+        _ => Err(format!("invalid attribute `@{}`", s)),
+    }
+}
+
+// Parses the node ids in `s` into the inputs referring to `loaded`.
+fn parse_inputs<F: Float>(s: &str, loaded: &[Tensor<F>]) -> Result<Vec<Input>, String> {
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
+    s.split(',')
+        .map(|a| {
+            let (a, mut_usage) = match a.strip_suffix('!') {
+                Some(a) => (a, true),
+                None => (a, false),
+            };
+            let x = loaded_node(parse(a)?, loaded)?;
+            Ok(Input {
+                id: x.id(),
+                mut_usage,
+                is_placeholder: x.is_placeholder(),
+            })
+        })
+        .collect()
+}
+
+fn loaded_node<'a, 'g, F: Float>(
+    id: usize,
+    loaded: &'a [Tensor<'g, F>],
+) -> Result<&'a Tensor<'g, F>, String> {
+    loaded
+        .get(id)
+        .ok_or_else(|| format!("node {} is used before its definition", id))
+}
+
+impl<F: Float> Graph<F> {
+    /// Serializes the subgraph that `targets` depend on.
+    ///
+    /// The values of variables are saved as of now.
+    /// All the ops must implement [Op::attributes](op/trait.Op.html#method.attributes),
+    /// which excludes hooks and the ops created by
+    /// [fuse_elementwise_ops](#method.fuse_elementwise_ops).
+    pub fn serialize<'g, A>(&'g self, targets: &[A]) -> Result<String, SerializationError>
+    where
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        let mut visited = FxHashSet::default();
+        let mut stack: Vec<usize> = targets.iter().map(|t| t.as_ref().id()).collect();
+        while let Some(id) = stack.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            let node = self.access_node(id);
+            stack.extend(node.in_edges.iter().map(|x| x.id));
+            if let Some(ref bp) = node.backprop_inputs {
+                stack.extend(bp.iter().map(|x| x.id));
+            }
+            stack.extend(node.shape);
+        }
+        // Nodes are created after their inputs, so the ascending order of ids is topological.
+        let mut ids: Vec<usize> = visited.into_iter().collect();
+        ids.sort();
+        let new_ids: FxHashMap<usize, usize> =
+            ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+
+        let mut buf = format!("{}\n", HEADER);
+        for (i, &id) in ids.iter().enumerate() {
+            let node = self.access_node(id);
+            let attributes = node
+                .op
+                .attributes()
+                .ok_or_else(|| SerializationError::UnsupportedOp(node.op.name().to_string()))?;
+            write!(buf, "node {} {}", i, op_key(node.op.name())).unwrap();
+            if !node.in_edges.is_empty() {
+                buf += " in=";
+                write_inputs(&mut buf, &node.in_edges, &new_ids);
+            }
+            if node.input_indices.iter().any(|&i| i != 0) {
+                buf += " idx=";
+                write_list(&mut buf, &node.input_indices);
+            }
+            if let Some(ref bp) = node.backprop_inputs {
+                buf += " bp=";
+                write_inputs(&mut buf, bp, &new_ids);
+            }
+            if let Some(shape) = node.shape {
+                write!(buf, " shape={}", new_ids[&shape]).unwrap();
+            }
+            // The shapes of the other nodes are inferred again on load.
+            if let (true, Some(known_shape)) = (node.is_placeholder, &node.known_shape) {
+                buf += " known=";
+                write_list(&mut buf, known_shape.get());
+            }
+            if node.is_placeholder {
+                buf += " placeholder";
+            }
+            // The dtypes of the other nodes are inferred again on load.
+            if node.is_placeholder && node.dtype != DType::Float {
+                write!(buf, " dtype={}", node.dtype).unwrap();
+            }
+            if !node.is_differentiable {
+                buf += " nondiff";
+            }
+            if let Some(ref name) = node.name {
+                write!(buf, " name={}", name).unwrap();
+            }
+            if let Some(arr) = node.lock_variable_array() {
+                buf += " var=";
+                write_array(&mut buf, &arr);
+            }
+            if let Some(ref arr) = node.constant_array {
+                buf += " const=";
+                write_array(&mut buf, arr);
+            }
+            for attribute in &attributes {
+                buf += " @";
+                match attribute {
+                    Attribute::Int(a) => write!(buf, "i:{}", a).unwrap(),
+                    Attribute::Float(a) => {
+                        buf += "f:";
+                        write_float(&mut buf, *a);
+                    }
+                    Attribute::Bool(a) => write!(buf, "b:{}", a).unwrap(),
+                    Attribute::Ints(a) => {
+                        buf += "is:";
+                        write_list(&mut buf, a);
+                    }
+                    Attribute::Array(a) => {
+                        buf += "a:";
+                        write_array(&mut buf, a);
+                    }
+                }
+            }
+            buf.push('\n');
+        }
+        buf += "targets";
+        for t in targets {
+            write!(buf, " {}", new_ids[&t.as_ref().id()]).unwrap();
+        }
+        buf.push('\n');
+        Ok(buf)
+    }
+
+    /// Saves the subgraph that `targets` depend on to the file at `path`.
+    ///
+    /// See [serialize](#method.serialize) and [load](#method.load).
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor::Variable;
+    ///
+    /// let path = std::env::temp_dir().join("autograd_save_doctest.graph");
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.placeholder(&[-1, 3]);
+    ///     let w = g.variable(ag::ndarray_ext::ones(&[3, 2]));
+    ///     let y = g.sigmoid(g.matmul(x, w));
+    ///     g.save(&[y], &path).unwrap();
+    /// });
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let registry = ag::serialization::OpRegistry::new();
+    ///     let loaded = g.load(&path, &registry).unwrap();
+    ///     let (x, y) = (loaded.placeholders[0], loaded.targets[0]);
+    ///     let ret = y.eval(&[x.given(ag::ndarray_ext::zeros(&[4, 3]).view())]);
+    ///     assert_eq!(ret.unwrap().shape(), &[4, 2]);
+    /// });
+    /// ```
+    pub fn save<'g, A, P>(&'g self, targets: &[A], path: P) -> Result<(), SerializationError>
+    where
+        A: AsRef<Tensor<'g, F>> + Copy,
+        P: AsRef<Path>,
+    {
+        std::fs::write(path, self.serialize(targets)?)?;
+        Ok(())
+    }
+
+    /// Loads a graph saved by [save](#method.save) into this graph.
+    ///
+    /// The ops are constructed with `registry`.
+    pub fn load<'g, P: AsRef<Path>>(
+        &'g self,
+        path: P,
+        registry: &OpRegistry<F>,
+    ) -> Result<LoadedGraph<'g, F>, SerializationError> {
+        self.deserialize(&std::fs::read_to_string(path)?, registry)
+    }
+
+    /// Rebuilds a graph serialized by [serialize](#method.serialize) in this graph.
+    ///
+    /// The ops are constructed with `registry`.
+    pub fn deserialize<'g>(
+        &'g self,
+        text: &str,
+        registry: &OpRegistry<F>,
+    ) -> Result<LoadedGraph<'g, F>, SerializationError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim()))
+            .filter(|(_, l)| !l.is_empty());
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            Some((line, _)) => {
+                return Err(SerializationError::Parse {
+                    line,
+                    message: format!("expected `{}`", HEADER),
+                })
+            }
+            None => {
+                return Err(SerializationError::Parse {
+                    line: 1,
+                    message: "empty input".to_string(),
+                })
+            }
+        }
+
+        let mut loaded = Vec::new();
+        let mut ret = LoadedGraph {
+            targets: Vec::new(),
+            placeholders: Vec::new(),
+            variables: Vec::new(),
+        };
+        for (line, l) in lines {
+            let parse_error = |message| SerializationError::Parse { line, message };
+            let mut tokens = l.split_whitespace();
+            match tokens.next() {
+                Some("node") => {
+                    let id: usize = parse(tokens.next().unwrap_or("")).map_err(parse_error)?;
+                    if id != loaded.len() {
+                        return Err(parse_error(format!("expected node {}", loaded.len())));
+                    }
+                    let op_name = tokens
+                        .next()
+                        .ok_or_else(|| parse_error("missing op name".to_string()))?;
+                    let t = self.deserialize_node(op_name, tokens, &loaded, registry, line)?;
+                    if t.is_placeholder() {
+                        ret.placeholders.push(t);
+                    }
+                    if t.get_variable_array().is_some() {
+                        ret.variables.push(t);
+                    }
+                    loaded.push(t);
+                }
+                Some("targets") => {
+                    for a in tokens {
+                        let id = parse(a).map_err(parse_error)?;
+                        let t = *loaded_node(id, &loaded).map_err(parse_error)?;
+                        ret.targets.push(t);
+                    }
+                }
+                _ => return Err(parse_error(format!("unexpected line `{}`", l))),
+            }
+        }
+        Ok(ret)
+    }
+
+    fn deserialize_node<'g, 'a>(
+        &'g self,
+        op_name: &str,
+        tokens: impl Iterator<Item = &'a str>,
+        loaded: &[Tensor<'g, F>],
+        registry: &OpRegistry<F>,
+        line: usize,
+    ) -> Result<Tensor<'g, F>, SerializationError> {
+        let parse_error = |message| SerializationError::Parse { line, message };
+        let mut builder = Tensor::builder();
+        let mut num_inputs = 0;
+        let mut attributes = Vec::new();
+        let mut name = None;
+        for token in tokens {
+            if let Some(a) = token.strip_prefix('@') {
+                attributes.push(parse_attribute(a).map_err(parse_error)?);
+                continue;
+            }
+            let mut parts = token.splitn(2, '=');
+            let key = parts.next().unwrap();
+            let value = parts.next().unwrap_or("");
+            builder =
+                match key {
+                    "in" => {
+                        let inputs = parse_inputs(value, loaded).map_err(parse_error)?;
+                        num_inputs = inputs.len();
+                        builder.set_inputs(&inputs)
+                    }
+                    "idx" => {
+                        let indices: Vec<usize> = parse_list(value).map_err(parse_error)?;
+                        if indices.len() != num_inputs {
+                            return Err(parse_error("`idx` doesn't match `in`".to_string()));
+                        }
+                        builder.set_input_indices(&indices)
+                    }
+                    "bp" => builder
+                        .set_backprop_inputs(&parse_inputs(value, loaded).map_err(parse_error)?),
+                    "shape" => {
+                        let id = parse(value).map_err(parse_error)?;
+                        builder.set_shape(loaded_node(id, loaded).map_err(parse_error)?)
+                    }
+                    "known" => {
+                        let shape: Vec<isize> = parse_list(value).map_err(parse_error)?;
+                        if shape.iter().any(|&a| a != -1 && a <= 0) {
+                            return Err(parse_error(format!("invalid known shape `{}`", value)));
+                        }
+                        builder.set_known_shape(shape)
+                    }
+                    "placeholder" => builder.set_is_placeholder(true),
+                    "dtype" => match DType::from_name(value) {
+                        Some(dtype) => builder.set_dtype(dtype),
+                        None => return Err(parse_error(format!("unknown dtype `{}`", value))),
+                    },
+                    "nondiff" => builder.set_differentiable(false),
+                    "name" if !value.is_empty() => {
+                        name = Some(value.to_string());
+                        builder
+                    }
+                    "var" => {
+                        let arr = parse_array(value).map_err(parse_error)?;
+                        builder.set_variable_array(Arc::new(RwLock::new(arr)))
+                    }
+                    "const" => builder
+                        .set_constant_array(Arc::new(parse_array(value).map_err(parse_error)?)),
+                    _ => return Err(parse_error(format!("unexpected token `{}`", token))),
+                };
+        }
+        registry
+            .check_arity(op_name, num_inputs)
+            .map_err(parse_error)?;
+        let op = registry.construct(op_name, &attributes)?;
+        let ret = builder
+            .try_build_boxed(self, op)
+            .map_err(|e| parse_error(format!("{} rejects its inputs: {}", op_name, e)))?;
+        // Not created in the user's source.
+        unsafe {
+            self.access_node_mut(ret.id()).location = None;
+        }
+        if let Some(name) = name {
+            if !self.register_name(ret.id(), name.clone()) {
+                return Err(parse_error(format!("{} is already used", name)));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[test]
+fn test_save_and_load() {
+    use crate::tensor::Variable;
+    let rng = crate::ndarray_ext::ArrayRng::<f64>::default();
+    let x_arr = rng.standard_normal(&[4, 3]);
+    let w_arr = rng.standard_normal(&[3, 2]);
+    crate::with(|g: &mut crate::Graph<f64>| {
+        let x = g.placeholder(&[-1, 3]);
+        let w = g.variable(w_arr.clone());
+        let h = g.slice(g.matmul(x, w), &[0, 0], &[-1, 1]);
+        let y = g.softmax(g.concat(&[h, g.square(h) * 0.5], 1), 1);
+        let gw = g.grad(&[g.reduce_mean(y * y, &[0, 1], false)], &[w])[0];
+        let expected = g.eval(&[y, gw], &[x.given(x_arr.view())]);
+
+        let text = g.serialize(&[y, gw]).unwrap();
+        assert_eq!(text, g.serialize(&[y, gw]).unwrap());
+        let loaded = g.deserialize(&text, &OpRegistry::new()).unwrap();
+        assert_eq!(loaded.targets.len(), 2);
+        assert_eq!(loaded.placeholders.len(), 1);
+        assert_eq!(loaded.variables.len(), 1);
+        assert_eq!(*loaded.variables[0].lock_variable_array().unwrap(), w_arr);
+
+        let x2 = loaded.placeholders[0];
+        let actual = g.eval(&loaded.targets, &[x2.given(x_arr.view())]);
+        for (a, b) in actual.iter().zip(&expected) {
+            assert_eq!(a.as_ref().unwrap(), b.as_ref().unwrap());
+        }
+        // Serializing the loaded graph gives the same text.
+        assert_eq!(g.serialize(&loaded.targets).unwrap(), text);
+
+        match g.deserialize(&text, &OpRegistry::<f64>::empty()) {
+            Err(SerializationError::UnknownOp(_)) => {}
+            _ => panic!("expected UnknownOp"),
+        }
+        match g.deserialize(
+            &text.replace("MatMul in=", "MatMul in=99,"),
+            &OpRegistry::new(),
+        ) {
+            Err(SerializationError::Parse { .. }) => {}
+            _ => panic!("expected a parse error"),
+        }
+    });
+}
+
+#[test]
+fn test_save_and_load_dtypes() {
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let i = g.placeholder_int(&[-1]);
+        let y = g.gather(g.ones(&[4, 2]), i, 0);
+        let m = g.cast(y, DType::Bool);
+
+        let text = g.serialize(&[m]).unwrap();
+        assert!(text.contains("dtype=int"));
+        let loaded = g.deserialize(&text, &OpRegistry::new()).unwrap();
+        let (i2, m2) = (loaded.placeholders[0], loaded.targets[0]);
+        assert_eq!((i2.dtype(), m2.dtype()), (DType::Int, DType::Bool));
+
+        let value = ndarray::arr1(&[3, 0]);
+        let expected = m.eval_bool(&[i.given_int(value.view())]);
+        assert_eq!(m2.eval_bool(&[i2.given_int(value.view())]), expected);
+
+        let x = g.convert_to_tensor(ndarray::arr2(&[[1., 3.], [2., 0.]]));
+        let ys = [
+            g.argmax_int(x, 1, false),
+            g.shape_int(x),
+            g.greater_bool(x, x),
+        ];
+        let text = g.serialize(&ys).unwrap();
+        let loaded = g.deserialize(&text, &OpRegistry::new()).unwrap();
+        for (y, y2) in ys.iter().zip(&loaded.targets) {
+            assert_eq!(y.dtype(), y2.dtype());
+        }
+        assert_eq!(
+            loaded.targets[0].eval_int(&[]),
+            Ok(ndarray::arr1(&[1, 0]).into_dyn())
+        );
+    });
+}
+
+#[test]
+fn test_load_ops_without_dtype() {
+    // Graphs saved before the dtype attributes of these ops have float outputs.
+    let text = "autograd-graph 1
+node 0 Variable var=2;1.0,2.0
+node 1 Shape in=0
+node 2 Equal in=0,0
+node 3 ArgMax in=0 @i:0 @b:false
+targets 1 2 3
+";
+    crate::with(|g: &mut crate::Graph<f32>| {
+        let loaded = g.deserialize(text, &OpRegistry::new()).unwrap();
+        for y in &loaded.targets {
+            assert_eq!(y.dtype(), DType::Float);
+        }
+        assert_eq!(
+            loaded.targets[1].eval(&[]),
+            Ok(ndarray::arr1(&[1., 1.]).into_dyn())
+        );
+    });
+}
+#[test]
+fn test_load_incompatible_shapes() {
+    let text = "autograd-graph 1
+node 0 Variable var=4,2;1.0,1.0,1.0,1.0,1.0,1.0,1.0,1.0
+node 1 Variable var=3,2;1.0,1.0,1.0,1.0,1.0,1.0
+node 2 MatMul in=0,1 @b:false @b:false
+targets 2
+";
+    crate::with(
+        |g: &mut crate::Graph<f32>| match g.deserialize(text, &OpRegistry::new()) {
+            Err(SerializationError::Parse { line: 4, message }) => {
+                assert!(message.starts_with("MatMul rejects its inputs"))
+            }
+            _ => panic!("expected a parse error"),
+        },
+    );
+}
+
+#[test]
+fn test_load_wrong_arity() {
+    let text = "autograd-graph 1
+node 0 Variable var=3,2;1.0,1.0,1.0,1.0,1.0,1.0
+node 1 MatMul in=0 @b:false @b:false
+targets 1
+";
+    crate::with(|g: &mut crate::Graph<f32>| {
+        match g.deserialize(text, &OpRegistry::new()) {
+            Err(SerializationError::Parse { line: 3, message }) => {
+                assert_eq!(message, "MatMul takes 2 inputs, not 1")
+            }
+            _ => panic!("expected a parse error"),
+        }
+        let text = text.replace("MatMul in=0 @b:false", "Concat @i:0");
+        match g.deserialize(&text, &OpRegistry::new()) {
+            Err(SerializationError::Parse { message, .. }) => {
+                assert_eq!(message, "Concat takes at least 1 inputs, not 0")
+            }
+            _ => panic!("expected a parse error"),
+        }
+    });
+}
@@ -1,14 +1,17 @@
 //! Defining things related to `ag::Tensor`.
+use crate::ndarray_ext::{BoolArray, IntArray};
 use crate::op;
 use crate::Float;
 use crate::{NdArray, NdArrayView};
 
 use crate::graph::Graph;
 use crate::op::GradientContext;
+use crate::runtime::{AnyArray, FeedValue};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::ops::{Add, Div, Mul, Sub};
+use std::panic::Location;
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 /// Lazy N-dimensional array.
@@ -90,6 +93,65 @@ impl<'graph, 'tensor, F: Float> Tensor<'graph, F> {
         ret.remove(0)
     }
 
+    /// Evaluates this integer tensor as an `ndarray::Array<i64, ndarray::IxDyn>`.
+    ///
+    /// Unlike `eval`, which converts the values of non-float tensors into `F`, this returns
+    /// them exactly. Fails with `OpError::TypeUnsupported` unless `dtype()` is `DType::Int`.
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///    let a = g.cast(g.convert_to_tensor(array![1.5, -2.5]), ag::DType::Int);
+    ///    assert_eq!(a.eval_int(&[]), Ok(array![1, -2].into_dyn()));
+    ///    assert!(a.eval_bool(&[]).is_err());
+    /// });
+    /// ```
+    pub fn eval_int<'v>(
+        &'tensor self,
+        feeds: &'v [crate::runtime::Feed<'v, F>],
+    ) -> Result<IntArray, crate::EvalError> {
+        match self.eval_any(feeds)? {
+            AnyArray::Int(arr) => Ok(arr),
+            _ => Err(self.dtype_error(DType::Int)),
+        }
+    }
+
+    /// Evaluates this boolean tensor as an `ndarray::Array<bool, ndarray::IxDyn>`.
+    ///
+    /// Fails with `OpError::TypeUnsupported` unless `dtype()` is `DType::Bool`.
+    /// See also `eval_int`.
+    pub fn eval_bool<'v>(
+        &'tensor self,
+        feeds: &'v [crate::runtime::Feed<'v, F>],
+    ) -> Result<BoolArray, crate::EvalError> {
+        match self.eval_any(feeds)? {
+            AnyArray::Bool(arr) => Ok(arr),
+            _ => Err(self.dtype_error(DType::Bool)),
+        }
+    }
+
+    fn eval_any<'v>(
+        &'tensor self,
+        feeds: &'v [crate::runtime::Feed<'v, F>],
+    ) -> Result<AnyArray<F>, crate::EvalError> {
+        let (mut ret, _) = self
+            .graph
+            .eval_internal(&[self], feeds, self.graph.exec_options());
+        debug_assert_eq!(ret.len(), 1);
+        ret.remove(0)
+    }
+
+    fn dtype_error(&self, expected: DType) -> crate::EvalError {
+        crate::EvalError::OpError(op::OpError::TypeUnsupported(format!(
+            "{} has dtype {}, not {}",
+            self.inner().label(),
+            self.dtype(),
+            expected
+        )))
+    }
+
     /// Retruns a `Feed` assigning a given value to this (placeholder) tensor.
     ///
     /// Ensure that the return value is passed to `ag::Eval`, `ag::eval` or `Tensor::eval`.
@@ -116,7 +178,42 @@ impl<'graph, 'tensor, F: Float> Tensor<'graph, F> {
             self.is_placeholder(),
             "Receiver of Tensor::given must be a placeholder."
         );
-        crate::runtime::Feed::new(self.id(), value.into_dyn())
+        crate::runtime::Feed::new(self.id(), FeedValue::Float(value.into_dyn()))
+    }
+
+    /// Same as `given` but for the placeholders of `DType::Int`.
+    ///
+    /// ```
+    /// use ndarray::array;
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let i = g.placeholder_int(&[2]);
+    ///     let value = array![16777217, -1];  // 2^24 + 1 is not representable in f32
+    ///     assert_eq!(i.eval_int(&[i.given_int(value.view())]), Ok(value.into_dyn()));
+    /// });
+    /// ```
+    pub fn given_int<D>(self, value: ndarray::ArrayView<i64, D>) -> crate::runtime::Feed<F>
+    where
+        D: ndarray::Dimension,
+    {
+        assert!(
+            self.is_placeholder(),
+            "Receiver of Tensor::given_int must be a placeholder."
+        );
+        crate::runtime::Feed::new(self.id(), FeedValue::Int(value.into_dyn()))
+    }
+
+    /// Same as `given` but for the placeholders of `DType::Bool`.
+    pub fn given_bool<D>(self, value: ndarray::ArrayView<bool, D>) -> crate::runtime::Feed<F>
+    where
+        D: ndarray::Dimension,
+    {
+        assert!(
+            self.is_placeholder(),
+            "Receiver of Tensor::given_bool must be a placeholder."
+        );
+        crate::runtime::Feed::new(self.id(), FeedValue::Bool(value.into_dyn()))
     }
 
     #[inline]
@@ -130,9 +227,11 @@ impl<'graph, 'tensor, F: Float> Tensor<'graph, F> {
             constant_array: None,
             variable_array: None,
             is_placeholder: false,
+            is_subgraph_param: false,
             input_indices: None,
             backprop_inputs: None,
             known_shape: None,
+            dtype: None,
         }
     }
 
@@ -167,6 +266,8 @@ impl<'graph, 'tensor, F: Float> Tensor<'graph, F> {
 
     /// Sets a hook that displays the evaluation result of the receiver tensor to stderr.
     ///
+    /// The value is prefixed with the name of the tensor if it's named.
+    ///
     /// ```
     /// use autograd as ag;
     ///
@@ -181,7 +282,10 @@ impl<'graph, 'tensor, F: Float> Tensor<'graph, F> {
     /// ```
     #[inline]
     pub fn show(self) -> Tensor<'graph, F> {
-        self.register_hook(crate::hook::Show)
+        match self.name() {
+            Some(name) => self.register_hook(crate::hook::ShowWith(format!("{}:", name))),
+            None => self.register_hook(crate::hook::Show),
+        }
     }
 
     /// Sets a hook that displays the evaluation result of the receiver tensor to stderr, with given prefix.
@@ -202,11 +306,13 @@ impl<'graph, 'tensor, F: Float> Tensor<'graph, F> {
     /// ```
     #[inline]
     pub fn show_with(self, what: &'static str) -> Tensor<'graph, F> {
-        self.register_hook(crate::hook::ShowWith(what))
+        self.register_hook(crate::hook::ShowWith(what.to_string()))
     }
 
     /// Sets a hook that displays the shape of the evaluated receiver tensor to stderr.
     ///
+    /// The shape is prefixed with the name of the tensor if it's named.
+    ///
     /// ```
     /// use autograd as ag;
     ///
@@ -218,7 +324,10 @@ impl<'graph, 'tensor, F: Float> Tensor<'graph, F> {
     /// ```
     #[inline]
     pub fn show_shape(self) -> Tensor<'graph, F> {
-        self.register_hook(crate::hook::ShowShape)
+        match self.name() {
+            Some(name) => self.register_hook(crate::hook::ShowShapeWith(format!("{}:", name))),
+            None => self.register_hook(crate::hook::ShowShape),
+        }
     }
 
     /// Sets a hook that displays the shape of the evaluated receiver tensor to stderr, with given prefix.
@@ -235,7 +344,7 @@ impl<'graph, 'tensor, F: Float> Tensor<'graph, F> {
     /// ```
     #[inline]
     pub fn show_shape_with(self, what: &'static str) -> Tensor<'graph, F> {
-        self.register_hook(crate::hook::ShowShapeWith(what))
+        self.register_hook(crate::hook::ShowShapeWith(what.to_string()))
     }
 
     /// Sets a hook that displays the given string after evaluation of the receiver tensor.
@@ -281,6 +390,115 @@ impl<'graph, 'tensor, F: Float> Tensor<'graph, F> {
         self.inner().id()
     }
 
+    /// Names this tensor `name` prefixed with the current name scopes.
+    ///
+    /// Named tensors can be looked up with `Graph::tensor_by_name`, and named placeholders
+    /// can be fed with `Feed::named`. Names show up in `Debug`, error messages and hooks.
+    ///
+    /// Panics if another tensor in the graph has the same name, or `name` is empty or
+    /// contains whitespace.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let y = g.sigmoid(g.zeros(&[2])).with_name("y");
+    ///     assert_eq!(y.name(), Some("y"));
+    ///     assert!(format!("{:?}", y).contains("y"));
+    /// });
+    /// ```
+    pub fn with_name(self, name: &str) -> Tensor<'graph, F> {
+        assert!(
+            !name.is_empty() && !name.contains(char::is_whitespace),
+            "Tensor::with_name: invalid name {:?}",
+            name
+        );
+        let name = self.graph.scoped_name(name);
+        if !self.graph.register_name(self.id(), name.clone()) {
+            panic!("Tensor::with_name: {} is already used", name);
+        }
+        self
+    }
+
+    /// Returns the name of this tensor including the name scopes, if any.
+    #[inline]
+    pub fn name(&self) -> Option<&str> {
+        self.inner().name.as_deref()
+    }
+
+    /// Returns where in the source this tensor was created, if known.
+    ///
+    /// This is the caller of the `Graph` method (or the operator) creating this tensor.
+    /// Tensors loaded with `Graph::load` don't have the locations.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let y = g.sigmoid(g.zeros(&[2]));
+    ///     assert_eq!(y.location().unwrap().line(), line!() - 1);
+    /// });
+    /// ```
+    #[inline]
+    pub fn location(&self) -> Option<&'static Location<'static>> {
+        self.inner().location
+    }
+
+    /// Returns the element type of this tensor.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.zeros(&[2]);
+    ///     assert_eq!(x.dtype(), ag::DType::Float);
+    ///     assert_eq!(g.cast(x, ag::DType::Bool).dtype(), ag::DType::Bool);
+    /// });
+    /// ```
+    #[inline]
+    pub fn dtype(&self) -> DType {
+        self.inner().dtype
+    }
+
+    /// Returns the shape of this tensor inferred when it was built, using `-1` for unknown dims.
+    ///
+    /// Returns `None` if even the rank is unknown. See also `Op::infer_shape`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.placeholder(&[-1, 3]);
+    ///     let w = g.zeros(&[3, 4]);
+    ///     let y = g.matmul(x, w);
+    ///     assert_eq!(y.static_shape(), Some(vec![-1, 4]));
+    ///     assert_eq!(g.reduce_sum(y, &[1], false).static_shape(), Some(vec![-1]));
+    /// });
+    /// ```
+    #[inline]
+    pub fn static_shape(&self) -> Option<Vec<isize>> {
+        self.inner().known_shape.as_ref().map(|s| s.get().to_vec())
+    }
+
+    /// Returns the error of `Op::infer_shape` or `Op::output_dtype` if the op rejected the shapes
+    /// or dtypes of the inputs when this tensor was built.
+    ///
+    /// Such a tensor is created anyway, and its evaluations fail with this error.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::with(|g: &mut ag::Graph<f32>| {
+    ///     let x = g.placeholder(&[-1, 3]);
+    ///     assert!(g.matmul(x, g.zeros(&[3, 4])).shape_error().is_none());
+    ///     assert!(g.matmul(x, g.zeros(&[4, 5])).shape_error().is_some());
+    /// });
+    /// ```
+    #[inline]
+    pub fn shape_error(&self) -> Option<&op::OpError> {
+        self.inner().build_error.as_ref()
+    }
+
     #[inline]
     /// Returns true if this node has no incoming nodes.
     pub fn is_source(&self) -> bool {
@@ -344,6 +562,67 @@ impl<'b, T: Float> AsRef<Tensor<'b, T>> for Tensor<'b, T> {
     }
 }
 
+/// Element type of a tensor.
+///
+/// The tensors of a `Graph<F>` are float tensors of `F` unless created by e.g.
+/// `Graph::placeholder_int` or `Graph::cast`. Integer and boolean tensors hold `i64` and
+/// `bool` elements, which are evaluated exactly with `Tensor::eval_int` and `Tensor::eval_bool`.
+///
+/// Most ops accept only float tensors, which is checked when the tensors are built
+/// (see `Op::output_dtype`). Integer tensors can be used as the indices of `Graph::gather`
+/// and the labels of `Graph::sparse_softmax_cross_entropy`; otherwise, convert them with
+/// `Graph::cast`. Integer and boolean tensors are never differentiable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DType {
+    /// `F` of the graph
+    Float,
+    /// `i64`
+    Int,
+    /// `bool`
+    Bool,
+}
+
+impl DType {
+    // Index of this in `Attribute::Int`.
+    pub(crate) fn index(self) -> i64 {
+        match self {
+            DType::Float => 0,
+            DType::Int => 1,
+            DType::Bool => 2,
+        }
+    }
+
+    pub(crate) fn from_index(index: i64) -> Option<Self> {
+        match index {
+            0 => Some(DType::Float),
+            1 => Some(DType::Int),
+            2 => Some(DType::Bool),
+            _ => None,
+        }
+    }
+
+    // Parses the name written by `Display`.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "float" => Some(DType::Float),
+            "int" => Some(DType::Int),
+            "bool" => Some(DType::Bool),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DType::Float => "float",
+            DType::Int => "int",
+            DType::Bool => "bool",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 pub(crate) struct TensorInternal<T: Float> {
     pub(crate) id: usize,
 
@@ -372,6 +651,9 @@ pub(crate) struct TensorInternal<T: Float> {
     // This tensor is placeholder or not.
     pub(crate) is_placeholder: bool,
 
+    // This is true if this tensor is a placeholder filled by a control flow op.
+    pub(crate) is_subgraph_param: bool,
+
     // This is true if this tensor can have gradient for any objectives.
     pub(crate) is_differentiable: bool,
 
@@ -389,6 +671,18 @@ pub(crate) struct TensorInternal<T: Float> {
     /// Static shape of this tensor.
     /// Each dim size is *signed* for placeholders.
     pub(crate) known_shape: Option<KnownShape>,
+
+    /// Name given with `Tensor::with_name`, including the name scopes.
+    pub(crate) name: Option<String>,
+
+    /// Where in the source this tensor was created.
+    pub(crate) location: Option<&'static Location<'static>>,
+
+    /// Element type of the outputs.
+    pub(crate) dtype: DType,
+
+    /// Error of the op's shape or dtype inference, which fails the evaluations of this tensor.
+    pub(crate) build_error: Option<op::OpError>,
 }
 
 impl<T: Float> TensorInternal<T> {
@@ -463,13 +757,34 @@ impl<T: Float> TensorInternal<T> {
     }
 
     #[inline]
-    pub(crate) fn validate_feed_shape(&self, shape: &[usize]) {
-        if !self.known_shape.as_ref().unwrap().validate(shape) {
-            panic!(
-                "Shape error: placeholder required {:?}, but got {:?}",
-                self.known_shape.as_ref().unwrap().get(),
-                shape
-            );
+    pub(crate) fn validate_feed(&self, value: &FeedValue<T>) -> Result<(), crate::EvalError> {
+        if value.dtype() != self.dtype {
+            return Err(crate::EvalError::FeedTypeMismatch {
+                id: self.id,
+                name: self.name.clone(),
+                expected: self.dtype,
+                actual: value.dtype(),
+            });
+        }
+        let shape = value.shape();
+        match self.known_shape {
+            Some(ref known_shape) if !known_shape.validate(shape) => {
+                Err(crate::EvalError::FeedShapeMismatch {
+                    id: self.id,
+                    name: self.name.clone(),
+                    expected: known_shape.get().to_vec(),
+                    actual: shape.to_vec(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Name of this tensor in messages: the given name, or the op name and the id.
+    pub(crate) fn label(&self) -> String {
+        match self.name {
+            Some(ref name) => name.clone(),
+            None => format!("{}#{}", op::short_op_name(self.op.name()), self.id),
         }
     }
 
@@ -489,8 +804,9 @@ impl<T: Float> fmt::Debug for TensorInternal<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Node name: {}, id: {}, num of inputs: {}, in-edges: {:?}",
+            "Node name: {}, tensor name: {:?}, id: {}, num of inputs: {}, in-edges: {:?}",
             self.op.name(),
+            self.name,
             self.id(),
             self.in_edges.len(),
             self.in_edges
@@ -498,6 +814,12 @@ impl<T: Float> fmt::Debug for TensorInternal<T> {
     }
 }
 
+impl<'graph, T: Float> fmt::Debug for Tensor<'graph, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner().fmt(f)
+    }
+}
+
 // empty implementation
 impl<T: Float> Eq for TensorInternal<T> {}
 
@@ -525,7 +847,11 @@ impl<T: Float> AsRef<TensorInternal<T>> for TensorInternal<T> {
 
 impl<T: Float> fmt::Display for TensorInternal<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "name={}", self.op.name(),)
+        write!(f, "name={}", self.op.name())?;
+        match self.name {
+            Some(ref name) => write!(f, ", tensor name={}", name),
+            None => Ok(()),
+        }
     }
 }
 
@@ -611,16 +937,17 @@ pub struct TensorBuilder<T: Float> {
     in_edges: op::InputArray<Input>,
     can_have_gradient: bool,
     is_placeholder: bool,
+    is_subgraph_param: bool,
     constant_array: Option<Arc<NdArray<T>>>,
     variable_array: Option<Arc<RwLock<NdArray<T>>>>,
     input_indices: Option<op::InputArray<usize>>,
     backprop_inputs: Option<op::InputArray<Input>>,
     known_shape: Option<KnownShape>,
+    dtype: Option<DType>,
 }
 
 pub(crate) struct KnownShape {
     shape: Vec<isize>,
-    #[allow(dead_code)]
     is_fully_defined: bool,
 }
 
@@ -641,6 +968,16 @@ impl KnownShape {
         }
     }
 
+    // Same as `new` but allows zero-sized dims, which can appear in inferred shapes.
+    #[inline]
+    pub(crate) fn inferred(shape: Vec<isize>) -> Self {
+        let is_fully_defined = shape.iter().all(|&a| a >= 0);
+        Self {
+            shape,
+            is_fully_defined,
+        }
+    }
+
     #[inline]
     pub fn get(&self) -> &[isize] {
         self.shape.as_slice()
@@ -651,7 +988,7 @@ impl KnownShape {
             return false;
         }
         for (&i, &u) in self.shape.iter().zip(target) {
-            if i > 0 && i as usize != u {
+            if i >= 0 && i as usize != u {
                 return false;
             }
         }
@@ -659,7 +996,6 @@ impl KnownShape {
     }
 
     #[inline]
-    #[allow(dead_code)]
     pub fn is_fully_defined(&self) -> bool {
         self.is_fully_defined
     }
@@ -679,6 +1015,34 @@ fn test_build() {
     });
 }
 
+#[test]
+fn test_try_build() {
+    use crate::ops::dot_ops::MatMul;
+    crate::with(|s: &mut crate::Graph<f32>| {
+        let a = s.zeros(&[4, 2]);
+        let b = s.zeros(&[3, 2]);
+        let num_nodes = s.num_nodes();
+        let matmul = MatMul {
+            transpose_a: false,
+            transpose_b: false,
+        };
+        let c = Tensor::builder()
+            .set_ro_inputs(&[&a, &b])
+            .try_build(s, matmul);
+        assert!(matches!(c, Err(op::OpError::IncompatibleShape(_))));
+        assert_eq!(s.num_nodes(), num_nodes);
+
+        let matmul = MatMul {
+            transpose_a: false,
+            transpose_b: true,
+        };
+        let c = Tensor::builder()
+            .set_ro_inputs(&[&a, &b])
+            .try_build(s, matmul);
+        assert_eq!(c.unwrap().static_shape(), Some(vec![4, 3]));
+    });
+}
+
 impl<'tensor, 'graph, T: Float> TensorBuilder<T> {
     #[inline]
     pub(crate) fn set_known_shape(mut self, s: Vec<isize>) -> TensorBuilder<T> {
@@ -686,6 +1050,20 @@ impl<'tensor, 'graph, T: Float> TensorBuilder<T> {
         self
     }
 
+    // Same as `set_known_shape` but allows zero-sized dims, for shapes inferred from others.
+    #[inline]
+    pub(crate) fn set_inferred_shape(mut self, s: Vec<isize>) -> TensorBuilder<T> {
+        self.known_shape = Some(KnownShape::inferred(s));
+        self
+    }
+
+    // Sets the dtype instead of `Op::output_dtype`, e.g. for placeholders.
+    #[inline]
+    pub(crate) fn set_dtype(mut self, dtype: DType) -> TensorBuilder<T> {
+        self.dtype = Some(dtype);
+        self
+    }
+
     #[inline]
     pub(crate) fn set_shape(mut self, s: &Tensor<'graph, T>) -> TensorBuilder<T> {
         self.shape = Some(s.id());
@@ -727,6 +1105,12 @@ impl<'tensor, 'graph, T: Float> TensorBuilder<T> {
         self
     }
 
+    #[inline]
+    pub(crate) fn set_is_subgraph_param(mut self, a: bool) -> TensorBuilder<T> {
+        self.is_subgraph_param = a;
+        self
+    }
+
     #[inline]
     pub(crate) fn set_constant_array(mut self, a: Arc<NdArray<T>>) -> TensorBuilder<T> {
         self.constant_array = Some(a);
@@ -756,10 +1140,125 @@ impl<'tensor, 'graph, T: Float> TensorBuilder<T> {
 
     #[inline]
     /// Finalizes this builder and creates a tensor with given `Op` in the graph.
+    ///
+    /// If the op rejects the shapes or dtypes of the inputs, the tensor is created anyway and
+    /// its evaluations fail with that error, which `Tensor::shape_error` returns; use `try_build`
+    /// to get it here instead.
+    #[track_caller]
     pub fn build<O>(self, graph: &'graph Graph<T>, op: O) -> Tensor<'graph, T>
     where
         O: op::Op<T> + 'static,
     {
+        self.build_boxed(graph, Box::new(op))
+    }
+
+    /// Same as `build` but returns the error of `Op::infer_shape` or `Op::output_dtype`,
+    /// in which case no tensor is created.
+    #[track_caller]
+    pub fn try_build<O>(
+        self,
+        graph: &'graph Graph<T>,
+        op: O,
+    ) -> Result<Tensor<'graph, T>, op::OpError>
+    where
+        O: op::Op<T> + 'static,
+    {
+        self.try_build_boxed(graph, Box::new(op))
+    }
+
+    /// Same as `try_build` but takes a boxed `Op`.
+    #[track_caller]
+    pub(crate) fn try_build_boxed(
+        mut self,
+        graph: &'graph Graph<T>,
+        op: Box<dyn op::Op<T>>,
+    ) -> Result<Tensor<'graph, T>, op::OpError> {
+        let input_indices = self.input_indices();
+        let (known_shape, dtype) = self.infer(graph, &*op, &input_indices)?;
+        Ok(self.install(graph, op, input_indices, known_shape, dtype, None))
+    }
+
+    /// Same as `build` but takes a boxed `Op`.
+    #[track_caller]
+    pub(crate) fn build_boxed(
+        mut self,
+        graph: &'graph Graph<T>,
+        op: Box<dyn op::Op<T>>,
+    ) -> Tensor<'graph, T> {
+        let input_indices = self.input_indices();
+        match self.infer(graph, &*op, &input_indices) {
+            Ok((known_shape, dtype)) => {
+                self.install(graph, op, input_indices, known_shape, dtype, None)
+            }
+            Err(e) => {
+                let dtype = self.dtype.unwrap_or(DType::Float);
+                self.install(graph, op, input_indices, None, dtype, Some(e))
+            }
+        }
+    }
+
+    fn input_indices(&self) -> op::InputArray<usize> {
+        if let Some(ref a) = self.input_indices {
+            assert_eq!(
+                a.len(),
+                self.in_edges.len(),
+                "input_indices.len() must match inputs length"
+            );
+            a.clone()
+        } else {
+            smallvec::smallvec!(0; self.in_edges.len())
+        }
+    }
+
+    // Infers the known shape and the dtype of the new tensor unless they are given.
+    fn infer(
+        &mut self,
+        graph: &Graph<T>,
+        op: &dyn op::Op<T>,
+        input_indices: &[usize],
+    ) -> Result<(Option<KnownShape>, DType), op::OpError> {
+        let known_shape = match self.known_shape.take() {
+            Some(known_shape) => Some(known_shape),
+            None => {
+                let shape = if let Some(ref arr) = self.constant_array {
+                    Some(arr.shape().iter().map(|&a| a as isize).collect())
+                } else if let Some(ref arr) = self.variable_array {
+                    Some(
+                        arr.read()
+                            .unwrap()
+                            .shape()
+                            .iter()
+                            .map(|&a| a as isize)
+                            .collect(),
+                    )
+                } else {
+                    let ctx = op::ShapeContext::new(graph, &self.in_edges, input_indices);
+                    op.infer_shape(&ctx)?
+                };
+                shape.map(KnownShape::inferred)
+            }
+        };
+
+        let dtype = match self.dtype {
+            Some(dtype) => dtype,
+            None => {
+                let ctx = op::ShapeContext::new(graph, &self.in_edges, input_indices);
+                op.output_dtype(&ctx)?
+            }
+        };
+        Ok((known_shape, dtype))
+    }
+
+    #[track_caller]
+    fn install(
+        self,
+        graph: &'graph Graph<T>,
+        op: Box<dyn op::Op<T>>,
+        input_indices: op::InputArray<usize>,
+        known_shape: Option<KnownShape>,
+        dtype: DType,
+        build_error: Option<op::OpError>,
+    ) -> Tensor<'graph, T> {
         let rank = if self.in_edges.is_empty() {
             0
         } else {
@@ -771,21 +1270,10 @@ impl<'tensor, 'graph, T: Float> TensorBuilder<T> {
                 .unwrap_or(0)
         };
 
-        let input_indices = if let Some(a) = self.input_indices {
-            assert_eq!(
-                a.len(),
-                self.in_edges.len(),
-                "input_indices.len() must match inputs length"
-            );
-            a
-        } else {
-            smallvec::smallvec!(0; self.in_edges.len())
-        };
-
         let new = TensorInternal {
             // `id` is set in `Graph::install`
             id: usize::default(),
-            op: Box::new(op),
+            op,
             in_edges: self.in_edges,
             top_rank: rank,
             shape: self.shape,
@@ -793,10 +1281,15 @@ impl<'tensor, 'graph, T: Float> TensorBuilder<T> {
             variable_array: self.variable_array,
             constant_array: self.constant_array,
             is_placeholder: self.is_placeholder,
-            is_differentiable: self.can_have_gradient,
+            is_subgraph_param: self.is_subgraph_param,
+            is_differentiable: self.can_have_gradient && dtype == DType::Float,
             input_indices,
             backprop_inputs: self.backprop_inputs,
-            known_shape: self.known_shape,
+            known_shape,
+            name: None,
+            location: Some(Location::caller()),
+            dtype,
+            build_error,
         };
         Tensor {
             inner_: graph.install(new),
@@ -818,6 +1311,7 @@ macro_rules! impl_bin_op_between_tensor_and_float_trait {
         // Tensor op Float
         impl<'b, T: Float> $trt<T> for Tensor<'b, T> {
             type Output = Tensor<'b, T>;
+            #[track_caller]
             fn $func(self, rhs: T) -> Self::Output {
                 self.graph.$func(&self, &self.graph.scalar(rhs))
             }
@@ -826,6 +1320,7 @@ macro_rules! impl_bin_op_between_tensor_and_float_trait {
         // &Tensor op Float
         impl<'l, 'b, T: Float> $trt<T> for &'l Tensor<'b, T> {
             type Output = Tensor<'b, T>;
+            #[track_caller]
             fn $func(self, rhs: T) -> Self::Output {
                 self.graph.$func(self, &self.graph.scalar(rhs))
             }
@@ -838,6 +1333,7 @@ macro_rules! impl_bin_op_between_tensor_and_primitive {
         // primitive op Tensor
         impl<'r, 'b, T: Float> $trt<Tensor<'b, T>> for $scalar_type {
             type Output = Tensor<'b, T>;
+            #[track_caller]
             fn $func(self, rhs: Tensor<'b, T>) -> Self::Output {
                 rhs.graph
                     .$func(&rhs.graph.scalar(T::from(self).unwrap()), &rhs)
@@ -847,6 +1343,7 @@ macro_rules! impl_bin_op_between_tensor_and_primitive {
         // primitive op &Tensor
         impl<'r, 'b, T: Float> $trt<&'r Tensor<'b, T>> for $scalar_type {
             type Output = Tensor<'b, T>;
+            #[track_caller]
             fn $func(self, rhs: &'r Tensor<'b, T>) -> Self::Output {
                 rhs.graph
                     .$func(&rhs.graph.scalar(T::from(self).unwrap()), rhs)
@@ -875,6 +1372,7 @@ macro_rules! impl_bin_op_between_tensors {
         // Tensor op Tensor
         impl<'b, T: Float> $trt for Tensor<'b, T> {
             type Output = Tensor<'b, T>;
+            #[track_caller]
             fn $func(self, rhs: Tensor<'b, T>) -> Self::Output {
                 self.graph.$func(&self, &rhs)
             }
@@ -883,6 +1381,7 @@ macro_rules! impl_bin_op_between_tensors {
         // Tensor op &Tensor
         impl<'r, 'b, T: Float> $trt<&'r Tensor<'b, T>> for Tensor<'b, T> {
             type Output = Tensor<'b, T>;
+            #[track_caller]
             fn $func(self, rhs: &'r Tensor<'b, T>) -> Self::Output {
                 self.graph.$func(&self, rhs)
             }
@@ -891,6 +1390,7 @@ macro_rules! impl_bin_op_between_tensors {
         // &Tensor op Tensor
         impl<'l, 'b, T: Float> $trt<Tensor<'b, T>> for &'l Tensor<'b, T> {
             type Output = Tensor<'b, T>;
+            #[track_caller]
             fn $func(self, rhs: Tensor<'b, T>) -> Self::Output {
                 self.graph.$func(self, &rhs)
             }
@@ -900,6 +1400,7 @@ macro_rules! impl_bin_op_between_tensors {
         // lifetime of the two tensors are unrelated
         impl<'l, 'r, 'b, T: Float> $trt<&'r Tensor<'b, T>> for &'l Tensor<'b, T> {
             type Output = Tensor<'b, T>;
+            #[track_caller]
             fn $func(self, rhs: &'r Tensor<'b, T>) -> Self::Output {
                 self.graph.$func(self, rhs)
             }
@@ -1012,6 +1513,7 @@ impl<'graph, F: Float> Constant<'graph, F, Arc<ndarray::Array<F, ndarray::IxDyn>
     for crate::graph::Graph<F>
 {
     #[inline]
+    #[track_caller]
     fn constant(&'graph self, arr: Arc<ndarray::Array<F, ndarray::IxDyn>>) -> Tensor<'graph, F> {
         Tensor::builder()
             .set_constant_array(arr)
@@ -1026,6 +1528,7 @@ macro_rules! impl_constant_dim {
             for crate::graph::Graph<F>
         {
             #[inline]
+            #[track_caller]
             fn constant(&'graph self, arr: ndarray::Array<F, $d>) -> Tensor<'graph, F> {
                 Tensor::builder()
                     .set_constant_array(Arc::new(arr.into_dyn()))
@@ -1040,6 +1543,7 @@ impl<'graph, F: Float> Variable<'graph, F, Arc<RwLock<ndarray::Array<F, ndarray:
     for crate::graph::Graph<F>
 {
     #[inline]
+    #[track_caller]
     fn variable(
         &'graph self,
         arr: Arc<RwLock<ndarray::Array<F, ndarray::IxDyn>>>,
@@ -1057,6 +1561,7 @@ macro_rules! impl_variable_dim {
             for crate::graph::Graph<F>
         {
             #[inline]
+            #[track_caller]
             fn variable(&'graph self, arr: ndarray::Array<F, $d>) -> Tensor<'graph, F> {
                 Tensor::builder()
                     .set_variable_array(Arc::new(RwLock::new(arr.into_dyn())))
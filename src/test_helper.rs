@@ -105,3 +105,154 @@ pub fn check_theoretical_grads<'s: 't, 't, 'v, T: Float, A>(
         }
     }
 }
+
+// Adds `delta` to the `i`-th element of the shared variable `var`.
+fn shift_variable<'s, T: Float>(var: &Tensor<'s, T>, i: usize, delta: T) {
+    let mut guard = var
+        .lock_variable_array_mut()
+        .expect("This is not a variable");
+    let elem = guard
+        .iter_mut()
+        .nth(i)
+        .expect("Index out of bounds of the variable");
+    *elem += delta;
+}
+
+// Evaluates `xs` with the perturbations `+eps` and `-eps` applied to `variables` by `shift`,
+// returning the central differences `(xs(+eps) - xs(-eps)) / 2eps` as flat vectors.
+// `variables` are restored afterwards.
+fn central_differences<'s, 'v, T: Float, A, S>(
+    xs: &[A],
+    variables: &[A],
+    feeds: &'v [Feed<'v, T>],
+    eps: T,
+    shift: S,
+) -> Vec<Vec<T>>
+where
+    A: AsRef<Tensor<'s, T>> + Copy,
+    S: Fn(T),
+{
+    let graph = xs[0].as_ref().graph;
+    let saved: Vec<_> = variables
+        .iter()
+        .map(|v| {
+            v.as_ref()
+                .lock_variable_array()
+                .expect("This is not a variable")
+                .clone()
+        })
+        .collect();
+    let restore = || {
+        for (v, arr) in variables.iter().zip(&saved) {
+            v.as_ref().lock_variable_array_mut().unwrap().assign(arr);
+        }
+    };
+    shift(eps);
+    let pos = graph.eval(xs, feeds);
+    restore();
+    shift(-eps);
+    let neg = graph.eval(xs, feeds);
+    restore();
+
+    let two = T::one() + T::one();
+    pos.into_iter()
+        .zip(neg)
+        .map(|(p, n)| {
+            let (p, n) = (p.unwrap(), n.unwrap());
+            p.iter()
+                .zip(n.iter())
+                .map(|(&p, &n)| (p - n) / (two * eps))
+                .collect()
+        })
+        .collect()
+}
+
+fn assert_close<T: Float>(numerical: &[T], theoretical: &[T], tol: T, what: &str) {
+    assert_eq!(
+        numerical.len(),
+        theoretical.len(),
+        "{} checking failed with mismatched sizes",
+        what
+    );
+    for (&n, &t) in numerical.iter().zip(theoretical) {
+        if (n - t).abs() > tol {
+            panic!(
+                "{} checking failed with too large error: numerical={}, theoretical={}",
+                what, n, t
+            );
+        }
+    }
+}
+
+/// Checks the validity of a block Hessian with finite difference trick.
+///
+/// `hessian[i][j]` must be the block of shape `(size of variables[i], size of variables[j])`
+/// as returned by `Graph::hessian`, and `gradients` the first-order gradients of the objective
+/// with respect to `variables`, which are differentiated numerically.
+/// For this test only, `variables` must be *shared* variables.
+pub fn check_theoretical_hessian<'s: 't, 't, 'v, T: Float, A>(
+    gradients: &'t [A],
+    hessian: &'t [Vec<A>],
+    variables: &'t [A],
+    feeds: &'v [Feed<'v, T>],
+    eps: T,
+    tol: T,
+) where
+    A: AsRef<Tensor<'s, T>> + Copy,
+{
+    let graph = variables[0].as_ref().graph;
+    for (i, row) in hessian.iter().enumerate() {
+        let blocks = graph.eval(row, feeds);
+        for (j, (var, block)) in variables.iter().zip(blocks).enumerate() {
+            let block = block
+                .unwrap()
+                .into_dimensionality::<ndarray::Ix2>()
+                .expect("Hessian blocks must be matrices");
+            // The k-th column of the block (i, j) is `d gradients[i] / d variables[j][k]`.
+            for (k, col) in block.axis_iter(ndarray::Axis(1)).enumerate() {
+                let num = central_differences(&gradients[i..=i], variables, feeds, eps, |d| {
+                    shift_variable(var.as_ref(), k, d)
+                });
+                let th: Vec<T> = col.iter().cloned().collect();
+                assert_close(&num[0], &th, tol, &format!("Hessian block ({}, {})", i, j));
+            }
+        }
+    }
+}
+
+/// Checks the validity of Hessian-vector products `hvps` with finite difference trick.
+///
+/// `hvps[i]` must be the product for `variables[i]` as returned by `Graph::hvp`, and `gradients`
+/// the first-order gradients of the objective with respect to `variables`, which are
+/// differentiated numerically along `vectors`.
+/// For this test only, `variables` must be *shared* variables.
+pub fn check_theoretical_hvps<'s: 't, 't, 'v, T: Float, A>(
+    gradients: &'t [A],
+    hvps: &'t [A],
+    variables: &'t [A],
+    vectors: &'t [A],
+    feeds: &'v [Feed<'v, T>],
+    eps: T,
+    tol: T,
+) where
+    A: AsRef<Tensor<'s, T>> + Copy,
+{
+    let graph = variables[0].as_ref().graph;
+    let vectors: Vec<_> = graph
+        .eval(vectors, feeds)
+        .into_iter()
+        .map(|v| v.unwrap())
+        .collect();
+    let theoretical = graph.eval(hvps, feeds);
+    let numerical = central_differences(gradients, variables, feeds, eps, |d| {
+        for (var, v) in variables.iter().zip(&vectors) {
+            for (k, &vk) in v.iter().enumerate() {
+                shift_variable(var.as_ref(), k, d * vk);
+            }
+        }
+    });
+    for (num, th) in numerical.iter().zip(theoretical) {
+        let th: Vec<T> = th.unwrap().iter().cloned().collect();
+        assert_close(num, &th, tol, "Hessian-vector product");
+    }
+}
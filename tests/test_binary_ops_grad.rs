@@ -147,3 +147,21 @@ fn expr7() {
         );
     });
 }
+
+#[test]
+fn broadcast_mul_div() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let a = g.variable(ndarray::arr2(&[[1., 2.], [3., 4.]]));
+        let b = g.variable(ndarray::arr2(&[[2.], [4.]]));
+        // The gradient of `b` sums up over the broadcast axis after the product.
+        let y = g.reduce_sum_to_scalar(a * a * b);
+        let gb = g.grad(&[y], &[b])[0];
+        assert_eq!(gb.eval(&[]), Ok(ndarray::arr2(&[[5.], [25.]]).into_dyn()));
+        let y = g.reduce_sum_to_scalar(a * a / b);
+        let gb = g.grad(&[y], &[b])[0];
+        assert_eq!(
+            gb.eval(&[]),
+            Ok(ndarray::arr2(&[[-5. / 4.], [-25. / 16.]]).into_dyn())
+        );
+    });
+}
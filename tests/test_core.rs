@@ -1,5 +1,6 @@
 extern crate autograd as ag;
 extern crate ndarray;
+use ag::tensor::Variable;
 
 struct MultiOutputOp;
 
@@ -57,3 +58,934 @@ fn test_hook() {
         println!("{:?}", ggx.eval(&[])); // => Some(4.)
     });
 }
+
+#[test]
+fn test_names() {
+    let x_value = ndarray::arr1(&[1., 2.]);
+    let mut text = String::new();
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let x = g.name_scope("model", || g.placeholder_named("x", &[2]));
+        let y = g.name_scope("model", || g.square(x).with_name("y"));
+        assert_eq!(x.name(), Some("model/x"));
+        assert_eq!(g.tensor_by_name("model/y").map(|t| t.id()), Some(y.id()));
+        assert!(g.tensor_by_name("y").is_none());
+        assert!(format!("{:?}", y).contains("model/y"));
+
+        let feeds = [ag::Feed::named("model/x", x_value.view())];
+        assert_eq!(y.eval(&feeds), Ok(ndarray::arr1(&[1., 4.]).into_dyn()));
+        text = g.serialize(&[y]).unwrap();
+    });
+    ag::with(|g: &mut ag::Graph<f64>| {
+        g.deserialize(&text, &ag::serialization::OpRegistry::new())
+            .unwrap();
+        let y = g.tensor_by_name("model/y").unwrap();
+        let feeds = [ag::Feed::named("model/x", x_value.view())];
+        assert_eq!(y.eval(&feeds), Ok(ndarray::arr1(&[1., 4.]).into_dyn()));
+    });
+}
+
+#[test]
+fn test_name_scope_panic() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            g.name_scope("model", || panic!("oops"))
+        }));
+        assert!(ret.is_err());
+        // The scope is left even though its closure panicked.
+        let x = g.placeholder_named("x", &[2]);
+        assert_eq!(x.name(), Some("x"));
+    });
+}
+
+#[test]
+#[should_panic(expected = "x is already used")]
+fn test_duplicate_names() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        g.placeholder_named("x", &[2]);
+        g.placeholder_named("x", &[2]);
+    });
+}
+
+#[test]
+fn test_static_shapes() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        let x = g.placeholder(&[-1, 3, 8, 8]);
+        let w = g.zeros(&[4, 3, 3, 3]);
+        let y = g.conv2d(x, w, 1, 2);
+        assert_eq!(y.static_shape(), Some(vec![-1, 4, 4, 4]));
+        let y = g.max_pool2d(y, 2, 0, 2);
+        assert_eq!(y.static_shape(), Some(vec![-1, 4, 2, 2]));
+        let y = g.reshape(y, &[-1, 16]);
+        assert_eq!(y.static_shape(), Some(vec![-1, 16]));
+        let z = g.matmul(y, g.ones(&[16, 10])) + g.zeros(&[1, 10]);
+        assert_eq!(z.static_shape(), Some(vec![-1, 10]));
+        let z = g.concat(&[z, z], 1);
+        assert_eq!(z.static_shape(), Some(vec![-1, 20]));
+        assert_eq!(
+            g.reduce_mean(z, &[0], true).static_shape(),
+            Some(vec![1, 20])
+        );
+        assert_eq!(g.transpose(z, &[1, 0]).static_shape(), Some(vec![20, -1]));
+        assert_eq!(
+            g.slice(z, &[0, 5], &[-1, 9]).static_shape(),
+            Some(vec![-1, 4])
+        );
+        assert_eq!(g.shape(z).static_shape(), Some(vec![2]));
+
+        // Gradients of fully-defined graphs have the shapes of the variables.
+        let v = g.variable(ag::ndarray_ext::zeros(&[16, 10]));
+        let loss = g.reduce_sum_to_scalar(g.matmul(g.ones(&[5, 16]), v));
+        assert_eq!(loss.static_shape(), Some(vec![]));
+        assert_eq!(g.grad(&[loss], &[v])[0].static_shape(), Some(vec![16, 10]));
+
+        // Actual shapes are consistent with the static ones.
+        let x_val = ag::ndarray_ext::zeros(&[2, 3, 8, 8]);
+        let z_val = z.eval(&[x.given(x_val.view())]).unwrap();
+        assert_eq!(z_val.shape(), &[2, 20]);
+    });
+}
+
+#[test]
+fn test_incompatible_static_shapes() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        let x = g.placeholder(&[-1, 3]);
+        // The error is available once built, before any evaluation.
+        let y = g.matmul(x, g.zeros(&[4, 5]));
+        match y.shape_error() {
+            Some(ag::op::OpError::IncompatibleShape(_)) => {}
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(y.static_shape(), None);
+        assert!(g.matmul(x, g.zeros(&[3, 5])).shape_error().is_none());
+        let z = g.concat(&[x, g.zeros(&[2, 4, 1])], 0);
+        assert!(z.shape_error().is_some());
+        let images = g.placeholder(&[-1, 3, 8, 8]);
+        let filters = g.zeros(&[4, 2, 3, 3]);
+        assert!(g.conv2d(images, filters, 1, 1).shape_error().is_some());
+
+        // The evaluations fail with it as well.
+        let x_val = ag::ndarray_ext::zeros(&[2, 3]);
+        match y.eval(&[x.given(x_val.view())]) {
+            Err(ag::EvalError::OpError(ag::op::OpError::IncompatibleShape(_))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn test_cond() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.variable(rng.standard_normal(&[2, 3]));
+        let w = g.variable(rng.standard_normal(&[3, 4]));
+        let p = g.placeholder(&[]);
+        let ys = g.cond(
+            p,
+            || vec![g.matmul(x, w), g.sigmoid(x)],
+            || vec![g.tanh(g.matmul(x, w)), x],
+        );
+        assert_eq!(ys[0].static_shape(), Some(vec![2, 4]));
+        assert_eq!(ys[1].static_shape(), Some(vec![2, 3]));
+
+        let on = ndarray::arr0(1.);
+        let off = ndarray::arr0(0.);
+        let ret = g.eval(&ys, &[p.given(on.view())]);
+        assert_eq!(ret[0], g.matmul(x, w).eval(&[]));
+        assert_eq!(ret[1], g.sigmoid(x).eval(&[]));
+        let ret = g.eval(&ys, &[p.given(off.view())]);
+        assert_eq!(ret[0], g.tanh(g.matmul(x, w)).eval(&[]));
+        assert_eq!(ret[1], x.eval(&[]));
+
+        // The branch not taken is not evaluated.
+        let bad = g.placeholder(&[-1, -1]);
+        let z = g.cond(p, || vec![x], || vec![g.reshape(bad, &[-1, 4])])[0];
+        let bad_val = ag::ndarray_ext::zeros(&[2, 3]);
+        assert_eq!(
+            z.eval(&[p.given(on.view()), bad.given(bad_val.view())]),
+            x.eval(&[])
+        );
+        assert!(z
+            .eval(&[p.given(off.view()), bad.given(bad_val.view())])
+            .is_err());
+
+        // Gradients through either branch
+        let y = ys[0];
+        let grads = g.grad(&[y], &[x, w]);
+        let feeds = [p.given(on.view())];
+        ag::test_helper::check_theoretical_grads(y, &grads, &[x, w], &feeds, 1e-3, 1e-3);
+        let feeds = [p.given(off.view())];
+        ag::test_helper::check_theoretical_grads(y, &grads, &[x, w], &feeds, 1e-3, 1e-3);
+    });
+}
+
+#[test]
+fn test_nested_cond() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.variable(rng.standard_normal(&[3]));
+        let p = g.placeholder(&[]);
+        let q = g.placeholder(&[]);
+        let y = g.cond(
+            p,
+            || g.cond(q, || vec![x * x], || vec![x * 3.]),
+            || vec![g.exp(x)],
+        )[0];
+        let gx = g.grad(&[y], &[x])[0];
+        let gx_of = |p_val: f64, q_val: f64| {
+            let (p_val, q_val) = (ndarray::arr0(p_val), ndarray::arr0(q_val));
+            gx.eval(&[p.given(p_val.view()), q.given(q_val.view())])
+        };
+        assert_eq!(gx_of(1., 1.), (x * 2.).eval(&[]));
+        assert_eq!(gx_of(1., 0.), Ok(ndarray::arr1(&[3., 3., 3.]).into_dyn()));
+        assert_eq!(gx_of(0., 1.), g.exp(x).eval(&[]));
+    });
+}
+
+#[test]
+fn test_control_flow_errors() {
+    struct Panic;
+
+    impl ag::op::Op<f64> for Panic {
+        fn compute(&self, _: &mut ag::op::ComputeContext<f64>) {
+            panic!("bad input");
+        }
+
+        fn grad(&self, _: &mut ag::op::GradientContext<f64>) {}
+    }
+
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let x = g.placeholder(&[3]);
+        let p = g.placeholder(&[]);
+        let x_val = ndarray::arr1(&[1., 2., 3.]).into_dyn();
+        let on = ndarray::arr0(1.);
+
+        // A tensor created in a branch can't be evaluated outside of it.
+        let mut inner = None;
+        let y = g.cond(
+            p,
+            || {
+                let z = x * 2.;
+                inner = Some(z);
+                vec![z]
+            },
+            || vec![x],
+        )[0];
+        let feeds = [p.given(on.view()), x.given(x_val.view())];
+        assert_eq!(y.eval(&feeds), (x * 2.).eval(&feeds));
+        match inner.unwrap().eval(&feeds) {
+            Err(ag::EvalError::SubgraphTensor { .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Errors other than `OpError` in a subgraph fail the control flow op.
+        let y = g.cond(
+            p,
+            || {
+                vec![ag::Tensor::builder()
+                    .set_inputs(&[ag::tensor::Input::new(&x)])
+                    .build(g, Panic)]
+            },
+            || vec![x],
+        )[0];
+        match y.eval(&feeds) {
+            Err(ag::EvalError::Subgraph { error, .. }) => match *error {
+                ag::EvalError::OpPanicked { ref message, .. } => assert_eq!(message, "bad input"),
+                ref e => panic!("unexpected error: {}", e),
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+    });
+}
+#[test]
+fn test_while_loop() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let h0 = g.variable(rng.standard_normal(&[2, 3]));
+        let w = g.variable(rng.standard_normal(&[3, 3]));
+        let n = g.placeholder(&[]);
+        let ys = g.while_loop(
+            |vars| g.lesser(vars[0], n),
+            |vars| vec![vars[0] + 1., g.tanh(g.matmul(vars[1], w))],
+            &[g.scalar(0.), h0],
+        );
+        // Only the ranks of the loop variables are known in the loop.
+        assert_eq!(ys[1].static_shape(), Some(vec![-1, 3]));
+        let y = ys[1];
+
+        // Same as the unrolled loop
+        let mut unrolled = h0;
+        for _ in 0..4 {
+            unrolled = g.tanh(g.matmul(unrolled, w));
+        }
+        let n_val = ndarray::arr0(4.);
+        let feeds = [n.given(n_val.view())];
+        let ret = g.eval(&[ys[0], y], &feeds);
+        assert_eq!(ret[0], Ok(ndarray::arr0(4.).into_dyn()));
+        assert!(ret[1]
+            .as_ref()
+            .unwrap()
+            .all_close(&unrolled.eval(&[]).unwrap(), 1e-12));
+
+        let grads = g.grad(&[y], &[h0, w]);
+        let expected = g.grad(&[unrolled], &[h0, w]);
+        for (grad, expected) in grads.iter().zip(&expected) {
+            let grad = grad.eval(&feeds).unwrap();
+            assert!(grad.all_close(&expected.eval(&[]).unwrap(), 1e-12));
+        }
+        ag::test_helper::check_theoretical_grads(y, &grads, &[h0, w], &feeds, 1e-6, 1e-5);
+
+        // The loop may not run at all.
+        let n_val = ndarray::arr0(0.);
+        let feeds = [n.given(n_val.view())];
+        assert_eq!(y.eval(&feeds), h0.eval(&[]));
+        assert_eq!(grads[0].eval(&feeds), Ok(ag::ndarray_ext::ones(&[2, 3])));
+        assert_eq!(grads[1].eval(&feeds), Ok(ag::ndarray_ext::zeros(&[3, 3])));
+
+        // Second-order gradients fail instead of being zero.
+        let ggw = g.grad(&[grads[0]], &[w])[0];
+        match ggw.eval(&feeds) {
+            Err(ag::EvalError::OpError(ag::op::OpError::Unsupported(_))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn test_scan() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let h0 = g.variable(rng.standard_normal(&[2, 3]));
+        let w = g.variable(rng.standard_normal(&[3, 3]));
+        // Batch of sequences of any length
+        let xs = g.placeholder(&[2, -1, 3]);
+        let ys = g.scan(
+            |states, x| vec![g.tanh(g.matmul(states[0], w) + x)],
+            &[h0],
+            xs,
+            1,
+        );
+        assert_eq!(ys[0].static_shape(), Some(vec![2, -1, 3]));
+        let y = g.reduce_sum(ys[0], &[1], false);
+
+        // Same as the unrolled steps
+        let xs_val = rng.standard_normal(&[2, 4, 3]);
+        let xs_var = g.variable(xs_val.clone());
+        let mut h = h0;
+        let mut steps = Vec::new();
+        for t in 0..4 {
+            h = g.tanh(g.matmul(h, w) + g.gather(xs_var, g.scalar(t as f64), 1));
+            steps.push(h);
+        }
+        let unrolled = g.add_n(&steps);
+        let feeds = [xs.given(xs_val.view())];
+        let stacked = ys[0].eval(&feeds).unwrap();
+        assert_eq!(stacked.shape(), &[2, 4, 3]);
+        for (t, step) in steps.iter().enumerate() {
+            let step = step.eval(&[]).unwrap();
+            assert!(stacked
+                .index_axis(ndarray::Axis(1), t)
+                .all_close(&step, 1e-12));
+        }
+
+        let grads = g.grad(&[y], &[h0, w, xs]);
+        let expected = g.grad(&[unrolled], &[h0, w, xs_var]);
+        for (grad, expected) in grads.iter().zip(&expected) {
+            let grad = grad.eval(&feeds).unwrap();
+            assert!(grad.all_close(&expected.eval(&[]).unwrap(), 1e-12));
+        }
+        ag::test_helper::check_theoretical_grads(y, &grads[..2], &[h0, w], &feeds, 1e-6, 1e-5);
+
+        // Shorter sequences, and the empty one
+        for &len in &[1, 0] {
+            let xs_val = rng.standard_normal(&[2, len, 3]);
+            let feeds = [xs.given(xs_val.view())];
+            assert_eq!(ys[0].eval(&feeds).unwrap().shape(), &[2, len, 3]);
+            assert_eq!(grads[2].eval(&feeds).unwrap().shape(), &[2, len, 3]);
+            if len == 0 {
+                assert_eq!(y.eval(&feeds), Ok(ag::ndarray_ext::zeros(&[2, 3])));
+                assert_eq!(grads[0].eval(&feeds), Ok(ag::ndarray_ext::zeros(&[2, 3])));
+            }
+        }
+
+        // Second-order gradients fail instead of being zero.
+        let ggw = g.grad(&[grads[1]], &[w])[0];
+        match ggw.eval(&[xs.given(xs_val.view())]) {
+            Err(ag::EvalError::OpError(ag::op::OpError::Unsupported(_))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn test_checkpoint() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.placeholder(&[-1, 2, 6, 6]);
+        let w1 = g.variable(rng.standard_normal(&[3, 2, 3, 3]));
+        let w2 = g.variable(rng.standard_normal(&[2, 3, 3, 3]));
+        let block = |x, w| g.max_pool2d(g.relu(g.conv2d(x, w, 1, 1)), 2, 0, 1);
+        let h = g.checkpoint(|| vec![block(x, w1)])[0];
+        let y = g.checkpoint(|| vec![block(h, w2)])[0];
+        assert_eq!(y.static_shape(), Some(vec![-1, 2, 4, 4]));
+        let y = g.reduce_sum_to_scalar(y);
+        let grads = g.grad(&[y], &[x, w1, w2]);
+
+        // Same as the plain blocks
+        let expected = g.reduce_sum_to_scalar(block(block(x, w1), w2));
+        let expected_grads = g.grad(&[expected], &[x, w1, w2]);
+        let x_val = rng.standard_normal(&[2, 2, 6, 6]);
+        let feeds = [x.given(x_val.view())];
+        assert_eq!(y.eval(&feeds), expected.eval(&feeds));
+        for (grad, expected) in grads.iter().zip(&expected_grads) {
+            assert_eq!(grad.eval(&feeds), expected.eval(&feeds));
+        }
+
+        // The convolutions are computed only in the segments, both forward and backward.
+        let computes_conv = |ys: &[ag::Tensor<f64>]| {
+            let mut eval = ag::Eval::new(g);
+            eval.extend(ys).feed(&feeds).profiling(true).run();
+            let profile = eval.profile().unwrap();
+            profile
+                .nodes()
+                .iter()
+                .any(|node| node.op_name.contains("Conv2D"))
+        };
+        assert!(computes_conv(&expected_grads));
+        assert!(!computes_conv(&grads));
+    });
+}
+
+#[test]
+fn test_while_loop_with_changing_shapes() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let x = g.variable(ndarray::arr1(&[1., 2.]));
+        // Doubles the vector three times by concatenating it.
+        let ys = g.while_loop(
+            |vars| g.lesser(g.size(vars[0]), g.scalar(16.)),
+            |vars| vec![g.concat(&[vars[0], vars[0] * 2.], 0)],
+            &[x],
+        );
+        assert_eq!(ys[0].static_shape(), Some(vec![-1]));
+        let y = ys[0].eval(&[]).unwrap();
+        assert_eq!(y.shape(), &[16]);
+        assert_eq!(y[[15]], 16.);
+
+        let gx = g.grad(&[ys[0]], &[x])[0];
+        // Each element of `x` is scaled by 1, 2, 2, 4, 2, 4, 4, 8, ...
+        assert_eq!(gx.eval(&[]), Ok(ndarray::arr1(&[27., 27.]).into_dyn()));
+    });
+}
+
+#[test]
+fn test_jvp() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.variable(rng.standard_normal(&[2, 3]));
+        let w = g.variable(rng.standard_normal(&[3, 4]));
+        let b = g.variable(rng.standard_normal(&[1, 4]));
+        // `softmax` and `log_softmax` have no tangent rules, so use the default of `Op::jvp`.
+        let h = g.sigmoid(g.matmul(x, w) + b) * g.exp(g.tanh(g.matmul(x, w)));
+        let y1 = g.log_softmax(
+            g.softmax(h, 1) / (g.reduce_sum_to_scalar(g.square(x)) + 1.),
+            1,
+        );
+        let y2 = g.reduce_sum(g.sin(h), &[0], false);
+
+        let tangents: Vec<_> = [[2, 3], [3, 4], [1, 4]]
+            .iter()
+            .map(|shape| g.convert_to_tensor(rng.standard_normal(shape)))
+            .collect();
+        let tys = g.jvp(&[y1, y2], &[x, w, b], &tangents);
+
+        // <u, J t> = <J^T u, t> for any `u`, where the right side is from the reverse-mode.
+        let u1 = g.convert_to_tensor(rng.standard_normal(&[2, 4]));
+        let u2 = g.convert_to_tensor(rng.standard_normal(&[4]));
+        let lhs = g.reduce_sum_to_scalar(u1 * tys[0]) + g.reduce_sum_to_scalar(u2 * tys[1]);
+        let grads = g.grad(&[y1 * u1, y2 * u2], &[x, w, b]);
+        let products: Vec<_> = grads
+            .iter()
+            .zip(&tangents)
+            .map(|(gx, t)| g.reduce_sum_to_scalar(gx * t))
+            .collect();
+        let rhs = g.add_n(&products);
+        let ret = g.eval(&[lhs, rhs], &[]);
+        assert!(ret[0]
+            .as_ref()
+            .unwrap()
+            .all_close(ret[1].as_ref().unwrap(), 1e-10));
+
+        // Tangents of the tensors not depending on `xs` are zeros.
+        let ty = g.jvp(&[b], &[x], &[tangents[0]])[0];
+        assert_eq!(ty.eval(&[]), Ok(ag::ndarray_ext::zeros(&[1, 4])));
+    });
+}
+
+#[test]
+fn test_hessian() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.convert_to_tensor(rng.standard_normal(&[2, 3]));
+        let w = g.variable(rng.standard_normal(&[3, 4]));
+        let b = g.variable(rng.standard_normal(&[1, 4]));
+        let t = g.convert_to_tensor(ndarray::arr2(&[[1.], [3.]]));
+        let y = g.reduce_mean(
+            g.sparse_softmax_cross_entropy(g.tanh(g.matmul(x, w) + b), t),
+            &[0],
+            false,
+        );
+        let vars = [w, b];
+        let h = g.hessian(y, &vars);
+        assert_eq!(h[0][0].eval(&[]).unwrap().shape(), &[12, 12]);
+        assert_eq!(h[0][1].eval(&[]).unwrap().shape(), &[12, 4]);
+        assert_eq!(h[1][0].eval(&[]).unwrap().shape(), &[4, 12]);
+        assert_eq!(h[1][1].eval(&[]).unwrap().shape(), &[4, 4]);
+        let grads = g.grad(&[y], &vars);
+        ag::test_helper::check_theoretical_hessian(&grads, &h, &vars, &[], 1e-5, 1e-5);
+
+        // The Hessian is symmetric.
+        let ret = g.eval(&[h[0][1], g.transpose(h[1][0], &[1, 0])], &[]);
+        assert!(ret[0]
+            .as_ref()
+            .unwrap()
+            .all_close(ret[1].as_ref().unwrap(), 1e-10));
+
+        // Blocks of the pairs not interacting are zeros.
+        let y = g.reduce_sum_to_scalar(g.sin(b) * 2.) + g.reduce_sum_to_scalar(w);
+        let h = g.hessian(y, &vars);
+        assert_eq!(h[0][0].eval(&[]), Ok(ag::ndarray_ext::zeros(&[12, 12])));
+        assert_eq!(h[0][1].eval(&[]), Ok(ag::ndarray_ext::zeros(&[12, 4])));
+        assert_eq!(h[1][0].eval(&[]), Ok(ag::ndarray_ext::zeros(&[4, 12])));
+        let grads = g.grad(&[y], &vars);
+        ag::test_helper::check_theoretical_hessian(&grads, &h, &vars, &[], 1e-5, 1e-5);
+    });
+}
+
+#[test]
+fn test_hessian_graph_size() {
+    // The number of the nodes doesn't depend on the sizes of `xs`.
+    let num_nodes = |n: usize| {
+        let mut ret = 0;
+        ag::with(|g: &mut ag::Graph<f64>| {
+            let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+            let a = g.variable(rng.standard_normal(&[n, 2]));
+            let b = g.variable(rng.standard_normal(&[2, 3]));
+            let y = g.reduce_sum_to_scalar(g.tanh(g.matmul(a, b)));
+            let h = g.hessian(y, &[a, b]);
+            assert_eq!(h[0][1].eval(&[]).unwrap().shape(), &[n * 2, 2 * 3]);
+            // Tensor ids are sequential.
+            ret = g.scalar(0.).id() - y.id();
+        });
+        ret
+    };
+    assert_eq!(num_nodes(2), num_nodes(20));
+}
+
+#[test]
+fn test_hvp() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.placeholder(&[-1, 3]);
+        let w = g.variable(rng.standard_normal(&[3, 4]));
+        let b = g.variable(rng.standard_normal(&[1, 4]));
+        let t = g.convert_to_tensor(ndarray::arr2(&[[0., 1., 0., 0.], [0., 0., 0., 1.]]));
+        let y = g.reduce_mean(
+            g.softmax_cross_entropy(g.elu(g.matmul(x, w) + b, 1.), t),
+            &[0],
+            false,
+        );
+        let vars = [w, b];
+        let vectors = [
+            g.convert_to_tensor(rng.standard_normal(&[3, 4])),
+            g.convert_to_tensor(rng.standard_normal(&[1, 4])),
+        ];
+        let hvps = g.hvp(&[y], &vars, &vectors);
+        let grads = g.grad(&[y], &vars);
+        let x_value = rng.standard_normal(&[2, 3]);
+        let feeds = &[x.given(x_value.view())];
+        ag::test_helper::check_theoretical_hvps(&grads, &hvps, &vars, &vectors, feeds, 1e-5, 1e-5);
+
+        // Same as the products with the full Hessian.
+        let h = g.hessian(y, &vars);
+        let flat: Vec<_> = vectors.iter().map(|v| g.reshape(v, &[-1, 1])).collect();
+        let hv = g.matmul(h[0][0], flat[0]) + g.matmul(h[0][1], flat[1]);
+        let ret = g.eval(&[hv, g.reshape(hvps[0], &[-1, 1])], feeds);
+        assert!(ret[0]
+            .as_ref()
+            .unwrap()
+            .all_close(ret[1].as_ref().unwrap(), 1e-10));
+    });
+}
+
+#[test]
+fn test_jacobians() {
+    use ag::JacobianMode;
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.placeholder(&[-1, 3]);
+        let w = g.variable(rng.standard_normal(&[3, 4]));
+        let b = g.variable(rng.standard_normal(&[1, 4]));
+        let y = g.tanh(g.matmul(x, w) + b);
+        let vars = [w, b];
+        let x_value = rng.standard_normal(&[2, 3]);
+        let feeds = &[x.given(x_value.view())];
+
+        // Rows are the gradients of the elements of `y`.
+        let expected: Vec<_> = (0..8)
+            .map(|i| {
+                let grads = g.grad(&[g.flatten(y).access_elem(i)], &vars);
+                let rows: Vec<_> = grads.iter().map(|gx| g.reshape(gx, &[1, -1])).collect();
+                rows
+            })
+            .collect();
+        let expected: Vec<_> = (0..2)
+            .map(|j| g.concat(&expected.iter().map(|r| r[j]).collect::<Vec<_>>(), 0))
+            .collect();
+
+        for &mode in &[
+            JacobianMode::Reverse,
+            JacobianMode::Forward,
+            JacobianMode::Auto,
+        ] {
+            let j = g.jacobians_with_mode(y, &vars, 8, mode);
+            let ret = g.eval(&[j[0], j[1], expected[0], expected[1]], feeds);
+            assert_eq!(ret[0].as_ref().unwrap().shape(), &[8, 12]);
+            assert_eq!(ret[1].as_ref().unwrap().shape(), &[8, 4]);
+            assert!(ret[0]
+                .as_ref()
+                .unwrap()
+                .all_close(ret[2].as_ref().unwrap(), 1e-10));
+            assert!(ret[1]
+                .as_ref()
+                .unwrap()
+                .all_close(ret[3].as_ref().unwrap(), 1e-10));
+        }
+
+        // Jacobians of the tensors not depending on `xs` are zeros.
+        let c = g.variable(rng.standard_normal(&[5]));
+        for &mode in &[JacobianMode::Reverse, JacobianMode::Forward] {
+            let j = g.jacobians_with_mode(y, &[c], 8, mode);
+            assert_eq!(j[0].eval(feeds), Ok(ag::ndarray_ext::zeros(&[8, 5])));
+        }
+    });
+}
+
+#[test]
+fn test_jacobians_values() {
+    use ag::JacobianMode;
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let x = g.variable(ndarray::arr1(&[1., 2.]));
+        let w = g.variable(ndarray::arr2(&[[1., 2.], [3., 4.]]));
+        // The derivatives of y_j = (x w)_j^2 are 2 (x w)_j w_ij for x_i and 2 (x w)_j x_i for w_ij.
+        let y = g.square(g.matmul(g.expand_dims(x, &[0]), w));
+        for &mode in &[JacobianMode::Reverse, JacobianMode::Forward] {
+            let j = g.jacobians_with_mode(y, &[x, w], 2, mode);
+            assert_eq!(
+                j[0].eval(&[]),
+                Ok(ndarray::arr2(&[[14., 42.], [40., 80.]]).into_dyn())
+            );
+            assert_eq!(
+                j[1].eval(&[]),
+                Ok(ndarray::arr2(&[[14., 0., 28., 0.], [0., 20., 0., 40.]]).into_dyn())
+            );
+        }
+    });
+}
+
+#[test]
+fn test_jacobians_graph_size() {
+    // The number of the nodes doesn't depend on the size of `y`.
+    let num_nodes = |n: usize| {
+        let mut ret = 0;
+        ag::with(|g: &mut ag::Graph<f32>| {
+            let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
+            let a = g.variable(rng.standard_normal(&[n, 2]));
+            let b = g.variable(rng.standard_normal(&[2, 3]));
+            let c = g.matmul(a, b);
+            let j = g.jacobians(c, &[a, b], n * 3);
+            assert_eq!(j[0].eval(&[]).unwrap().shape(), &[n * 3, n * 2]);
+            // Tensor ids are sequential.
+            ret = g.scalar(0.).id() - c.id();
+        });
+        ret
+    };
+    assert_eq!(num_nodes(2), num_nodes(20));
+}
+
+#[test]
+fn test_custom_op() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.variable(rng.standard_normal(&[2, 3]));
+        let w = g.variable(rng.standard_normal(&[1, 3]));
+        // x * sin(w), with the gradients of both inputs
+        let y = g.custom_op(
+            &[x, w],
+            |ctx| {
+                let w = ctx.input(1).mapv(f64::sin);
+                let y = &ctx.input(0) * &w;
+                ctx.append_output(y);
+            },
+            |ctx| {
+                let g = ctx.graph();
+                let (x, w, gy) = (ctx.input(0), ctx.input(1), ctx.output_grad());
+                let gx = gy * g.sin(w);
+                let gw = g.reduce_sum(gy * x * g.cos(w), &[0], true);
+                ctx.append_input_grad(Some(gx));
+                ctx.append_input_grad(Some(gw));
+            },
+        );
+        let expected = x * g.sin(w);
+        assert_eq!(y.eval(&[]), expected.eval(&[]));
+        let grads = g.grad(&[y], &[x, w]);
+        ag::test_helper::check_theoretical_grads(y, &grads, &[x, w], &[], 1e-5, 1e-5);
+
+        // Differentiable twice through the symbolic gradients.
+        let ggw = g.grad(&[grads[1]], &[w])[0];
+        let expected_ggw = g.grad(&[g.grad(&[expected], &[w])[0]], &[w])[0];
+        let ret = g.eval(&[ggw, expected_ggw], &[]);
+        assert!(ret[0]
+            .as_ref()
+            .unwrap()
+            .all_close(ret[1].as_ref().unwrap(), 1e-10));
+    });
+}
+
+#[test]
+fn test_custom_op_error() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        let x = g.zeros(&[2]);
+        let y = g.custom_op(
+            &[x],
+            |ctx| ctx.set_error(ag::op::OpError::IncompatibleShape("custom".to_string())),
+            |ctx| ctx.append_input_grad(None),
+        );
+        assert!(y.eval(&[]).is_err());
+
+        // A closure without outputs fails the evaluation instead of the process.
+        let y = g.custom_op(&[x], |_| {}, |ctx| ctx.append_input_grad(None));
+        match y.eval(&[]) {
+            Err(ag::EvalError::OpPanicked { message, .. }) => {
+                assert!(message.contains("empty return value"))
+            }
+            ret => panic!("expected OpPanicked, got {:?}", ret),
+        }
+    });
+}
+
+#[test]
+#[should_panic(expected = "`grad` must append one gradient for each of `xs`")]
+fn test_custom_gradient_missing_grads() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        let x = g.zeros(&[2]);
+        let w = g.variable(ag::ndarray_ext::ones(&[2]));
+        let y = g.custom_gradient(
+            &[x, w],
+            |xs| xs[0] * xs[1],
+            |ctx| ctx.append_input_grad(Some(ctx.output_grad())),
+        );
+        g.grad(&[y], &[w]);
+    });
+}
+
+#[test]
+fn test_custom_gradient() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let x = g.placeholder(&[-1]);
+        let w = g.variable(ndarray::arr1(&[2., -3.]));
+        // Clips the gradient of `x` in backward.
+        let y = g.custom_gradient(
+            &[x, w],
+            |xs| xs[0] * xs[1],
+            |ctx| {
+                let g = ctx.graph();
+                let (w, gy) = (ctx.input(1), ctx.output_grad());
+                let gx = g.clip(gy * w, -1., 1.);
+                let gw = gy * ctx.input(0);
+                ctx.append_input_grad(Some(gx));
+                ctx.append_input_grad(Some(gw));
+            },
+        );
+        let grads = g.grad(&[y * 3.], &[x, w]);
+
+        let x_val = ndarray::arr1(&[0.5, 4.]).into_dyn();
+        let ret = g.eval(&[y, grads[0], grads[1]], &[x.given(x_val.view())]);
+        assert_eq!(ret[0], Ok(ndarray::arr1(&[1., -12.]).into_dyn()));
+        assert_eq!(ret[1], Ok(ndarray::arr1(&[1., -1.]).into_dyn()));
+        assert_eq!(ret[2], Ok(ndarray::arr1(&[1.5, 12.]).into_dyn()));
+    });
+
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.variable(rng.standard_normal(&[3]));
+        let w = g.variable(rng.standard_normal(&[3]));
+        // Gradients are not propagated through the subgraph, even to the tensors not in `xs`.
+        let y = g.custom_gradient(
+            &[x],
+            |xs| g.exp(xs[0] * w),
+            |ctx| {
+                let gy = ctx.output_grad();
+                ctx.append_input_grad(Some(gy * 2.));
+            },
+        );
+        let gx = g.grad(&[y], &[x])[0];
+        assert_eq!(gx.eval(&[]), Ok(ndarray::arr1(&[2., 2., 2.]).into_dyn()));
+        let tangent = g.convert_to_tensor(ndarray::arr1(&[1., 0., -1.]));
+        let ty = g.jvp(&[y], &[x], &[tangent])[0];
+        assert_eq!(ty.eval(&[]), Ok(ndarray::arr1(&[2., 0., -2.]).into_dyn()));
+    });
+}
+
+#[test]
+fn test_int_indices() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        let param = g.variable(ndarray::arr2(&[[0., 1.], [2., 3.], [4., 5.]]));
+        let indices = g.placeholder_int(&[-1]);
+        let y = g.gather_common(param, indices, 0);
+        let gp = g.grad(&[y], &[param])[0];
+        assert_eq!(indices.dtype(), ag::DType::Int);
+        assert!(!indices.is_differentiable());
+
+        let value = ndarray::arr1(&[2, -1, 0]);
+        let ret = g.eval(&[y, gp], &[indices.given_int(value.view())]);
+        assert_eq!(
+            ret[0],
+            Ok(ndarray::arr2(&[[4., 5.], [4., 5.], [0., 1.]]).into_dyn())
+        );
+        assert_eq!(
+            ret[1],
+            Ok(ndarray::arr2(&[[1., 1.], [0., 0.], [2., 2.]]).into_dyn())
+        );
+    });
+}
+
+#[test]
+fn test_int_labels() {
+    ag::with(|g: &mut ag::Graph<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = g.variable(rng.standard_normal(&[3, 4]));
+        let int_t = g.placeholder_int(&[-1]);
+        let float_t = g.placeholder(&[-1]);
+        let int_loss = g.sparse_softmax_cross_entropy(x, int_t);
+        let float_loss = g.sparse_softmax_cross_entropy(x, float_t);
+        let int_gx = g.grad(&[int_loss], &[x])[0];
+        let float_gx = g.grad(&[float_loss], &[x])[0];
+
+        let int_val = ndarray::arr1(&[3, 0, 1]);
+        let float_val = ndarray::arr1(&[3., 0., 1.]);
+        let ret = g.eval(
+            &[int_loss, float_loss, int_gx, float_gx],
+            &[
+                int_t.given_int(int_val.view()),
+                float_t.given(float_val.view()),
+            ],
+        );
+        assert_eq!(ret[0], ret[1]);
+        assert_eq!(ret[2], ret[3]);
+    });
+}
+
+#[test]
+fn test_cast() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        // Not representable in f32
+        let big = ndarray::arr1(&[16_777_217, -3]);
+        let i = g.placeholder_int(&[2]);
+        let feeds = [i.given_int(big.view())];
+        let j = g.cast(g.cast(i, ag::DType::Bool), ag::DType::Int);
+        let expected = Ok(big.view().into_dyn().to_owned());
+        assert_eq!(i.eval_int(&feeds), expected);
+        assert_eq!(g.cast(i, ag::DType::Int).eval_int(&feeds), expected);
+        assert_eq!(j.eval_int(&feeds), Ok(ndarray::arr1(&[1, 1]).into_dyn()));
+        // `eval` converts the values into floats.
+        assert_eq!(j.eval(&feeds), Ok(ndarray::arr1(&[1., 1.]).into_dyn()));
+        assert!(j.eval_bool(&feeds).is_err());
+
+        let x = g.variable(ndarray::arr1(&[-1.5, 0., 2.7]));
+        let y = g.cast(g.cast(x, ag::DType::Int), ag::DType::Float);
+        let z = g.cast(x, ag::DType::Float) * y;
+        assert_eq!(y.eval(&[]), Ok(ndarray::arr1(&[-1., 0., 2.]).into_dyn()));
+        assert_eq!(
+            g.cast(x, ag::DType::Bool).eval_bool(&[]),
+            Ok(ndarray::arr1(&[true, false, true]).into_dyn())
+        );
+        // The integer path is not differentiable.
+        let gx = g.grad(&[z], &[x])[0];
+        assert_eq!(gx.eval(&[]), Ok(ndarray::arr1(&[-1., 0., 2.]).into_dyn()));
+    });
+}
+
+#[test]
+fn test_int_and_bool_outputs() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        // The indices and the size are not representable in f32.
+        let n = 16_777_219;
+        let x = g.placeholder(&[-1]);
+        let mut value = ndarray::Array1::zeros(n);
+        value[n - 2] = 1.;
+        let feeds = [x.given(value.view())];
+        let i = g.argmax_int(g.reshape(x, &[1, -1]), 1, true);
+        assert_eq!(i.dtype(), ag::DType::Int);
+        assert_eq!(
+            i.eval_int(&feeds),
+            Ok(ndarray::arr2(&[[n as i64 - 2]]).into_dyn())
+        );
+        let size = g.size_int(x).eval_int(&feeds);
+        assert_eq!(size, Ok(ndarray::arr0(n as i64).into_dyn()));
+        assert_eq!(
+            g.shape_int(x).eval_int(&feeds),
+            Ok(ndarray::arr1(&[n as i64]).into_dyn())
+        );
+        assert_eq!(
+            g.rank_int(x).eval_int(&feeds),
+            Ok(ndarray::arr0(1).into_dyn())
+        );
+
+        let a = g.convert_to_tensor(ndarray::arr1(&[1., 2., 3.]));
+        let b = g.scalar(2.);
+        let masks = [
+            (g.equal_bool(a, b), [false, true, false]),
+            (g.not_equal_bool(a, b), [true, false, true]),
+            (g.greater_bool(a, b), [false, false, true]),
+            (g.greater_equal_bool(a, b), [false, true, true]),
+            (g.lesser_bool(a, b), [true, false, false]),
+            (g.lesser_equal_bool(a, b), [true, true, false]),
+        ];
+        for (mask, expected) in masks.iter() {
+            assert_eq!(mask.dtype(), ag::DType::Bool);
+            assert_eq!(mask.eval_bool(&[]), Ok(ndarray::arr1(expected).into_dyn()));
+        }
+        // The masks are not differentiable.
+        assert!(g.sigmoid(masks[0].0).shape_error().is_some());
+    });
+}
+
+#[test]
+fn test_feed_dtype_mismatch() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        let i = g.placeholder_int(&[2]);
+        let y = g.cast(i, ag::DType::Float);
+        let value = ndarray::arr1(&[1., 2.]);
+        match y.eval(&[i.given(value.view())]) {
+            Err(ag::EvalError::FeedTypeMismatch {
+                expected, actual, ..
+            }) => {
+                assert_eq!(expected, ag::DType::Int);
+                assert_eq!(actual, ag::DType::Float);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn test_int_input_of_float_op() {
+    ag::with(|g: &mut ag::Graph<f32>| {
+        let i = g.placeholder_int(&[2]);
+        let y = g.sigmoid(i);
+        let i_val = ndarray::arr1(&[1, 2]).into_dyn();
+        match y.eval(&[i.given_int(i_val.view())]) {
+            Err(ag::EvalError::OpError(ag::op::OpError::TypeUnsupported(_))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    });
+}
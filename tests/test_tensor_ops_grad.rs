@@ -496,6 +496,18 @@ fn mul() {
     });
 }
 
+#[test]
+fn mul_scalar() {
+    with(|graph| {
+        // The ranks differ but no broadcast occurs.
+        let a = graph.variable(ndarray::arr0(2.));
+        let b = graph.variable(ndarray::arr1(&[3.]));
+        let z = a * b;
+        let g = graph.grad(&[z], &[a, b]);
+        ag::test_helper::check_theoretical_grads(z, g.as_slice(), &[a, b], &[], 1e-3, 1e-3);
+    });
+}
+
 #[test]
 fn sigmoid() {
     with(|graph| {
@@ -550,6 +562,17 @@ fn logsumexp() {
     });
 }
 
+#[test]
+fn logsumexp_no_keep_dims() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let v = graph.variable(rng.standard_normal(&[2, 3]));
+        let z = graph.reduce_logsumexp(v, 1, false);
+        let g = graph.grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(z, g.as_slice(), &[v], &[], 1e-3, 1e-3);
+    });
+}
+
 #[test]
 fn log_softmax() {
     with(|graph| {
@@ -609,6 +632,18 @@ fn gather() {
     });
 }
 
+#[test]
+fn gather_negative_axis() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let v = graph.variable(rng.standard_normal(&[3, 4]));
+        let x = graph.constant(ndarray::arr1(&[3., 0., 3.]));
+        let z = graph.gather(v, x, -1);
+        let g = graph.grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(z, g.as_slice(), &[v], &[], 1e-3, 1e-3);
+    });
+}
+
 #[test]
 fn concat() {
     with(|graph| {
@@ -621,6 +656,30 @@ fn concat() {
     });
 }
 
+#[test]
+fn concat_following_inputs() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let v1 = graph.variable(rng.standard_normal(&[2, 3]));
+        let v2 = graph.variable(rng.standard_normal(&[2, 2]));
+        let v3 = graph.variable(rng.standard_normal(&[2, 1]));
+        let z = graph.concat(&[v1, v2, v3], 1);
+        let g = graph.grad(&[z], &[v2, v3]);
+        ag::test_helper::check_theoretical_grads(z, g.as_slice(), &[v2, v3], &[], 1e-3, 1e-3);
+    });
+}
+
+#[test]
+fn tile() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let v = graph.variable(rng.standard_normal(&[2, 3]));
+        let z = graph.tile(v, 0, 3);
+        let g = graph.grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(z, g.as_slice(), &[v], &[], 1e-3, 1e-3);
+    });
+}
+
 #[test]
 fn slice() {
     with(|graph| {
@@ -869,3 +928,157 @@ fn primitive_back_propagation_through_time() {
         );
     });
 }
+
+// Checks the Hessian of `y` with respect to `vars` with finite differences.
+fn check_hessian<'g>(
+    graph: &'g ag::Graph<f64>,
+    y: ag::Tensor<'g, f64>,
+    vars: &[ag::Tensor<'g, f64>],
+) {
+    let grads = graph.grad(&[y], vars);
+    let h = graph.hessian(y, vars);
+    ag::test_helper::check_theoretical_hessian(&grads, &h, vars, &[], 1e-5, 1e-4);
+}
+
+#[test]
+fn conv2d_second_order() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = graph.variable(rng.standard_normal(&[1, 2, 4, 4]));
+        let w = graph.variable(rng.standard_normal(&[2, 2, 3, 3]));
+        let y = graph.reduce_sum_to_scalar(graph.square(graph.conv2d(x, w, 1, 1)));
+        check_hessian(graph, y, &[x, w]);
+    });
+}
+
+#[test]
+fn conv2d_transpose_second_order() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = graph.variable(rng.standard_normal(&[1, 2, 2, 2]));
+        let w = graph.variable(rng.standard_normal(&[2, 1, 3, 3]));
+        let y = graph.reduce_sum_to_scalar(graph.square(graph.conv2d_transpose(x, w, 0, 2)));
+        check_hessian(graph, y, &[x, w]);
+    });
+}
+
+#[test]
+fn max_pool2d_second_order() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = graph.variable(rng.standard_normal(&[1, 1, 4, 4]));
+        let y = graph.max_pool2d(graph.sin(x), 2, 0, 2);
+        let y = graph.reduce_sum_to_scalar(graph.square(y));
+        // differentiates max_pool2d three times
+        let gx = graph.grad(&[y], &[x])[0];
+        check_hessian(graph, graph.reduce_sum_to_scalar(graph.square(gx)), &[x]);
+    });
+}
+
+#[test]
+fn softmax_cross_entropy_second_order() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = graph.variable(rng.standard_normal(&[2, 3]));
+        let t = graph.constant(ndarray::arr2(&[[0., 1., 0.], [1., 0., 0.]]));
+        let y = graph.reduce_sum_to_scalar(graph.softmax_cross_entropy(x, t));
+        check_hessian(graph, y, &[x]);
+    });
+}
+
+#[test]
+fn sparse_softmax_cross_entropy_second_order() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = graph.variable(rng.standard_normal(&[2, 3]));
+        let t = graph.constant(ndarray::arr2(&[[1.], [0.]]));
+        let y = graph.reduce_sum_to_scalar(graph.sparse_softmax_cross_entropy(x, t));
+        // differentiates sparse_softmax_cross_entropy three times
+        let gx = graph.grad(&[y], &[x])[0];
+        check_hessian(graph, graph.reduce_sum_to_scalar(graph.square(gx)), &[x]);
+    });
+}
+
+#[test]
+fn logsumexp_second_order() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = graph.variable(rng.standard_normal(&[2, 3]));
+        let y = graph.square(graph.reduce_logsumexp(x, 1, false));
+        check_hessian(graph, graph.reduce_sum_to_scalar(y), &[x]);
+    });
+}
+
+#[test]
+fn elu_second_order() {
+    with(|graph| {
+        let x = graph.variable(ndarray::arr1(&[-1.5, -0.3, 0.4, 1.2]));
+        let gx = graph.grad(&[graph.elu(x, 1.)], &[x])[0];
+        check_hessian(graph, graph.reduce_sum_to_scalar(graph.square(gx)), &[x]);
+    });
+}
+
+#[test]
+fn broadcast_binary_ops_second_order() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let a = graph.variable(rng.standard_normal(&[2, 3]));
+        let b = graph.variable(rng.standard_normal(&[1, 3]));
+        let y = graph.square(a + b)
+            + graph.square(a - b)
+            + graph.square(a * b)
+            + graph.sin(a / (graph.square(b) + 1.));
+        check_hessian(graph, graph.reduce_sum_to_scalar(y), &[a, b]);
+    });
+}
+
+#[test]
+fn reduction_ops_second_order() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = graph.variable(rng.standard_normal(&[2, 3]));
+        let y = graph.square(graph.reduce_sum(graph.sin(x), &[1], false))
+            + graph.square(graph.reduce_mean(graph.sin(x), &[1], false))
+            + graph.square(graph.reduce_max(x, &[1], false))
+            + graph.reduce_prod(x, &[1], false);
+        check_hessian(graph, graph.reduce_sum_to_scalar(y), &[x]);
+    });
+}
+
+#[test]
+fn matmul_second_order() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let a = graph.variable(rng.standard_normal(&[2, 3]));
+        let b = graph.variable(rng.standard_normal(&[2, 3, 2]));
+        let y = graph.matmul(graph.transpose(a, &[1, 0]), a);
+        let z = graph.batch_matmul(graph.tile(graph.expand_dims(a, &[0]), 0, 2), b);
+        let y =
+            graph.reduce_sum_to_scalar(graph.tanh(y)) + graph.reduce_sum_to_scalar(graph.square(z));
+        check_hessian(graph, y, &[a, b]);
+    });
+}
+
+#[test]
+fn array_ops_second_order() {
+    with(|graph| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let a = graph.variable(rng.standard_normal(&[4, 2]));
+        let b = graph.variable(rng.standard_normal(&[2, 2]));
+        let indices = graph.constant(ndarray::arr1(&[3., 0., 3.]));
+        let parts = graph.split(a, &[1, 3], 0);
+        let ys = [
+            graph.concat(&[b, graph.sin(a)], 0) * graph.concat(&[a, b], 0),
+            graph.slice(graph.sin(a), &[1, 0], &[3, 1]),
+            graph.reduce_sum_to_scalar(parts[1]) * parts[0],
+            graph.tile(graph.sin(b), 1, 3),
+            graph.gather(graph.sin(a), indices, 0),
+            graph.clip(a, -0.5, 0.5) * a,
+            graph.sin(b).access_elem(2) * b,
+        ];
+        let y = ys.iter().fold(graph.scalar(0.), |acc, y| {
+            acc + graph.reduce_sum_to_scalar(graph.square(y))
+        });
+        check_hessian(graph, y, &[a, b]);
+    });
+}